@@ -1,40 +1,57 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_std::io;
-use async_std::net::{TcpListener, TcpStream};
+use async_std::net::TcpListener;
 use async_std::prelude::*;
 use async_std::task;
-use bytes::BytesMut;
-use mini_http::{Response, Request};
-
-async fn process(mut stream: TcpStream) -> io::Result<()> {
-    let mut v =vec![0u8; 16 * 1024];
-    let mut input = BytesMut::new();
-    let mut output = BytesMut::new();
-
-    loop {
-        match stream.read(&mut v).await? {
-            0 => return Ok(()),
-            n => input.extend_from_slice(&v[..n]),
-        }
+use futures::FutureExt;
 
-        while let Some(_) = Request::decode(&mut input)?{
-            Response::new()
-                .header("Content-Type","text/plain")
-                .body("Hello World")
-                .encode(&mut output);
-            stream.write_all(&output).await?;
-            output.clear();
-        }
-    }
+use mini_http::{serve, Logger, Request, Response, Router, Timeout};
+
+async fn hello(req: Request) -> Response {
+    let name = req.query_param("name").unwrap_or_else(|| "World".to_string());
+    let mut response = Response::new();
+    response
+        .header("Content-Type", "text/plain")
+        .body(&format!("Hello {}", name));
+    response
+}
+
+async fn user(req: Request) -> Response {
+    let mut response = Response::new();
+    response
+        .header("Content-Type", "text/plain")
+        .body(&format!("user {}", req.param("id").unwrap()));
+    response
+}
+
+async fn echo(req: Request) -> Response {
+    let mut response = Response::new();
+    response.body_bytes(req.body());
+    response
 }
 
 fn main() -> io::Result<()> {
+    let mut router = Router::new();
+    router
+        .get("/", hello)
+        .get("/users/:id", user)
+        .post("/echo", echo)
+        .middleware(Logger)
+        .middleware(Timeout::with_timer(Duration::from_secs(5), |duration| {
+            task::sleep(duration).boxed()
+        }));
+    let router = Arc::new(router);
+
     task::block_on(async {
         let listener = TcpListener::bind("0.0.0.0:8080").await?;
         let mut incoming = listener.incoming();
 
         while let Some(stream) = incoming.next().await {
-            task::spawn(process(stream?));
+            // async-std streams implement the `futures` traits already
+            task::spawn(serve(stream?, router.clone()));
         }
         Ok(())
     })
-}
\ No newline at end of file
+}
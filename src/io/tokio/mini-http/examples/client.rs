@@ -0,0 +1,26 @@
+use std::env;
+use std::io::{self, Write};
+
+use async_std::net::TcpStream;
+use async_std::task;
+
+use mini_http::{Client, Error};
+
+/// Fetches the URLs given as arguments, over a shared pool of connections, e.g. from the
+/// `tokio` example: `cargo run --example client http://localhost:8080/ http://localhost:8080/users/1`
+fn main() -> Result<(), Error> {
+    let client = Client::new(TcpStream::connect);
+    task::block_on(async {
+        for url in env::args().skip(1) {
+            let response = client.get(&url).await?;
+            println!("{} {} {}", url, response.status(), response.reason());
+            for (name, value) in response.headers() {
+                println!("{}: {}", name, value);
+            }
+            println!();
+            io::stdout().write_all(response.get_body())?;
+            println!();
+        }
+        Ok(())
+    })
+}
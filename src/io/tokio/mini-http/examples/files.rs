@@ -0,0 +1,23 @@
+use std::env;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use mini_http::{serve_blocking, Compress, Files, Logger, Router};
+
+/// Serves a directory, the current one by default, at http://localhost:8080/
+fn main() {
+    let root = env::args().nth(1).unwrap_or_else(|| ".".to_string());
+    let mut router = Router::new();
+    router
+        .get("/*path", Files::new("/", root))
+        .middleware(Logger)
+        .middleware(Compress::new());
+    let router = Arc::new(router);
+
+    let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
+    for stream in listener.incoming() {
+        let router = router.clone();
+        thread::spawn(move || serve_blocking(stream.unwrap(), &*router));
+    }
+}
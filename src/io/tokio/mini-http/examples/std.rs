@@ -1,42 +1,46 @@
-use std::io;
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
+use std::net::TcpListener;
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
-use bytes::BytesMut;
+use mini_http::{serve_blocking, Logger, Request, Response, Router, Timeout};
 
-use mini_http::request::Request;
-use mini_http::response::Response;
-
-fn process(mut stream: TcpStream) -> io::Result<()> {
-    let mut v = vec![0u8; 16 * 1024];
-    let mut input = BytesMut::new();
-    let mut output = BytesMut::new();
-
-    loop {
-        match stream.read(&mut v)? {
-            0 => return Ok(()),
-            n => input.extend_from_slice(&v[..n]),
-        }
+async fn hello(req: Request) -> Response {
+    let name = req.query_param("name").unwrap_or_else(|| "World".to_string());
+    let mut response = Response::new();
+    response
+        .header("Content-Type", "text/plain")
+        .body(&format!("Hello {}", name));
+    response
+}
 
-        while let Some(_) = Request::decode(&mut input)? {
-            Response::new()
-                .header("Content-Type", "text/plain")
-                .body("Hello World")
-                .encode(&mut output);
+async fn user(req: Request) -> Response {
+    let mut response = Response::new();
+    response
+        .header("Content-Type", "text/plain")
+        .body(&format!("user {}", req.param("id").unwrap()));
+    response
+}
 
-            stream.write_all(&output)?;
-            output.clear();
-        }
-    }
+async fn echo(req: Request) -> Response {
+    let mut response = Response::new();
+    response.body_bytes(req.body());
+    response
 }
 
 fn main() {
-    let listener = TcpListener::bind("0.0.0.0:8080");
-    let listener = listener.unwrap();
-    let mut incoming = listener.incoming();
-
-    while let Some(stream) = incoming.next() {
-        thread::spawn(|| process(stream.unwrap()));
+    let mut router = Router::new();
+    router
+        .get("/", hello)
+        .get("/users/:id", user)
+        .post("/echo", echo)
+        .middleware(Logger)
+        .middleware(Timeout::new(Duration::from_secs(5)));
+    let router = Arc::new(router);
+
+    let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
+    for stream in listener.incoming() {
+        let router = router.clone();
+        thread::spawn(move || serve_blocking(stream.unwrap(), &*router));
     }
 }
@@ -1,40 +1,100 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
 use futures::prelude::*;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::prelude::*;
 use tokio::task;
-use std::io;
-use bytes::BytesMut;
-use mini_http::{Response, Request};
-
-async fn process(mut stream: TcpStream) -> io::Result<()> {
-    let mut v =vec![0u8; 16 * 1024];
-    let mut input = BytesMut::new();
-    let mut output = BytesMut::new();
-
-    loop {
-        match stream.read(&mut v).await? {
-            0 => return Ok(()),
-            n => input.extend_from_slice(&v[..n]),
-        }
-
-        while let Some(_) = Request::decode(&mut input)?{
-            Response::new()
-                .header("Content-Type","text/plain")
-                .body("Hello World")
-                .encode(&mut output);
-            stream.write_all(&output).await?;
-            output.clear();
-        }
+use tokio::time;
+
+use mini_http::negotiate;
+use mini_http::{serve, Compress, Logger, Request, Response, Router, Timeout};
+
+/// Adapts a tokio stream to the `futures` traits `serve` reads and writes with
+struct Compat(TcpStream);
+
+impl AsyncRead for Compat {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, buf)
     }
 }
 
+impl AsyncWrite for Compat {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.0), cx)
+    }
+}
+
+async fn hello(req: Request) -> Response {
+    let name = req.query_param("name").unwrap_or_else(|| "World".to_string());
+    let mut response = Response::new();
+    response
+        .header("Content-Type", "text/plain")
+        .body(&format!("Hello {}", name));
+    response
+}
+
+async fn user(req: Request) -> Response {
+    let id = req.param("id").unwrap();
+    let mut response = Response::new();
+    match negotiate::media_type(&req, &["text/plain", "application/json"]) {
+        Some("application/json") => response
+            .header("Content-Type", "application/json")
+            .body(&format!("{{\"id\":\"{}\"}}", id)),
+        Some(content_type) => response
+            .header("Content-Type", content_type)
+            .body(&format!("user {}", id)),
+        None => response
+            .status_code(406, "Not Acceptable")
+            .header("Content-Type", "text/plain")
+            .body("text/plain or application/json"),
+    };
+    response
+}
+
+async fn echo(req: Request) -> Response {
+    let mut response = Response::new();
+    response.body_bytes(req.body());
+    response
+}
+
 #[tokio::main]
 async fn main() -> io::Result<()> {
+    let mut router = Router::new();
+    router
+        .get("/", hello)
+        .get("/users/:id", user)
+        .post("/echo", echo)
+        .middleware(Logger)
+        .middleware(Compress::new())
+        .middleware(Timeout::with_timer(Duration::from_secs(5), |duration| {
+            time::delay_for(duration).boxed()
+        }));
+    let router = Arc::new(router);
+
     let mut listener = TcpListener::bind("0.0.0.0:8080").await?;
     let mut incoming = listener.incoming();
 
     while let Some(stream) = incoming.next().await {
-        task::spawn(process(stream?));
+        task::spawn(serve(Compat(stream?), router.clone()));
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::prelude::*;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task;
+
+use mini_http::websocket::Handler;
+use mini_http::{serve, Error, Logger, Message, Request, Response, Router, Upgraded, WebSocket};
+
+/// Adapts a tokio stream to the `futures` traits `serve` reads and writes with
+struct Compat(TcpStream);
+
+impl AsyncRead for Compat {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, buf)
+    }
+}
+
+impl AsyncWrite for Compat {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.0), cx)
+    }
+}
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<body>
+<pre id="log"></pre>
+<input id="input" autofocus>
+<script>
+  const name = prompt("Please enter your username:") || "anonymous";
+  const ws = new WebSocket(`ws://${location.host}/chat?name=${encodeURIComponent(name)}`);
+  const log = document.getElementById("log");
+  const input = document.getElementById("input");
+  ws.onmessage = (event) => log.textContent += event.data + "\n";
+  ws.onclose = () => log.textContent += "disconnected\n";
+  input.onkeydown = (event) => {
+    if (event.key === "Enter" && input.value) {
+      ws.send(input.value);
+      input.value = "";
+    }
+  };
+</script>
+</body>
+</html>
+"#;
+
+/// Shorthand for the transmit half of the message channel
+type Tx = mpsc::UnboundedSender<String>;
+/// Shorthand for the receive half of the message channel
+type Rx = mpsc::UnboundedReceiver<String>;
+
+struct Shared {
+    peers: HashMap<usize, Tx>,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Shared {
+            peers: HashMap::new(),
+        }
+    }
+
+    fn broadcast(&mut self, sender: usize, message: &str) {
+        for (id, tx) in self.peers.iter_mut() {
+            if *id != sender {
+                let _ = tx.send(message.into());
+            }
+        }
+    }
+}
+
+struct Peer {
+    ws: WebSocket<Upgraded>,
+    rx: Rx,
+}
+
+enum Event {
+    /// A message that should be broadcasted to others
+    Broadcast(Message),
+    /// A message that should be received by client
+    Received(String),
+}
+
+impl Stream for Peer {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // First poll the `UnboundedReceiver`
+        if let Poll::Ready(Some(v)) = Pin::new(&mut self.rx).poll_next(cx) {
+            return Poll::Ready(Some(Ok(Event::Received(v))));
+        }
+        // Secondly poll the socket
+        let result = futures::ready!(Pin::new(&mut self.ws).poll_next(cx));
+        Poll::Ready(result.map(|r| r.map(Event::Broadcast)))
+    }
+}
+
+/// Sends every message back
+async fn echo(_: Request, mut ws: WebSocket<Upgraded>) {
+    while let Some(Ok(message)) = ws.next().await {
+        let reply = match message {
+            Message::Text(_) | Message::Binary(_) => message,
+            _ => continue,
+        };
+        if ws.send(reply).await.is_err() {
+            break;
+        }
+    }
+    let _ = ws.close().await;
+}
+
+/// Sends every text to the other peers in the chat
+async fn chat(state: Arc<Mutex<Shared>>, id: usize, req: Request, ws: WebSocket<Upgraded>) {
+    let username = req
+        .query_param("name")
+        .unwrap_or_else(|| format!("peer {}", id));
+    let (tx, rx) = mpsc::unbounded_channel();
+    state.lock().await.peers.insert(id, tx);
+    let mut peer = Peer { ws, rx };
+
+    let msg = format!("{} has joined the chat", username);
+    println!("{}", msg);
+    state.lock().await.broadcast(id, &msg);
+
+    while let Some(result) = peer.next().await {
+        match result {
+            Ok(Event::Broadcast(Message::Text(text))) => {
+                let msg = format!("{}: {}", username, text);
+                state.lock().await.broadcast(id, &msg);
+            }
+            Ok(Event::Broadcast(_)) => {}
+            Ok(Event::Received(msg)) => {
+                if peer.ws.send(Message::Text(msg)).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                println!(
+                    "an error occurred while processing messages for {}; error = {}",
+                    username, e
+                );
+                break;
+            }
+        }
+    }
+    let _ = peer.ws.close().await;
+
+    // The socket is closed, so remove the peer and let everyone know
+    let mut state = state.lock().await;
+    state.peers.remove(&id);
+    let msg = format!("{} has left the chat", username);
+    println!("{}", msg);
+    state.broadcast(id, &msg);
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let state = Arc::new(Mutex::new(Shared::new()));
+    let next_id = AtomicUsize::new(0);
+
+    let mut router = Router::new();
+    router
+        .get("/", |_| async {
+            let mut response = Response::new();
+            response.header("Content-Type", "text/html").body(PAGE);
+            response
+        })
+        .get("/echo", Handler::new(echo))
+        .get(
+            "/chat",
+            Handler::new(move |req, ws| {
+                let id = next_id.fetch_add(1, Ordering::Relaxed);
+                chat(state.clone(), id, req, ws)
+            }),
+        )
+        .middleware(Logger);
+    let router = Arc::new(router);
+
+    let mut listener = TcpListener::bind("0.0.0.0:8080").await?;
+    let mut incoming = listener.incoming();
+
+    while let Some(stream) = incoming.next().await {
+        task::spawn(serve(Compat(stream?), router.clone()));
+    }
+    Ok(())
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::sync::Mutex;
+
+use bytes::BytesMut;
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::Error;
+use crate::request::{Limits, Request};
+use crate::response::{self, Response};
+use crate::url::Url;
+
+/// A connection the client can send requests over, such as an async-std `TcpStream`
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+type Connect = Box<dyn Fn(String) -> BoxFuture<'static, io::Result<Box<dyn Stream>>> + Send + Sync>;
+
+/// Sends requests to `http://` URLs, keeping connections alive between them and following
+/// redirects. Connections are opened with a function given the `host:port` to connect to,
+/// which ties the client to a runtime no more than `serve` is.
+pub struct Client {
+    connect: Connect,
+    /// Connections kept alive, by `host:port`
+    idle: Mutex<HashMap<String, Vec<Box<dyn Stream>>>>,
+    max_idle_per_host: usize,
+    max_redirects: usize,
+    limits: Limits,
+}
+
+/// How a round trip over a connection failed
+struct Failure {
+    error: Error,
+    /// Whether the request may be sent again on a new connection: it could not be written, or
+    /// it is idempotent and nothing of a response arrived
+    retry: bool,
+}
+
+impl Client {
+    pub fn new<F, Fut, S>(connect: F) -> Client
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<S>> + Send + 'static,
+        S: Stream,
+    {
+        Client {
+            connect: Box::new(move |addr| {
+                connect(addr)
+                    .map_ok(|stream| Box::new(stream) as Box<dyn Stream>)
+                    .boxed()
+            }),
+            idle: Mutex::new(HashMap::new()),
+            max_idle_per_host: 8,
+            max_redirects: 10,
+            limits: Limits::default(),
+        }
+    }
+
+    /// How many redirects to follow before failing with `Error::TooManyRedirects`, 10 by
+    /// default. With 0, redirects are returned as they are.
+    pub fn max_redirects(&mut self, max_redirects: usize) -> &mut Client {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// How many idle connections to keep per `host:port`, 8 by default
+    pub fn max_idle_per_host(&mut self, max_idle_per_host: usize) -> &mut Client {
+        self.max_idle_per_host = max_idle_per_host;
+        self
+    }
+
+    /// Limits the size of responses, as `Decoder::with_limits` does for requests
+    pub fn limits(&mut self, limits: Limits) -> &mut Client {
+        self.limits = limits;
+        self
+    }
+
+    pub async fn get(&self, url: &str) -> Result<Response, Error> {
+        self.send("GET", url, &[], b"").await
+    }
+
+    /// Sends a request and returns the response, after following redirects. 303, and 301 or
+    /// 302 to a POST, are followed with a GET without the body; 307 and 308 repeat the
+    /// request. `Authorization` and `Cookie` are not sent on to other hosts.
+    pub async fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Response, Error> {
+        let mut url = Url::parse(url).ok_or_else(|| Error::InvalidUrl(url.to_string()))?;
+        let mut method = method.to_string();
+        let mut body = body;
+        let mut same_host = true;
+        let mut redirects = 0;
+        loop {
+            let mut req = Request::new(&method, &url.target);
+            req.add_header("Host", &url.host_header());
+            for (name, value) in headers {
+                let credentials = name.eq_ignore_ascii_case("Authorization")
+                    || name.eq_ignore_ascii_case("Cookie");
+                if same_host || !credentials {
+                    req.add_header(name, value);
+                }
+            }
+            req.set_body(body);
+            let response = self.round_trip(&url, &req).await?;
+
+            let status = response.status();
+            let location = match (status, response.get_header("Location")) {
+                (301 | 302 | 303 | 307 | 308, Some(location)) => location,
+                _ => return Ok(response),
+            };
+            if redirects == self.max_redirects {
+                return match self.max_redirects {
+                    0 => Ok(response),
+                    _ => Err(Error::TooManyRedirects),
+                };
+            }
+            redirects += 1;
+
+            let next = url
+                .join(location)
+                .ok_or_else(|| Error::InvalidUrl(location.to_string()))?;
+            if status == 303 || (matches!(status, 301 | 302) && method == "POST") {
+                if method != "HEAD" {
+                    method = "GET".to_string();
+                }
+                body = b"";
+            }
+            same_host &= next.authority() == url.authority();
+            url = next;
+        }
+    }
+
+    /// Sends a request on an idle connection to the host if there is one, and on a new one if
+    /// not or if the idle one turns out to have been closed
+    async fn round_trip(&self, url: &Url, req: &Request) -> Result<Response, Error> {
+        let authority = url.authority();
+        let mut out = BytesMut::new();
+        req.encode(&mut out);
+        let head = req.method() == "HEAD";
+        // a request that was written may have been processed even if the connection closed
+        // without a response, so only one that is safe to repeat is sent again
+        let idempotent = matches!(
+            req.method(),
+            "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE"
+        );
+
+        let idle = self
+            .idle
+            .lock()
+            .unwrap()
+            .get_mut(&authority)
+            .and_then(Vec::pop);
+        if let Some(stream) = idle {
+            match self.exchange(stream, &out, head, idempotent).await {
+                Ok((response, stream)) => {
+                    self.release(authority, stream);
+                    return Ok(response);
+                }
+                Err(Failure { retry: true, .. }) => {}
+                Err(failure) => return Err(failure.error),
+            }
+        }
+
+        let stream = (self.connect)(authority.clone()).await?;
+        match self.exchange(stream, &out, head, idempotent).await {
+            Ok((response, stream)) => {
+                self.release(authority, stream);
+                Ok(response)
+            }
+            Err(failure) => Err(failure.error),
+        }
+    }
+
+    /// Writes a request and reads its response, and returns the connection too if it may be
+    /// reused
+    async fn exchange(
+        &self,
+        mut stream: Box<dyn Stream>,
+        out: &[u8],
+        head: bool,
+        idempotent: bool,
+    ) -> Result<(Response, Option<Box<dyn Stream>>), Failure> {
+        let fail = |error: Error, retry: bool| Failure { error, retry };
+        stream
+            .write_all(out)
+            .await
+            .map_err(|e| fail(e.into(), true))?;
+
+        let mut buf = BytesMut::new();
+        let mut chunk = vec![0u8; 16 * 1024];
+        loop {
+            let eof = match stream.read(&mut chunk).await {
+                Ok(0) => true,
+                Ok(n) => {
+                    buf.extend_from_slice(&chunk[..n]);
+                    false
+                }
+                Err(e) => return Err(fail(e.into(), idempotent && buf.is_empty())),
+            };
+            if eof && buf.is_empty() {
+                let closed = io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed");
+                return Err(fail(closed.into(), idempotent));
+            }
+            loop {
+                match response::decode(&mut buf, &self.limits, eof, head) {
+                    // interim responses come before the one to the request, except for a
+                    // switch of protocols
+                    Ok(Some((response, _))) if matches!(response.status(), 100 | 102..=199) => {}
+                    Ok(Some((response, keep_alive))) => {
+                        let reusable = keep_alive && !eof && buf.is_empty();
+                        return Ok((response, if reusable { Some(stream) } else { None }));
+                    }
+                    Ok(None) => break,
+                    Err(e) => return Err(fail(e, false)),
+                }
+            }
+        }
+    }
+
+    fn release(&self, authority: String, stream: Option<Box<dyn Stream>>) {
+        if let Some(stream) = stream {
+            let mut idle = self.idle.lock().unwrap();
+            let streams = idle.entry(authority).or_default();
+            if streams.len() < self.max_idle_per_host {
+                streams.push(stream);
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::io::Write;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use futures::future::{BoxFuture, FutureExt};
+
+use crate::negotiate;
+use crate::request::{has_token, Request};
+use crate::response::Response;
+use crate::service::{Middleware, Next};
+
+/// Compresses response bodies with gzip or deflate, whichever the client prefers by its
+/// `Accept-Encoding`. Only textual bodies of at least `min_size` bytes are compressed, and not
+/// those already encoded, partial, or marked `Cache-Control: no-transform`. Such bodies vary
+/// by `Accept-Encoding` whether compressed or not, which is added to `Vary` for caches.
+pub struct Compress {
+    min_size: usize,
+    level: u32,
+}
+
+impl Compress {
+    pub fn new() -> Compress {
+        Compress {
+            min_size: 1024,
+            level: 6,
+        }
+    }
+
+    /// The smallest body to compress, 1 KiB by default, as compressing less saves little
+    pub fn min_size(&mut self, min_size: usize) -> &mut Compress {
+        self.min_size = min_size;
+        self
+    }
+
+    /// From 0 for none to 9 for the smallest, 6 by default
+    pub fn level(&mut self, level: u32) -> &mut Compress {
+        self.level = level.min(9);
+        self
+    }
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Compress::new()
+    }
+}
+
+impl Middleware for Compress {
+    fn call(&self, req: Request, next: Next) -> BoxFuture<'static, Response> {
+        let coding = negotiate::encoding(&req, &["gzip", "deflate"]);
+        let (min_size, level) = (self.min_size, Compression::new(self.level));
+        async move {
+            let mut response = next.run(req).await;
+            if !compressible(&response) || response.get_body().len() < min_size {
+                return response;
+            }
+            vary(&mut response);
+
+            let coding = match coding {
+                Some(coding) => coding,
+                None => return response,
+            };
+            let body = response.get_body();
+            let compressed = if coding == "gzip" {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                let mut encoder = ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(body).unwrap();
+                encoder.finish().unwrap()
+            };
+            if compressed.len() >= body.len() {
+                return response;
+            }
+            response
+                .header("Content-Encoding", coding)
+                .body_bytes(&compressed);
+            // the bytes differ from the uncompressed ones, though they mean the same
+            if let Some(etag) = response.get_header("ETag").map(str::to_string) {
+                if etag.starts_with('"') {
+                    response
+                        .remove_header("ETag")
+                        .header("ETag", &format!("W/{}", etag));
+                }
+            }
+            response
+        }
+        .boxed()
+    }
+}
+
+fn compressible(response: &Response) -> bool {
+    let header = |name| response.get_header(name).unwrap_or_default();
+    if matches!(response.status(), 100..=199 | 204 | 206 | 304)
+        || response.get_header("Content-Encoding").is_some()
+        || response.is_streamed()
+        || has_token(header("Cache-Control").as_bytes(), "no-transform")
+    {
+        return false;
+    }
+    let content_type = header("Content-Type").to_ascii_lowercase();
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || essence.ends_with("+json")
+        || matches!(
+            essence,
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// Adds `Accept-Encoding` to `Vary`, unless it is there or everything varies
+fn vary(response: &mut Response) {
+    let vary = match response.get_header("Vary") {
+        None => "Accept-Encoding".to_string(),
+        Some(vary) if has_token(vary.as_bytes(), "accept-encoding") || vary.trim() == "*" => return,
+        Some(vary) => format!("{}, Accept-Encoding", vary),
+    };
+    response.remove_header("Vary").header("Vary", &vary);
+}
+
+#[test]
+fn compress_bodies() {
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    let text = "all work and no play makes jack a dull boy\n".repeat(100);
+    let body = text.clone();
+    let service = move |_: Request| {
+        let body = body.clone();
+        async move {
+            let mut response = Response::new();
+            response
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .header("ETag", "\"v1\"")
+                .header("Vary", "Cookie")
+                .body(&body);
+            response
+        }
+    };
+    let next = || {
+        Next::new(
+            std::sync::Arc::new([]),
+            std::sync::Arc::new(service.clone()),
+        )
+    };
+    let call = |accept: &str| {
+        let req = crate::request::parse(&format!("GET / HTTP/1.1\r\n{}\r\n\r\n", accept));
+        futures::executor::block_on(Compress::new().call(req, next()))
+    };
+
+    let gzip = call("Accept-Encoding: gzip, deflate");
+    assert_eq!(gzip.get_header("Content-Encoding"), Some("gzip"));
+    assert_eq!(gzip.get_header("Vary"), Some("Cookie, Accept-Encoding"));
+    assert_eq!(gzip.get_header("ETag"), Some("W/\"v1\""));
+    let mut decoded = String::new();
+    GzDecoder::new(gzip.get_body())
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, text);
+
+    let deflate = call("Accept-Encoding: deflate");
+    let mut decoded = String::new();
+    ZlibDecoder::new(deflate.get_body())
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, text);
+
+    let identity = call("");
+    assert_eq!(identity.get_header("Content-Encoding"), None);
+    assert_eq!(identity.get_header("Vary"), Some("Cookie, Accept-Encoding"));
+    assert_eq!(identity.get_body(), text.as_bytes());
+
+    let mut small = Compress::new();
+    small.min_size(1 << 20);
+    let req = crate::request::parse("GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    let response = futures::executor::block_on(small.call(req, next()));
+    assert_eq!(response.get_header("Content-Encoding"), None);
+    assert_eq!(response.get_header("Vary"), Some("Cookie"));
+}
@@ -1,6 +1,7 @@
 use std::cell::RefCell;
-use std::fmt::{self, Write, Formatter};
+use std::fmt::{self, Formatter, Write};
 use std::str;
+use std::time::{Duration as StdDuration, SystemTime, UNIX_EPOCH};
 
 use time::{self, Duration};
 
@@ -10,6 +11,26 @@ pub fn now() -> Now {
     Now(())
 }
 
+/// Formats a time as an HTTP-date, the same way as `now()`
+pub fn format(t: SystemTime) -> String {
+    let secs = t
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    time::at_utc(time::Timespec::new(secs, 0))
+        .rfc822()
+        .to_string()
+}
+
+/// Parses an HTTP-date in the IMF-fixdate form `format` writes
+pub fn parse(s: &str) -> Option<SystemTime> {
+    let tm = time::strptime(s.trim(), "%a, %d %b %Y %T GMT").ok()?;
+    let secs = tm.to_timespec().sec;
+    if secs < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + StdDuration::from_secs(secs as u64))
+}
+
 struct LastRenderedNow {
     bytes: [u8; 128],
     amt: usize,
@@ -44,7 +65,7 @@ impl LastRenderedNow {
 
     fn update(&mut self, now: time::Timespec) {
         self.amt = 0;
-        write!(LocalBuffer(self), "{}", time::at(now).rfc822()).unwrap();
+        write!(LocalBuffer(self), "{}", time::at_utc(now).rfc822()).unwrap();
         self.next_update = now + Duration::seconds(1);
         self.next_update.nsec = 0;
     }
@@ -60,4 +81,4 @@ impl<'a> fmt::Write for LocalBuffer<'a> {
         self.0.amt += s.len();
         Ok(())
     }
-}
\ No newline at end of file
+}
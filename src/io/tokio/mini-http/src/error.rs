@@ -0,0 +1,90 @@
+use std::{error, fmt, io};
+
+use crate::response::Response;
+
+/// Why a request could not be decoded, or a response obtained from another server. Each kind
+/// maps to the status to answer with, before closing the connection for decoding errors.
+#[derive(Debug)]
+pub enum Error {
+    /// The request line and headers exceed `Limits::max_head`, or there are more headers
+    /// than `Limits::max_headers`: 431
+    HeadersTooLarge,
+    /// The body exceeds `Limits::max_body`: 413
+    PayloadTooLarge,
+    /// The request is malformed: 400
+    BadRequest(String),
+    /// The URL is not an `http://` one: 500
+    InvalidUrl(String),
+    /// The other server answered with something other than an HTTP response: 502
+    BadResponse(String),
+    /// More redirects were followed than the client allows: 502
+    TooManyRedirects,
+    /// The other end of a WebSocket broke the protocol, and the socket was closed with the
+    /// close code: 400
+    WebSocket(u16, String),
+    /// The other server could not be reached: 502
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn status(&self) -> (u32, &'static str) {
+        match self {
+            Error::HeadersTooLarge => (431, "Request Header Fields Too Large"),
+            Error::PayloadTooLarge => (413, "Payload Too Large"),
+            Error::BadRequest(_) | Error::WebSocket(..) => (400, "Bad Request"),
+            Error::InvalidUrl(_) => (500, "Internal Server Error"),
+            Error::BadResponse(_) | Error::TooManyRedirects | Error::Io(_) => (502, "Bad Gateway"),
+        }
+    }
+
+    /// The response to send before closing the connection
+    pub fn response(&self) -> Response {
+        let (code, message) = self.status();
+        let mut response = Response::new();
+        response
+            .status_code(code, message)
+            .header("Content-Type", "text/plain")
+            .keep_alive(false)
+            .body(&self.to_string());
+        response
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::HeadersTooLarge => f.write_str("request header fields too large"),
+            Error::PayloadTooLarge => f.write_str("payload too large"),
+            Error::BadRequest(msg) => write!(f, "bad request: {}", msg),
+            Error::InvalidUrl(url) => write!(f, "invalid url `{}`", url),
+            Error::BadResponse(msg) => write!(f, "bad response: {}", msg),
+            Error::TooManyRedirects => f.write_str("too many redirects"),
+            Error::WebSocket(code, msg) => write!(f, "websocket closed with {}: {}", code, msg),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::future::{self, BoxFuture, FutureExt};
+
+use crate::date;
+use crate::request::Request;
+use crate::response::Response;
+use crate::service::Service;
+use crate::url::{percent_decode, percent_encode};
+
+/// The most ranges served for one request. The whole file is sent for more, so that many
+/// small or overlapping ranges cannot make a response much larger than the file.
+const MAX_RANGES: usize = 16;
+
+/// Serves the files under a directory at a URL prefix, e.g. `/assets/app.js` from
+/// `public/app.js` with `Files::new("/assets", "public")`. It can be used as a service on its
+/// own or routed to with a pattern like `/assets/*path`.
+///
+/// Files are read with blocking calls on the task answering the request, which suits
+/// development tools and small assets.
+pub struct Files {
+    prefix: String,
+    root: PathBuf,
+    listing: bool,
+}
+
+impl Files {
+    pub fn new<P: Into<PathBuf>>(prefix: &str, root: P) -> Files {
+        Files {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            root: root.into(),
+            listing: true,
+        }
+    }
+
+    /// Whether directories without an `index.html` are answered with a list of their entries,
+    /// which they are by default
+    pub fn listing(&mut self, listing: bool) -> &mut Files {
+        self.listing = listing;
+        self
+    }
+
+    fn serve(&self, req: &Request) -> Response {
+        if req.method() != "GET" && req.method() != "HEAD" {
+            let mut response = error(405, "Method Not Allowed");
+            response.header("Allow", "GET, HEAD");
+            return response;
+        }
+        let path = req.path().split('?').next().unwrap_or_default();
+        let rest = match path.strip_prefix(&self.prefix) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+            _ => return error(404, "Not Found"),
+        };
+
+        let mut file = self.root.clone();
+        for segment in rest.split('/') {
+            let segment = percent_decode(segment);
+            match segment.as_str() {
+                "" | "." => {}
+                ".." => return error(403, "Forbidden"),
+                s if s.contains(['/', '\\', '\0']) => return error(403, "Forbidden"),
+                s => file.push(s),
+            }
+        }
+
+        let result = fs::metadata(&file).and_then(|metadata| {
+            if !metadata.is_dir() {
+                return send_file(req, &file, &metadata);
+            }
+            if !path.ends_with('/') {
+                // such that relative links resolve inside the directory
+                let mut location = format!("{}/", path);
+                if let Some(query) = req.query_string() {
+                    location.push('?');
+                    location.push_str(query);
+                }
+                let mut response = error(301, "Moved Permanently");
+                response.header("Location", &location);
+                return Ok(response);
+            }
+            let index = file.join("index.html");
+            match fs::metadata(&index) {
+                Ok(metadata) if metadata.is_file() => send_file(req, &index, &metadata),
+                _ if self.listing => list(path, path.len() > self.prefix.len() + 1, &file),
+                _ => Ok(error(404, "Not Found")),
+            }
+        });
+        result.unwrap_or_else(|e| match e.kind() {
+            io::ErrorKind::NotFound => error(404, "Not Found"),
+            io::ErrorKind::PermissionDenied => error(403, "Forbidden"),
+            _ => error(500, "Internal Server Error"),
+        })
+    }
+}
+
+impl Service for Files {
+    fn call(&self, req: Request) -> BoxFuture<'static, Response> {
+        future::ready(self.serve(&req)).boxed()
+    }
+}
+
+/// Answers with a file, a part of it, or 304 if the client's copy is current
+fn send_file(req: &Request, path: &Path, metadata: &Metadata) -> io::Result<Response> {
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    let since_epoch = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}\"", since_epoch.as_nanos(), len);
+    let last_modified = modified.map(date::format);
+    let content_type = mime_type(path);
+
+    let mut response = Response::new();
+    response
+        .header("ETag", &etag)
+        .header("Accept-Ranges", "bytes");
+    if let Some(last_modified) = &last_modified {
+        response.header("Last-Modified", last_modified);
+    }
+    if not_modified(req, &etag, modified) {
+        response.status_code(304, "Not Modified");
+        return Ok(response);
+    }
+
+    let if_range = match header(req, "If-Range") {
+        Some(tag) if tag.starts_with('"') => tag == etag,
+        Some(date) => Some(date) == last_modified.as_deref(),
+        None => true,
+    };
+    let ranges = match header(req, "Range") {
+        Some(range) if if_range => parse_ranges(range, len),
+        _ => None,
+    };
+
+    match ranges.as_deref() {
+        None => {
+            response
+                .header("Content-Type", content_type)
+                .body_bytes(&fs::read(path)?);
+        }
+        Some([]) => {
+            response
+                .status_code(416, "Range Not Satisfiable")
+                .header("Content-Range", &format!("bytes */{}", len));
+        }
+        Some(&[(start, end)]) => {
+            let mut file = File::open(path)?;
+            response
+                .status_code(206, "Partial Content")
+                .header("Content-Type", content_type)
+                .header("Content-Range", &format!("bytes {}-{}/{}", start, end, len))
+                .body_bytes(&read_range(&mut file, start, end)?);
+        }
+        Some(ranges) => {
+            let mut file = File::open(path)?;
+            let boundary = format!("mini-http-{}", etag.trim_matches('"'));
+            let mut body = Vec::new();
+            for &(start, end) in ranges {
+                write!(
+                    body,
+                    "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, content_type, start, end, len
+                )?;
+                body.extend_from_slice(&read_range(&mut file, start, end)?);
+                body.extend_from_slice(b"\r\n");
+            }
+            write!(body, "--{}--\r\n", boundary)?;
+            response
+                .status_code(206, "Partial Content")
+                .header(
+                    "Content-Type",
+                    &format!("multipart/byteranges; boundary={}", boundary),
+                )
+                .body_bytes(&body);
+        }
+    }
+    Ok(response)
+}
+
+/// Whether `If-None-Match`, or failing that `If-Modified-Since`, says the client's copy is
+/// current. Dates are compared to the second, as that is all they hold.
+fn not_modified(req: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = header(req, "If-None-Match") {
+        return tags
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+    let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+    match (
+        header(req, "If-Modified-Since").and_then(date::parse),
+        modified,
+    ) {
+        (Some(since), Some(modified)) => secs(modified) <= secs(since),
+        _ => false,
+    }
+}
+
+/// Parses a `Range` header into inclusive byte ranges of a file, leaving out those past its
+/// end. `None` means the header is malformed or asks for too many ranges, and is ignored.
+fn parse_ranges(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+    let specs: Vec<&str> = header
+        .trim()
+        .strip_prefix("bytes=")?
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        if start.is_empty() {
+            // the last bytes
+            let suffix: u64 = end.parse().ok()?;
+            if suffix > 0 && len > 0 {
+                ranges.push((len.saturating_sub(suffix), len - 1));
+            }
+            continue;
+        }
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => u64::MAX,
+            end => end.parse().ok().filter(|end| *end >= start)?,
+        };
+        if start < len {
+            ranges.push((start, end.min(len - 1)));
+        }
+    }
+    Some(ranges)
+}
+
+fn read_range(file: &mut File, start: u64, end: u64) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; (end - start + 1) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Lists a directory as HTML, subdirectories first
+fn list(path: &str, has_parent: bool, dir: &Path) -> io::Result<Response> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let is_dir = entry.file_type()?.is_dir();
+        entries.push((!is_dir, entry.file_name().to_string_lossy().into_owned()));
+    }
+    entries.sort();
+
+    let title = escape_html(&percent_decode(path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n\
+         <body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if has_parent {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_file, name) in entries {
+        let slash = if is_file { "" } else { "/" };
+        html.push_str(&format!(
+            "<li><a href=\"{}{}\">{}{}</a></li>\n",
+            percent_encode(&name),
+            slash,
+            escape_html(&name),
+            slash
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    let mut response = Response::new();
+    response
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(&html);
+    Ok(response)
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Guesses the media type of a file from its extension
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        _ => "application/octet-stream",
+    }
+}
+
+fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.header(name)
+        .and_then(|value| str::from_utf8(value).ok())
+}
+
+fn error(code: u32, message: &str) -> Response {
+    let mut response = Response::new();
+    response
+        .status_code(code, message)
+        .header("Content-Type", "text/plain")
+        .body(&message.to_ascii_lowercase());
+    response
+}
+
+/// Serves a fresh directory holding `a.txt` and `sub/b.css`, and returns the response to a
+/// request as text
+#[cfg(test)]
+fn fetch(head: &str) -> String {
+    static SETUP: std::sync::Once = std::sync::Once::new();
+    let root = std::env::temp_dir().join(format!("mini-http-files-{}", std::process::id()));
+    SETUP.call_once(|| {
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "0123456789").unwrap();
+        fs::write(root.join("sub/b.css"), "b {}").unwrap();
+    });
+
+    let mut buf = bytes::BytesMut::new();
+    Files::new("/static", &root)
+        .serve(&crate::request::parse(head))
+        .encode(&mut buf);
+    String::from_utf8(buf.to_vec()).unwrap()
+}
+
+#[test]
+fn serve_files() {
+    let response = fetch("GET /static/a.txt HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+    assert!(response.ends_with("\r\n\r\n0123456789"));
+
+    assert!(fetch("GET /static/sub/b.css HTTP/1.1\r\n\r\n").contains("text/css"));
+    assert!(fetch("GET /static/../a.txt HTTP/1.1\r\n\r\n").contains(" 403 "));
+    assert!(fetch("GET /static/sub/%2e%2e/%2E%2E/a.txt HTTP/1.1\r\n\r\n").contains(" 403 "));
+    assert!(fetch("GET /static/missing HTTP/1.1\r\n\r\n").contains(" 404 "));
+    assert!(fetch("GET /other/a.txt HTTP/1.1\r\n\r\n").contains(" 404 "));
+
+    let response = fetch("GET /static/sub HTTP/1.1\r\n\r\n");
+    assert!(response.contains(" 301 ") && response.contains("Location: /static/sub/\r\n"));
+    let response = fetch("GET /static/ HTTP/1.1\r\n\r\n");
+    assert!(response.contains("<a href=\"sub/\">sub/</a>"));
+    assert!(response.contains("<a href=\"a.txt\">a.txt</a>"));
+    assert!(!response.contains("../"));
+}
+
+#[test]
+fn serve_ranges() {
+    let response = fetch("GET /static/a.txt HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+    assert!(response.contains("Content-Range: bytes 2-4/10\r\n"));
+    assert!(response.ends_with("\r\n\r\n234"));
+
+    let response = fetch("GET /static/a.txt HTTP/1.1\r\nRange: bytes=-3, 0-0\r\n\r\n");
+    assert!(response.contains("multipart/byteranges; boundary=mini-http-"));
+    assert!(response.contains("Content-Range: bytes 7-9/10\r\n\r\n789\r\n--"));
+    assert!(response.contains("Content-Range: bytes 0-0/10\r\n\r\n0\r\n--"));
+
+    let response = fetch("GET /static/a.txt HTTP/1.1\r\nRange: bytes=10-\r\n\r\n");
+    assert!(response.contains(" 416 ") && response.contains("Content-Range: bytes */10\r\n"));
+    let response = fetch("GET /static/a.txt HTTP/1.1\r\nRange: lines=1-2\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    let response =
+        fetch("GET /static/a.txt HTTP/1.1\r\nRange: bytes=0-0\r\nIf-Range: \"x\"\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+fn conditional_get() {
+    let response = fetch("GET /static/a.txt HTTP/1.1\r\n\r\n");
+    let etag = response
+        .lines()
+        .find_map(|line| line.strip_prefix("ETag: "))
+        .unwrap();
+    let last_modified = response
+        .lines()
+        .find_map(|line| line.strip_prefix("Last-Modified: "))
+        .unwrap();
+    assert_eq!(
+        date::format(date::parse(last_modified).unwrap()),
+        last_modified
+    );
+
+    let response = fetch(&format!(
+        "GET /static/a.txt HTTP/1.1\r\nIf-None-Match: \"x\", {}\r\n\r\n",
+        etag
+    ));
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    assert!(!response.contains("Content-Length") && response.ends_with("\r\n\r\n"));
+
+    let response = fetch(&format!(
+        "GET /static/a.txt HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n",
+        last_modified
+    ));
+    assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    let response = fetch(
+        "GET /static/a.txt HTTP/1.1\r\nIf-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
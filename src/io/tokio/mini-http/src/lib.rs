@@ -1,6 +1,24 @@
+pub mod client;
+pub mod compress;
 mod date;
+pub mod error;
+pub mod files;
+pub mod negotiate;
 pub mod request;
 pub mod response;
+pub mod router;
+pub mod server;
+pub mod service;
+mod url;
+pub mod websocket;
 
-pub use request::Request;
-pub use response::Response;
\ No newline at end of file
+pub use client::Client;
+pub use compress::Compress;
+pub use error::Error;
+pub use files::Files;
+pub use request::{Decoder, Limits, Request};
+pub use response::Response;
+pub use router::Router;
+pub use server::{serve, serve_blocking, Upgraded};
+pub use service::{Logger, Middleware, Next, Service, Timeout};
+pub use websocket::{Message, WebSocket};
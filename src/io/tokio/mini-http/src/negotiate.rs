@@ -0,0 +1,124 @@
+use crate::request::Request;
+
+/// Picks the media type the client prefers by its `Accept` header among those offered, or the
+/// first one offered when there is no header. Each offer takes the quality of the most
+/// specific range matching it, as `text/html` before `text/*` before `*/*`, and ties go to the
+/// earlier offer. `None` means none is acceptable, which may be answered with 406.
+pub fn media_type<'a>(req: &Request, offered: &[&'a str]) -> Option<&'a str> {
+    let accept = match header(req, "Accept") {
+        Some(accept) => qualities(accept),
+        None => return offered.first().copied(),
+    };
+    best(offered, |offer| {
+        let (kind, _) = offer.split_once('/').unwrap_or((offer, ""));
+        accept
+            .iter()
+            .filter_map(|(range, q)| {
+                let specificity = match range.split_once('/')? {
+                    ("*", "*") => 0,
+                    (range_kind, "*") if range_kind.eq_ignore_ascii_case(kind) => 1,
+                    _ if range.eq_ignore_ascii_case(offer) => 2,
+                    _ => return None,
+                };
+                Some((specificity, *q))
+            })
+            .max_by_key(|&(specificity, _)| specificity)
+            .map(|(_, q)| q)
+    })
+}
+
+/// Picks the content coding the client prefers by its `Accept-Encoding` header among those
+/// offered. A coding not listed takes the quality of `*` if there is one. Without the header,
+/// or when none is acceptable, the body is best sent as it is.
+pub fn encoding<'a>(req: &Request, offered: &[&'a str]) -> Option<&'a str> {
+    let accept = qualities(header(req, "Accept-Encoding")?);
+    best(offered, |offer| {
+        let quality = |coding: &str| {
+            accept
+                .iter()
+                .find(|(c, _)| c.eq_ignore_ascii_case(coding))
+                .map(|(_, q)| *q)
+        };
+        quality(offer).or_else(|| quality("*"))
+    })
+}
+
+/// The offer of the highest quality above 0, the earliest among equals
+fn best<'a, F>(offered: &[&'a str], quality: F) -> Option<&'a str>
+where
+    F: Fn(&str) -> Option<u16>,
+{
+    let mut best = None;
+    for offer in offered {
+        match quality(offer) {
+            Some(q) if q > 0 && best.is_none_or(|(_, best)| q > best) => best = Some((*offer, q)),
+            _ => {}
+        }
+    }
+    best.map(|(offer, _)| offer)
+}
+
+/// Parses a list such as `gzip;q=0.8, br` into its items and their qualities, in thousandths
+/// as `q` has at most three decimals. Other parameters are ignored.
+fn qualities(list: &str) -> Vec<(&str, u16)> {
+    list.split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().filter(|name| !name.is_empty())?;
+            let q = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+                .map_or(Some(1000), |(_, value)| parse_quality(value.trim()))?;
+            Some((name, q))
+        })
+        .collect()
+}
+
+fn parse_quality(value: &str) -> Option<u16> {
+    let q: f32 = value.parse().ok()?;
+    if (0.0..=1.0).contains(&q) {
+        Some((q * 1000.0).round() as u16)
+    } else {
+        None
+    }
+}
+
+fn header<'r>(req: &'r Request, name: &str) -> Option<&'r str> {
+    req.header(name)
+        .and_then(|value| std::str::from_utf8(value).ok())
+}
+
+#[test]
+fn negotiate_media_types() {
+    let req =
+        |accept: &str| crate::request::parse(&format!("GET / HTTP/1.1\r\n{}\r\n\r\n", accept));
+    let offered = ["application/json", "text/html", "text/plain"];
+
+    assert_eq!(media_type(&req(""), &offered), Some("application/json"));
+    let browser = req("Accept: text/html,application/xhtml+xml,*/*;q=0.8");
+    assert_eq!(media_type(&browser, &offered), Some("text/html"));
+    let plain = req("Accept: text/*;q=0.5, text/plain, application/json;q=0.2");
+    assert_eq!(media_type(&plain, &offered), Some("text/plain"));
+    // the most specific range decides, though a broader one allows it
+    let not_html = req("Accept: text/*, text/html;q=0");
+    assert_eq!(media_type(&not_html, &offered), Some("text/plain"));
+    assert_eq!(media_type(&req("Accept: image/png"), &offered), None);
+}
+
+#[test]
+fn negotiate_encodings() {
+    let req =
+        |accept: &str| crate::request::parse(&format!("GET / HTTP/1.1\r\n{}\r\n\r\n", accept));
+    let offered = ["gzip", "deflate"];
+
+    assert_eq!(encoding(&req(""), &offered), None);
+    assert_eq!(
+        encoding(&req("Accept-Encoding: gzip, deflate, br"), &offered),
+        Some("gzip")
+    );
+    let deflate = req("Accept-Encoding: gzip;q=0.5, deflate;q=0.9");
+    assert_eq!(encoding(&deflate, &offered), Some("deflate"));
+    let any = req("Accept-Encoding: *;q=0.1, gzip;q=0");
+    assert_eq!(encoding(&any, &offered), Some("deflate"));
+    assert_eq!(encoding(&req("Accept-Encoding: identity"), &offered), None);
+}
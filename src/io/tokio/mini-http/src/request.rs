@@ -1,7 +1,10 @@
 use bytes::BytesMut;
 use httparse;
 use std::fmt::Formatter;
-use std::{fmt, io, slice, str};
+use std::{fmt, slice, str};
+
+use crate::error::Error;
+use crate::url;
 
 pub struct Request {
     method: Slice,
@@ -9,6 +12,9 @@ pub struct Request {
     version: u8,
     headers: Vec<(Slice, Slice)>,
     data: BytesMut,
+    body: BytesMut,
+    /// Set by the router from the matching route's pattern
+    params: Vec<(String, String)>,
 }
 
 type Slice = (usize, usize);
@@ -18,73 +24,416 @@ pub struct RequestHeaders<'req> {
     req: &'req Request,
 }
 
+/// How large a request may be before it is refused
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// The request line and headers, in bytes
+    pub max_head: usize,
+    pub max_headers: usize,
+    /// The decoded body, in bytes
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_head: 8 * 1024,
+            max_headers: 64,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+/// Decodes the requests of a connection, keeping track of `Expect: 100-continue`
+pub struct Decoder {
+    limits: Limits,
+    /// Whether the interim response was asked for the request being read
+    continue_sent: bool,
+    continue_pending: bool,
+}
+
+/// How the length of a request body is known
+enum Framing {
+    Empty,
+    Length(usize),
+    Chunked,
+}
+
+struct Head {
+    method: Slice,
+    path: Slice,
+    version: u8,
+    headers: Vec<(Slice, Slice)>,
+    len: usize,
+}
+
 impl Request {
     pub fn method(&self) -> &str {
         str::from_utf8(self.slice(&self.method)).unwrap()
     }
 
+    /// The request target, including any query string
     pub fn path(&self) -> &str {
         str::from_utf8(self.slice(&self.path)).unwrap()
     }
 
+    /// The part of the target after `?`, as sent
+    pub fn query_string(&self) -> Option<&str> {
+        self.path().split_once('?').map(|(_, query)| query)
+    }
+
+    /// The decoded pairs of the query string, in order. Keys without `=` have an empty value.
+    pub fn query(&self) -> Vec<(String, String)> {
+        self.query_string()
+            .map(url::parse_query)
+            .unwrap_or_default()
+    }
+
+    /// Returns the decoded value of the first query parameter with the key
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        self.query()
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns a parameter captured by the route, e.g. `id` for `/users/:id`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn set_params(&mut self, params: Vec<(String, String)>) {
+        self.params = params;
+    }
+
     pub fn version(&self) -> u8 {
         self.version
     }
 
-    pub fn headers(&self) -> RequestHeaders {
+    pub fn headers(&self) -> RequestHeaders<'_> {
         RequestHeaders {
             headers: self.headers.iter(),
             req: self,
         }
     }
 
+    /// Returns the value of the first header with the name, ignoring case
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// The body, with any chunked transfer-encoding removed
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Whether the connection may be reused after the response. HTTP/1.1 connections persist
+    /// unless the client sends `Connection: close`, HTTP/1.0 ones only with `keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").unwrap_or_default();
+        if has_token(connection, "close") {
+            false
+        } else {
+            self.version == 1 || has_token(connection, "keep-alive")
+        }
+    }
+
+    /// Starts an HTTP/1.1 request to send, with no headers and an empty body
+    pub fn new(method: &str, path: &str) -> Request {
+        let mut data = BytesMut::with_capacity(method.len() + path.len());
+        data.extend_from_slice(method.as_bytes());
+        data.extend_from_slice(path.as_bytes());
+        Request {
+            method: (0, method.len()),
+            path: (method.len(), data.len()),
+            version: 1,
+            headers: Vec::new(),
+            data,
+            body: BytesMut::new(),
+            params: Vec::new(),
+        }
+    }
+
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Request {
+        let start = self.data.len();
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.extend_from_slice(value.as_bytes());
+        let name_end = start + name.len();
+        self.headers
+            .push(((start, name_end), (name_end, self.data.len())));
+        self
+    }
+
+    pub fn set_body(&mut self, body: &[u8]) -> &mut Request {
+        self.body = BytesMut::from(body);
+        self
+    }
+
+    /// Writes the request as sent by a client. The body is framed by a `Content-Length` in
+    /// place of any framing headers, as it is held decoded.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.extend_from_slice(self.method().as_bytes());
+        buf.extend_from_slice(b" ");
+        buf.extend_from_slice(self.path().as_bytes());
+        buf.extend_from_slice(if self.version == 0 {
+            b" HTTP/1.0\r\n"
+        } else {
+            b" HTTP/1.1\r\n"
+        });
+        for (name, value) in self.headers() {
+            if name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                continue;
+            }
+            buf.extend_from_slice(name.as_bytes());
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(value);
+            buf.extend_from_slice(b"\r\n");
+        }
+        let expects_body = matches!(self.method(), "POST" | "PUT" | "PATCH");
+        if !self.body.is_empty() || expects_body {
+            buf.extend_from_slice(format!("Content-Length: {}\r\n", self.body.len()).as_bytes());
+        }
+        buf.extend_from_slice(b"\r\n");
+        buf.extend_from_slice(&self.body);
+    }
+
     fn slice(&self, slice: &Slice) -> &[u8] {
         &self.data[slice.0..slice.1]
     }
 
-    pub fn decode(buf: &mut BytesMut) -> io::Result<Option<Request>> {
-        let (method, path, version, headers, amt) = {
-            let mut headers = [httparse::EMPTY_HEADER; 16];
-            let mut r = httparse::Request::new(&mut headers);
-            let status = r.parse(buf).map_err(|e| {
-                let msg = format!("failed to parse http request: {:?}", e);
-                return io::Error::new(io::ErrorKind::Other, msg);
-            })?;
+    /// Decodes a request with the default limits, once its body has arrived
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Request>, Error> {
+        Decoder::new().decode(buf)
+    }
+}
 
-            let amt = match status {
-                httparse::Status::Complete(amt) => amt,
-                httparse::Status::Partial => return Ok(None),
-            };
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::with_limits(Limits::default())
+    }
 
-            let to_slice = |a: &[u8]| {
-                let start = a.as_ptr() as usize - buf.as_ptr() as usize;
-                assert!(start < buf.len());
-                (start, start + a.len())
-            };
+    pub fn with_limits(limits: Limits) -> Decoder {
+        Decoder {
+            limits,
+            continue_sent: false,
+            continue_pending: false,
+        }
+    }
 
-            (
-                to_slice(r.method.unwrap().as_bytes()),
-                to_slice(r.path.unwrap().as_bytes()),
-                r.version.unwrap(),
-                r.headers
-                    .iter()
-                    .map(|h| (to_slice(h.name.as_bytes()), to_slice(h.value)))
-                    .collect(),
-                amt,
-            )
+    /// Removes the next request from the buffer once its head and body have arrived. Bodies
+    /// are delimited by `Content-Length` or chunked transfer-encoding, and requests with
+    /// neither have none.
+    pub fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Request>, Error> {
+        let head = match parse_head(buf, &self.limits)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let rest = &buf[head.len..];
+        let (body, consumed) = match framing(&head, buf)? {
+            Framing::Empty => (Some(BytesMut::new()), 0),
+            Framing::Length(len) if len > self.limits.max_body => {
+                return Err(Error::PayloadTooLarge)
+            }
+            Framing::Length(len) if rest.len() < len => return Ok(self.incomplete(&head, buf)),
+            // the body is split off the buffer as it is
+            Framing::Length(len) => (None, len),
+            Framing::Chunked => match chunked_body(rest, &self.limits)? {
+                Some((body, consumed)) => (Some(body), consumed),
+                None => return Ok(self.incomplete(&head, buf)),
+            },
         };
 
-        Ok(Request {
-            method,
-            path,
-            version,
-            headers,
-            data: buf.split_to(amt),
+        self.continue_sent = false;
+        let data = buf.split_to(head.len);
+        let consumed = buf.split_to(consumed);
+        Ok(Some(Request {
+            method: head.method,
+            path: head.path,
+            version: head.version,
+            headers: head.headers,
+            data,
+            body: body.unwrap_or(consumed),
+            params: Vec::new(),
+        }))
+    }
+
+    /// Returns true once per request if the client waits for `100 Continue` before sending
+    /// its body. The caller then writes `response::CONTINUE` and goes on reading.
+    pub fn wants_continue(&mut self) -> bool {
+        let pending = self.continue_pending;
+        self.continue_pending = false;
+        pending
+    }
+
+    fn incomplete(&mut self, head: &Head, buf: &[u8]) -> Option<Request> {
+        let expects_continue = head.headers.iter().any(|(name, value)| {
+            buf[name.0..name.1].eq_ignore_ascii_case(b"Expect")
+                && buf[value.0..value.1].eq_ignore_ascii_case(b"100-continue")
+        });
+        if expects_continue && head.version == 1 && !self.continue_sent {
+            self.continue_sent = true;
+            self.continue_pending = true;
+        }
+        None
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+fn parse_head(buf: &[u8], limits: &Limits) -> Result<Option<Head>, Error> {
+    let mut headers = vec![httparse::EMPTY_HEADER; limits.max_headers];
+    let mut r = httparse::Request::new(&mut headers);
+    let status = r.parse(buf).map_err(|e| match e {
+        httparse::Error::TooManyHeaders => Error::HeadersTooLarge,
+        e => Error::BadRequest(e.to_string()),
+    })?;
+
+    let len = match status {
+        httparse::Status::Complete(len) if len > limits.max_head => {
+            return Err(Error::HeadersTooLarge)
+        }
+        httparse::Status::Complete(len) => len,
+        httparse::Status::Partial if buf.len() > limits.max_head => {
+            return Err(Error::HeadersTooLarge)
+        }
+        httparse::Status::Partial => return Ok(None),
+    };
+
+    let to_slice = |a: &[u8]| {
+        let start = a.as_ptr() as usize - buf.as_ptr() as usize;
+        assert!(start <= buf.len());
+        (start, start + a.len())
+    };
+
+    Ok(Some(Head {
+        method: to_slice(r.method.unwrap().as_bytes()),
+        path: to_slice(r.path.unwrap().as_bytes()),
+        version: r.version.unwrap(),
+        headers: r
+            .headers
+            .iter()
+            .map(|h| (to_slice(h.name.as_bytes()), to_slice(h.value)))
+            .collect(),
+        len,
+    }))
+}
+
+fn framing(head: &Head, buf: &[u8]) -> Result<Framing, Error> {
+    let mut length = None;
+    let mut chunked = None;
+    for (name, value) in &head.headers {
+        let (name, value) = (&buf[name.0..name.1], &buf[value.0..value.1]);
+        if name.eq_ignore_ascii_case(b"Content-Length") {
+            let len = str::from_utf8(value)
+                .ok()
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .ok_or_else(|| Error::BadRequest("invalid Content-Length".to_string()))?;
+            if length.is_some_and(|l| l != len) {
+                return Err(Error::BadRequest("conflicting Content-Length".to_string()));
+            }
+            length = Some(len);
+        } else if name.eq_ignore_ascii_case(b"Transfer-Encoding") {
+            // only the last coding tells whether the body is chunked
+            let last = value.rsplit(|&b| b == b',').next().unwrap_or_default();
+            chunked = Some(trim(last).eq_ignore_ascii_case(b"chunked"));
         }
-        .into())
+    }
+    match (chunked, length) {
+        (Some(true), None) => Ok(Framing::Chunked),
+        (Some(true), Some(_)) => Err(Error::BadRequest(
+            "both Content-Length and Transfer-Encoding".to_string(),
+        )),
+        (Some(false), _) => Err(Error::BadRequest(
+            "Transfer-Encoding does not end with chunked".to_string(),
+        )),
+        (None, Some(0)) | (None, None) => Ok(Framing::Empty),
+        (None, Some(len)) => Ok(Framing::Length(len)),
     }
 }
 
+/// Decodes a complete chunked body at the start of `buf`, and returns it along with how many
+/// bytes it takes up with its trailers
+pub(crate) fn chunked_body(
+    buf: &[u8],
+    limits: &Limits,
+) -> Result<Option<(BytesMut, usize)>, Error> {
+    let mut chunks = Vec::new();
+    let mut total = 0;
+    let mut pos = 0;
+    loop {
+        let (len, size) = match httparse::parse_chunk_size(&buf[pos..]) {
+            Ok(httparse::Status::Complete(chunk)) => chunk,
+            Ok(httparse::Status::Partial) => return Ok(None),
+            Err(_) => return Err(Error::BadRequest("invalid chunk size".to_string())),
+        };
+        pos += len;
+        if size > (limits.max_body - total) as u64 {
+            return Err(Error::PayloadTooLarge);
+        }
+        let size = size as usize;
+
+        if size == 0 {
+            // trailers are read and dropped
+            let mut trailers = vec![httparse::EMPTY_HEADER; limits.max_headers];
+            return match httparse::parse_headers(&buf[pos..], &mut trailers) {
+                Ok(httparse::Status::Complete((len, _))) => {
+                    let mut body = BytesMut::with_capacity(total);
+                    for chunk in chunks {
+                        body.extend_from_slice(&buf[chunk]);
+                    }
+                    Ok(Some((body, pos + len)))
+                }
+                Ok(httparse::Status::Partial) if buf.len() - pos > limits.max_head => {
+                    Err(Error::HeadersTooLarge)
+                }
+                Ok(httparse::Status::Partial) => Ok(None),
+                Err(httparse::Error::TooManyHeaders) => Err(Error::HeadersTooLarge),
+                Err(e) => Err(Error::BadRequest(e.to_string())),
+            };
+        }
+
+        if buf.len() < pos + size + 2 {
+            return Ok(None);
+        }
+        if &buf[pos + size..pos + size + 2] != b"\r\n" {
+            return Err(Error::BadRequest(
+                "chunk is longer than its size".to_string(),
+            ));
+        }
+        chunks.push(pos..pos + size);
+        total += size;
+        pos += size + 2;
+    }
+}
+
+/// Whether a comma-separated header value contains a token, ignoring case
+pub(crate) fn has_token(value: &[u8], token: &str) -> bool {
+    value
+        .split(|&b| b == b',')
+        .any(|t| trim(t).eq_ignore_ascii_case(token.as_bytes()))
+}
+
+pub(crate) fn trim(mut s: &[u8]) -> &[u8] {
+    while let [b' ', rest @ ..] | [b'\t', rest @ ..] = s {
+        s = rest;
+    }
+    while let [rest @ .., b' '] | [rest @ .., b'\t'] = s {
+        s = rest;
+    }
+    s
+}
+
 impl fmt::Debug for Request {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "<HTTP Request {} {}>", self.method(), self.path())
@@ -95,10 +444,103 @@ impl<'req> Iterator for RequestHeaders<'req> {
     type Item = (&'req str, &'req [u8]);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.headers.next().map(|&(ref a, ref b)| {
+        self.headers.next().map(|(a, b)| {
             let a = self.req.slice(a);
             let b = self.req.slice(b);
             (str::from_utf8(a).unwrap(), b)
         })
     }
 }
+
+/// Decodes a whole request
+#[cfg(test)]
+pub(crate) fn parse(text: &str) -> Request {
+    let mut buf = BytesMut::from(text.as_bytes());
+    Request::decode(&mut buf).unwrap().unwrap()
+}
+
+#[test]
+fn decode_bodies() {
+    let mut buf = BytesMut::from(
+        &b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /b HTTP/1.0\r\n\r\n"[..],
+    );
+    let req = Request::decode(&mut buf).unwrap().unwrap();
+    assert_eq!((req.path(), req.body()), ("/a", &b"hello"[..]));
+    assert!(req.keep_alive());
+    let req = Request::decode(&mut buf).unwrap().unwrap();
+    assert_eq!((req.path(), req.body()), ("/b", &b""[..]));
+    assert!(!req.keep_alive());
+    assert!(buf.is_empty());
+
+    let chunked = &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                     5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: x\r\n\r\n"[..];
+    for end in 0..chunked.len() {
+        let mut buf = BytesMut::from(&chunked[..end]);
+        assert!(Request::decode(&mut buf).unwrap().is_none());
+    }
+    let mut buf = BytesMut::from(chunked);
+    let req = Request::decode(&mut buf).unwrap().unwrap();
+    assert_eq!(req.body(), &b"hello world"[..]);
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn encode_requests() {
+    let mut req = Request::new("POST", "/submit?x=1");
+    req.add_header("Host", "example.com")
+        .add_header("Transfer-Encoding", "chunked")
+        .set_body(b"data");
+    let mut buf = BytesMut::new();
+    req.encode(&mut buf);
+    assert_eq!(
+        &buf[..],
+        &b"POST /submit?x=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 4\r\n\r\ndata"[..]
+    );
+    let decoded = Request::decode(&mut buf).unwrap().unwrap();
+    assert_eq!(decoded.header("host"), Some(&b"example.com"[..]));
+    assert_eq!(decoded.body(), &b"data"[..]);
+}
+
+#[test]
+fn expect_continue() {
+    let mut decoder = Decoder::new();
+    let mut buf =
+        BytesMut::from(&b"PUT / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 3\r\n\r\n"[..]);
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+    assert!(decoder.wants_continue());
+    assert!(decoder.decode(&mut buf).unwrap().is_none());
+    assert!(!decoder.wants_continue());
+    buf.extend_from_slice(b"abc");
+    assert_eq!(
+        decoder.decode(&mut buf).unwrap().unwrap().body(),
+        &b"abc"[..]
+    );
+}
+
+#[test]
+fn enforce_limits() {
+    let limits = Limits {
+        max_head: 64,
+        max_headers: 2,
+        max_body: 4,
+    };
+    let status = |input: &[u8]| {
+        let mut buf = BytesMut::from(input);
+        match Decoder::with_limits(limits).decode(&mut buf) {
+            Err(e) => e.status().0,
+            Ok(_) => 200,
+        }
+    };
+    assert_eq!(status(&[b'a'; 65][..]), 431);
+    assert_eq!(
+        status(b"GET / HTTP/1.1\r\na: 1\r\nb: 2\r\nc: 3\r\n\r\n"),
+        431
+    );
+    assert_eq!(status(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n"), 413);
+    assert_eq!(
+        status(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\n"),
+        413
+    );
+    assert_eq!(status(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), 400);
+    assert_eq!(status(b"GET / HTTP/1.1\r\n\r\n"), 200);
+}
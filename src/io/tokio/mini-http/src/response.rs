@@ -1,12 +1,43 @@
-use std::fmt::{self, Write};
 use bytes::BytesMut;
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use httparse;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::error::Error;
+use crate::request::{chunked_body, has_token, Limits};
+use crate::server::Upgraded;
+
+/// The interim response to a request sent with `Expect: 100-continue`
+pub const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// What takes over the connection after a `101 Switching Protocols`, shared such that
+/// responses stay cheap to clone
+type OnUpgrade = Arc<Mutex<Option<Box<dyn FnOnce(Upgraded) -> BoxFuture<'static, ()> + Send>>>>;
+
+/// The rest of a streamed body, shared like `OnUpgrade`
+type Chunks = Arc<Mutex<Option<BoxStream<'static, Vec<u8>>>>>;
 
+#[derive(Clone)]
 pub struct Response {
     headers: Vec<(String, String)>,
     response: Vec<u8>,
     status_message: StatusMessage,
+    keep_alive: Option<bool>,
+    chunked: bool,
+    without_body: bool,
+    upgrade: Option<OnUpgrade>,
+    chunks: Option<Chunks>,
 }
 
+impl Default for Response {
+    fn default() -> Self {
+        Response::new()
+    }
+}
+
+#[derive(Clone)]
 enum StatusMessage {
     Ok,
     Custom(u32, String),
@@ -18,6 +49,11 @@ impl Response {
             headers: Vec::new(),
             response: Vec::new(),
             status_message: StatusMessage::Ok,
+            keep_alive: None,
+            chunked: false,
+            without_body: false,
+            upgrade: None,
+            chunks: None,
         }
     }
 
@@ -26,11 +62,47 @@ impl Response {
         self
     }
 
+    pub fn status(&self) -> u32 {
+        match self.status_message {
+            StatusMessage::Ok => 200,
+            StatusMessage::Custom(code, _) => code,
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        match self.status_message {
+            StatusMessage::Ok => "OK",
+            StatusMessage::Custom(_, ref reason) => reason,
+        }
+    }
+
+    /// Returns the value of the first header with the name, ignoring case
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    pub fn get_body(&self) -> &[u8] {
+        &self.response
+    }
+
     pub fn header(&mut self, name: &str, val: &str) -> &mut Response {
         self.headers.push((name.to_string(), val.to_string()));
         self
     }
 
+    /// Removes every header with the name, ignoring case
+    pub fn remove_header(&mut self, name: &str) -> &mut Response {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self
+    }
+
     pub fn body(&mut self, s: &str) -> &mut Response {
         self.response = s.as_bytes().to_vec();
         self
@@ -41,40 +113,273 @@ impl Response {
         self
     }
 
+    /// Sends `Connection: keep-alive` or `Connection: close`. The connection should be closed
+    /// after a response that does not keep it alive.
+    pub fn keep_alive(&mut self, keep_alive: bool) -> &mut Response {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Whether the connection may be reused after this response
+    pub fn keeps_alive(&self) -> bool {
+        self.keep_alive != Some(false)
+    }
+
+    /// Sends the body with chunked transfer-encoding. `encode` then writes the head and the
+    /// body so far as the first chunk, and the rest follows with `encode_chunk` and
+    /// `encode_last_chunk`.
+    pub fn chunked(&mut self) -> &mut Response {
+        self.chunked = true;
+        self
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.chunked
+    }
+
+    /// Streams the body in chunks, after the body so far: `serve` writes each chunk as soon
+    /// as the stream yields it, and ends the body when the stream does
+    pub fn body_stream<S>(&mut self, chunks: S) -> &mut Response
+    where
+        S: Stream<Item = Vec<u8>> + Send + 'static,
+    {
+        self.chunked = true;
+        self.chunks = Some(Arc::new(Mutex::new(Some(chunks.boxed()))));
+        self
+    }
+
+    /// Whether the body is given by `body_stream`, rather than whole
+    pub fn is_streamed(&self) -> bool {
+        self.chunks.is_some()
+    }
+
+    /// Takes the stream given to `body_stream`, which a clone of the response shares
+    pub(crate) fn take_chunks(&mut self) -> Option<BoxStream<'static, Vec<u8>>> {
+        self.chunks.take()?.lock().unwrap().take()
+    }
+
+    /// Answers a HEAD request: `encode` writes the headers the body would have, or the
+    /// `Content-Length` header given, but not the body
+    pub fn without_body(&mut self) -> &mut Response {
+        self.without_body = true;
+        self
+    }
+
+    /// Hands the connection over once this response, a `101 Switching Protocols`, has been
+    /// written by `serve`, along with anything the client sent after the request
+    pub fn on_upgrade<F>(&mut self, upgrade: F) -> &mut Response
+    where
+        F: FnOnce(Upgraded) -> BoxFuture<'static, ()> + Send + 'static,
+    {
+        self.upgrade = Some(Arc::new(Mutex::new(Some(Box::new(upgrade)))));
+        self
+    }
+
+    /// Takes what was given to `on_upgrade`, if this is a `101 Switching Protocols`
+    pub(crate) fn take_upgrade(
+        &mut self,
+    ) -> Option<Box<dyn FnOnce(Upgraded) -> BoxFuture<'static, ()> + Send>> {
+        match self.status() {
+            101 => self.upgrade.take()?.lock().unwrap().take(),
+            _ => None,
+        }
+    }
+
     pub fn encode(&self, buf: &mut BytesMut) {
-        let length = self.response.len();
         let now = crate::date::now();
 
-        write!(
-            FastWrite(buf),
-            "\
-             HTTP/1.1 {}\r\n\
-             Server: Example\r\n\
-             Content-Length: {}\r\n\
-             Date: {}\r\n\
-             ",
-            self.status_message,
-            length,
-            now
-        ).unwrap();
-
-        for &(ref k, ref v) in &self.headers {
+        write!(FastWrite(buf), "HTTP/1.1 {}\r\n", self.status_message).unwrap();
+        // unless set, as when passing on a decoded response
+        if self.get_header("Server").is_none() {
+            push(buf, b"Server: Example\r\n");
+        }
+        if self.get_header("Date").is_none() {
+            write!(FastWrite(buf), "Date: {}\r\n", now).unwrap();
+        }
+
+        // informational, 204 and 304 responses have no body to frame
+        let bodyless = matches!(self.status(), 100..=199 | 204 | 304);
+        // a response to HEAD may give the length its body would have, as a decoded one does
+        let length_given = self.without_body && self.get_header("Content-Length").is_some();
+        if !bodyless {
+            if self.chunked {
+                push(buf, b"Transfer-Encoding: chunked\r\n");
+            } else if !length_given {
+                write!(
+                    FastWrite(buf),
+                    "Content-Length: {}\r\n",
+                    self.response.len()
+                )
+                .unwrap();
+            }
+        }
+        // a switch of protocols says `Connection: Upgrade` instead
+        match self.keep_alive {
+            _ if self.status() == 101 => {}
+            Some(true) => push(buf, b"Connection: keep-alive\r\n"),
+            Some(false) => push(buf, b"Connection: close\r\n"),
+            None => {}
+        }
+
+        for (k, v) in &self.headers {
             push(buf, k.as_bytes());
-            push(buf,": ".as_bytes());
-            push(buf,v.as_bytes());
-            push(buf,"\r\n".as_bytes());
+            push(buf, ": ".as_bytes());
+            push(buf, v.as_bytes());
+            push(buf, "\r\n".as_bytes());
+        }
+
+        push(buf, "\r\n".as_bytes());
+        if bodyless || self.without_body {
+            return;
+        }
+        if self.chunked {
+            Response::encode_chunk(&self.response, buf);
+        } else {
+            push(buf, self.response.as_slice());
         }
+    }
+
+    /// Writes a chunk of a chunked body. Empty chunks are skipped, as they would end the body.
+    pub fn encode_chunk(data: &[u8], buf: &mut BytesMut) {
+        if data.is_empty() {
+            return;
+        }
+        write!(FastWrite(buf), "{:x}\r\n", data.len()).unwrap();
+        push(buf, data);
+        push(buf, b"\r\n");
+    }
+
+    /// Ends a chunked body
+    pub fn encode_last_chunk(buf: &mut BytesMut) {
+        push(buf, b"0\r\n\r\n");
+    }
 
-        push(buf,"\r\n".as_bytes());
-        push(buf,self.response.as_slice());
+    /// Decodes a response as a client, once its body has arrived. Bodies are delimited by
+    /// `Content-Length`, chunked transfer-encoding, or else by the server closing the
+    /// connection, for which see `decode_eof`. `Content-Length` and `Transfer-Encoding` are
+    /// left out of the headers, as the body is held decoded, except that a response to HEAD
+    /// keeps the `Content-Length` its body would have.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Response>, Error> {
+        decode(buf, &Limits::default(), false, false).map(|r| r.map(|(response, _)| response))
+    }
+
+    /// Decodes the last response once the server has closed the connection
+    pub fn decode_eof(buf: &mut BytesMut) -> Result<Option<Response>, Error> {
+        decode(buf, &Limits::default(), true, false).map(|r| r.map(|(response, _)| response))
     }
 }
 
+/// Decodes a response, along with whether the connection may be reused after it. `eof` says
+/// the connection is closed, and `head` that the response is to a HEAD request, which has no
+/// body whatever its headers say.
+pub(crate) fn decode(
+    buf: &mut BytesMut,
+    limits: &Limits,
+    eof: bool,
+    head: bool,
+) -> Result<Option<(Response, bool)>, Error> {
+    let closed = || Error::BadResponse("connection closed before the response ended".to_string());
+    let mut response = Response::new();
+    let mut length = None;
+    let mut chunked = None;
+    let mut keep_alive;
+    let len = {
+        let mut headers = vec![httparse::EMPTY_HEADER; limits.max_headers];
+        let mut r = httparse::Response::new(&mut headers);
+        let status = r.parse(buf).map_err(|e| match e {
+            httparse::Error::TooManyHeaders => Error::HeadersTooLarge,
+            e => Error::BadResponse(e.to_string()),
+        })?;
+        let len = match status {
+            httparse::Status::Complete(len) if len > limits.max_head => {
+                return Err(Error::HeadersTooLarge)
+            }
+            httparse::Status::Complete(len) => len,
+            httparse::Status::Partial if buf.len() > limits.max_head => {
+                return Err(Error::HeadersTooLarge)
+            }
+            httparse::Status::Partial if eof => return Err(closed()),
+            httparse::Status::Partial => return Ok(None),
+        };
+
+        response.status_code(u32::from(r.code.unwrap()), r.reason.unwrap_or_default());
+        keep_alive = r.version == Some(1);
+        for h in r.headers.iter() {
+            if h.name.eq_ignore_ascii_case("Content-Length") {
+                let value = std::str::from_utf8(h.value)
+                    .ok()
+                    .and_then(|v| v.trim().parse::<usize>().ok())
+                    .ok_or_else(|| Error::BadResponse("invalid Content-Length".to_string()))?;
+                if length.is_some_and(|l| l != value) {
+                    return Err(Error::BadResponse("conflicting Content-Length".to_string()));
+                }
+                length = Some(value);
+                continue;
+            }
+            if h.name.eq_ignore_ascii_case("Transfer-Encoding") {
+                let last = h.value.rsplit(|&b| b == b',').next().unwrap_or_default();
+                chunked = Some(crate::request::trim(last).eq_ignore_ascii_case(b"chunked"));
+                continue;
+            }
+            if h.name.eq_ignore_ascii_case("Connection") {
+                if has_token(h.value, "close") {
+                    keep_alive = false;
+                } else if has_token(h.value, "keep-alive") {
+                    keep_alive = true;
+                }
+            }
+            response.header(h.name, &String::from_utf8_lossy(h.value));
+        }
+        if let (true, Some(length)) = (head, length) {
+            response.header("Content-Length", &length.to_string());
+        }
+        len
+    };
+
+    let rest = &buf[len..];
+    let consumed = if head || matches!(response.status(), 100..=199 | 204 | 304) {
+        0
+    } else if chunked == Some(true) {
+        match chunked_body(rest, limits).map_err(|e| match e {
+            Error::BadRequest(msg) => Error::BadResponse(msg),
+            e => e,
+        })? {
+            Some((body, consumed)) => {
+                response.response = body.to_vec();
+                consumed
+            }
+            None if eof => return Err(closed()),
+            None => return Ok(None),
+        }
+    } else if let (None, Some(length)) = (chunked, length) {
+        if length > limits.max_body {
+            return Err(Error::PayloadTooLarge);
+        }
+        if rest.len() < length {
+            return if eof { Err(closed()) } else { Ok(None) };
+        }
+        response.response = rest[..length].to_vec();
+        length
+    } else {
+        // the body lasts until the server closes the connection
+        if rest.len() > limits.max_body {
+            return Err(Error::PayloadTooLarge);
+        }
+        if !eof {
+            return Ok(None);
+        }
+        keep_alive = false;
+        response.response = rest.to_vec();
+        rest.len()
+    };
+    let _ = buf.split_to(len + consumed);
+    Ok(Some((response, keep_alive)))
+}
+
 fn push(buf: &mut BytesMut, data: &[u8]) {
     buf.extend_from_slice(data);
 }
 
-
 struct FastWrite<'a>(&'a mut BytesMut);
 
 impl<'a> fmt::Write for FastWrite<'a> {
@@ -92,7 +397,61 @@ impl fmt::Display for StatusMessage {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             StatusMessage::Ok => f.pad("200 OK"),
-            StatusMessage::Custom(c, ref s) => write!(f, "{} {}",c,s)
+            StatusMessage::Custom(c, ref s) => write!(f, "{} {}", c, s),
         }
     }
-}
\ No newline at end of file
+}
+#[test]
+fn encode_chunked() {
+    let mut buf = BytesMut::new();
+    Response::new()
+        .keep_alive(false)
+        .chunked()
+        .body("hello")
+        .encode(&mut buf);
+    Response::encode_chunk(b"", &mut buf);
+    Response::encode_chunk(b" chunked world", &mut buf);
+    Response::encode_last_chunk(&mut buf);
+    let text = std::str::from_utf8(&buf).unwrap();
+    assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(text.contains("\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n"));
+    assert!(!text.contains("Content-Length"));
+    assert!(text.ends_with("\r\n\r\n5\r\nhello\r\ne\r\n chunked world\r\n0\r\n\r\n"));
+}
+
+#[test]
+fn decode_responses() {
+    let mut buf = BytesMut::from(
+        &b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nX-A: 1\r\n\r\nhi\
+           HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n\
+           HTTP/1.0 200 OK\r\n\r\nuntil "[..],
+    );
+    let response = Response::decode(&mut buf).unwrap().unwrap();
+    assert_eq!((response.status(), response.get_body()), (200, &b"hi"[..]));
+    assert_eq!(response.get_header("x-a"), Some("1"));
+    assert_eq!(response.get_header("Content-Length"), None);
+    let response = Response::decode(&mut buf).unwrap().unwrap();
+    assert_eq!(
+        (response.reason(), response.get_body()),
+        ("Not Found", &b"abc"[..])
+    );
+
+    assert!(Response::decode(&mut buf).unwrap().is_none());
+    buf.extend_from_slice(b"closed");
+    let response = Response::decode_eof(&mut buf).unwrap().unwrap();
+    assert_eq!(response.get_body(), &b"until closed"[..]);
+    assert!(buf.is_empty());
+
+    let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nab"[..]);
+    assert!(Response::decode_eof(&mut buf).is_err());
+
+    let mut buf = BytesMut::new();
+    Response::new().body("round trip").encode(&mut buf);
+    let response = Response::decode(&mut buf).unwrap().unwrap();
+    let mut again = BytesMut::new();
+    response.encode(&mut again);
+    assert_eq!(
+        Response::decode(&mut again).unwrap().unwrap().get_body(),
+        &b"round trip"[..]
+    );
+}
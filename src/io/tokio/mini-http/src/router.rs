@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use futures::future::{self, BoxFuture, FutureExt};
+
+use crate::request::Request;
+use crate::response::Response;
+use crate::service::{Middleware, Next, Service};
+use crate::url::percent_decode;
+
+/// Dispatches requests to services by method and path pattern, running middleware around
+/// them. Routes are tried in the order they were added.
+///
+/// Patterns are matched by `/`-separated segments. `:name` captures a segment and `*name`
+/// captures the rest of the path, e.g. `/users/:id` or `/static/*path`; both are read with
+/// `Request::param`. No route for the path gives 404, and none for the method 405. GET routes
+/// also answer HEAD requests.
+pub struct Router {
+    routes: Vec<Route>,
+    middleware: Arc<[Arc<dyn Middleware>]>,
+}
+
+/// The parameters captured by a pattern, by name
+type Params = Vec<(String, String)>;
+
+struct Route {
+    method: String,
+    pattern: Vec<Segment>,
+    service: Arc<dyn Service>,
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            middleware: Arc::new([]),
+        }
+    }
+
+    pub fn route<S: Service>(&mut self, method: &str, pattern: &str, service: S) -> &mut Router {
+        let pattern = pattern
+            .split('/')
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+        self.routes.push(Route {
+            method: method.to_string(),
+            pattern,
+            service: Arc::new(service),
+        });
+        self
+    }
+
+    pub fn get<S: Service>(&mut self, pattern: &str, service: S) -> &mut Router {
+        self.route("GET", pattern, service)
+    }
+
+    pub fn post<S: Service>(&mut self, pattern: &str, service: S) -> &mut Router {
+        self.route("POST", pattern, service)
+    }
+
+    pub fn put<S: Service>(&mut self, pattern: &str, service: S) -> &mut Router {
+        self.route("PUT", pattern, service)
+    }
+
+    pub fn delete<S: Service>(&mut self, pattern: &str, service: S) -> &mut Router {
+        self.route("DELETE", pattern, service)
+    }
+
+    /// Adds middleware around every request, including those no route matches. The first
+    /// added runs outermost.
+    pub fn middleware<M: Middleware>(&mut self, middleware: M) -> &mut Router {
+        let mut all = self.middleware.to_vec();
+        all.push(Arc::new(middleware));
+        self.middleware = all.into();
+        self
+    }
+
+    /// Finds the service for a request, along with the parameters its pattern captures
+    fn find(&self, req: &Request) -> Result<(Arc<dyn Service>, Params), Response> {
+        let path = req.path().split('?').next().unwrap_or_default();
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            if let Some(params) = matches(&route.pattern, path) {
+                // GET routes answer HEAD too, and the body is left out when encoding
+                if route.method == req.method() || (req.method() == "HEAD" && route.method == "GET")
+                {
+                    return Ok((route.service.clone(), params));
+                }
+                if !allowed.contains(&route.method.as_str()) {
+                    allowed.push(route.method.as_str());
+                }
+            }
+        }
+
+        let mut response = Response::new();
+        response.header("Content-Type", "text/plain");
+        if allowed.is_empty() {
+            response.status_code(404, "Not Found").body("not found");
+        } else {
+            response
+                .status_code(405, "Method Not Allowed")
+                .header("Allow", &allowed.join(", "))
+                .body("method not allowed");
+        }
+        Err(response)
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Service for Router {
+    fn call(&self, mut req: Request) -> BoxFuture<'static, Response> {
+        let service: Arc<dyn Service> = match self.find(&req) {
+            Ok((service, params)) => {
+                req.set_params(params);
+                service
+            }
+            Err(response) => Arc::new(Fixed(response)),
+        };
+        Next::new(self.middleware.clone(), service).run(req)
+    }
+}
+
+/// Answers with a response made by the router, such that middleware sees it too
+struct Fixed(Response);
+
+impl Service for Fixed {
+    fn call(&self, _: Request) -> BoxFuture<'static, Response> {
+        future::ready(self.0.clone()).boxed()
+    }
+}
+
+/// Returns the decoded parameters if the path matches the pattern
+fn matches(pattern: &[Segment], path: &str) -> Option<Params> {
+    let mut params = Vec::new();
+    let mut segments = path.split('/');
+    for expected in pattern {
+        match expected {
+            Segment::Wildcard(name) => {
+                let rest: Vec<&str> = segments.by_ref().collect();
+                params.push((name.clone(), percent_decode(&rest.join("/"))));
+                return Some(params);
+            }
+            Segment::Literal(literal) => {
+                if percent_decode(segments.next()?) != *literal {
+                    return None;
+                }
+            }
+            Segment::Param(name) => match segments.next()? {
+                "" => return None,
+                segment => params.push((name.clone(), percent_decode(segment))),
+            },
+        }
+    }
+    match segments.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+#[test]
+fn route_requests() {
+    let mut router = Router::new();
+    router
+        .get("/", |_| async { Response::new() })
+        .get("/users/:id", |req: Request| async move {
+            let mut response = Response::new();
+            response.body(&format!("user {}", req.param("id").unwrap()));
+            response
+        })
+        .get("/static/*path", |req: Request| async move {
+            let mut response = Response::new();
+            response.body(req.param("path").unwrap());
+            response
+        });
+    let call = |head: &str| futures::executor::block_on(router.call(crate::request::parse(head)));
+
+    assert_eq!(call("GET / HTTP/1.1\r\n\r\n").status(), 200);
+    let mut buf = bytes::BytesMut::new();
+    call("GET /users/a%20b?x=1 HTTP/1.1\r\n\r\n").encode(&mut buf);
+    assert!(buf.ends_with(b"\r\n\r\nuser a b"));
+    buf.clear();
+    call("GET /static/css/site.css HTTP/1.1\r\n\r\n").encode(&mut buf);
+    assert!(buf.ends_with(b"\r\n\r\ncss/site.css"));
+
+    assert_eq!(call("GET /users/ HTTP/1.1\r\n\r\n").status(), 404);
+    assert_eq!(call("GET /users/1/posts HTTP/1.1\r\n\r\n").status(), 404);
+    assert_eq!(call("POST /users/1 HTTP/1.1\r\n\r\n").status(), 405);
+}
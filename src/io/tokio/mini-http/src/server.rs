@@ -0,0 +1,278 @@
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BytesMut};
+use futures::executor;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures::stream::{self, BoxStream, StreamExt};
+
+use crate::client::Stream;
+use crate::request::{Decoder, Request};
+use crate::response::{Response, CONTINUE};
+use crate::service::Service;
+
+/// The state of a connection between reads and writes, whatever drives it
+struct Connection {
+    decoder: Decoder,
+    input: BytesMut,
+    output: BytesMut,
+}
+
+enum Step {
+    /// Answer the request, then go on if the connection is kept alive
+    Respond(Request),
+    /// Write the interim response, then read the body
+    Continue,
+    /// Write the output, then close
+    Close,
+    Read,
+}
+
+impl Connection {
+    fn new() -> Connection {
+        Connection {
+            decoder: Decoder::new(),
+            input: BytesMut::new(),
+            output: BytesMut::new(),
+        }
+    }
+
+    fn step(&mut self) -> Step {
+        match self.decoder.decode(&mut self.input) {
+            Ok(Some(req)) => Step::Respond(req),
+            Ok(None) if self.decoder.wants_continue() => Step::Continue,
+            Ok(None) => Step::Read,
+            Err(e) => {
+                e.response().encode(&mut self.output);
+                Step::Close
+            }
+        }
+    }
+
+    /// Encodes the response to a request, and returns whether the connection stays open along
+    /// with the rest of a streamed body, whose chunks go through `encode_chunk`
+    fn encode(
+        &mut self,
+        mut response: Response,
+        keep_alive: bool,
+        head: bool,
+    ) -> (bool, Option<BoxStream<'static, Vec<u8>>>) {
+        let keep_alive = keep_alive && response.keeps_alive();
+        response.keep_alive(keep_alive);
+        if head {
+            response.without_body();
+        }
+        let chunks = response.take_chunks().filter(|_| !head);
+        response.encode(&mut self.output);
+        // unless streamed, the whole body has been given, so it ends with the first chunk
+        if response.is_chunked() && !head && chunks.is_none() {
+            Response::encode_last_chunk(&mut self.output);
+        }
+        (keep_alive, chunks)
+    }
+
+    /// Encodes a chunk of a streamed body, or its end
+    fn encode_chunk(&mut self, chunk: Option<Vec<u8>>) {
+        match chunk {
+            Some(chunk) => Response::encode_chunk(&chunk, &mut self.output),
+            None => Response::encode_last_chunk(&mut self.output),
+        }
+    }
+}
+
+/// A connection handed over after a `101 Switching Protocols`, which first reads what the
+/// client sent after the request
+pub struct Upgraded {
+    io: Box<dyn Stream>,
+    read: BytesMut,
+}
+
+impl AsyncRead for Upgraded {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.read.is_empty() {
+            return Pin::new(&mut self.io).poll_read(cx, buf);
+        }
+        let n = buf.len().min(self.read.len());
+        buf[..n].copy_from_slice(&self.read[..n]);
+        self.read.advance(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for Upgraded {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_close(cx)
+    }
+}
+
+/// Answers the requests read from a connection until either side closes it. The stream is any
+/// `futures` one, such as an async-std `TcpStream`, and the service's futures are run on the
+/// task calling this. A `101 Switching Protocols` response hands the connection over to what
+/// it was given with `Response::on_upgrade`, which also runs on this task.
+pub async fn serve<T, S>(mut io: T, service: Arc<S>) -> io::Result<()>
+where
+    T: Stream,
+    S: Service + ?Sized,
+{
+    let mut conn = Connection::new();
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        match conn.step() {
+            Step::Respond(req) => {
+                let (keep_alive, head) = (req.keep_alive(), req.method() == "HEAD");
+                let mut response = service.call(req).await;
+                let upgrade = response.take_upgrade();
+                let (open, chunks) = conn.encode(response, keep_alive, head);
+                io.write_all(&conn.output).await?;
+                conn.output.clear();
+                if let Some(chunks) = chunks {
+                    let mut chunks = chunks.map(Some).chain(stream::iter(Some(None)));
+                    while let Some(chunk) = chunks.next().await {
+                        conn.encode_chunk(chunk);
+                        io.write_all(&conn.output).await?;
+                        conn.output.clear();
+                    }
+                }
+                if let Some(upgrade) = upgrade {
+                    let read = conn.input.split();
+                    upgrade(Upgraded {
+                        io: Box::new(io),
+                        read,
+                    })
+                    .await;
+                    return Ok(());
+                }
+                if !open {
+                    return io.close().await;
+                }
+            }
+            Step::Continue => io.write_all(CONTINUE).await?,
+            Step::Close => {
+                io.write_all(&conn.output).await?;
+                return io.close().await;
+            }
+            Step::Read => match io.read(&mut buf).await? {
+                0 => return Ok(()),
+                n => conn.input.extend_from_slice(&buf[..n]),
+            },
+        }
+    }
+}
+
+/// Answers the requests read from a blocking stream, such as a `std::net::TcpStream`, blocking
+/// the thread on each of the service's futures. Switching protocols needs `serve`, so a
+/// response given `Response::on_upgrade` is answered with 501 here.
+pub fn serve_blocking<T, S>(mut io: T, service: &S) -> io::Result<()>
+where
+    T: Read + Write,
+    S: Service + ?Sized,
+{
+    let mut conn = Connection::new();
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        match conn.step() {
+            Step::Respond(req) => {
+                let (keep_alive, head) = (req.keep_alive(), req.method() == "HEAD");
+                let mut response = executor::block_on(service.call(req));
+                if response.take_upgrade().is_some() {
+                    response = Response::new();
+                    response
+                        .status_code(501, "Not Implemented")
+                        .header("Content-Type", "text/plain")
+                        .body("switching protocols is not supported here");
+                }
+                let (open, chunks) = conn.encode(response, keep_alive, head);
+                io.write_all(&conn.output)?;
+                conn.output.clear();
+                if let Some(chunks) = chunks {
+                    for chunk in executor::block_on_stream(chunks)
+                        .map(Some)
+                        .chain(Some(None))
+                    {
+                        conn.encode_chunk(chunk);
+                        io.write_all(&conn.output)?;
+                        conn.output.clear();
+                    }
+                }
+                if !open {
+                    return io.flush();
+                }
+            }
+            Step::Continue => io.write_all(CONTINUE)?,
+            Step::Close => {
+                io.write_all(&conn.output)?;
+                return io.flush();
+            }
+            Step::Read => match io.read(&mut buf)? {
+                0 => return Ok(()),
+                n => conn.input.extend_from_slice(&buf[..n]),
+            },
+        }
+    }
+}
+
+/// A blocking stream reading from a buffer and writing to another
+#[cfg(test)]
+struct Mock {
+    input: io::Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+#[cfg(test)]
+impl Read for Mock {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Read::read(&mut self.input, buf)
+    }
+}
+
+#[cfg(test)]
+impl Write for Mock {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Write::write(&mut self.output, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn serve_pipelined_requests() {
+    let echo = |req: Request| async move {
+        let mut response = Response::new();
+        response.body_bytes(req.body());
+        response
+    };
+    let mut mock = Mock {
+        input: io::Cursor::new(
+            b"POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nhi\
+              GET / HTTP/1.1\r\nConnection: close\r\n\r\n\
+              GET /ignored HTTP/1.1\r\n\r\n"
+                .to_vec(),
+        ),
+        output: Vec::new(),
+    };
+    serve_blocking(&mut mock, &echo).unwrap();
+    let output = String::from_utf8(mock.output).unwrap();
+    assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 2);
+    assert!(output.contains("\r\n\r\nhiHTTP/1.1"));
+    assert!(output.ends_with("Connection: close\r\n\r\n"));
+}
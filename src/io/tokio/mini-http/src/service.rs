@@ -0,0 +1,265 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, Either, FutureExt};
+
+use crate::request::Request;
+use crate::response::Response;
+
+/// Answers requests, independently of the runtime driving the connection. Functions and
+/// closures taking a `Request` to a future `Response` are services.
+pub trait Service: Send + Sync + 'static {
+    fn call(&self, req: Request) -> BoxFuture<'static, Response>;
+}
+
+impl<F, Fut> Service for F
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send + 'static,
+{
+    fn call(&self, req: Request) -> BoxFuture<'static, Response> {
+        self(req).boxed()
+    }
+}
+
+/// Runs around a service, seeing each request before it and each response after it
+pub trait Middleware: Send + Sync + 'static {
+    fn call(&self, req: Request, next: Next) -> BoxFuture<'static, Response>;
+}
+
+/// The rest of the chain after a middleware, ending with the service
+pub struct Next {
+    middleware: Arc<[Arc<dyn Middleware>]>,
+    /// Of the middleware to run next
+    index: usize,
+    service: Arc<dyn Service>,
+}
+
+impl Next {
+    pub(crate) fn new(middleware: Arc<[Arc<dyn Middleware>]>, service: Arc<dyn Service>) -> Next {
+        Next {
+            middleware,
+            index: 0,
+            service,
+        }
+    }
+
+    pub fn run(self, req: Request) -> BoxFuture<'static, Response> {
+        match self.middleware.get(self.index).cloned() {
+            Some(middleware) => middleware.call(
+                req,
+                Next {
+                    index: self.index + 1,
+                    ..self
+                },
+            ),
+            None => self.service.call(req),
+        }
+    }
+}
+
+/// Prints a line per request with the status and how long it took
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn call(&self, req: Request, next: Next) -> BoxFuture<'static, Response> {
+        let start = Instant::now();
+        let line = format!("{} {}", req.method(), req.path());
+        async move {
+            let response = next.run(req).await;
+            println!("{} {} {:?}", line, response.status(), start.elapsed());
+            response
+        }
+        .boxed()
+    }
+}
+
+/// Answers 503 when the service takes longer than a duration
+pub struct Timeout {
+    duration: Duration,
+    sleep: Box<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>,
+}
+
+impl Timeout {
+    /// Times requests on a thread of its own, which works on any runtime. `with_timer` takes
+    /// the runtime's own timer instead.
+    pub fn new(duration: Duration) -> Timeout {
+        let timer = Timer::spawn();
+        Timeout::with_timer(duration, move |duration| timer.sleep(duration).boxed())
+    }
+
+    pub fn with_timer<F>(duration: Duration, sleep: F) -> Timeout
+    where
+        F: Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        Timeout {
+            duration,
+            sleep: Box::new(sleep),
+        }
+    }
+}
+
+impl Middleware for Timeout {
+    fn call(&self, req: Request, next: Next) -> BoxFuture<'static, Response> {
+        let sleep = (self.sleep)(self.duration);
+        future::select(next.run(req), sleep)
+            .map(|either| match either {
+                Either::Left((response, _)) => response,
+                Either::Right(_) => {
+                    let mut response = Response::new();
+                    response
+                        .status_code(503, "Service Unavailable")
+                        .header("Content-Type", "text/plain")
+                        .body("timed out");
+                    response
+                }
+            })
+            .boxed()
+    }
+}
+
+/// A thread waking the sleeps of a `Timeout` in deadline order. It stops once the `Timer` is
+/// dropped and the sleeps it has left are done.
+struct Timer {
+    shared: Arc<TimerShared>,
+}
+
+struct TimerShared {
+    state: Mutex<TimerState>,
+    /// Notified when the earliest deadline or `stopped` changes
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+    /// Earliest first. A sleep dropped before its deadline leaves its entry here, which is
+    /// skipped as its sender is gone.
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    senders: HashMap<u64, oneshot::Sender<()>>,
+    next_id: u64,
+    stopped: bool,
+}
+
+impl Timer {
+    fn spawn() -> Timer {
+        let shared = Arc::new(TimerShared {
+            state: Mutex::new(TimerState::default()),
+            changed: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        thread::spawn(move || thread_shared.run());
+        Timer { shared }
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let deadline = Instant::now() + duration;
+        let (tx, rx) = oneshot::channel();
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let earliest = state
+            .deadlines
+            .peek()
+            .is_none_or(|Reverse((first, _))| deadline < *first);
+        state.deadlines.push(Reverse((deadline, id)));
+        state.senders.insert(id, tx);
+        if earliest {
+            self.shared.changed.notify_one();
+        }
+        Sleep {
+            shared: self.shared.clone(),
+            id,
+            rx,
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.changed.notify_one();
+    }
+}
+
+impl TimerShared {
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            while let Some(&Reverse((deadline, id))) = state.deadlines.peek() {
+                if deadline > now {
+                    break;
+                }
+                state.deadlines.pop();
+                if let Some(tx) = state.senders.remove(&id) {
+                    let _ = tx.send(());
+                }
+            }
+            if state.stopped && state.senders.is_empty() {
+                return;
+            }
+            state = match state.deadlines.peek() {
+                Some(&Reverse((deadline, _))) => {
+                    self.changed.wait_timeout(state, deadline - now).unwrap().0
+                }
+                None => self.changed.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+/// Finishes at a deadline of a `Timer`, which forgets it if dropped before
+struct Sleep {
+    shared: Arc<TimerShared>,
+    id: u64,
+    rx: oneshot::Receiver<()>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.rx.poll_unpin(cx).map(|_| ())
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders.remove(&self.id);
+        // the timer may be waiting for this sleep alone to stop
+        if state.stopped && state.senders.is_empty() {
+            self.shared.changed.notify_one();
+        }
+    }
+}
+
+#[test]
+fn time_out_requests() {
+    let mut router = crate::Router::new();
+    router
+        .get("/fast", |_| async { Response::new() })
+        .get("/slow", |_| future::pending::<Response>())
+        .middleware(Timeout::new(Duration::from_millis(50)));
+    let call =
+        |head: &str| futures::executor::block_on(router.call(crate::request::parse(head))).status();
+
+    assert_eq!(call("GET /fast HTTP/1.1\r\n\r\n"), 200);
+    let start = Instant::now();
+    assert_eq!(call("GET /slow HTTP/1.1\r\n\r\n"), 503);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // a request that finishes first leaves nothing for the timer to wake
+    let timer = Timer::spawn();
+    let shared = timer.shared.clone();
+    drop(timer.sleep(Duration::from_secs(60)));
+    assert!(shared.state.lock().unwrap().senders.is_empty());
+    futures::executor::block_on(timer.sleep(Duration::from_millis(1)));
+}
@@ -0,0 +1,174 @@
+//! Decoding of paths and query strings, and the `http://` URLs the client fetches
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are. Bytes that are not UTF-8 are
+/// replaced.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' => std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escapes everything but unreserved characters, for use in a path segment
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Splits a query string into decoded pairs, where `+` stands for a space
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            let key = kv.next().unwrap_or_default().replace('+', " ");
+            let value = kv.next().unwrap_or_default().replace('+', " ");
+            (percent_decode(&key), percent_decode(&value))
+        })
+        .collect()
+}
+
+/// An `http://` URL split into where to connect and what to ask for
+#[derive(Clone, Debug, PartialEq)]
+pub struct Url {
+    /// Without brackets for IPv6 addresses
+    pub host: String,
+    pub port: u16,
+    /// The path and query, starting with `/`
+    pub target: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Option<Url> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        let target = target.split('#').next().unwrap_or_default().to_string();
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => {
+                let (host, port) = v6.split_once(']')?;
+                (host, port)
+            }
+            None => match authority.rfind(':') {
+                Some(i) => (&authority[..i], &authority[i..]),
+                None => (authority, ""),
+            },
+        };
+        let port = match port {
+            "" => 80,
+            port => port.strip_prefix(':')?.parse().ok()?,
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Url {
+            host: host.to_string(),
+            port,
+            target,
+        })
+    }
+
+    /// The address to connect to, which also keys the connection pool
+    pub fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// The value of the `Host` header, which leaves out the default port
+    pub fn host_header(&self) -> String {
+        match self.port {
+            80 => self.authority().trim_end_matches(":80").to_string(),
+            _ => self.authority(),
+        }
+    }
+
+    /// Resolves a `Location` against this URL
+    pub fn join(&self, location: &str) -> Option<Url> {
+        if location.starts_with("http://") {
+            Url::parse(location)
+        } else if let Some(rest) = location.strip_prefix("//") {
+            Url::parse(&format!("http://{}", rest))
+        } else if location.starts_with('/') {
+            Some(Url {
+                target: location.to_string(),
+                ..self.clone()
+            })
+        } else if location.contains("://") {
+            None
+        } else {
+            let path = self.target.split('?').next().unwrap_or_default();
+            let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+            Some(Url {
+                target: format!("{}{}", dir, location),
+                ..self.clone()
+            })
+        }
+    }
+}
+
+#[test]
+fn parse_urls() {
+    let url = Url::parse("http://example.com:8080/a/b?q=1#top").unwrap();
+    assert_eq!(
+        (url.host.as_str(), url.port, url.target.as_str()),
+        ("example.com", 8080, "/a/b?q=1")
+    );
+    assert_eq!(url.join("c").unwrap().target, "/a/c");
+    assert_eq!(url.join("/d").unwrap().authority(), "example.com:8080");
+    assert_eq!(url.join("http://other/").unwrap().host_header(), "other");
+    assert_eq!(
+        Url::parse("http://[::1]?x").unwrap().authority(),
+        "[::1]:80"
+    );
+    assert_eq!(Url::parse("http://[::1]?x").unwrap().target, "/?x");
+    assert!(Url::parse("https://example.com/").is_none());
+    assert!(Url::parse("http://:80/").is_none());
+}
+
+#[test]
+fn decode_queries() {
+    assert_eq!(percent_decode("a%20b%2Fc%zz%4"), "a b/c%zz%4");
+    assert_eq!(percent_encode("a b/é~"), "a%20b%2F%C3%A9~");
+    assert_eq!(
+        parse_query("q=rust+http&lang=&flag&x=%3D1"),
+        vec![
+            ("q".to_string(), "rust http".to_string()),
+            ("lang".to_string(), String::new()),
+            ("flag".to_string(), String::new()),
+            ("x".to_string(), "=1".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,735 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BytesMut};
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::{ready, Sink, Stream};
+use sha1::{Digest, Sha1};
+
+use crate::error::Error;
+use crate::request::{has_token, Request};
+use crate::response::Response;
+use crate::server::Upgraded;
+use crate::service::Service;
+
+/// Appended to the client's key to make the accept key
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const OP_CONTINUATION: u8 = 0x0;
+pub const OP_TEXT: u8 = 0x1;
+pub const OP_BINARY: u8 = 0x2;
+pub const OP_CLOSE: u8 = 0x8;
+pub const OP_PING: u8 = 0x9;
+pub const OP_PONG: u8 = 0xA;
+
+/// The largest payload of a close, ping or pong frame
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_UNSUPPORTED: u16 = 1003;
+pub const CLOSE_INVALID_PAYLOAD: u16 = 1007;
+pub const CLOSE_POLICY: u16 = 1008;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// The accept key answering a `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.input(key.as_bytes());
+    sha1.input(GUID.as_bytes());
+    base64::encode(sha1.result())
+}
+
+/// A random `Sec-WebSocket-Key` for a client to open a WebSocket with
+pub fn client_key() -> String {
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&random().to_be_bytes());
+    key[8..].copy_from_slice(&random().to_be_bytes());
+    base64::encode(key)
+}
+
+/// The request a client opens a WebSocket with, to be answered with the accept key for `key`
+pub fn client_request(host: &str, path: &str, key: &str) -> Request {
+    let mut req = Request::new("GET", path);
+    req.add_header("Host", host)
+        .add_header("Upgrade", "websocket")
+        .add_header("Connection", "Upgrade")
+        .add_header("Sec-WebSocket-Key", key)
+        .add_header("Sec-WebSocket-Version", "13");
+    req
+}
+
+/// Checks a request to open a WebSocket, and returns the `101 Switching Protocols` answering
+/// it, or else the response refusing it
+pub fn handshake(req: &Request) -> Result<Response, Response> {
+    let header = |name| req.header(name).unwrap_or_default();
+    let refuse = |code, reason, message: &str| {
+        let mut response = Response::new();
+        response
+            .status_code(code, reason)
+            .header("Content-Type", "text/plain")
+            .body(message);
+        Err(response)
+    };
+
+    if req.method() != "GET" || req.version() != 1 {
+        return refuse(
+            400,
+            "Bad Request",
+            "a WebSocket is opened with GET over HTTP/1.1",
+        );
+    }
+    if !has_token(header("Upgrade"), "websocket") || !has_token(header("Connection"), "upgrade") {
+        return refuse(400, "Bad Request", "expected `Upgrade: websocket`");
+    }
+    if crate::request::trim(header("Sec-WebSocket-Version")) != b"13" {
+        let mut response = refuse(426, "Upgrade Required", "only version 13 is supported")?;
+        response.header("Sec-WebSocket-Version", "13");
+        return Err(response);
+    }
+    let key = std::str::from_utf8(crate::request::trim(header("Sec-WebSocket-Key")))
+        .ok()
+        .filter(|key| base64::decode(key).is_ok_and(|key| key.len() == 16));
+    let key = match key {
+        Some(key) => key,
+        None => return refuse(400, "Bad Request", "invalid `Sec-WebSocket-Key`"),
+    };
+
+    let mut response = Response::new();
+    response
+        .status_code(101, "Switching Protocols")
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", &accept_key(key));
+    Ok(response)
+}
+
+type OnOpen = Arc<dyn Fn(Request, WebSocket<Upgraded>) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Opens WebSockets on the requests it is routed, and hands each one over to a function
+/// along with the request that opened it. Requests that are not handshakes are refused.
+pub struct Handler {
+    on_open: OnOpen,
+}
+
+impl Handler {
+    pub fn new<F, Fut>(on_open: F) -> Handler
+    where
+        F: Fn(Request, WebSocket<Upgraded>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Handler {
+            on_open: Arc::new(move |req, ws| on_open(req, ws).boxed()),
+        }
+    }
+}
+
+impl Service for Handler {
+    fn call(&self, req: Request) -> BoxFuture<'static, Response> {
+        let mut response = match handshake(&req) {
+            Ok(response) => response,
+            Err(response) => return future::ready(response).boxed(),
+        };
+        let on_open = self.on_open.clone();
+        response.on_upgrade(move |io| on_open(req, WebSocket::new(io, Role::Server)));
+        future::ready(response).boxed()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Answered with a pong by the socket itself
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// Answered with the same close by the socket itself, after which no more messages come
+    Close(Option<CloseFrame>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// Which end of the connection a socket is: clients mask the frames they send, and servers
+/// require them masked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    /// Unmasked
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Writes the frame, masked with the key if given
+    pub fn encode(&self, mask: Option<[u8; 4]>, buf: &mut BytesMut) {
+        let len = self.payload.len();
+        buf.reserve(len + 14);
+        buf.extend_from_slice(&[(self.fin as u8) << 7 | self.opcode]);
+        let masked = (mask.is_some() as u8) << 7;
+        if len < 126 {
+            buf.extend_from_slice(&[masked | len as u8]);
+        } else if len <= u16::MAX as usize {
+            buf.extend_from_slice(&[masked | 126]);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            buf.extend_from_slice(&[masked | 127]);
+            buf.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        match mask {
+            Some(key) => {
+                buf.extend_from_slice(&key);
+                let start = buf.len();
+                buf.extend_from_slice(&self.payload);
+                apply_mask(&mut buf[start..], key);
+            }
+            None => buf.extend_from_slice(&self.payload),
+        }
+    }
+
+    /// Decodes a frame once it has arrived, checking it is one the role may receive, with a
+    /// payload of at most `max_payload` bytes
+    pub fn decode(
+        buf: &mut BytesMut,
+        role: Role,
+        max_payload: usize,
+    ) -> Result<Option<Frame>, Error> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let (fin, opcode) = (buf[0] & 0x80 != 0, buf[0] & 0x0F);
+        if buf[0] & 0x70 != 0 {
+            return Err(protocol("reserved bits are set"));
+        }
+        match opcode {
+            OP_CONTINUATION | OP_TEXT | OP_BINARY => {}
+            OP_CLOSE | OP_PING | OP_PONG if !fin => {
+                return Err(protocol("control frames may not be fragmented"))
+            }
+            OP_CLOSE | OP_PING | OP_PONG if (buf[1] & 0x7F) as usize > MAX_CONTROL_PAYLOAD => {
+                return Err(protocol("control frames carry at most 125 bytes"))
+            }
+            OP_CLOSE | OP_PING | OP_PONG => {}
+            _ => return Err(protocol("unknown opcode")),
+        }
+        let masked = buf[1] & 0x80 != 0;
+        match role {
+            Role::Server if !masked => return Err(protocol("client frames must be masked")),
+            Role::Client if masked => return Err(protocol("server frames must not be masked")),
+            _ => {}
+        }
+
+        let (len, mut header) = match buf[1] & 0x7F {
+            126 if buf.len() < 4 => return Ok(None),
+            126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+            127 if buf.len() < 10 => return Ok(None),
+            127 => {
+                let mut len = [0u8; 8];
+                len.copy_from_slice(&buf[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            len => (len as u64, 2),
+        };
+        if len > max_payload as u64 {
+            return Err(Error::WebSocket(
+                CLOSE_TOO_BIG,
+                "message too big".to_string(),
+            ));
+        }
+        let len = len as usize;
+        let mask = if masked { Some(header) } else { None };
+        if masked {
+            header += 4;
+        }
+        if buf.len() < header + len {
+            return Ok(None);
+        }
+
+        let mut payload = buf[header..header + len].to_vec();
+        if let Some(at) = mask {
+            apply_mask(
+                &mut payload,
+                [buf[at], buf[at + 1], buf[at + 2], buf[at + 3]],
+            );
+        }
+        buf.advance(header + len);
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+}
+
+/// A WebSocket over a connection after the handshake, reading messages as a `Stream` and
+/// sending them as a `Sink`. Messages are reassembled from fragments, and sent in frames of at
+/// most `max_frame` bytes. Pings are answered, and so is a close, after which the stream ends;
+/// the answers go out as the stream is read, and closing the sink flushes them.
+pub struct WebSocket<S> {
+    io: S,
+    role: Role,
+    read: BytesMut,
+    write: BytesMut,
+    /// The opcode and payload so far of a message arriving in fragments
+    fragments: Option<(u8, Vec<u8>)>,
+    max_message: usize,
+    max_frame: usize,
+    close_sent: bool,
+    /// Whether the stream has ended
+    done: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocket<S> {
+    pub fn new(io: S, role: Role) -> WebSocket<S> {
+        WebSocket {
+            io,
+            role,
+            read: BytesMut::new(),
+            write: BytesMut::new(),
+            fragments: None,
+            max_message: 16 << 20,
+            max_frame: 64 << 10,
+            close_sent: false,
+            done: false,
+        }
+    }
+
+    /// The largest message to receive, 16 MiB by default. Larger ones close the socket with
+    /// `CLOSE_TOO_BIG`.
+    pub fn max_message(&mut self, max_message: usize) -> &mut WebSocket<S> {
+        self.max_message = max_message;
+        self
+    }
+
+    /// The largest frame to send, 64 KiB by default. Larger messages are sent fragmented.
+    pub fn max_frame(&mut self, max_frame: usize) -> &mut WebSocket<S> {
+        self.max_frame = max_frame.max(1);
+        self
+    }
+
+    /// Queues a message as frames, masked if sent by a client. Control frames, which callers
+    /// keep within `MAX_CONTROL_PAYLOAD`, are never fragmented.
+    fn queue(&mut self, opcode: u8, payload: &[u8]) {
+        let max_frame = match opcode {
+            OP_CLOSE | OP_PING | OP_PONG => MAX_CONTROL_PAYLOAD,
+            _ => self.max_frame,
+        };
+        let mut chunks = payload.chunks(max_frame).peekable();
+        let mut opcode = opcode;
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            let frame = Frame {
+                fin: chunks.peek().is_none(),
+                opcode,
+                payload: chunk.to_vec(),
+            };
+            let mask = match self.role {
+                Role::Client => Some((random() as u32).to_be_bytes()),
+                Role::Server => None,
+            };
+            frame.encode(mask, &mut self.write);
+            if frame.fin {
+                return;
+            }
+            opcode = OP_CONTINUATION;
+        }
+    }
+
+    fn queue_close(&mut self, close: Option<&CloseFrame>) {
+        if self.close_sent {
+            return;
+        }
+        self.close_sent = true;
+        let mut payload = Vec::new();
+        if let Some(close) = close {
+            // a reason too long for the frame is cut short, on a character boundary
+            let mut end = close.reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+            while !close.reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.extend_from_slice(&close.code.to_be_bytes());
+            payload.extend_from_slice(&close.reason.as_bytes()[..end]);
+        }
+        self.queue(OP_CLOSE, &payload);
+    }
+
+    /// Writes out what was queued
+    fn poll_write_queued(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write.is_empty() {
+            match ready!(Pin::new(&mut self.io).poll_write(cx, &self.write))? {
+                0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                n => self.write.advance(n),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Turns a frame into a message, unless it is part of one
+    fn receive(&mut self, frame: Frame) -> Result<Option<Message>, Error> {
+        let (opcode, payload) = match frame.opcode {
+            OP_CONTINUATION => {
+                let (opcode, mut payload) = self
+                    .fragments
+                    .take()
+                    .ok_or_else(|| protocol("continuation of no message"))?;
+                if payload.len() + frame.payload.len() > self.max_message {
+                    return Err(Error::WebSocket(
+                        CLOSE_TOO_BIG,
+                        "message too big".to_string(),
+                    ));
+                }
+                payload.extend_from_slice(&frame.payload);
+                (opcode, payload)
+            }
+            OP_TEXT | OP_BINARY if self.fragments.is_some() => {
+                return Err(protocol("message started within another"))
+            }
+            OP_PING => {
+                self.queue(OP_PONG, &frame.payload);
+                return Ok(Some(Message::Ping(frame.payload)));
+            }
+            OP_PONG => return Ok(Some(Message::Pong(frame.payload))),
+            OP_CLOSE => {
+                let close = close_frame(&frame.payload)?;
+                self.queue_close(close.as_ref());
+                self.done = true;
+                return Ok(Some(Message::Close(close)));
+            }
+            opcode => (opcode, frame.payload),
+        };
+        if !frame.fin {
+            self.fragments = Some((opcode, payload));
+            return Ok(None);
+        }
+        match opcode {
+            OP_TEXT => String::from_utf8(payload)
+                .map(|text| Some(Message::Text(text)))
+                .map_err(|_| {
+                    Error::WebSocket(CLOSE_INVALID_PAYLOAD, "text is not UTF-8".to_string())
+                }),
+            _ => Ok(Some(Message::Binary(payload))),
+        }
+    }
+
+    /// Closes the socket with the code of a protocol error, and ends the stream
+    fn fail(&mut self, error: Error, cx: &mut Context<'_>) -> Error {
+        if let Error::WebSocket(code, _) = error {
+            self.queue_close(Some(&CloseFrame {
+                code,
+                reason: String::new(),
+            }));
+            let _ = self.poll_write_queued(cx);
+        }
+        self.done = true;
+        error
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for WebSocket<S> {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        // answers to pings go out as the next message is waited for
+        if let Poll::Ready(Err(e)) = this.poll_write_queued(cx) {
+            this.done = true;
+            return Poll::Ready(Some(Err(e.into())));
+        }
+
+        let mut buf = [0u8; 8 * 1024];
+        loop {
+            let received = match Frame::decode(&mut this.read, this.role, this.max_message) {
+                Ok(Some(frame)) => this.receive(frame),
+                Ok(None) => {
+                    match ready!(Pin::new(&mut this.io).poll_read(cx, &mut buf)) {
+                        Ok(0) => {
+                            this.done = true;
+                            return Poll::Ready(None);
+                        }
+                        Ok(n) => this.read.extend_from_slice(&buf[..n]),
+                        Err(e) => {
+                            this.done = true;
+                            return Poll::Ready(Some(Err(e.into())));
+                        }
+                    }
+                    continue;
+                }
+                Err(e) => Err(e),
+            };
+            match received {
+                Ok(Some(message)) => {
+                    // the answer to a close goes out before the stream ends
+                    if this.done {
+                        let _ = this.poll_write_queued(cx);
+                    }
+                    return Poll::Ready(Some(Ok(message)));
+                }
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Some(Err(this.fail(e, cx)))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<Message> for WebSocket<S> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        if this.write.len() >= this.max_frame {
+            ready!(this.poll_write_queued(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<(), Error> {
+        let this = self.get_mut();
+        if this.close_sent {
+            return Err(
+                io::Error::new(io::ErrorKind::BrokenPipe, "the WebSocket is closed").into(),
+            );
+        }
+        match message {
+            Message::Text(text) => this.queue(OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => this.queue(OP_BINARY, &data),
+            Message::Ping(data) | Message::Pong(data) if data.len() > MAX_CONTROL_PAYLOAD => {
+                return Err(protocol("control frames carry at most 125 bytes"))
+            }
+            Message::Ping(data) => this.queue(OP_PING, &data),
+            Message::Pong(data) => this.queue(OP_PONG, &data),
+            Message::Close(close) => this.queue_close(close.as_ref()),
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_queued(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx).map_err(Error::from)
+    }
+
+    /// Sends a normal close unless one was sent, then closes the connection
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.queue_close(Some(&CloseFrame {
+            code: CLOSE_NORMAL,
+            reason: String::new(),
+        }));
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.io).poll_close(cx).map_err(Error::from)
+    }
+}
+
+fn protocol(message: &str) -> Error {
+    Error::WebSocket(CLOSE_PROTOCOL_ERROR, message.to_string())
+}
+
+/// Parses the payload of a close frame, which is empty or a code followed by a UTF-8 reason
+fn close_frame(payload: &[u8]) -> Result<Option<CloseFrame>, Error> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(protocol("close frame with half a code")),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            // codes not to be sent, or reserved for future use
+            if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+                return Err(protocol("invalid close code"));
+            }
+            let reason = String::from_utf8(reason.to_vec()).map_err(|_| {
+                Error::WebSocket(
+                    CLOSE_INVALID_PAYLOAD,
+                    "close reason is not UTF-8".to_string(),
+                )
+            })?;
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}
+
+fn apply_mask(data: &mut [u8], key: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= key[i % 4];
+    }
+}
+
+/// Unpredictable bits for masks and keys, from the randomly keyed hasher of the standard
+/// library
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[test]
+fn accept_handshake() {
+    // the example of RFC 6455
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+
+    let req = crate::request::parse(
+        "GET /chat HTTP/1.1\r\nHost: example.com\r\nUpgrade: websocket\r\n\
+         Connection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+    );
+    let response = handshake(&req).ok().unwrap();
+    assert_eq!(response.status(), 101);
+    assert_eq!(
+        response.get_header("Sec-WebSocket-Accept"),
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+
+    let old = crate::request::parse(
+        "GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n",
+    );
+    assert_eq!(handshake(&old).err().unwrap().status(), 426);
+    let plain = crate::request::parse("GET /chat HTTP/1.1\r\n\r\n");
+    assert_eq!(handshake(&plain).err().unwrap().status(), 400);
+}
+
+#[test]
+fn code_frames() {
+    // the examples of RFC 6455, section 5.7
+    let hello = Frame {
+        fin: true,
+        opcode: OP_TEXT,
+        payload: b"Hello".to_vec(),
+    };
+    let mut buf = BytesMut::new();
+    hello.encode(None, &mut buf);
+    assert_eq!(&buf[..], b"\x81\x05Hello");
+    assert_eq!(
+        Frame::decode(&mut buf, Role::Client, 125).unwrap(),
+        Some(hello)
+    );
+
+    let mut masked = BytesMut::from(&b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58"[..]);
+    let frame = Frame::decode(&mut masked, Role::Server, 125)
+        .unwrap()
+        .unwrap();
+    assert_eq!(frame.payload, b"Hello");
+    assert!(masked.is_empty());
+
+    let mut long = BytesMut::new();
+    Frame {
+        fin: true,
+        opcode: OP_BINARY,
+        payload: vec![7; 70_000],
+    }
+    .encode(Some([1, 2, 3, 4]), &mut long);
+    assert_eq!(&long[..2], b"\x82\xff");
+    let mut partial = BytesMut::from(&long[..100]);
+    assert_eq!(
+        Frame::decode(&mut partial, Role::Server, 1 << 20).unwrap(),
+        None
+    );
+    let frame = Frame::decode(&mut long, Role::Server, 1 << 20)
+        .unwrap()
+        .unwrap();
+    assert_eq!(frame.payload, vec![7; 70_000]);
+
+    let mut unmasked = BytesMut::from(&b"\x81\x05Hello"[..]);
+    assert!(Frame::decode(&mut unmasked, Role::Server, 125).is_err());
+    let mut fragmented_ping = BytesMut::from(&b"\x09\x00"[..]);
+    assert!(Frame::decode(&mut fragmented_ping, Role::Client, 125).is_err());
+}
+
+#[test]
+fn exchange_messages() {
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use futures::{SinkExt, StreamExt};
+
+    // a fragmented text, a ping and a close, as a client sends them
+    let mut input = BytesMut::new();
+    let key = Some([9, 8, 7, 6]);
+    for (fin, opcode, payload) in [
+        (false, OP_TEXT, &b"Hel"[..]),
+        (true, OP_PING, b"?"),
+        (true, OP_CONTINUATION, b"lo"),
+        (true, OP_CLOSE, b"\x03\xe8bye"),
+    ] {
+        let payload = payload.to_vec();
+        Frame {
+            fin,
+            opcode,
+            payload,
+        }
+        .encode(key, &mut input);
+    }
+    let mut ws = WebSocket::new(Cursor::new(input.to_vec()), Role::Server);
+
+    block_on(async {
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            Message::Ping(b"?".to_vec())
+        );
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            Message::Text("Hello".to_string())
+        );
+        let close = CloseFrame {
+            code: CLOSE_NORMAL,
+            reason: "bye".to_string(),
+        };
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            Message::Close(Some(close))
+        );
+        assert!(ws.next().await.is_none());
+        assert!(ws.send(Message::Text("late".to_string())).await.is_err());
+        ws.flush().await.unwrap();
+    });
+
+    // the pong and the close echoed, written after the input in the cursor
+    let output = ws.io.into_inner();
+    assert_eq!(&output[input.len()..], b"\x8a\x01?\x88\x05\x03\xe8bye");
+}
+
+#[test]
+fn send_control_frames_whole() {
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use futures::SinkExt;
+
+    let mut ws = WebSocket::new(Cursor::new(Vec::new()), Role::Server);
+    ws.max_frame(2);
+    let close = CloseFrame {
+        code: CLOSE_NORMAL,
+        reason: "é".repeat(100),
+    };
+    block_on(async {
+        ws.send(Message::Text("abc".to_string())).await.unwrap();
+        ws.send(Message::Ping(b"hello".to_vec())).await.unwrap();
+        assert!(ws.send(Message::Pong(vec![0; 126])).await.is_err());
+        ws.send(Message::Close(Some(close))).await.unwrap();
+    });
+
+    let output = ws.io.into_inner();
+    let (text, rest) = output.split_at(7);
+    assert_eq!(text, b"\x01\x02ab\x80\x01c");
+    let (ping, close) = rest.split_at(7);
+    assert_eq!(ping, b"\x89\x05hello");
+    // the reason is cut to 122 bytes, as the next character would not fit
+    assert_eq!(&close[..4], b"\x88\x7c\x03\xe8");
+    assert_eq!(close[4..], "é".repeat(61).into_bytes()[..]);
+}
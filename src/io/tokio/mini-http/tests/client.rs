@@ -0,0 +1,222 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task;
+
+use futures::channel::mpsc;
+use mini_http::{serve, Client, Error, Request, Response, Router};
+
+/// Starts a server on a free port, and returns its address along with how many connections
+/// it has accepted
+async fn start() -> (String, Arc<AtomicUsize>) {
+    let mut router = Router::new();
+    router
+        .get("/", |_| async {
+            let mut response = Response::new();
+            response.body("hello");
+            response
+        })
+        .get("/chunked", |_| async {
+            let mut response = Response::new();
+            response.chunked().body("in chunks");
+            response
+        })
+        .post("/echo", |req: Request| async move {
+            let mut response = Response::new();
+            response.body_bytes(req.body());
+            response
+        })
+        .get("/redirect/:n", |req: Request| async move {
+            let n: usize = req.param("n").unwrap().parse().unwrap();
+            let mut response = Response::new();
+            match n {
+                0 => response.body("arrived"),
+                n => response
+                    .status_code(302, "Found")
+                    .header("Location", &(n - 1).to_string()),
+            };
+            response
+        })
+        .post("/see-other", |_| async {
+            let mut response = Response::new();
+            response
+                .status_code(303, "See Other")
+                .header("Location", "/");
+            response
+        });
+    let router = Arc::new(router);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            task::spawn(serve(stream.unwrap(), router.clone()));
+        }
+    });
+    (addr, accepted)
+}
+
+#[test]
+fn reuse_connections() {
+    task::block_on(async {
+        let (addr, accepted) = start().await;
+        let client = Client::new(TcpStream::connect);
+
+        for _ in 0..3 {
+            let response = client.get(&format!("http://{}/", addr)).await.unwrap();
+            assert_eq!(response.get_body(), &b"hello"[..]);
+        }
+        let response = client
+            .get(&format!("http://{}/chunked", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.get_body(), &b"in chunks"[..]);
+        let url = format!("http://{}/echo", addr);
+        let response = client.send("POST", &url, &[], b"posted").await.unwrap();
+        assert_eq!(response.get_body(), &b"posted"[..]);
+        let response = client
+            .send("HEAD", &format!("http://{}/", addr), &[], b"")
+            .await
+            .unwrap();
+        assert_eq!((response.status(), response.get_body()), (200, &b""[..]));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        let close = [("Connection", "close")];
+        client
+            .send("GET", &format!("http://{}/", addr), &close, b"")
+            .await
+            .unwrap();
+        client.get(&format!("http://{}/", addr)).await.unwrap();
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    })
+}
+
+#[test]
+fn follow_redirects() {
+    task::block_on(async {
+        let (addr, _) = start().await;
+        let mut client = Client::new(TcpStream::connect);
+
+        let response = client
+            .get(&format!("http://{}/redirect/3", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.get_body(), &b"arrived"[..]);
+        let url = format!("http://{}/see-other", addr);
+        let response = client.send("POST", &url, &[], b"form").await.unwrap();
+        assert_eq!(response.get_body(), &b"hello"[..]);
+
+        client.max_redirects(2);
+        match client.get(&format!("http://{}/redirect/3", addr)).await {
+            Err(Error::TooManyRedirects) => {}
+            other => panic!("unexpected result {:?}", other.map(|r| r.status())),
+        }
+        client.max_redirects(0);
+        let response = client
+            .get(&format!("http://{}/redirect/3", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 302);
+    })
+}
+
+/// Reads until what was read ends with `end`
+async fn read_until(stream: &mut TcpStream, read: &mut Vec<u8>, end: &[u8]) {
+    let mut buf = [0u8; 1024];
+    while !read.ends_with(end) {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_ne!(n, 0, "closed after {:?}", String::from_utf8_lossy(read));
+        read.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[test]
+fn stream_chunks() {
+    task::block_on(async {
+        let (tx, rx) = mpsc::unbounded();
+        let rx = Mutex::new(Some(rx));
+        let service = Arc::new(move |_: Request| {
+            let rx = rx.lock().unwrap().take();
+            async move {
+                let mut response = Response::new();
+                response.body("first").body_stream(rx.unwrap());
+                response
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, service).await.unwrap();
+        });
+
+        // each chunk arrives as the stream yields it, before the body ends
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let mut read = Vec::new();
+        read_until(&mut stream, &mut read, b"\r\n\r\n5\r\nfirst\r\n").await;
+        assert!(read.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(String::from_utf8_lossy(&read).contains("Transfer-Encoding: chunked\r\n"));
+        tx.unbounded_send(b"second".to_vec()).unwrap();
+        read_until(&mut stream, &mut read, b"6\r\nsecond\r\n").await;
+        drop(tx);
+        read_until(&mut stream, &mut read, b"6\r\nsecond\r\n0\r\n\r\n").await;
+    })
+}
+
+#[test]
+fn retry_idempotent_requests() {
+    task::block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let closed_on = Arc::new(Mutex::new(Vec::new()));
+        let methods = closed_on.clone();
+        // answers GETs, and closes the connection on reading any other request
+        task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(stream) = incoming.next().await {
+                let (mut stream, methods) = (stream.unwrap(), methods.clone());
+                task::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let mut read = Vec::new();
+                    while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                        read.extend_from_slice(&buf[..n]);
+                        if !read.ends_with(b"\r\n\r\n") {
+                            continue;
+                        }
+                        if !read.starts_with(b"GET ") {
+                            let method = read.split(|&b| b == b' ').next().unwrap();
+                            methods
+                                .lock()
+                                .unwrap()
+                                .push(String::from_utf8_lossy(method).into_owned());
+                            break;
+                        }
+                        read.clear();
+                        stream
+                            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                            .await
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        let client = Client::new(TcpStream::connect);
+
+        // a POST that was written is not sent again when the kept-alive connection closes
+        client.get(&url).await.unwrap();
+        assert!(client.send("POST", &url, &[], b"").await.is_err());
+        assert_eq!(*closed_on.lock().unwrap(), vec!["POST"]);
+
+        // while an idempotent request is, on a new connection
+        client.get(&url).await.unwrap();
+        assert!(client.send("DELETE", &url, &[], b"").await.is_err());
+        assert_eq!(*closed_on.lock().unwrap(), vec!["POST", "DELETE", "DELETE"]);
+    })
+}
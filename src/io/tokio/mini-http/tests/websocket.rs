@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task;
+use bytes::BytesMut;
+use futures::SinkExt;
+
+use mini_http::websocket::{self, Frame, Handler, Role, OP_TEXT};
+use mini_http::{serve, Message, Request, Response, Router, WebSocket};
+
+#[test]
+fn echo_after_upgrade() {
+    task::block_on(async {
+        let mut router = Router::new();
+        router.get(
+            "/echo",
+            Handler::new(|req: Request, mut ws: WebSocket<_>| async move {
+                assert_eq!(req.query_param("room").as_deref(), Some("a"));
+                while let Some(Ok(Message::Text(text))) = ws.next().await {
+                    ws.send(Message::Text(text)).await.unwrap();
+                }
+                ws.close().await.unwrap();
+            }),
+        );
+        let router = Arc::new(router);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, router).await.unwrap();
+        });
+
+        // the first message follows the request straight away, before the switch is answered
+        let key = websocket::client_key();
+        let mut out = BytesMut::new();
+        websocket::client_request(&addr, "/echo?room=a", &key).encode(&mut out);
+        let text = Frame {
+            fin: true,
+            opcode: OP_TEXT,
+            payload: b"early".to_vec(),
+        };
+        text.encode(Some([1, 2, 3, 4]), &mut out);
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream.write_all(&out).await.unwrap();
+
+        // the head is read a byte at a time, to leave the frames after it to the socket
+        let mut head = BytesMut::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            stream.read_exact(&mut byte).await.unwrap();
+            head.extend_from_slice(&byte);
+        }
+        let response = Response::decode(&mut head).unwrap().unwrap();
+        assert_eq!(response.status(), 101);
+        let accept = websocket::accept_key(&key);
+        assert_eq!(
+            response.get_header("Sec-WebSocket-Accept"),
+            Some(accept.as_str())
+        );
+
+        let mut ws = WebSocket::new(stream, Role::Client);
+        let early = ws.next().await.unwrap().unwrap();
+        assert_eq!(early, Message::Text("early".to_string()));
+        let long = "x".repeat(100_000);
+        ws.send(Message::Text(long.clone())).await.unwrap();
+        assert_eq!(ws.next().await.unwrap().unwrap(), Message::Text(long));
+
+        ws.send(Message::Close(None)).await.unwrap();
+        assert_eq!(ws.next().await.unwrap().unwrap(), Message::Close(None));
+        assert!(ws.next().await.is_none());
+    });
+}
+
+#[test]
+fn refuse_plain_requests() {
+    task::block_on(async {
+        let mut router = Router::new();
+        router.get("/echo", Handler::new(|_, _| async {}));
+        let router = Arc::new(router);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, router).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET /echo HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        let response = Response::decode_eof(&mut BytesMut::from(&buf[..]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    });
+}
@@ -0,0 +1,213 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// Destinations matched by network and port, such as `10.0.0.0/8:443`, `[::1]:8000-8999`,
+/// `192.168.1.1` or `*:22`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    /// Any address if missing
+    network: Option<(IpAddr, u8)>,
+    /// Any port if missing
+    ports: Option<(u16, u16)>,
+}
+
+impl Rule {
+    fn matches(&self, addr: &SocketAddr) -> bool {
+        let network = match self.network {
+            Some((net, len)) => in_network(addr.ip(), net, len),
+            None => true,
+        };
+        let port = match self.ports {
+            Some((first, last)) => (first..=last).contains(&addr.port()),
+            None => true,
+        };
+        network && port
+    }
+}
+
+impl FromStr for Rule {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Rule, ()> {
+        // IPv6 networks are bracketed when a port follows, as in addresses
+        let (network, ports) = if let Some(rest) = s.strip_prefix('[') {
+            let (network, rest) = rest.split_once(']').ok_or(())?;
+            let (len, ports) = match rest.strip_prefix('/') {
+                Some(rest) => match rest.split_once(':') {
+                    Some((len, ports)) => (Some(len), Some(ports)),
+                    None => (Some(rest), None),
+                },
+                None if rest.is_empty() => (None, None),
+                None => (None, Some(rest.strip_prefix(':').ok_or(())?)),
+            };
+            (Some((network, len)), ports)
+        } else if s.matches(':').count() > 1 {
+            (Some(split_len(s)), None)
+        } else {
+            let (network, ports) = match s.split_once(':') {
+                Some((network, ports)) => (network, Some(ports)),
+                None => (s, None),
+            };
+            let network = Some(split_len(network)).filter(|(net, _)| *net != "*");
+            (network, ports)
+        };
+
+        let network = match network {
+            Some((net, len)) => {
+                let net: IpAddr = net.parse().map_err(|_| ())?;
+                let max = if net.is_ipv4() { 32 } else { 128 };
+                let len = match len {
+                    Some(len) => len.parse().map_err(|_| ())?,
+                    None => max,
+                };
+                if len > max {
+                    return Err(());
+                }
+                match ipv4_mapped(net) {
+                    IpAddr::V4(v4) if net.is_ipv6() && len >= 96 => {
+                        Some((IpAddr::V4(v4), len - 96))
+                    }
+                    _ => Some((net, len)),
+                }
+            }
+            None => None,
+        };
+        let ports = match ports {
+            Some(ports) => {
+                let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+                let (first, last) = (
+                    first.parse().map_err(|_| ())?,
+                    last.parse().map_err(|_| ())?,
+                );
+                if first > last {
+                    return Err(());
+                }
+                Some((first, last))
+            }
+            None => None,
+        };
+        Ok(Rule { network, ports })
+    }
+}
+
+/// Which destinations tunnels may be opened to. One matching a deny rule is refused, as is one
+/// matching no allow rule when there are any. IPv4 addresses mapped into IPv6, such as
+/// `::ffff:127.0.0.1`, are matched as the IPv4 addresses they reach.
+#[derive(Clone, Debug, Default)]
+pub struct Acl {
+    pub allow: Vec<Rule>,
+    pub deny: Vec<Rule>,
+}
+
+impl Acl {
+    pub fn permits(&self, addr: &SocketAddr) -> bool {
+        let addr = &SocketAddr::new(ipv4_mapped(addr.ip()), addr.port());
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(addr));
+        allowed && !self.deny.iter().any(|rule| rule.matches(addr))
+    }
+}
+
+fn split_len(network: &str) -> (&str, Option<&str>) {
+    match network.split_once('/') {
+        Some((net, len)) => (net, Some(len)),
+        None => (network, None),
+    }
+}
+
+/// The IPv4 address an IPv4-mapped IPv6 address stands for, or else the address as is
+fn ipv4_mapped(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+fn in_network(ip: IpAddr, net: IpAddr, len: u8) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn parse_rules() {
+    let rule = |s: &str| s.parse::<Rule>();
+    let v4 = |net: &str, len| Some((net.parse().unwrap(), len));
+
+    assert_eq!(
+        rule("10.0.0.0/8:443"),
+        Ok(Rule {
+            network: v4("10.0.0.0", 8),
+            ports: Some((443, 443)),
+        })
+    );
+    assert_eq!(
+        rule("192.168.1.1"),
+        Ok(Rule {
+            network: v4("192.168.1.1", 32),
+            ports: None,
+        })
+    );
+    assert_eq!(
+        rule("*:1-1023"),
+        Ok(Rule {
+            network: None,
+            ports: Some((1, 1023)),
+        })
+    );
+    assert_eq!(
+        rule("[fd00::]/8:8000-8999"),
+        Ok(Rule {
+            network: Some(("fd00::".parse().unwrap(), 8)),
+            ports: Some((8000, 8999)),
+        })
+    );
+    assert_eq!(
+        rule("::1"),
+        Ok(Rule {
+            network: Some(("::1".parse().unwrap(), 128)),
+            ports: None,
+        })
+    );
+    for invalid in &["10.0.0.0/33", "*:80-22", "[::1", "example.com:80", "*:http"] {
+        assert_eq!(rule(invalid), Err(()), "{}", invalid);
+    }
+}
+
+#[test]
+fn permit_destinations() {
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+    let rules = |rules: &[&str]| rules.iter().map(|r| r.parse().unwrap()).collect();
+
+    let open = Acl::default();
+    assert!(open.permits(&addr("8.8.8.8:53")));
+
+    let acl = Acl {
+        allow: rules(&["10.0.0.0/8", "[::1]"]),
+        deny: rules(&["10.0.0.1", "*:22"]),
+    };
+    assert!(acl.permits(&addr("10.1.2.3:80")));
+    assert!(acl.permits(&addr("[::1]:80")));
+    assert!(!acl.permits(&addr("10.0.0.1:80")));
+    assert!(!acl.permits(&addr("10.1.2.3:22")));
+    assert!(!acl.permits(&addr("11.0.0.1:80")));
+    assert!(!acl.permits(&addr("[::2]:80")));
+    assert!(acl.permits(&addr("[::ffff:10.1.2.3]:80")));
+    assert!(!acl.permits(&addr("[::ffff:10.0.0.1]:80")));
+    assert!(!acl.permits(&addr("[::ffff:11.0.0.1]:80")));
+
+    let acl = Acl {
+        allow: Vec::new(),
+        deny: rules(&["127.0.0.0/8", "[::ffff:192.168.0.0]/112"]),
+    };
+    assert!(!acl.permits(&addr("[::ffff:127.0.0.1]:80")));
+    assert!(!acl.permits(&addr("192.168.3.4:80")));
+    assert!(acl.permits(&addr("[::1]:80")));
+}
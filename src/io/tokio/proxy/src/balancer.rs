@@ -0,0 +1,261 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Points on the hash ring per upstream, such that clients spread evenly and only those of an
+/// upstream move when it leaves
+const POINTS_PER_UPSTREAM: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// Each upstream in turn
+    RoundRobin,
+    /// The upstream with the fewest open connections
+    LeastConnections,
+    /// The same upstream for the same client IP, as long as it is available
+    Hash,
+}
+
+impl FromStr for Strategy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Strategy, ()> {
+        match s {
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "least-conn" => Ok(Strategy::LeastConnections),
+            "hash" => Ok(Strategy::Hash),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Upstream {
+    pub addr: String,
+    /// Connections open to it
+    active: AtomicUsize,
+    /// As found by the last health check
+    healthy: AtomicBool,
+    /// Failed connections in a row
+    failures: AtomicUsize,
+    /// When it comes back after being ejected for failing
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn new(addr: String) -> Upstream {
+        Upstream {
+            addr,
+            active: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            failures: AtomicUsize::new(0),
+            ejected_until: Mutex::new(None),
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Records the result of a health check, and returns whether it changed
+    pub fn set_healthy(&self, healthy: bool) -> bool {
+        self.healthy.swap(healthy, Ordering::SeqCst) != healthy
+    }
+
+    fn available(&self) -> bool {
+        let ejected_until = *self.ejected_until.lock().unwrap();
+        self.healthy.load(Ordering::SeqCst) && ejected_until.is_none_or(|t| Instant::now() >= t)
+    }
+}
+
+/// Counts a connection to an upstream while it is open
+pub struct Active(Arc<Upstream>);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Picks upstreams for clients, leaving out those failing health checks and, for a while,
+/// those failing `max_fails` connections in a row
+pub struct Balancer {
+    upstreams: Vec<Arc<Upstream>>,
+    strategy: Strategy,
+    /// Where the next round starts
+    next: AtomicUsize,
+    /// Hashes of the points of each upstream on the ring, sorted
+    ring: Vec<(u64, usize)>,
+    max_fails: usize,
+    fail_timeout: Duration,
+}
+
+impl Balancer {
+    pub fn new(
+        addrs: Vec<String>,
+        strategy: Strategy,
+        max_fails: usize,
+        fail_timeout: Duration,
+    ) -> Balancer {
+        let mut ring = Vec::new();
+        for (i, addr) in addrs.iter().enumerate() {
+            for point in 0..POINTS_PER_UPSTREAM {
+                ring.push((hash(&(addr, point)), i));
+            }
+        }
+        ring.sort_unstable();
+        Balancer {
+            upstreams: addrs
+                .into_iter()
+                .map(|a| Arc::new(Upstream::new(a)))
+                .collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+            ring,
+            max_fails,
+            fail_timeout,
+        }
+    }
+
+    pub fn upstreams(&self) -> &[Arc<Upstream>] {
+        &self.upstreams
+    }
+
+    /// The upstreams to try for a client, in order. Those available come by the strategy, or if
+    /// none is, all of them, as trying beats refusing the client outright.
+    pub fn candidates(&self, client: IpAddr) -> Vec<Arc<Upstream>> {
+        let n = self.upstreams.len();
+        let order: Vec<usize> = match self.strategy {
+            Strategy::RoundRobin => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..n).map(|i| (start + i) % n).collect()
+            }
+            Strategy::LeastConnections => {
+                // rotated first, such that ties spread
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                let mut order: Vec<usize> = (0..n).map(|i| (start + i) % n).collect();
+                order.sort_by_key(|&i| self.upstreams[i].active());
+                order
+            }
+            Strategy::Hash => {
+                // the upstreams of the points after the client's, going round the ring
+                let key = hash(&client);
+                let at = self.ring.partition_point(|&(point, _)| point < key);
+                let mut order = Vec::with_capacity(n);
+                for &(_, i) in self.ring[at..].iter().chain(&self.ring[..at]) {
+                    if !order.contains(&i) {
+                        order.push(i);
+                        if order.len() == n {
+                            break;
+                        }
+                    }
+                }
+                order
+            }
+        };
+
+        let available: Vec<_> = order
+            .iter()
+            .map(|&i| self.upstreams[i].clone())
+            .filter(|upstream| upstream.available())
+            .collect();
+        if available.is_empty() {
+            order.iter().map(|&i| self.upstreams[i].clone()).collect()
+        } else {
+            available
+        }
+    }
+
+    /// Counts a connection or request to an upstream as open until dropped
+    pub fn open(&self, upstream: &Arc<Upstream>) -> Active {
+        upstream.active.fetch_add(1, Ordering::SeqCst);
+        Active(upstream.clone())
+    }
+
+    /// Records that an upstream answered, which ends a run of failures
+    pub fn succeeded(&self, upstream: &Upstream) {
+        upstream.failures.store(0, Ordering::SeqCst);
+    }
+
+    /// Records a failed connection to an upstream, and returns whether it got ejected for it
+    pub fn failed(&self, upstream: &Upstream) -> bool {
+        let failures = upstream.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.max_fails == 0 || failures < self.max_fails {
+            return false;
+        }
+        upstream.failures.store(0, Ordering::SeqCst);
+        *upstream.ejected_until.lock().unwrap() = Some(Instant::now() + self.fail_timeout);
+        true
+    }
+}
+
+fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+fn addrs(n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("127.0.0.1:{}", 9000 + i)).collect()
+}
+
+#[test]
+fn pick_by_strategy() {
+    let client: IpAddr = "10.0.0.1".parse().unwrap();
+    let timeout = Duration::from_secs(60);
+
+    let round_robin = Balancer::new(addrs(3), Strategy::RoundRobin, 3, timeout);
+    let firsts: Vec<_> = (0..4)
+        .map(|_| round_robin.candidates(client)[0].addr.clone())
+        .collect();
+    assert_eq!(
+        firsts,
+        [
+            "127.0.0.1:9000",
+            "127.0.0.1:9001",
+            "127.0.0.1:9002",
+            "127.0.0.1:9000"
+        ]
+    );
+
+    let least = Balancer::new(addrs(3), Strategy::LeastConnections, 3, timeout);
+    let busy: Vec<_> = (0..2)
+        .map(|_| {
+            let upstream = least.candidates(client)[0].clone();
+            least.open(&upstream)
+        })
+        .collect();
+    assert_eq!(least.candidates(client)[0].addr, "127.0.0.1:9002");
+    drop(busy);
+
+    // a client keeps to its upstream, and moves only when that one goes
+    let hash = Balancer::new(addrs(5), Strategy::Hash, 3, timeout);
+    let order = hash.candidates(client);
+    assert_eq!(order.len(), 5);
+    assert_eq!(hash.candidates(client)[0].addr, order[0].addr);
+    order[0].set_healthy(false);
+    assert_eq!(hash.candidates(client)[0].addr, order[1].addr);
+}
+
+#[test]
+fn eject_after_failures() {
+    let client: IpAddr = "10.0.0.1".parse().unwrap();
+    let balancer = Balancer::new(addrs(2), Strategy::RoundRobin, 2, Duration::from_secs(60));
+    let first = balancer.upstreams()[0].clone();
+
+    assert!(!balancer.failed(&first));
+    assert!(balancer.failed(&first));
+    for _ in 0..2 {
+        let candidates = balancer.candidates(client);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].addr, "127.0.0.1:9001");
+    }
+
+    // with none available, all are tried
+    balancer.upstreams()[1].set_healthy(false);
+    assert_eq!(balancer.candidates(client).len(), 2);
+}
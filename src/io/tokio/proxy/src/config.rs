@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use crate::acl::{Acl, Rule};
+use crate::balancer::Strategy;
+
+pub const USAGE: &str = "usage: proxy [options] [listen_addr] [upstream,...]
+
+Listens on 127.0.0.1:8081 and forwards each connection to one of the upstreams, separated by
+commas, 127.0.0.1:8080 by default. On SIGTERM or Ctrl-C, stops accepting and waits for the open
+connections to finish.
+
+In http mode, requests are forwarded one by one instead, by the route with the longest path
+prefix matching them, among those for their host or else among those for any host. The
+upstreams given after the listen address make a route for `/` on any host.
+
+In socks5 and connect modes, clients name their destinations themselves, through SOCKS5 or
+HTTP `CONNECT` requests, and no upstreams are given. A destination matching a --deny rule is
+refused, as is one matching no --allow rule when there are any. Rules are `network[:ports]`,
+such as `10.0.0.0/8`, `*:22`, `192.168.1.1:8000-8999` or `[fd00::]/8:443`.
+
+options:
+    --mode <mode>             tcp (default) to forward connections, http to forward requests,
+                              socks5 to serve SOCKS5, or connect to tunnel HTTP CONNECT requests
+    --route <route>           in http mode, `[host]/prefix=upstream,...` such as
+                              `api.example.com/v1=127.0.0.1:9001,127.0.0.1:9002`
+    --timeout <ms>            in http mode, answer 504 when an upstream takes longer than this to
+                              respond, 30000 by default; in socks5 and connect modes, how long a
+                              client has to make its request
+    --auth <user:password>    in socks5 and connect modes, the credentials clients must give
+    --allow <rule>            in socks5 and connect modes, permit tunnels to these destinations
+    --deny <rule>             in socks5 and connect modes, refuse tunnels to these destinations
+    --strategy <name>         round-robin (default), least-conn, or hash to keep each client IP
+                              on the same upstream
+    --connect-timeout <ms>    give up on connecting to an upstream after this long, 1000 by default
+    --retries <n>             upstreams to try after the first one fails, 2 by default
+    --health-interval <ms>    connect to each upstream this often to check it, 5000 by default,
+                              0 to never check
+    --max-fails <n>           eject an upstream after this many failed connections in a row,
+                              3 by default
+    --fail-timeout <ms>       how long an ejected upstream is left out, 10000 by default
+    --drain-timeout <ms>      how long to wait for open connections on shutdown, 30000 by default";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Tcp,
+    Http,
+    Socks5,
+    Connect,
+}
+
+/// Where requests for a host and path go, in http mode
+pub struct Route {
+    /// Any host if missing
+    pub host: Option<String>,
+    pub prefix: String,
+    pub upstreams: Vec<String>,
+}
+
+pub struct Config {
+    pub mode: Mode,
+    pub listen: String,
+    pub upstreams: Vec<String>,
+    pub routes: Vec<Route>,
+    pub timeout: Duration,
+    pub auth: Option<(String, String)>,
+    pub acl: Acl,
+    pub strategy: Strategy,
+    pub connect_timeout: Duration,
+    pub retries: usize,
+    pub health_interval: Option<Duration>,
+    pub max_fails: usize,
+    pub fail_timeout: Duration,
+    pub drain_timeout: Duration,
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+    let mut config = Config {
+        mode: Mode::Tcp,
+        listen: "127.0.0.1:8081".to_string(),
+        upstreams: vec!["127.0.0.1:8080".to_string()],
+        routes: Vec::new(),
+        timeout: Duration::from_millis(30_000),
+        auth: None,
+        acl: Acl::default(),
+        strategy: Strategy::RoundRobin,
+        connect_timeout: Duration::from_millis(1000),
+        retries: 2,
+        health_interval: Some(Duration::from_millis(5000)),
+        max_fails: 3,
+        fail_timeout: Duration::from_millis(10_000),
+        drain_timeout: Duration::from_millis(30_000),
+    };
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                config.mode = match value(&arg, args.next())?.as_str() {
+                    "tcp" => Mode::Tcp,
+                    "http" => Mode::Http,
+                    "socks5" => Mode::Socks5,
+                    "connect" => Mode::Connect,
+                    mode => return Err(format!("unknown mode `{}`\n\n{}", mode, USAGE)),
+                }
+            }
+            "--route" => {
+                let route = value(&arg, args.next())?;
+                let route = parse_route(&route)
+                    .ok_or_else(|| format!("invalid route `{}`\n\n{}", route, USAGE))?;
+                config.routes.push(route);
+            }
+            "--timeout" => config.timeout = millis(&arg, args.next())?,
+            "--auth" => {
+                let auth = value(&arg, args.next())?;
+                let (user, password) = auth.split_once(':').ok_or_else(|| {
+                    format!("expected `user:password` for `{}`\n\n{}", arg, USAGE)
+                })?;
+                config.auth = Some((user.to_string(), password.to_string()));
+            }
+            "--allow" => config.acl.allow.push(rule(&arg, args.next())?),
+            "--deny" => config.acl.deny.push(rule(&arg, args.next())?),
+            "--strategy" => {
+                let name = value(&arg, args.next())?;
+                config.strategy = name
+                    .parse()
+                    .map_err(|_| format!("unknown strategy `{}`\n\n{}", name, USAGE))?;
+            }
+            "--connect-timeout" => config.connect_timeout = millis(&arg, args.next())?,
+            "--retries" => config.retries = number(&arg, args.next())?,
+            "--health-interval" => {
+                let interval = millis(&arg, args.next())?;
+                config.health_interval = Some(interval).filter(|i| *i > Duration::from_millis(0));
+            }
+            "--max-fails" => config.max_fails = number(&arg, args.next())?,
+            "--fail-timeout" => config.fail_timeout = millis(&arg, args.next())?,
+            "--drain-timeout" => config.drain_timeout = millis(&arg, args.next())?,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => {
+                return Err(format!("unknown option `{}`\n\n{}", arg, USAGE))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    if let Some(listen) = positional.next() {
+        config.listen = listen;
+    }
+    let upstreams = positional.next();
+    if let Some(upstreams) = &upstreams {
+        config.upstreams =
+            split_upstreams(upstreams).ok_or_else(|| format!("no upstreams given\n\n{}", USAGE))?;
+    }
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument `{}`\n\n{}", extra, USAGE));
+    }
+    if matches!(config.mode, Mode::Socks5 | Mode::Connect) && upstreams.is_some() {
+        return Err(format!(
+            "upstreams are not used in socks5 and connect modes\n\n{}",
+            USAGE
+        ));
+    }
+    if config.mode == Mode::Http && (upstreams.is_some() || config.routes.is_empty()) {
+        config.routes.push(Route {
+            host: None,
+            prefix: "/".to_string(),
+            upstreams: config.upstreams.clone(),
+        });
+    }
+    Ok(config)
+}
+
+/// Parses `[host]/prefix=upstream,...`
+fn parse_route(route: &str) -> Option<Route> {
+    let (pattern, upstreams) = route.split_once('=')?;
+    let (host, prefix) = match pattern.find('/') {
+        Some(i) => (&pattern[..i], &pattern[i..]),
+        None => (pattern, "/"),
+    };
+    Some(Route {
+        host: Some(host.to_ascii_lowercase()).filter(|host| !host.is_empty()),
+        prefix: prefix.to_string(),
+        upstreams: split_upstreams(upstreams)?,
+    })
+}
+
+fn split_upstreams(upstreams: &str) -> Option<Vec<String>> {
+    let upstreams: Vec<String> = upstreams
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(str::to_string)
+        .collect();
+    Some(upstreams).filter(|upstreams| !upstreams.is_empty())
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("missing value for `{}`\n\n{}", option, USAGE))
+}
+
+fn number(option: &str, value: Option<String>) -> Result<usize, String> {
+    let value = self::value(option, value)?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for `{}`\n\n{}", value, option, USAGE))
+}
+
+fn rule(option: &str, value: Option<String>) -> Result<Rule, String> {
+    let value = self::value(option, value)?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid rule `{}` for `{}`\n\n{}", value, option, USAGE))
+}
+
+fn millis(option: &str, value: Option<String>) -> Result<Duration, String> {
+    number(option, value).map(|ms| Duration::from_millis(ms as u64))
+}
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::time;
+
+use crate::balancer::Balancer;
+
+/// Connects to each upstream every `interval`, and takes it out of rotation while that fails or
+/// takes longer than `timeout`
+pub fn spawn(balancer: &Balancer, interval: Duration, timeout: Duration) {
+    for upstream in balancer.upstreams() {
+        let upstream = Arc::clone(upstream);
+        tokio::spawn(async move {
+            let mut ticks = time::interval(interval);
+            loop {
+                ticks.tick().await;
+                let check = time::timeout(timeout, TcpStream::connect(&upstream.addr)).await;
+                let healthy = matches!(check, Ok(Ok(_)));
+                if upstream.set_healthy(healthy) {
+                    let state = if healthy { "up" } else { "down" };
+                    println!("Upstream {} is {}", upstream.addr, state);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,423 @@
+use std::borrow::Cow;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::{BoxFuture, FutureExt};
+use futures::io::{AsyncRead, AsyncWrite};
+use mini_http::{Client, Error, Limits, Request, Response, Service};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use crate::balancer::Balancer;
+use crate::config::Config;
+
+/// Headers about a connection rather than the message, which are not passed on, along with
+/// those the `Connection` header names
+const HOP_BY_HOP: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Adapts a tokio stream to the `futures` traits mini-http reads and writes with
+pub struct Compat(pub TcpStream);
+
+impl AsyncRead for Compat {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, buf)
+    }
+}
+
+impl AsyncWrite for Compat {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut self.0), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut self.0), cx)
+    }
+}
+
+struct Backend {
+    host: Option<String>,
+    prefix: String,
+    balancer: Arc<Balancer>,
+}
+
+/// Forwards requests to the upstreams of their route. Connections to upstreams are kept alive
+/// by a client shared by all client connections, which close and stay open on their own.
+pub struct Proxy {
+    backends: Vec<Backend>,
+    client: Client,
+    config: Arc<Config>,
+}
+
+impl Proxy {
+    pub fn new(config: Arc<Config>) -> Proxy {
+        let backends = config
+            .routes
+            .iter()
+            .map(|route| Backend {
+                host: route.host.clone(),
+                prefix: route.prefix.clone(),
+                balancer: Arc::new(Balancer::new(
+                    route.upstreams.clone(),
+                    config.strategy,
+                    config.max_fails,
+                    config.fail_timeout,
+                )),
+            })
+            .collect();
+
+        let connect_timeout = config.connect_timeout;
+        let mut client = Client::new(move |addr: String| async move {
+            match time::timeout(connect_timeout, TcpStream::connect(addr)).await {
+                Ok(stream) => stream.map(Compat),
+                Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out")),
+            }
+        });
+        // redirects are for the client to follow, and bodies are held whole while forwarded
+        client.max_redirects(0).limits(Limits {
+            max_body: 64 * 1024 * 1024,
+            ..Limits::default()
+        });
+
+        Proxy {
+            backends,
+            client,
+            config,
+        }
+    }
+
+    pub fn balancers(&self) -> impl Iterator<Item = &Balancer> {
+        self.backends.iter().map(|backend| &*backend.balancer)
+    }
+
+    /// Finds the upstreams for a request by the longest matching path prefix, among the routes
+    /// for its host or else among those for any host
+    fn route(&self, req: &Request) -> Option<Arc<Balancer>> {
+        let host = req
+            .header("Host")
+            .and_then(|host| str::from_utf8(host).ok())
+            .map(|host| strip_port(host).to_ascii_lowercase());
+        let target = origin_form(req.path());
+        let path = target.split('?').next().unwrap_or_default();
+        let longest = |for_host: bool| {
+            self.backends
+                .iter()
+                .rev()
+                .filter(|backend| match &backend.host {
+                    Some(route_host) => for_host && host.as_deref() == Some(route_host.as_str()),
+                    None => !for_host,
+                })
+                .filter(|backend| prefix_matches(&backend.prefix, path))
+                .max_by_key(|backend| backend.prefix.len())
+        };
+        longest(true)
+            .or_else(|| longest(false))
+            .map(|backend| backend.balancer.clone())
+    }
+
+    /// Forwards a request, trying the next upstream when one cannot be reached, and answers
+    /// 502 when none answers or 504 when one takes longer than the timeout
+    async fn forward(self: Arc<Self>, req: Request, client: SocketAddr) -> Response {
+        let balancer = match self.route(&req) {
+            Some(balancer) => balancer,
+            None => return error(404, "Not Found", "no route"),
+        };
+        let headers = forwarded_headers(&req, client);
+        let headers: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        // others may have had an effect by the time they failed
+        let idempotent = matches!(
+            req.method(),
+            "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE"
+        );
+
+        let target = origin_form(req.path());
+
+        let candidates = balancer.candidates(client.ip());
+        for upstream in candidates.into_iter().take(self.config.retries + 1) {
+            let _active = balancer.open(&upstream);
+            let url = format!("http://{}{}", upstream.addr, target);
+            let sent = self.client.send(req.method(), &url, &headers, req.body());
+            let e = match time::timeout(self.config.timeout, sent).await {
+                Ok(Ok(mut response)) => {
+                    balancer.succeeded(&upstream);
+                    remove_hop_by_hop(&mut response);
+                    return response;
+                }
+                Ok(Err(e)) => e,
+                Err(_) => {
+                    println!("Timed out waiting for {}", upstream.addr);
+                    return error(504, "Gateway Timeout", "the upstream timed out");
+                }
+            };
+            println!("Failed to forward to {}; error: {}", upstream.addr, e);
+            if balancer.failed(&upstream) {
+                println!(
+                    "Ejected {} for {:?}",
+                    upstream.addr, self.config.fail_timeout
+                );
+            }
+            let unreached = match &e {
+                Error::Io(e) => matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionRefused | io::ErrorKind::TimedOut
+                ),
+                _ => false,
+            };
+            if !idempotent && !unreached {
+                break;
+            }
+        }
+        error(502, "Bad Gateway", "no upstream answered")
+    }
+}
+
+/// The proxy as seen from a client connection, knowing the client's address
+struct ClientConnection {
+    proxy: Arc<Proxy>,
+    client: SocketAddr,
+}
+
+impl Service for ClientConnection {
+    fn call(&self, req: Request) -> BoxFuture<'static, Response> {
+        self.proxy.clone().forward(req, self.client).boxed()
+    }
+}
+
+/// Answers the requests of a client connection until either side closes it
+pub async fn serve(proxy: Arc<Proxy>, inbound: TcpStream, client: SocketAddr) -> io::Result<()> {
+    let connection = Arc::new(ClientConnection { proxy, client });
+    mini_http::serve(Compat(inbound), connection).await
+}
+
+/// The headers to send on, with `Host` passed as `X-Forwarded-Host` as the client sets its own,
+/// and the client added to `X-Forwarded-For`
+fn forwarded_headers(req: &Request, client: SocketAddr) -> Vec<(String, String)> {
+    let connection = req.header("Connection").unwrap_or_default();
+    let connection = String::from_utf8_lossy(connection);
+    let mut headers = Vec::new();
+    let mut forwarded_for = Vec::new();
+    for (name, value) in req.headers() {
+        let value = String::from_utf8_lossy(value).into_owned();
+        if name.eq_ignore_ascii_case("X-Forwarded-For") {
+            forwarded_for.push(value);
+        } else if name.eq_ignore_ascii_case("Host") {
+            headers.push(("X-Forwarded-Host".to_string(), value));
+        } else if !name.eq_ignore_ascii_case("X-Forwarded-Host")
+            && !name.eq_ignore_ascii_case("X-Forwarded-Proto")
+            && !is_hop_by_hop(name, &connection)
+        {
+            headers.push((name.to_string(), value));
+        }
+    }
+    forwarded_for.push(client.ip().to_string());
+    headers.push(("X-Forwarded-For".to_string(), forwarded_for.join(", ")));
+    headers.push(("X-Forwarded-Proto".to_string(), "http".to_string()));
+    headers
+}
+
+fn remove_hop_by_hop(response: &mut Response) {
+    let connection = response
+        .get_header("Connection")
+        .unwrap_or_default()
+        .to_string();
+    let names: Vec<String> = response
+        .headers()
+        .iter()
+        .map(|(name, _)| name.clone())
+        .filter(|name| is_hop_by_hop(name, &connection))
+        .collect();
+    for name in names {
+        response.remove_header(&name);
+    }
+}
+
+fn is_hop_by_hop(name: &str, connection: &str) -> bool {
+    HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+        || connection
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case(name))
+}
+
+/// The path and query of a request target, which clients that know they talk to a proxy send
+/// in absolute form, e.g. `http://example.com/index.html`
+fn origin_form(target: &str) -> Cow<'_, str> {
+    let authority = match target.find("://") {
+        Some(i) if i > 0 && target[..i].chars().all(|c| c.is_ascii_alphabetic()) => {
+            &target[i + 3..]
+        }
+        _ => return Cow::Borrowed(target),
+    };
+    match authority.find(['/', '?']) {
+        Some(i) if authority[i..].starts_with('/') => Cow::Borrowed(&authority[i..]),
+        Some(i) => Cow::Owned(format!("/{}", &authority[i..])),
+        None => Cow::Borrowed("/"),
+    }
+}
+
+/// Whether a path is under a prefix, by whole segments
+fn prefix_matches(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    }
+}
+
+pub fn error(code: u32, reason: &str, message: &str) -> Response {
+    let mut response = Response::new();
+    response
+        .status_code(code, reason)
+        .header("Content-Type", "text/plain")
+        .body(message);
+    response
+}
+
+#[test]
+fn route_requests() {
+    use crate::config::{parse_args, Mode};
+
+    let args = [
+        "--mode",
+        "http",
+        "--route",
+        "api.example.com/v1=127.0.0.1:9001",
+        "--route",
+        "/static=127.0.0.1:9002",
+        "--route",
+        "/=127.0.0.1:9003",
+    ];
+    let config = parse_args(args.iter().map(|arg| arg.to_string())).unwrap();
+    assert_eq!(config.mode, Mode::Http);
+    let proxy = Proxy::new(Arc::new(config));
+    let upstream = |host: &str, path: &str| {
+        let mut req = Request::new("GET", path);
+        req.add_header("Host", host);
+        proxy
+            .route(&req)
+            .map(|balancer| balancer.upstreams()[0].addr.clone())
+    };
+
+    let api = upstream("API.example.com:8081", "/v1/users");
+    assert_eq!(api.as_deref(), Some("127.0.0.1:9001"));
+    let other = upstream("example.com", "/v1/users");
+    assert_eq!(other.as_deref(), Some("127.0.0.1:9003"));
+    let css = upstream("example.com", "/static/site.css?v=2");
+    assert_eq!(css.as_deref(), Some("127.0.0.1:9002"));
+    let lookalike = upstream("example.com", "/staticky");
+    assert_eq!(lookalike.as_deref(), Some("127.0.0.1:9003"));
+    let absolute = upstream("example.com", "http://example.com/static/site.css");
+    assert_eq!(absolute.as_deref(), Some("127.0.0.1:9002"));
+
+    assert_eq!(origin_form("/a?b=c"), "/a?b=c");
+    assert_eq!(origin_form("http://example.com:8080/a?b=c"), "/a?b=c");
+    assert_eq!(origin_form("http://example.com?b=c"), "/?b=c");
+    assert_eq!(origin_form("http://example.com"), "/");
+    assert_eq!(
+        origin_form("/redirect?to=http://example.com/"),
+        "/redirect?to=http://example.com/"
+    );
+}
+
+#[test]
+fn forward_headers() {
+    let mut req = Request::new("GET", "/");
+    req.add_header("Host", "example.com")
+        .add_header("Connection", "keep-alive, X-Secret")
+        .add_header("X-Secret", "1")
+        .add_header("X-Forwarded-For", "10.0.0.1")
+        .add_header("Accept", "*/*");
+    let headers = forwarded_headers(&req, "192.168.1.2:5000".parse().unwrap());
+    let headers: Vec<(&str, &str)> = headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+    assert_eq!(
+        headers,
+        [
+            ("X-Forwarded-Host", "example.com"),
+            ("Accept", "*/*"),
+            ("X-Forwarded-For", "10.0.0.1, 192.168.1.2"),
+            ("X-Forwarded-Proto", "http"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn forward_through_serve() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // an upstream answering with the target it was sent
+    let mut upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    let echo = Arc::new(|req: Request| async move {
+        let mut response = Response::new();
+        response.body(req.path());
+        response
+    });
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = upstream.accept().await.unwrap();
+            tokio::spawn(mini_http::serve(Compat(stream), echo.clone()));
+        }
+    });
+
+    let route = format!("/={}", upstream_addr);
+    let args = ["--mode", "http", "--route", &route];
+    let config = crate::config::parse_args(args.iter().map(|arg| arg.to_string())).unwrap();
+    let proxy = Arc::new(Proxy::new(Arc::new(config)));
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, client) = listener.accept().await.unwrap();
+        serve(proxy, stream, client).await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"HEAD /hello HTTP/1.1\r\nHost: a\r\n\r\n\
+              GET http://a/x?y=1 HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut output = String::new();
+    stream.read_to_string(&mut output).await.unwrap();
+    let (head, get) = output.split_at(output.rfind("HTTP/1.1 200 OK").unwrap());
+    // the length the upstream gave, with no body
+    assert!(head.contains("Content-Length: 6\r\n"), "{:?}", head);
+    assert!(head.ends_with("\r\n\r\n"));
+    assert!(get.ends_with("\r\n\r\n/x?y=1"), "{:?}", get);
+}
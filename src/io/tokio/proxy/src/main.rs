@@ -1,44 +1,269 @@
-use tokio::io;
+mod acl;
+mod balancer;
+mod config;
+mod health;
+mod http;
+mod socks5;
+mod target;
+mod tunnel;
+
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tokio::time;
 
 use futures::future::try_join;
-use futures::FutureExt;
 use std::env;
 use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::balancer::{Balancer, Upstream};
+use crate::config::{Config, Mode};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let listen_addr = env::args().nth(1)
-        .unwrap_or_else(||"127.0.0.1:8081".to_string());
-    let server_addr = env::args().nth(2)
-        .unwrap_or_else(||"127.0.0.1:8080".to_string());
-
-    println!("Listening on: {}", listen_addr);
-    println!("Proxying to: {}", server_addr);
-
-    let mut listener = TcpListener::bind(listen_addr).await?;
-    while let Ok((inbound, _)) = listener.accept().await {
-        let transfer = transfer(inbound, server_addr.clone()).map(|r| {
-            if let Err(e) = r {
-                println!("Failed to transfer; error: {}", e);
+    let config = match config::parse_args(env::args().skip(1)) {
+        Ok(config) => Arc::new(config),
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+
+    println!("Listening on: {}", config.listen);
+    let handler = match config.mode {
+        Mode::Tcp => {
+            println!(
+                "Proxying to: {} ({:?})",
+                config.upstreams.join(", "),
+                config.strategy
+            );
+            Handler::Tcp(Arc::new(Balancer::new(
+                config.upstreams.clone(),
+                config.strategy,
+                config.max_fails,
+                config.fail_timeout,
+            )))
+        }
+        Mode::Http => {
+            for route in &config.routes {
+                println!(
+                    "Routing {}{} to: {} ({:?})",
+                    route.host.as_deref().unwrap_or_default(),
+                    route.prefix,
+                    route.upstreams.join(", "),
+                    config.strategy
+                );
+            }
+            Handler::Http(Arc::new(http::Proxy::new(config.clone())))
+        }
+        Mode::Socks5 => {
+            println!("Serving SOCKS5");
+            Handler::Socks5
+        }
+        Mode::Connect => {
+            println!("Tunneling HTTP CONNECT requests");
+            Handler::Connect
+        }
+    };
+    if let Some(interval) = config.health_interval {
+        for balancer in handler.balancers() {
+            health::spawn(balancer, interval, config.connect_timeout);
+        }
+    }
+
+    let mut listener = TcpListener::bind(&config.listen).await?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let open = Arc::new(OpenConnections::default());
+    loop {
+        let (inbound, client) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    println!("Failed to accept; error: {}", e);
+                    continue;
+                }
+            },
+            _ = terminate.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let (handler, config) = (handler.clone(), config.clone());
+        let open = open.clone();
+        open.count.fetch_add(1, Ordering::SeqCst);
+        tokio::spawn(async move {
+            match handler {
+                Handler::Tcp(balancer) => {
+                    if let Err(e) = transfer(inbound, client, &balancer, &config).await {
+                        println!("Failed to transfer; error: {}", e);
+                    }
+                }
+                Handler::Http(proxy) => {
+                    if let Err(e) = http::serve(proxy, inbound, client).await {
+                        println!("Failed to serve; error: {}", e);
+                    }
+                }
+                Handler::Socks5 => {
+                    if let Err(e) = socks5::serve(inbound, client, &config).await {
+                        println!("Failed to tunnel; error: {}", e);
+                    }
+                }
+                Handler::Connect => {
+                    if let Err(e) = tunnel::serve(inbound, client, &config).await {
+                        println!("Failed to tunnel; error: {}", e);
+                    }
+                }
             }
+            open.close();
         });
-        tokio::spawn(transfer);
     }
+
+    // stop accepting, and let the open connections finish
+    drop(listener);
+    println!("Draining {} connections", open.count());
+    if time::timeout(config.drain_timeout, open.drained())
+        .await
+        .is_err()
+    {
+        println!("Closing {} connections after draining", open.count());
+    }
+    Ok(())
+}
+
+/// What accepted connections are handed to, by mode
+#[derive(Clone)]
+enum Handler {
+    Tcp(Arc<Balancer>),
+    Http(Arc<http::Proxy>),
+    /// Clients name their destinations, so there are no upstreams to balance
+    Socks5,
+    Connect,
+}
+
+impl Handler {
+    fn balancers(&self) -> Vec<&Balancer> {
+        match self {
+            Handler::Tcp(balancer) => vec![&**balancer],
+            Handler::Http(proxy) => proxy.balancers().collect(),
+            Handler::Socks5 | Handler::Connect => Vec::new(),
+        }
+    }
+}
+
+/// The connections being transferred, for waiting on them to finish before exiting
+#[derive(Default)]
+struct OpenConnections {
+    count: AtomicUsize,
+    closed: Notify,
+}
+
+impl OpenConnections {
+    fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    fn close(&self) {
+        if self.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.closed.notify();
+        }
+    }
+
+    async fn drained(&self) {
+        while self.count() > 0 {
+            self.closed.notified().await;
+        }
+    }
+}
+
+async fn transfer(
+    inbound: TcpStream,
+    client: SocketAddr,
+    balancer: &Balancer,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let (outbound, upstream) = connect(balancer, client.ip(), config).await?;
+    balancer.succeeded(&upstream);
+    let _active = balancer.open(&upstream);
+    relay(inbound, outbound, client, &upstream.addr).await?;
+
     Ok(())
 }
 
-async fn transfer(mut inbound: TcpStream, proxy_addr: String) -> Result<(), Box<dyn Error>> {
-    let mut outbound = TcpStream::connect(proxy_addr).await?;
+/// Copies both ways between a client and where it is connected to until both sides are done,
+/// then logs how much went each way
+pub async fn relay(
+    mut inbound: TcpStream,
+    mut outbound: TcpStream,
+    client: SocketAddr,
+    target: &str,
+) -> io::Result<()> {
+    let start = Instant::now();
 
     let (mut ri, mut wi) = inbound.split();
     let (mut ro, mut wo) = outbound.split();
 
-    let client_to_server = io::copy(&mut ri, &mut wo);
-    let server_to_client = io::copy(&mut ro, &mut wi);
+    let (mut sent, mut received) = (0, 0);
+    let client_to_server = copy(&mut ri, &mut wo, &mut sent);
+    let server_to_client = copy(&mut ro, &mut wi, &mut received);
 
+    let result = try_join(client_to_server, server_to_client).await;
+    println!(
+        "{} <-> {}: sent {} bytes, received {} bytes in {:?}",
+        client,
+        target,
+        sent,
+        received,
+        start.elapsed()
+    );
+    result.map(|_| ())
+}
 
-    try_join(client_to_server, server_to_client).await?;
+/// Connects to an upstream for the client, going on to the next when one fails or takes longer
+/// than the connect timeout, up to `retries` times
+async fn connect(
+    balancer: &Balancer,
+    client: IpAddr,
+    config: &Config,
+) -> io::Result<(TcpStream, Arc<Upstream>)> {
+    let mut last_error = None;
+    for upstream in balancer
+        .candidates(client)
+        .into_iter()
+        .take(config.retries + 1)
+    {
+        let error =
+            match time::timeout(config.connect_timeout, TcpStream::connect(&upstream.addr)).await {
+                Ok(Ok(stream)) => return Ok((stream, upstream)),
+                Ok(Err(e)) => e,
+                Err(_) => io::Error::new(io::ErrorKind::TimedOut, "connect timed out"),
+            };
+        println!("Failed to connect to {}; error: {}", upstream.addr, error);
+        if balancer.failed(&upstream) {
+            println!("Ejected {} for {:?}", upstream.addr, config.fail_timeout);
+        }
+        last_error = Some(error);
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no upstreams")))
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// Copies until the reader ends, counting the bytes as they go, then shuts the writer down so
+/// that the other side sees the end too
+async fn copy<R, W>(reader: &mut R, writer: &mut W, count: &mut u64) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = [0u8; 8 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        writer.write_all(&buf[..n]).await?;
+        *count += n as u64;
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time;
+
+use crate::config::Config;
+use crate::target::{self, Address, Failure};
+
+const VERSION: u8 = 5;
+
+const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+/// The version of the username/password subnegotiation, RFC 1929
+const USER_PASS_VERSION: u8 = 1;
+
+const CMD_CONNECT: u8 = 1;
+const CMD_UDP_ASSOCIATE: u8 = 3;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+const REP_SUCCEEDED: u8 = 0x00;
+const REP_GENERAL_FAILURE: u8 = 0x01;
+const REP_NOT_ALLOWED: u8 = 0x02;
+const REP_HOST_UNREACHABLE: u8 = 0x04;
+const REP_CONNECTION_REFUSED: u8 = 0x05;
+const REP_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REP_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+/// Answers a SOCKS5 client, RFC 1928: authenticates it, then tunnels its TCP connection or
+/// relays its UDP datagrams until it closes the connection
+pub async fn serve(
+    mut inbound: TcpStream,
+    client: SocketAddr,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request = time::timeout(config.timeout, handshake(&mut inbound, config)).await;
+    let (command, addr) = match request {
+        Ok(Ok(Some(request))) => request,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Err(e)) => return Err(e.into()),
+        Err(_) => return Err("timed out waiting for a request".into()),
+    };
+
+    match command {
+        CMD_CONNECT => {
+            let outbound = match target::connect(&addr, config).await {
+                Ok(outbound) => outbound,
+                Err(e) => {
+                    reply(&mut inbound, failure_reply(&e), None).await?;
+                    return Err(format!("{} to {}", e, addr).into());
+                }
+            };
+            reply(&mut inbound, REP_SUCCEEDED, Some(outbound.local_addr()?)).await?;
+            crate::relay(inbound, outbound, client, &addr.to_string()).await?;
+        }
+        CMD_UDP_ASSOCIATE => {
+            let socket = UdpSocket::bind((inbound.local_addr()?.ip(), 0)).await?;
+            reply(&mut inbound, REP_SUCCEEDED, Some(socket.local_addr()?)).await?;
+            // a client that does not know its port yet sends zeros
+            let from = match addr {
+                Address::Ip(addr) if !addr.ip().is_unspecified() && addr.port() != 0 => {
+                    Some(SocketAddr::new(client.ip(), addr.port()))
+                }
+                _ => None,
+            };
+            associate(inbound, socket, client, from, config).await?;
+        }
+        _ => reply(&mut inbound, REP_COMMAND_NOT_SUPPORTED, None).await?,
+    }
+    Ok(())
+}
+
+/// Negotiates the method and reads the request, or replies with the error and returns `None`
+/// when either is refused
+async fn handshake(inbound: &mut TcpStream, config: &Config) -> io::Result<Option<(u8, Address)>> {
+    let mut head = [0u8; 2];
+    inbound.read_exact(&mut head).await?;
+    if head[0] != VERSION {
+        return Err(invalid("not a SOCKS5 client"));
+    }
+    let mut methods = vec![0u8; head[1] as usize];
+    inbound.read_exact(&mut methods).await?;
+    let method = if config.auth.is_some() {
+        USER_PASS
+    } else {
+        NO_AUTH
+    };
+    if !methods.contains(&method) {
+        inbound.write_all(&[VERSION, NO_ACCEPTABLE_METHOD]).await?;
+        return Ok(None);
+    }
+    inbound.write_all(&[VERSION, method]).await?;
+
+    if let Some((user, password)) = &config.auth {
+        let mut version = [0u8; 2];
+        inbound.read_exact(&mut version).await?;
+        if version[0] != USER_PASS_VERSION {
+            return Err(invalid("unknown authentication version"));
+        }
+        let mut given_user = vec![0u8; version[1] as usize];
+        inbound.read_exact(&mut given_user).await?;
+        let mut given_password = vec![0u8; inbound.read_u8().await? as usize];
+        inbound.read_exact(&mut given_password).await?;
+        let valid = given_user == user.as_bytes() && given_password == password.as_bytes();
+        inbound
+            .write_all(&[USER_PASS_VERSION, if valid { 0 } else { 1 }])
+            .await?;
+        if !valid {
+            return Ok(None);
+        }
+    }
+
+    let mut head = [0u8; 4];
+    inbound.read_exact(&mut head).await?;
+    if head[0] != VERSION {
+        return Err(invalid("not a SOCKS5 request"));
+    }
+    let len = match head[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => inbound.read_u8().await? as usize,
+        _ => {
+            reply(inbound, REP_ADDRESS_NOT_SUPPORTED, None).await?;
+            return Ok(None);
+        }
+    };
+    let mut addr = vec![head[3]];
+    if head[3] == ATYP_DOMAIN {
+        addr.push(len as u8);
+    }
+    addr.resize(addr.len() + len + 2, 0);
+    let at = addr.len() - len - 2;
+    inbound.read_exact(&mut addr[at..]).await?;
+    match decode_address(&addr) {
+        Some((addr, _)) => Ok(Some((head[1], addr))),
+        None => Err(invalid("invalid address")),
+    }
+}
+
+/// Relays datagrams between the client and the destinations it sends to, as long as the
+/// connection that asked for it is open. Only destinations the client has sent to may answer.
+async fn associate(
+    mut inbound: TcpStream,
+    mut socket: UdpSocket,
+    client: SocketAddr,
+    mut from: Option<SocketAddr>,
+    config: &Config,
+) -> io::Result<()> {
+    let mut peers = HashSet::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut control = [0u8; 1];
+    let (mut sent, mut received) = (0, 0);
+    // datagrams can only be sent to addresses of the socket's family
+    let ipv4 = socket.local_addr()?.is_ipv4();
+    loop {
+        let (n, source) = tokio::select! {
+            closed = inbound.read(&mut control) => match closed {
+                Ok(0) | Err(_) => break,
+                // anything else sent on it means nothing
+                Ok(_) => continue,
+            },
+            datagram = socket.recv_from(&mut buf) => datagram?,
+        };
+
+        let from_client = match from {
+            Some(from) => source == from,
+            None => source.ip() == client.ip(),
+        };
+        if from_client {
+            from = Some(source);
+            // fragments are not supported, and are dropped as RFC 1928 allows
+            let (addr, at) = match decode_address(buf.get(3..n).unwrap_or_default()) {
+                Some((addr, len)) if buf[..3] == [0, 0, 0] => (addr, 3 + len),
+                _ => continue,
+            };
+            let dest = match target::resolve(&addr, &config.acl).await {
+                Ok(addrs) => match addrs.into_iter().find(|a| a.is_ipv4() == ipv4) {
+                    Some(dest) => dest,
+                    None => {
+                        println!(
+                            "Dropped datagram to {}; error: no address of the relay's family",
+                            addr
+                        );
+                        continue;
+                    }
+                },
+                Err(e) => {
+                    println!("Dropped datagram to {}; error: {}", addr, e);
+                    continue;
+                }
+            };
+            if let Err(e) = socket.send_to(&buf[at..n], dest).await {
+                println!("Dropped datagram to {}; error: {}", dest, e);
+                continue;
+            }
+            peers.insert(dest);
+            sent += (n - at) as u64;
+        } else if let (Some(from), true) = (from, peers.contains(&source)) {
+            let mut datagram = vec![0, 0, 0];
+            encode_address(&source, &mut datagram);
+            datagram.extend_from_slice(&buf[..n]);
+            if let Err(e) = socket.send_to(&datagram, from).await {
+                println!("Dropped datagram from {}; error: {}", source, e);
+                continue;
+            }
+            received += n as u64;
+        }
+    }
+    println!(
+        "{} <-> UDP {} destinations: sent {} bytes, received {} bytes",
+        client,
+        peers.len(),
+        sent,
+        received
+    );
+    Ok(())
+}
+
+async fn reply(inbound: &mut TcpStream, rep: u8, bound: Option<SocketAddr>) -> io::Result<()> {
+    let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+    let mut reply = vec![VERSION, rep, 0];
+    encode_address(&bound.unwrap_or(unspecified), &mut reply);
+    inbound.write_all(&reply).await
+}
+
+fn failure_reply(failure: &Failure) -> u8 {
+    match failure {
+        Failure::Denied => REP_NOT_ALLOWED,
+        Failure::Unresolved(_) => REP_HOST_UNREACHABLE,
+        Failure::Connect(e) => match e.kind() {
+            io::ErrorKind::ConnectionRefused => REP_CONNECTION_REFUSED,
+            io::ErrorKind::TimedOut => REP_HOST_UNREACHABLE,
+            _ => REP_GENERAL_FAILURE,
+        },
+    }
+}
+
+/// Decodes `ATYP DST.ADDR DST.PORT`, and returns how many bytes it took
+fn decode_address(buf: &[u8]) -> Option<(Address, usize)> {
+    let (addr, at) = match *buf.first()? {
+        ATYP_IPV4 => {
+            let octets: [u8; 4] = buf.get(1..5)?.try_into().ok()?;
+            (Some(IpAddr::V4(Ipv4Addr::from(octets))), 5)
+        }
+        ATYP_IPV6 => {
+            let octets: [u8; 16] = buf.get(1..17)?.try_into().ok()?;
+            (Some(IpAddr::V6(Ipv6Addr::from(octets))), 17)
+        }
+        ATYP_DOMAIN => (None, 2 + *buf.get(1)? as usize),
+        _ => return None,
+    };
+    let port = u16::from_be_bytes(buf.get(at..at + 2)?.try_into().ok()?);
+    let addr = match addr {
+        Some(ip) => Address::Ip(SocketAddr::new(ip, port)),
+        None => {
+            let domain = std::str::from_utf8(&buf[2..at]).ok()?;
+            Address::Domain(domain.to_string(), port)
+        }
+    };
+    Some((addr, at + 2))
+}
+
+fn encode_address(addr: &SocketAddr, buf: &mut Vec<u8>) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[test]
+fn decode_addresses() {
+    let ipv4 = [ATYP_IPV4, 10, 0, 0, 1, 0x01, 0xbb, 0xff];
+    assert_eq!(
+        decode_address(&ipv4),
+        Some((Address::Ip("10.0.0.1:443".parse().unwrap()), 7))
+    );
+
+    let mut ipv6 = vec![ATYP_IPV6];
+    ipv6.extend_from_slice(&"::1".parse::<Ipv6Addr>().unwrap().octets());
+    ipv6.extend_from_slice(&[0, 80]);
+    assert_eq!(
+        decode_address(&ipv6),
+        Some((Address::Ip("[::1]:80".parse().unwrap()), 19))
+    );
+
+    let mut domain = vec![ATYP_DOMAIN, 11];
+    domain.extend_from_slice(b"example.com");
+    domain.extend_from_slice(&[0, 80]);
+    assert_eq!(
+        decode_address(&domain),
+        Some((Address::Domain("example.com".to_string(), 80), 15))
+    );
+
+    assert_eq!(decode_address(&domain[..14]), None);
+    assert_eq!(decode_address(&[2, 0, 0]), None);
+
+    let mut encoded = Vec::new();
+    encode_address(&"[::1]:80".parse().unwrap(), &mut encoded);
+    assert_eq!(encoded, ipv6);
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::{self, TcpStream};
+use tokio::time;
+
+use crate::acl::Acl;
+use crate::config::Config;
+
+/// Where a client asks a tunnel to go
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Ip(addr) => write!(f, "{}", addr),
+            Address::Domain(domain, port) => write!(f, "{}:{}", domain, port),
+        }
+    }
+}
+
+/// Why a tunnel could not be opened
+#[derive(Debug)]
+pub enum Failure {
+    /// The destination, or all the addresses of its domain, are not permitted
+    Denied,
+    /// The domain did not resolve
+    Unresolved(io::Error),
+    Connect(io::Error),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Denied => write!(f, "not permitted"),
+            Failure::Unresolved(e) => write!(f, "failed to resolve: {}", e),
+            Failure::Connect(e) => write!(f, "failed to connect: {}", e),
+        }
+    }
+}
+
+/// The permitted addresses of a destination. Domains are resolved before they are checked, such
+/// that a name cannot stand in for a denied address.
+pub async fn resolve(addr: &Address, acl: &Acl) -> Result<Vec<SocketAddr>, Failure> {
+    let addrs: Vec<SocketAddr> = match addr {
+        Address::Ip(addr) => vec![*addr],
+        Address::Domain(domain, port) => net::lookup_host((domain.as_str(), *port))
+            .await
+            .map_err(Failure::Unresolved)?
+            .collect(),
+    };
+    let permitted: Vec<SocketAddr> = addrs.into_iter().filter(|a| acl.permits(a)).collect();
+    if permitted.is_empty() {
+        return Err(Failure::Denied);
+    }
+    Ok(permitted)
+}
+
+/// Connects to the first permitted address of a destination that answers within the connect
+/// timeout
+pub async fn connect(addr: &Address, config: &Config) -> Result<TcpStream, Failure> {
+    let mut last_error = None;
+    for addr in resolve(addr, &config.acl).await? {
+        match time::timeout(config.connect_timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = Some(e),
+            Err(_) => {
+                last_error = Some(io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))
+            }
+        }
+    }
+    Err(Failure::Connect(last_error.unwrap()))
+}
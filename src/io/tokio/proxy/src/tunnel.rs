@@ -0,0 +1,146 @@
+use std::io;
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
+use mini_http::{Request, Response};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use crate::config::Config;
+use crate::http::error;
+use crate::target::{self, Address, Failure};
+
+/// Answers an HTTP `CONNECT host:port` by connecting to the destination and tunneling the
+/// connection to it, once the client has the `200`
+pub async fn serve(
+    mut inbound: TcpStream,
+    client: SocketAddr,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut buf = BytesMut::with_capacity(4 * 1024);
+    let req = match time::timeout(config.timeout, read_request(&mut inbound, &mut buf)).await {
+        Ok(Ok(Some(req))) => req,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Err(mini_http::Error::Io(e))) => return Err(e.into()),
+        Ok(Err(e)) => {
+            respond(&mut inbound, e.response()).await?;
+            return Err(e.into());
+        }
+        Err(_) => return Err("timed out waiting for a request".into()),
+    };
+
+    let refused = if req.method() != "CONNECT" {
+        let mut response = error(405, "Method Not Allowed", "only CONNECT is supported");
+        response.header("Allow", "CONNECT");
+        Some(response)
+    } else if !authorized(&req, config) {
+        let mut response = error(
+            407,
+            "Proxy Authentication Required",
+            "proxy credentials required",
+        );
+        response.header("Proxy-Authenticate", "Basic realm=\"proxy\"");
+        Some(response)
+    } else {
+        None
+    };
+    let addr = match (refused, parse_authority(req.path())) {
+        (None, Some(addr)) => addr,
+        (refused, _) => {
+            let response =
+                refused.unwrap_or_else(|| error(400, "Bad Request", "expected CONNECT host:port"));
+            respond(&mut inbound, response).await?;
+            return Ok(());
+        }
+    };
+
+    let mut outbound = match target::connect(&addr, config).await {
+        Ok(outbound) => outbound,
+        Err(e) => {
+            let response = match &e {
+                Failure::Denied => error(403, "Forbidden", "destination not permitted"),
+                Failure::Connect(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    error(504, "Gateway Timeout", "the destination timed out")
+                }
+                _ => error(502, "Bad Gateway", "the destination could not be reached"),
+            };
+            respond(&mut inbound, response).await?;
+            return Err(format!("{} to {}", e, addr).into());
+        }
+    };
+    inbound
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .await?;
+    // what the client sent on without waiting for the 200 belongs to the tunnel
+    outbound.write_all(&buf).await?;
+    crate::relay(inbound, outbound, client, &addr.to_string()).await?;
+    Ok(())
+}
+
+/// Reads until a request head, leaving what follows it in `buf`, or `None` if the client closed
+/// the connection first
+async fn read_request(
+    inbound: &mut TcpStream,
+    buf: &mut BytesMut,
+) -> Result<Option<Request>, mini_http::Error> {
+    loop {
+        if let Some(req) = Request::decode(buf)? {
+            return Ok(Some(req));
+        }
+        if inbound.read_buf(buf).await? == 0 {
+            return Ok(None);
+        }
+    }
+}
+
+fn authorized(req: &Request, config: &Config) -> bool {
+    let (user, password) = match &config.auth {
+        Some(auth) => auth,
+        None => return true,
+    };
+    let credentials = req
+        .header("Proxy-Authorization")
+        .and_then(|value| std::str::from_utf8(value).ok())
+        .and_then(|value| value.trim().strip_prefix("Basic "))
+        .and_then(|token| base64::decode(token.trim()).ok());
+    credentials.as_deref() == Some(format!("{}:{}", user, password).as_bytes())
+}
+
+/// Parses the `host:port` a `CONNECT` names, with IPv6 addresses in brackets
+fn parse_authority(authority: &str) -> Option<Address> {
+    if let Ok(addr) = authority.parse() {
+        return Some(Address::Ip(addr));
+    }
+    let (host, port) = authority.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    if host.is_empty() || host.contains(['[', ']', ':']) {
+        return None;
+    }
+    Some(Address::Domain(host.to_string(), port))
+}
+
+async fn respond(inbound: &mut TcpStream, mut response: Response) -> io::Result<()> {
+    let mut buf = BytesMut::new();
+    response.keep_alive(false).encode(&mut buf);
+    inbound.write_all(&buf).await
+}
+
+#[test]
+fn parse_authorities() {
+    assert_eq!(
+        parse_authority("example.com:443"),
+        Some(Address::Domain("example.com".to_string(), 443))
+    );
+    assert_eq!(
+        parse_authority("127.0.0.1:8080"),
+        Some(Address::Ip("127.0.0.1:8080".parse().unwrap()))
+    );
+    assert_eq!(
+        parse_authority("[::1]:443"),
+        Some(Address::Ip("[::1]:443".parse().unwrap()))
+    );
+    assert_eq!(parse_authority("example.com"), None);
+    assert_eq!(parse_authority("::1:443"), None);
+    assert_eq!(parse_authority("/index.html"), None);
+}
@@ -1,27 +1,64 @@
+mod wal;
+
 use futures::SinkExt;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::env;
 use std::error::Error;
+use std::process;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::stream::StreamExt;
+use tokio::task;
+use tokio::time;
 use tokio_util::codec::{Framed, LinesCodec};
 
+use crate::wal::Log;
+
+const USAGE: &str = "usage: tinydb [--json] [--compact-interval <secs>] [addr] [log]
+
+Listens on 127.0.0.1:8080 and keeps the data in the log at tinydb.log by default.
+
+commands, one per line, where keys and values are either bare words or quoted with `\"`, with
+`\\\"`, `\\\\`, `\\n`, `\\r`, `\\t`, `\\0` and `\\u{...}` escapes:
+    GET <key>
+    SET <key> <value>         a bare value runs to the end of the line
+    DEL <key>
+    INCR <key> [<amount>]     add 1 or the amount to an integer value, missing keys being 0
+    LIST [<prefix>]           the keys starting with the prefix, and their values
+
+options:
+    --json                    answer with a JSON object per line
+    --compact-interval <secs> check this often whether the log needs compacting, 60 by default";
+
 /// The in-memory database shared amongst all clients
 ///
 /// This database will be shared via `Arc`, so to mutate
-/// the internal map we're going to use a `Mutex` for interior mutability
+/// the internal state we're going to use a `Mutex` for interior mutability
 struct Database {
-    map: Mutex<HashMap<String, String>>,
+    state: Mutex<State>,
+}
+
+/// The map along with the log of its changes, under one lock such that changes are logged in
+/// the order they are applied
+struct State {
+    map: HashMap<String, String>,
+    log: Log,
 }
 
 /// Possible requests our clients can send us
+#[derive(Debug, PartialEq)]
 enum Request {
     Get { key: String },
     Set { key: String, value: String },
+    Del { key: String },
+    Incr { key: String, by: i64 },
+    List { prefix: String },
 }
 
 /// Responses to the `Request` command above
+#[derive(Debug, PartialEq)]
 enum Response {
     Value {
         key: String,
@@ -32,131 +69,587 @@ enum Response {
         value: String,
         previous: Option<String>,
     },
+    Deleted {
+        key: String,
+        previous: Option<String>,
+    },
+    List {
+        entries: Vec<(String, String)>,
+    },
     Error {
         msg: String,
     },
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let addr = env::args()
-                .nth(1)
-                .unwrap_or_else(||"127.0.0.1:8080".to_string());
+/// How responses are written
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// For people, as `key = value`
+    Text,
+    /// A JSON object per line, for programs
+    Json,
+}
 
-    let mut listener = TcpListener::bind(&addr).await?;
+struct Config {
+    addr: String,
+    log: String,
+    format: Format,
+    compact_interval: Duration,
+}
 
-    println!("Listening on: {}",addr);
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let config = match parse_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
 
-    let mut initial_db = HashMap::new();
-    initial_db.insert("foo".to_string(), "bar".to_string());
+    let (log, map) = Log::open(&config.log)?;
+    println!("Loaded {} keys from {}", map.len(), config.log);
     let db = Arc::new(Database {
-        map: Mutex::new(initial_db),
+        state: Mutex::new(State { map, log }),
     });
 
+    let mut listener = TcpListener::bind(&config.addr).await?;
+
+    println!("Listening on: {}", config.addr);
+
+    tokio::spawn(compact(db.clone(), config.compact_interval));
+
+    let format = config.format;
     loop {
         match listener.accept().await {
-            Ok((socket,_)) => {
+            Ok((socket, _)) => {
                 let db = db.clone();
                 tokio::spawn(async move {
                     let mut lines = Framed::new(socket, LinesCodec::new());
 
                     while let Some(result) = lines.next().await {
-                        match result { 
+                        match result {
                             Ok(line) => {
-                                let response = handle_request(&line, &db);
-                                let response = response.serialize();
+                                // on a blocking thread, as changes wait for the log to be
+                                // synced to disk, and any request may wait on the lock meanwhile
+                                let db = db.clone();
+                                let handled =
+                                    task::spawn_blocking(move || handle_request(&line, &db));
+                                let response = match handled.await {
+                                    Ok(response) => response,
+                                    Err(e) => Response::Error {
+                                        msg: format!("request failed: {}", e),
+                                    },
+                                };
+                                let response = response.serialize(format);
                                 if let Err(e) = lines.send(response.as_str()).await {
-                                    println!("error on sending response; error = {:?}",e);
+                                    println!("error on sending response; error = {:?}", e);
                                 }
                             }
                             Err(e) => {
-                                println!("error on decoding from socket, error = {:?}",e);
+                                println!("error on decoding from socket, error = {:?}", e);
                             }
                         }
                     }
                 });
             }
-            Err(e) => println!("error accepting socket; error = {:?}",e),
+            Err(e) => println!("error accepting socket; error = {:?}", e),
+        }
+    }
+}
+
+/// Rewrites the log every `interval` once enough of it has been overwritten. The map is copied
+/// under the lock, and written out on a blocking thread without it, such that requests go on
+/// meanwhile. Only putting the new log in place takes the lock again.
+async fn compact(db: Arc<Database>, interval: Duration) {
+    let mut ticks = time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let mut compaction = {
+            let mut state = db.state.lock().unwrap();
+            let State { map, log } = &mut *state;
+            if !log.is_stale(map.len()) {
+                continue;
+            }
+            log.start_compaction(map)
+        };
+        let db = db.clone();
+        let compacted = task::spawn_blocking(move || {
+            let written = compaction.write();
+            let log = &mut db.state.lock().unwrap().log;
+            match written {
+                Ok(()) => log.finish_compaction(compaction),
+                Err(e) => {
+                    log.abort_compaction();
+                    Err(e)
+                }
+            }
+        });
+        match compacted.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => println!("error compacting the log; error = {:?}", e),
+            Err(e) => println!("compacting the log failed; error = {:?}", e),
         }
     }
 }
 
 fn handle_request(line: &str, db: &Arc<Database>) -> Response {
-    let request = match Request::parse(&line) {
+    let request = match Request::parse(line) {
         Ok(req) => req,
-        Err(e) => return Response::Error{ msg: e},
+        Err(e) => return Response::Error { msg: e },
     };
 
-    let mut db = db.map.lock().unwrap();
+    let mut state = db.state.lock().unwrap();
+    let State { map, log } = &mut *state;
     match request {
-        Request::Get {key} => match db.get(&key) {
+        Request::Get { key } => match map.get(&key) {
             Some(value) => Response::Value {
                 key,
                 value: value.clone(),
             },
             None => Response::Error {
-                msg: format!("no key {}",key),
+                msg: format!("no key {}", display(&key)),
             },
         },
-        Request::Set {key, value} => {
-            let previous = db.insert(key.clone(), value.clone());
+        Request::Set { key, value } => {
+            if let Err(e) = log.set(&key, &value) {
+                return not_logged(e);
+            }
+            let previous = map.insert(key.clone(), value.clone());
             Response::Set {
                 key,
                 value,
                 previous,
             }
         }
+        Request::Del { key } => {
+            if map.contains_key(&key) {
+                if let Err(e) = log.del(&key) {
+                    return not_logged(e);
+                }
+            }
+            let previous = map.remove(&key);
+            Response::Deleted { key, previous }
+        }
+        Request::Incr { key, by } => {
+            let current = match map.get(&key).map(|value| value.parse::<i64>()) {
+                Some(Ok(current)) => current,
+                Some(Err(_)) => {
+                    return Response::Error {
+                        msg: format!("value of {} is not an integer", display(&key)),
+                    }
+                }
+                None => 0,
+            };
+            let value = match current.checked_add(by) {
+                Some(value) => value.to_string(),
+                None => {
+                    return Response::Error {
+                        msg: format!("incrementing {} would overflow", display(&key)),
+                    }
+                }
+            };
+            if let Err(e) = log.set(&key, &value) {
+                return not_logged(e);
+            }
+            map.insert(key.clone(), value.clone());
+            Response::Value { key, value }
+        }
+        Request::List { prefix } => {
+            let mut entries: Vec<(String, String)> = map
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            entries.sort();
+            Response::List { entries }
+        }
+    }
+}
+
+fn not_logged(e: std::io::Error) -> Response {
+    Response::Error {
+        msg: format!("failed to write the log: {}", e),
     }
 }
 
 impl Request {
     fn parse(input: &str) -> Result<Request, String> {
-        let mut parts = input.splitn(3, ' ');
-        match parts.next() {
-            Some("GET") => {
-                let key = parts.next().ok_or("GET must be followed by a key")?;
-                if parts.next().is_some() {
+        let input = input.trim_start();
+        let (cmd, rest) = match input.find(char::is_whitespace) {
+            Some(i) => (&input[..i], &input[i..]),
+            None => (input, ""),
+        };
+        match cmd {
+            "GET" => {
+                let (key, rest) = word(rest)?.ok_or("GET must be followed by a key")?;
+                if !rest.trim().is_empty() {
                     return Err("GET's key must not be followed by anything".into());
                 }
-                Ok(Request::Get {
-                    key: key.to_string(),
-                })
+                Ok(Request::Get { key })
             }
-            Some("SET") => {
-                let key = match parts.next() {
+            "SET" => {
+                let (key, rest) = match word(rest)? {
                     Some(key) => key,
                     None => return Err("SET must be followed by a key".into()),
                 };
-                let value = match parts.next() {
-                    Some(value) => value,
-                    None => return Err("SET needs a value".into()),
+                // quoted, or else the rest of the line as it is
+                let rest = rest.trim_start();
+                let value = if rest.starts_with('"') {
+                    let (value, rest) = word(rest)?.unwrap_or_default();
+                    if !rest.trim().is_empty() {
+                        return Err("SET's value must not be followed by anything".into());
+                    }
+                    value
+                } else if rest.is_empty() {
+                    return Err("SET needs a value".into());
+                } else {
+                    rest.to_string()
+                };
+                Ok(Request::Set { key, value })
+            }
+            "DEL" => {
+                let (key, rest) = word(rest)?.ok_or("DEL must be followed by a key")?;
+                if !rest.trim().is_empty() {
+                    return Err("DEL's key must not be followed by anything".into());
+                }
+                Ok(Request::Del { key })
+            }
+            "INCR" => {
+                let (key, rest) = word(rest)?.ok_or("INCR must be followed by a key")?;
+                let by = match word(rest)? {
+                    Some((by, rest)) if rest.trim().is_empty() => by
+                        .parse()
+                        .map_err(|_| format!("invalid amount: {}", display(&by)))?,
+                    Some(_) => return Err("INCR's amount must not be followed by anything".into()),
+                    None => 1,
                 };
-                Ok(Request::Set {
-                    key: key.to_string(),
-                    value: value.to_string(),
-                })
+                Ok(Request::Incr { key, by })
+            }
+            "LIST" => {
+                let (prefix, rest) = word(rest)?.unwrap_or_default();
+                if !rest.trim().is_empty() {
+                    return Err("LIST's prefix must not be followed by anything".into());
+                }
+                Ok(Request::List { prefix })
             }
-            Some(cmd) => Err(format!("unknown command: {}",cmd)),
-            None => Err("empty input".into()),
+            "" => Err("empty input".into()),
+            cmd => Err(format!("unknown command: {}", cmd)),
         }
     }
 }
 
+/// Splits the next word off the input, either bare up to whitespace or quoted
+fn word(input: &str) -> Result<Option<(String, &str)>, String> {
+    let input = input.trim_start();
+    let mut rest = match input.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None if input.is_empty() => return Ok(None),
+        None => {
+            let end = input.find(char::is_whitespace).unwrap_or(input.len());
+            return Ok(Some((input[..end].to_string(), &input[end..])));
+        }
+    };
+
+    let mut word = String::new();
+    loop {
+        let mut chars = rest.chars();
+        let c = chars.next().ok_or("unterminated quoted string")?;
+        rest = chars.as_str();
+        match c {
+            '"' if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+                return Ok(Some((word, rest)))
+            }
+            '"' => return Err("a quoted string must be followed by a space".into()),
+            '\\' => {
+                let escape = chars.next().ok_or("unterminated quoted string")?;
+                rest = chars.as_str();
+                word.push(match escape {
+                    '"' => '"',
+                    '\\' => '\\',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    '0' => '\0',
+                    'u' => {
+                        let (hex, after) = rest
+                            .strip_prefix('{')
+                            .and_then(|rest| rest.split_once('}'))
+                            .ok_or("expected \\u{...}")?;
+                        rest = after;
+                        u32::from_str_radix(hex, 16)
+                            .ok()
+                            .and_then(std::char::from_u32)
+                            .ok_or_else(|| format!("invalid escape: \\u{{{}}}", hex))?
+                    }
+                    c => return Err(format!("unknown escape: \\{}", c)),
+                });
+            }
+            c => word.push(c),
+        }
+    }
+}
+
+/// Quotes a key or value such that `word` reads it back as it is
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\0' => quoted.push_str("\\0"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A key or value as shown in text responses, quoted only if it would be unclear otherwise
+fn display(s: &str) -> Cow<'_, str> {
+    let unclear =
+        s.is_empty() || s.starts_with('"') || s.trim() != s || s.contains(char::is_control);
+    if unclear {
+        Cow::Owned(quote(s))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// A string as a JSON string
+fn json(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_option(s: &Option<String>) -> String {
+    match s {
+        Some(s) => json(s),
+        None => "null".to_string(),
+    }
+}
 
 impl Response {
-    fn serialize(&self) -> String {
+    fn serialize(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.serialize_text(),
+            Format::Json => self.serialize_json(),
+        }
+    }
+
+    fn serialize_text(&self) -> String {
         match *self {
-            Response::Value {
-                ref key, ref value
-            } => format!("{} = {}",key,value),
+            Response::Value { ref key, ref value } => {
+                format!("{} = {}", display(key), display(value))
+            }
             Response::Set {
                 ref key,
                 ref value,
                 ref previous,
-            } => format!("set {} = `{}`, previous: {:?}",key, value,previous),
-            Response::Error {
-                ref msg
-            } => format!("error: {}",msg),
+            } => format!(
+                "set {} = {}, previous: {:?}",
+                display(key),
+                display(value),
+                previous
+            ),
+            Response::Deleted {
+                ref key,
+                ref previous,
+            } => format!("deleted {}, previous: {:?}", display(key), previous),
+            Response::List { ref entries } if entries.is_empty() => "no keys".to_string(),
+            Response::List { ref entries } => entries
+                .iter()
+                .map(|(key, value)| format!("{} = {}", display(key), display(value)))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Response::Error { ref msg } => format!("error: {}", msg),
         }
     }
-}
\ No newline at end of file
+
+    fn serialize_json(&self) -> String {
+        match *self {
+            Response::Value { ref key, ref value } => {
+                format!(r#"{{"key":{},"value":{}}}"#, json(key), json(value))
+            }
+            Response::Set {
+                ref key,
+                ref value,
+                ref previous,
+            } => format!(
+                r#"{{"key":{},"value":{},"previous":{}}}"#,
+                json(key),
+                json(value),
+                json_option(previous)
+            ),
+            Response::Deleted {
+                ref key,
+                ref previous,
+            } => format!(
+                r#"{{"key":{},"deleted":{},"previous":{}}}"#,
+                json(key),
+                previous.is_some(),
+                json_option(previous)
+            ),
+            Response::List { ref entries } => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        format!(r#"{{"key":{},"value":{}}}"#, json(key), json(value))
+                    })
+                    .collect();
+                format!(r#"{{"entries":[{}]}}"#, entries.join(","))
+            }
+            Response::Error { ref msg } => format!(r#"{{"error":{}}}"#, json(msg)),
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+    let mut config = Config {
+        addr: "127.0.0.1:8080".to_string(),
+        log: "tinydb.log".to_string(),
+        format: Format::Text,
+        compact_interval: Duration::from_secs(60),
+    };
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => config.format = Format::Json,
+            "--compact-interval" => {
+                config.compact_interval = args
+                    .next()
+                    .and_then(|secs| secs.parse().ok())
+                    .filter(|&secs| secs > 0)
+                    .map(Duration::from_secs)
+                    .ok_or_else(|| format!("invalid value for `{}`\n\n{}", arg, USAGE))?;
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => {
+                return Err(format!("unknown option `{}`\n\n{}", arg, USAGE))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    if let Some(addr) = positional.next() {
+        config.addr = addr;
+    }
+    if let Some(log) = positional.next() {
+        config.log = log;
+    }
+    if let Some(extra) = positional.next() {
+        return Err(format!("unexpected argument `{}`\n\n{}", extra, USAGE));
+    }
+    Ok(config)
+}
+
+#[test]
+fn parse_requests() {
+    let set = |key: &str, value: &str| {
+        Ok(Request::Set {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    };
+    assert_eq!(
+        Request::parse("SET foo hello world"),
+        set("foo", "hello world")
+    );
+    assert_eq!(
+        Request::parse(r#"SET "a key" "line\none \"two\"\t\u{1F600}""#),
+        set("a key", "line\none \"two\"\t\u{1F600}")
+    );
+    assert_eq!(Request::parse(r#"SET foo """#), set("foo", ""));
+    assert_eq!(
+        Request::parse("INCR hits"),
+        Ok(Request::Incr {
+            key: "hits".to_string(),
+            by: 1
+        })
+    );
+    assert_eq!(
+        Request::parse("INCR hits -5"),
+        Ok(Request::Incr {
+            key: "hits".to_string(),
+            by: -5
+        })
+    );
+    assert_eq!(
+        Request::parse("LIST"),
+        Ok(Request::List {
+            prefix: String::new()
+        })
+    );
+    assert_eq!(
+        Request::parse(r#"DEL "a key""#),
+        Ok(Request::Del {
+            key: "a key".to_string()
+        })
+    );
+
+    assert!(Request::parse("SET foo").is_err());
+    assert!(Request::parse(r#"SET foo "bar"baz"#).is_err());
+    assert!(Request::parse(r#"SET foo "bar" baz"#).is_err());
+    assert!(Request::parse(r#"SET foo "unterminated"#).is_err());
+    assert!(Request::parse(r#"SET foo "\q""#).is_err());
+    assert!(Request::parse("INCR hits many").is_err());
+    assert!(Request::parse("GET a b").is_err());
+    assert!(Request::parse("").is_err());
+
+    for s in &["", "plain", " padded ", "\"quoted\"", "a\\b\r\n\0\u{7}é"] {
+        let line = format!("SET k {}", quote(s));
+        assert_eq!(Request::parse(&line), set("k", s));
+    }
+}
+
+#[test]
+fn serialize_responses() {
+    let set = Response::Set {
+        key: "foo".to_string(),
+        value: "two\nlines".to_string(),
+        previous: Some("bar".to_string()),
+    };
+    assert_eq!(
+        set.serialize(Format::Text),
+        r#"set foo = "two\nlines", previous: Some("bar")"#
+    );
+    assert_eq!(
+        set.serialize(Format::Json),
+        r#"{"key":"foo","value":"two\nlines","previous":"bar"}"#
+    );
+
+    let list = Response::List {
+        entries: vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "say \"hi\"".to_string()),
+        ],
+    };
+    assert_eq!(list.serialize(Format::Text), r#"a = 1, b = say "hi""#);
+    assert_eq!(
+        list.serialize(Format::Json),
+        r#"{"entries":[{"key":"a","value":"1"},{"key":"b","value":"say \"hi\""}]}"#
+    );
+
+    let error = Response::Error {
+        msg: "no key foo".to_string(),
+    };
+    assert_eq!(error.serialize(Format::Json), r#"{"error":"no key foo"}"#);
+}
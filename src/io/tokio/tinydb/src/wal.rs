@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{quote, Request};
+
+/// The write-ahead log the database is rebuilt from on start
+///
+/// Each change is appended as the request that makes it, one per line with the key and value
+/// quoted, and synced to the disk before it is applied to the map, such that an acknowledged
+/// change survives a crash. As the log also holds changes since overwritten, a `Compaction`
+/// rewrites it with one `SET` per key.
+pub struct Log {
+    path: PathBuf,
+    file: File,
+    /// Bytes of whole records in the file, which it is cut back to if a write fails midway
+    len: u64,
+    /// Records in the file, including overwritten ones
+    records: usize,
+    /// The records appended since a compaction took its snapshot, which the compacted log
+    /// needs too, or `None` when not compacting
+    pending: Option<Vec<String>>,
+}
+
+/// A compacted log being written beside the log, from a snapshot of the map, such that the
+/// log takes appends meanwhile
+pub struct Compaction {
+    tmp: PathBuf,
+    map: HashMap<String, String>,
+    len: u64,
+}
+
+impl Log {
+    /// Opens the log at `path`, creating it if missing, and returns it along with the map its
+    /// records replay to. A last record cut short by a crash is dropped.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<(Log, HashMap<String, String>)> {
+        let path = path.into();
+        let mut map = HashMap::new();
+        let (mut len, mut records) = (0, 0);
+        match File::open(&path) {
+            Ok(file) => {
+                let mut reader = BufReader::new(file);
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    let n = reader.read_line(&mut line)?;
+                    if n == 0 {
+                        break;
+                    }
+                    if !line.ends_with('\n') {
+                        println!("dropping a partial record at the end of {}", path.display());
+                        break;
+                    }
+                    match Request::parse(&line[..line.len() - 1]) {
+                        Ok(Request::Set { key, value }) => {
+                            map.insert(key, value);
+                        }
+                        Ok(Request::Del { key }) => {
+                            map.remove(&key);
+                        }
+                        _ => {
+                            let msg = format!(
+                                "invalid record on line {} of {}",
+                                records + 1,
+                                path.display()
+                            );
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                        }
+                    }
+                    len += n as u64;
+                    records += 1;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.set_len(len)?;
+        let log = Log {
+            path,
+            file,
+            len,
+            records,
+            pending: None,
+        };
+        Ok((log, map))
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        self.append(&format!("SET {} {}\n", quote(key), quote(value)))
+    }
+
+    pub fn del(&mut self, key: &str) -> io::Result<()> {
+        self.append(&format!("DEL {}\n", quote(key)))
+    }
+
+    fn append(&mut self, record: &str) -> io::Result<()> {
+        let written = self.file.write_all(record.as_bytes());
+        if let Err(e) = written.and_then(|_| self.file.sync_data()) {
+            // a partial record would run into the next one, and one not known to be on the
+            // disk is not acknowledged
+            let _ = self.file.set_len(self.len);
+            return Err(e);
+        }
+        self.len += record.len() as u64;
+        self.records += 1;
+        if let Some(pending) = &mut self.pending {
+            pending.push(record.to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether at least half the records have been overwritten since
+    pub fn is_stale(&self, keys: usize) -> bool {
+        self.records > keys && self.records >= keys * 2
+    }
+
+    /// Starts a compaction to a log holding a `SET` for each key of `map`. The records appended
+    /// until it finishes are kept to be added to it.
+    pub fn start_compaction(&mut self, map: &HashMap<String, String>) -> Compaction {
+        let mut tmp = OsString::from(&self.path);
+        tmp.push(".tmp");
+        self.pending = Some(Vec::new());
+        Compaction {
+            tmp: PathBuf::from(tmp),
+            map: map.clone(),
+            len: 0,
+        }
+    }
+
+    /// Replaces the log with a written compaction, once the records appended since it started
+    /// are added to it. It is renamed over the log, such that a crash leaves one or the other
+    /// whole.
+    pub fn finish_compaction(&mut self, compaction: Compaction) -> io::Result<()> {
+        let pending = self.pending.take().unwrap_or_default();
+        // opened before the rename, such that the log is never left without a file to append to
+        let mut file = OpenOptions::new().append(true).open(&compaction.tmp)?;
+        let mut len = compaction.len;
+        for record in &pending {
+            file.write_all(record.as_bytes())?;
+            len += record.len() as u64;
+        }
+        file.sync_data()?;
+        fs::rename(&compaction.tmp, &self.path)?;
+
+        // the log is the compacted file from here on, even if syncing the rename fails, as the
+        // old file is no longer at the path
+        self.file = file;
+        self.len = len;
+        self.records = compaction.map.len() + pending.len();
+        sync_dir(&self.path)
+    }
+
+    /// Gives up on a compaction, whose file is left to be overwritten by the next
+    pub fn abort_compaction(&mut self) {
+        self.pending = None;
+    }
+}
+
+impl Compaction {
+    /// Writes the compacted log, which takes blocking I/O but not the log
+    pub fn write(&mut self) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.tmp)?);
+        let mut len = 0;
+        for (key, value) in &self.map {
+            let record = format!("SET {} {}\n", quote(key), quote(value));
+            out.write_all(record.as_bytes())?;
+            len += record.len() as u64;
+        }
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        self.len = len;
+        Ok(())
+    }
+}
+
+/// Syncs the directory of a file, such that a rename to it survives a crash
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[test]
+fn replay_and_compact() {
+    let path = std::env::temp_dir().join(format!("tinydb-{}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let (mut log, map) = Log::open(&path).unwrap();
+    assert!(map.is_empty());
+    log.set("foo", "bar").unwrap();
+    log.set("multi line", "a\n\"b\"").unwrap();
+    log.set("foo", "baz").unwrap();
+    log.del("gone").unwrap();
+    drop(log);
+    // as left by a crash midway through a write
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"SET \"half").unwrap();
+
+    let (mut log, map) = Log::open(&path).unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["foo"], "baz");
+    assert_eq!(map["multi line"], "a\n\"b\"");
+    assert!(log.is_stale(map.len()));
+
+    let mut compaction = log.start_compaction(&map);
+    compaction.write().unwrap();
+    // made after the snapshot, so carried over to the compacted log
+    log.set("late", "1").unwrap();
+    log.finish_compaction(compaction).unwrap();
+    assert!(!log.is_stale(map.len() + 1));
+    log.del("foo").unwrap();
+    drop(log);
+    let (mut log, replayed) = Log::open(&path).unwrap();
+    assert_eq!(replayed.len(), 2);
+    assert_eq!(replayed["multi line"], "a\n\"b\"");
+    assert_eq!(replayed["late"], "1");
+
+    // appends no longer kept once a compaction is given up
+    let _ = log.start_compaction(&replayed);
+    log.abort_compaction();
+    log.set("after", "2").unwrap();
+    assert!(log.pending.is_none());
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// A byte that does not fit the grammar, along with what was expected instead
+    Expected(&'static str),
+    InvalidNumber,
+    InvalidEscape,
+    /// A `\u` escape of a lone surrogate
+    InvalidUnicodeEscape,
+    /// An unescaped control character in a string
+    ControlCharacter,
+    InvalidUtf8,
+    /// Anything but whitespace after the document
+    TrailingCharacters,
+    /// The input ended before the document did
+    UnexpectedEnd,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Expected(expected) => write!(f, "expected {}", expected),
+            ErrorKind::InvalidNumber => f.write_str("invalid number"),
+            ErrorKind::InvalidEscape => f.write_str("invalid escape"),
+            ErrorKind::InvalidUnicodeEscape => f.write_str("invalid unicode escape"),
+            ErrorKind::ControlCharacter => f.write_str("control character in string"),
+            ErrorKind::InvalidUtf8 => f.write_str("invalid UTF-8"),
+            ErrorKind::TrailingCharacters => f.write_str("trailing characters"),
+            ErrorKind::UnexpectedEnd => f.write_str("unexpected end of input"),
+        }
+    }
+}
+
+/// A syntax error, located by its byte offset from the start of the stream
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub position: u64,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.kind, self.position)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,83 @@
+use std::borrow::Cow;
+
+/// One step through a JSON document
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event<'a> {
+    StartObject,
+    EndObject,
+    StartArray,
+    EndArray,
+    /// An object key, always followed by the events of its value
+    Key(JsonStr<'a>),
+    Value(Scalar<'a>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scalar<'a> {
+    Null,
+    Bool(bool),
+    /// The number's text, which is valid JSON but may not fit any Rust number type
+    Number(&'a str),
+    String(JsonStr<'a>),
+}
+
+/// A string as it appears in the input, between the quotes and with escapes intact. The
+/// tokenizer has already checked that the escapes are valid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JsonStr<'a> {
+    raw: &'a str,
+    escaped: bool,
+}
+
+impl<'a> JsonStr<'a> {
+    pub(crate) fn new(raw: &'a str, escaped: bool) -> Self {
+        JsonStr { raw, escaped }
+    }
+
+    pub fn raw(&self) -> &'a str {
+        self.raw
+    }
+
+    /// Returns the string with escapes replaced, which only allocates if there are any
+    pub fn unescape(&self) -> Cow<'a, str> {
+        if !self.escaped {
+            return Cow::Borrowed(self.raw);
+        }
+        let mut unescaped = String::with_capacity(self.raw.len());
+        let mut chars = self.raw.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            let c = match chars.next() {
+                Some('b') => '\u{08}',
+                Some('f') => '\u{0C}',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('u') => {
+                    let high = hex4(&mut chars);
+                    let code = if (0xD800..=0xDBFF).contains(&high) {
+                        // skip the `\u` of the low surrogate
+                        chars.nth(1);
+                        0x10000 + ((high - 0xD800) << 10 | (hex4(&mut chars) - 0xDC00))
+                    } else {
+                        high
+                    };
+                    std::char::from_u32(code).unwrap_or(std::char::REPLACEMENT_CHARACTER)
+                }
+                Some(c) => c,
+                None => break,
+            };
+            unescaped.push(c);
+        }
+        Cow::Owned(unescaped)
+    }
+}
+
+fn hex4(chars: &mut std::str::Chars<'_>) -> u32 {
+    chars
+        .take(4)
+        .fold(0, |code, c| code << 4 | c.to_digit(16).unwrap_or(0))
+}
@@ -0,0 +1,14 @@
+//! A streaming JSON tokenizer for input that arrives in chunks, e.g. from a socket.
+//!
+//! The `Tokenizer` turns bytes into `Event`s one at a time, borrowing strings and numbers from
+//! the input instead of copying them. When a chunk ends in the middle of a token it returns
+//! `nom::Err::Incomplete`, and picks up from the same token once more bytes are appended, such
+//! that only the unfinished token has to be kept around between reads.
+
+mod error;
+mod event;
+mod tokenizer;
+
+pub use error::{Error, ErrorKind};
+pub use event::{Event, JsonStr, Scalar};
+pub use tokenizer::Tokenizer;
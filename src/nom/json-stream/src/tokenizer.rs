@@ -0,0 +1,473 @@
+use nom::{
+    bytes::streaming::{tag, take, take_till, take_while},
+    combinator::value,
+    error::ParseError,
+    Err, IResult, Needed,
+};
+use std::str;
+
+use crate::error::{Error, ErrorKind};
+use crate::event::{Event, JsonStr, Scalar};
+
+/// What the tokenizer expects next
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Value,
+    /// A value or the end of an array, right after `[`
+    FirstValue,
+    /// A key or the end of an object, right after `{`
+    FirstKey,
+    Key,
+    Colon,
+    /// A comma or the end of the innermost array or object
+    AfterValue,
+    /// Only whitespace, after the end of the document
+    Done,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Container {
+    Object,
+    Array,
+}
+
+/// Splits a single JSON document into events.
+///
+/// Each call to `next_event` must pass the input starting where the previous successful call
+/// left off. On `Incomplete` nothing is consumed, so the caller appends the next chunk to the
+/// remaining bytes and calls again. Once the stream ends, `finish` checks that the document is
+/// complete, and returns the last event if the document is a bare number.
+///
+/// ```
+/// use json_stream::{Event, Scalar, Tokenizer};
+///
+/// let mut tokenizer = Tokenizer::new();
+/// let mut buffer = Vec::new();
+/// let mut events = Vec::new();
+/// for chunk in &[&b"{\"ids\": [1, 2"[..], b"3]}"] {
+///     buffer.extend_from_slice(chunk);
+///     let mut input = &buffer[..];
+///     while let Ok((rest, event)) = tokenizer.next_event(input) {
+///         events.push(format!("{:?}", event));
+///         input = rest;
+///     }
+///     let consumed = buffer.len() - input.len();
+///     buffer.drain(..consumed);
+/// }
+/// assert_eq!(tokenizer.finish(&buffer), Ok(None));
+/// assert_eq!(events[4], format!("{:?}", Event::Value(Scalar::Number("23"))));
+/// ```
+#[derive(Debug)]
+pub struct Tokenizer {
+    state: State,
+    stack: Vec<Container>,
+    /// Bytes consumed so far, to locate errors
+    position: u64,
+}
+
+/// An error while parsing a chunk, located by the length of the input left at that point
+struct Fail {
+    kind: ErrorKind,
+    remaining: usize,
+}
+
+// only `tag` reports errors through this, when a literal is misspelled
+impl<'a> ParseError<&'a [u8]> for Fail {
+    fn from_error_kind(input: &'a [u8], _kind: nom::error::ErrorKind) -> Self {
+        Fail {
+            kind: ErrorKind::Expected("value"),
+            remaining: input.len(),
+        }
+    }
+
+    fn append(_input: &'a [u8], _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+fn fail<T>(kind: ErrorKind, input: &[u8]) -> IResult<&[u8], T, Fail> {
+    Err(Err::Error(Fail {
+        kind,
+        remaining: input.len(),
+    }))
+}
+
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\n' || b == b'\r'
+}
+
+impl Tokenizer {
+    pub fn new() -> Self {
+        Tokenizer {
+            state: State::Value,
+            stack: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Parses the next event from the start of `input`, returning the rest of the input with it
+    pub fn next_event<'a>(&mut self, input: &'a [u8]) -> IResult<&'a [u8], Event<'a>, Error> {
+        match self.parse(input, false) {
+            Ok((rest, event)) => {
+                self.position += (input.len() - rest.len()) as u64;
+                Ok((rest, event))
+            }
+            Err(e) => Err(e.map(|fail| self.error(input, fail))),
+        }
+    }
+
+    /// Ends the stream, given the input left over after the last `Incomplete`. Returns the final
+    /// event if the document is a number at the end of the input, which `next_event` can't tell
+    /// apart from a number that continues in the next chunk.
+    pub fn finish<'a>(&mut self, input: &'a [u8]) -> Result<Option<Event<'a>>, Error> {
+        let (rest, event) = match self.parse(input, true) {
+            Ok((rest, event)) => (rest, Some(event)),
+            Err(Err::Incomplete(_)) => (input, None),
+            Err(Err::Error(fail)) | Err(Err::Failure(fail)) => return Err(self.error(input, fail)),
+        };
+        self.position += (input.len() - rest.len()) as u64;
+        if self.state != State::Done {
+            return Err(Error {
+                kind: ErrorKind::UnexpectedEnd,
+                position: self.position + rest.len() as u64,
+            });
+        }
+        match rest.iter().position(|b| !is_whitespace(*b)) {
+            Some(i) => Err(Error {
+                kind: ErrorKind::TrailingCharacters,
+                position: self.position + i as u64,
+            }),
+            None => Ok(event),
+        }
+    }
+
+    /// Returns how deeply nested the tokenizer currently is in arrays and objects
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    fn error(&self, input: &[u8], fail: Fail) -> Error {
+        Error {
+            kind: fail.kind,
+            position: self.position + (input.len() - fail.remaining) as u64,
+        }
+    }
+
+    /// Skips whitespace and separators until the next event. The state is only updated once an
+    /// event is complete, such that an `Incomplete` input can be parsed again from the start.
+    fn parse<'a>(&mut self, input: &'a [u8], eof: bool) -> IResult<&'a [u8], Event<'a>, Fail> {
+        let mut state = self.state;
+        let mut input = input;
+        loop {
+            input = take_while(is_whitespace)(input)?.0;
+            let byte = input[0];
+            match state {
+                State::Colon if byte == b':' => state = State::Value,
+                State::Colon => return fail(ErrorKind::Expected("':'"), input),
+                State::AfterValue => match (self.stack.last(), byte) {
+                    (Some(Container::Array), b',') => state = State::Value,
+                    (Some(Container::Array), b']') => {
+                        return Ok(self.close(&input[1..], Event::EndArray))
+                    }
+                    (Some(Container::Array), _) => {
+                        return fail(ErrorKind::Expected("',' or ']'"), input)
+                    }
+                    (_, b',') => state = State::Key,
+                    (_, b'}') => return Ok(self.close(&input[1..], Event::EndObject)),
+                    (_, _) => return fail(ErrorKind::Expected("',' or '}'"), input),
+                },
+                State::Done => return fail(ErrorKind::TrailingCharacters, input),
+                State::FirstKey if byte == b'}' => {
+                    return Ok(self.close(&input[1..], Event::EndObject))
+                }
+                State::FirstKey | State::Key if byte == b'"' => {
+                    let (rest, key) = string(&input[1..])?;
+                    self.state = State::Colon;
+                    return Ok((rest, Event::Key(key)));
+                }
+                State::FirstKey | State::Key => {
+                    return fail(ErrorKind::Expected("string key"), input)
+                }
+                State::FirstValue if byte == b']' => {
+                    return Ok(self.close(&input[1..], Event::EndArray))
+                }
+                State::FirstValue | State::Value => return self.value(input, eof),
+            }
+            input = &input[1..];
+        }
+    }
+
+    fn value<'a>(&mut self, input: &'a [u8], eof: bool) -> IResult<&'a [u8], Event<'a>, Fail> {
+        let (rest, scalar) = match input[0] {
+            b'{' => return Ok(self.open(&input[1..], Container::Object)),
+            b'[' => return Ok(self.open(&input[1..], Container::Array)),
+            b'"' => {
+                let (rest, s) = string(&input[1..])?;
+                (rest, Scalar::String(s))
+            }
+            b'-' | b'0'..=b'9' => {
+                let (rest, n) = number(input, eof)?;
+                (rest, Scalar::Number(n))
+            }
+            b't' => value(Scalar::Bool(true), tag("true"))(input)?,
+            b'f' => value(Scalar::Bool(false), tag("false"))(input)?,
+            b'n' => value(Scalar::Null, tag("null"))(input)?,
+            _ => return fail(ErrorKind::Expected("value"), input),
+        };
+        self.state = self.after_value();
+        Ok((rest, Event::Value(scalar)))
+    }
+
+    fn open<'a>(&mut self, rest: &'a [u8], container: Container) -> (&'a [u8], Event<'a>) {
+        self.stack.push(container);
+        match container {
+            Container::Object => {
+                self.state = State::FirstKey;
+                (rest, Event::StartObject)
+            }
+            Container::Array => {
+                self.state = State::FirstValue;
+                (rest, Event::StartArray)
+            }
+        }
+    }
+
+    fn close<'a>(&mut self, rest: &'a [u8], event: Event<'a>) -> (&'a [u8], Event<'a>) {
+        self.stack.pop();
+        self.state = self.after_value();
+        (rest, event)
+    }
+
+    fn after_value(&self) -> State {
+        if self.stack.is_empty() {
+            State::Done
+        } else {
+            State::AfterValue
+        }
+    }
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Tokenizer::new()
+    }
+}
+
+/// Parses the rest of a string after its opening quote, checking escapes without replacing them
+fn string(input: &[u8]) -> IResult<&[u8], JsonStr<'_>, Fail> {
+    let mut rest = input;
+    let mut escaped = false;
+    loop {
+        rest = take_till(|b| b == b'"' || b == b'\\' || b < 0x20)(rest)?.0;
+        match rest[0] {
+            b'"' => break,
+            b'\\' => {
+                escaped = true;
+                rest = escape(&rest[1..])?.0;
+            }
+            _ => return fail(ErrorKind::ControlCharacter, rest),
+        }
+    }
+    let raw = &input[..input.len() - rest.len()];
+    match str::from_utf8(raw) {
+        Ok(raw) => Ok((&rest[1..], JsonStr::new(raw, escaped))),
+        Err(e) => fail(ErrorKind::InvalidUtf8, &input[e.valid_up_to()..]),
+    }
+}
+
+/// Checks the escape after a backslash. A high surrogate must be followed by the escape of a low
+/// surrogate, together making up a character outside the basic multilingual plane.
+fn escape(input: &[u8]) -> IResult<&[u8], (), Fail> {
+    let (rest, c) = take(1usize)(input)?;
+    match c[0] {
+        b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => Ok((rest, ())),
+        b'u' => match hex4(rest)? {
+            (rest, 0xD800..=0xDBFF) => {
+                let low = match tag::<_, _, Fail>("\\u")(rest) {
+                    Ok((low, _)) => low,
+                    Err(Err::Incomplete(needed)) => return Err(Err::Incomplete(needed)),
+                    Err(_) => return fail(ErrorKind::InvalidUnicodeEscape, rest),
+                };
+                match hex4(low)? {
+                    (rest, 0xDC00..=0xDFFF) => Ok((rest, ())),
+                    _ => fail(ErrorKind::InvalidUnicodeEscape, low),
+                }
+            }
+            (_, 0xDC00..=0xDFFF) => fail(ErrorKind::InvalidUnicodeEscape, rest),
+            (rest, _) => Ok((rest, ())),
+        },
+        _ => fail(ErrorKind::InvalidEscape, input),
+    }
+}
+
+fn hex4(input: &[u8]) -> IResult<&[u8], u16, Fail> {
+    let (rest, digits) = take(4usize)(input)?;
+    match str::from_utf8(digits)
+        .ok()
+        .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+    {
+        Some(code) if digits.iter().all(u8::is_ascii_hexdigit) => Ok((rest, code)),
+        _ => fail(ErrorKind::InvalidEscape, input),
+    }
+}
+
+/// Parses a number per the JSON grammar. nom's streaming number parsers can't be used, since a
+/// number running to the end of the input is only complete if the stream ends there too.
+fn number(input: &[u8], eof: bool) -> IResult<&[u8], &str, Fail> {
+    let digits = |from: usize| {
+        input[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    // digits are missing at `at`, which is fine only if they may still arrive
+    let truncated = |at: usize| {
+        if at == input.len() && !eof {
+            Err(Err::Incomplete(Needed::Unknown))
+        } else {
+            fail(ErrorKind::InvalidNumber, &input[at..])
+        }
+    };
+
+    let mut end = if input[0] == b'-' { 1 } else { 0 };
+    match input.get(end) {
+        Some(b'0') => end += 1,
+        Some(b'1'..=b'9') => end += digits(end),
+        _ => return truncated(end),
+    }
+    if input.get(end) == Some(&b'.') {
+        match digits(end + 1) {
+            0 => return truncated(end + 1),
+            n => end += 1 + n,
+        }
+    }
+    if let Some(b'e') | Some(b'E') = input.get(end) {
+        let mut exponent = end + 1;
+        if let Some(b'+') | Some(b'-') = input.get(exponent) {
+            exponent += 1;
+        }
+        match digits(exponent) {
+            0 => return truncated(exponent),
+            n => end = exponent + n,
+        }
+    }
+    if end == input.len() && !eof {
+        return Err(Err::Incomplete(Needed::Unknown));
+    }
+    // the number is all ASCII
+    let text = str::from_utf8(&input[..end]).unwrap();
+    Ok((&input[end..], text))
+}
+
+#[cfg(test)]
+fn events(chunks: &[&[u8]]) -> Result<Vec<String>, Error> {
+    let mut tokenizer = Tokenizer::new();
+    let mut buffer = Vec::new();
+    let mut events = Vec::new();
+    for chunk in chunks {
+        buffer.extend_from_slice(chunk);
+        let mut input = &buffer[..];
+        loop {
+            match tokenizer.next_event(input) {
+                Ok((rest, event)) => {
+                    events.push(format!("{:?}", event));
+                    input = rest;
+                }
+                Err(Err::Incomplete(_)) => break,
+                Err(Err::Error(e)) | Err(Err::Failure(e)) => return Err(e),
+            }
+        }
+        let consumed = buffer.len() - input.len();
+        buffer.drain(..consumed);
+    }
+    if let Some(event) = tokenizer.finish(&buffer)? {
+        events.push(format!("{:?}", event));
+    }
+    Ok(events)
+}
+
+#[test]
+fn tokenize_document() {
+    let events =
+        events(&[br#"{"a": [1, -2.5e3, true, null], "b\n": {"c": "d"}, "e": []}"#]).unwrap();
+    assert_eq!(
+        events,
+        vec![
+            "StartObject",
+            r#"Key(JsonStr { raw: "a", escaped: false })"#,
+            "StartArray",
+            r#"Value(Number("1"))"#,
+            r#"Value(Number("-2.5e3"))"#,
+            "Value(Bool(true))",
+            "Value(Null)",
+            "EndArray",
+            r#"Key(JsonStr { raw: "b\\n", escaped: true })"#,
+            "StartObject",
+            r#"Key(JsonStr { raw: "c", escaped: false })"#,
+            r#"Value(String(JsonStr { raw: "d", escaped: false }))"#,
+            "EndObject",
+            r#"Key(JsonStr { raw: "e", escaped: false })"#,
+            "StartArray",
+            "EndArray",
+            "EndObject",
+        ]
+    );
+}
+
+#[test]
+fn resume_after_every_byte() {
+    let document = "[\"caf\u{e9} \\ud83d\\ude00\", 12.75, false, {\"k\": null}] ";
+    let whole = events(&[document.as_bytes()]).unwrap();
+    let bytes: Vec<&[u8]> = document.as_bytes().chunks(1).collect();
+    assert_eq!(events(&bytes).unwrap(), whole);
+    assert_eq!(
+        events(&[b"12", b"3"]).unwrap(),
+        vec![r#"Value(Number("123"))"#]
+    );
+}
+
+#[test]
+fn unescape_strings() {
+    let mut tokenizer = Tokenizer::new();
+    let (_, event) = tokenizer
+        .next_event(br#""a\"\u00e9\ud83d\ude00\/\n""#)
+        .unwrap();
+    match event {
+        Event::Value(Scalar::String(s)) => assert_eq!(s.unescape(), "a\"\u{e9}\u{1F600}/\n"),
+        other => panic!("unexpected event {:?}", other),
+    }
+}
+
+#[test]
+fn report_errors() {
+    let error = |input: &[u8]| events(&[input]).unwrap_err();
+    assert_eq!(
+        error(b"[1, 2,]"),
+        Error {
+            kind: ErrorKind::Expected("value"),
+            position: 6
+        }
+    );
+    assert_eq!(error(b"{\"a\" 1}").kind, ErrorKind::Expected("':'"));
+    assert_eq!(error(b"[01]").kind, ErrorKind::Expected("',' or ']'"));
+    assert_eq!(error(b"[1.]").kind, ErrorKind::InvalidNumber);
+    assert_eq!(error(b"-").kind, ErrorKind::InvalidNumber);
+    assert_eq!(error(b"\"\\x\"").kind, ErrorKind::InvalidEscape);
+    assert_eq!(error(b"\"\\udc00\"").kind, ErrorKind::InvalidUnicodeEscape);
+    assert_eq!(
+        error(b"\"\\ud800\\u0041\"").kind,
+        ErrorKind::InvalidUnicodeEscape
+    );
+    assert_eq!(error(b"\"a\tb\"").kind, ErrorKind::ControlCharacter);
+    assert_eq!(error(b"\"\xff\"").position, 1);
+    assert_eq!(error(b"[tru]").kind, ErrorKind::Expected("value"));
+    assert_eq!(error(b"{} {}").kind, ErrorKind::TrailingCharacters);
+    assert_eq!(
+        error(b"{\"a\": [1"),
+        Error {
+            kind: ErrorKind::UnexpectedEnd,
+            position: 8
+        }
+    );
+    assert_eq!(error(b"  ").kind, ErrorKind::UnexpectedEnd);
+}
@@ -2,22 +2,22 @@ extern crate nom;
 
 use nom::{
     branch::alt,
-    bytes::complete::{escaped, tag, take_while},
-    character::complete::{alphanumeric1 as alphanumeric, char, one_of},
-    combinator::{cut, map, opt, value},
-    error::{context, ContextError, ErrorKind, ParseError},
-    multi::separated_list0,
-    number::complete::double,
-    sequence::{delimited, preceded, separated_pair, terminated},
-    IResult,
+    bytes::complete::{tag, take_while, take_while1, take_while_m_n},
+    character::complete::{char, digit1, one_of},
+    combinator::{all_consuming, cut, map, map_opt, opt, recognize, value, verify},
+    error::{context, ContextError, ErrorKind, ParseError, VerboseError, VerboseErrorKind},
+    multi::{fold_many0, separated_list0},
+    sequence::{pair, preceded, separated_pair, terminated, tuple},
+    Err, IResult, Offset,
 };
 
-use nom::bytes::complete::tag_no_case;
 use std::collections::HashMap;
+use std::fmt;
 use std::str;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum JsonValue {
+    Null,
     Str(String),
     Boolean(bool),
     Num(f64),
@@ -30,19 +30,121 @@ fn sp<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, &str, E> {
     take_while(move |c| chars.contains(c))(i)
 }
 
-fn parse_str<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, &'a str, E> {
-    escaped(alphanumeric, '\\', one_of("\"n\\"))(i)
+fn parse_hex4<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u32, E> {
+    let parse_hex = take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit());
+    map_opt(parse_hex, move |hex| u32::from_str_radix(hex, 16).ok())(i)
+}
+
+/// Parses the `uXXXX` after a backslash. Characters outside the basic multilingual plane are
+/// written as a surrogate pair, i.e. two escapes in a row, and a lone surrogate is an error.
+fn parse_unicode<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, char, E> {
+    let (rest, high) = preceded(char('u'), parse_hex4)(i)?;
+    let (rest, code) = match high {
+        0xD800..=0xDBFF => {
+            let low_surrogate = verify(parse_hex4, |low| (0xDC00..=0xDFFF).contains(low));
+            let (rest, low) = context("surrogate pair", preceded(tag("\\u"), low_surrogate))(rest)?;
+            (rest, 0x10000 + ((high - 0xD800) << 10 | (low - 0xDC00)))
+        }
+        0xDC00..=0xDFFF => return Err(Err::Error(E::from_error_kind(i, ErrorKind::Verify))),
+        _ => (rest, high),
+    };
+    match std::char::from_u32(code) {
+        Some(c) => Ok((rest, c)),
+        None => Err(Err::Error(E::from_error_kind(i, ErrorKind::Verify))),
+    }
+}
+
+fn parse_escaped_char<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, char, E> {
+    context(
+        "escape",
+        preceded(
+            char('\\'),
+            alt((
+                parse_unicode,
+                value('\n', char('n')),
+                value('\r', char('r')),
+                value('\t', char('t')),
+                value('\u{08}', char('b')),
+                value('\u{0C}', char('f')),
+                value('\\', char('\\')),
+                value('/', char('/')),
+                value('"', char('"')),
+            )),
+        ),
+    )(i)
+}
+
+/// Parses a run of characters that need no escaping. Control characters must be escaped.
+fn parse_literal<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, &'a str, E> {
+    take_while1(|c| c != '"' && c != '\\' && c >= '\u{20}')(i)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StringFragment<'a> {
+    Literal(&'a str),
+    EscapedChar(char),
+}
+
+fn parse_fragment<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, StringFragment<'a>, E> {
+    alt((
+        map(parse_literal, StringFragment::Literal),
+        map(parse_escaped_char, StringFragment::EscapedChar),
+    ))(i)
+}
+
+fn parse_str<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, String, E> {
+    fold_many0(parse_fragment, String::new(), |mut string, fragment| {
+        match fragment {
+            StringFragment::Literal(s) => string.push_str(s),
+            StringFragment::EscapedChar(c) => string.push(c),
+        }
+        string
+    })(i)
 }
 
 fn boolean<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, bool, E> {
-    let parse_true = value(true, tag_no_case("true"));
-    let parse_false = value(false, tag_no_case("false"));
+    let parse_true = value(true, tag("true"));
+    let parse_false = value(false, tag("false"));
     alt((parse_true, parse_false))(input)
 }
 
+fn null<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, JsonValue, E> {
+    value(JsonValue::Null, tag("null"))(input)
+}
+
+/// Parses a number as written in JSON, which unlike `double` rejects leading zeros, a leading
+/// `+`, and a missing integer or fraction part such as `.5` or `1.`
+fn number<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
+    i: &'a str,
+) -> IResult<&'a str, f64, E> {
+    let integer = alt((tag("0"), digit1));
+    let fraction = pair(char('.'), cut(digit1));
+    let exponent = tuple((one_of("eE"), opt(one_of("+-")), cut(digit1)));
+    context(
+        "number",
+        map_opt(
+            recognize(tuple((
+                opt(char('-')),
+                integer,
+                opt(fraction),
+                opt(exponent),
+            ))),
+            |s: &str| s.parse().ok(),
+        ),
+    )(i)
+}
+
 fn string<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
     i: &'a str,
-) -> IResult<&'a str, &'a str, E> {
+) -> IResult<&'a str, String, E> {
     context(
         "string",
         preceded(char('\"'), cut(terminated(parse_str, char('\"')))),
@@ -66,7 +168,7 @@ fn array<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
 
 fn key_value<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
     i: &'a str,
-) -> IResult<&'a str, (&'a str, JsonValue), E> {
+) -> IResult<&'a str, (String, JsonValue), E> {
     separated_pair(
         preceded(sp, string),
         cut(preceded(sp, char(':'))),
@@ -84,12 +186,7 @@ fn hash<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
             cut(terminated(
                 map(
                     separated_list0(preceded(sp, char(',')), key_value),
-                    |tuple_vec| {
-                        tuple_vec
-                            .into_iter()
-                            .map(|(k, v)| (String::from(k), v))
-                            .collect()
-                    },
+                    |tuple_vec| tuple_vec.into_iter().collect(),
                 ),
                 preceded(sp, char('}')),
             )),
@@ -105,25 +202,87 @@ fn json_value<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
         alt((
             map(hash, JsonValue::Object),
             map(array, JsonValue::Array),
-            map(string, |s| JsonValue::Str(String::from(s))),
-            map(double, JsonValue::Num),
+            map(string, JsonValue::Str),
+            map(number, JsonValue::Num),
             map(boolean, JsonValue::Boolean),
+            null,
         )),
     )(i)
 }
 
+/// Parses a whole document, which may be any value surrounded by whitespace
 fn root<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
     i: &'a str,
 ) -> IResult<&'a str, JsonValue, E> {
-    delimited(
-        sp,
-        alt((map(hash, JsonValue::Object), map(array, JsonValue::Array))),
-        opt(sp),
-    )(i)
+    all_consuming(terminated(json_value, sp))(i)
+}
+
+/// Where and why parsing failed, at one level of the parser stack
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// 1-based
+    pub line: usize,
+    /// 1-based, counted in characters
+    pub column: usize,
+    pub message: String,
+    /// The input line containing the error, without its line break
+    pub source_line: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}:{}: {}", self.line, self.column, self.message)?;
+        writeln!(f, "{}", self.source_line)?;
+        write!(f, "{:>width$}", '^', width = self.column)
+    }
+}
+
+/// Converts the error stack of a failed parse into diagnostics, innermost first
+fn diagnostics(input: &str, error: VerboseError<&str>) -> Vec<Diagnostic> {
+    error
+        .errors
+        .into_iter()
+        .map(|(substring, kind)| {
+            let offset = input.offset(substring);
+            let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = input[offset..]
+                .find('\n')
+                .map_or(input.len(), |i| offset + i);
+            let found = match substring.chars().next() {
+                Some(c) => format!("found {:?}", c),
+                None => "got end of input".to_string(),
+            };
+            let message = match kind {
+                VerboseErrorKind::Char(c) => format!("expected {:?}, {}", c, found),
+                VerboseErrorKind::Context(context) => format!("in {}", context),
+                VerboseErrorKind::Nom(ErrorKind::Eof) => {
+                    format!("expected end of input, {}", found)
+                }
+                VerboseErrorKind::Nom(kind) => format!("{}, {}", kind.description(), found),
+            };
+            Diagnostic {
+                line: input[..offset].matches('\n').count() + 1,
+                column: input[line_start..offset].chars().count() + 1,
+                message,
+                source_line: input[line_start..line_end]
+                    .trim_end_matches('\r')
+                    .to_string(),
+            }
+        })
+        .collect()
+}
+
+pub fn parse_json(input: &str) -> Result<JsonValue, Vec<Diagnostic>> {
+    match root::<VerboseError<&str>>(input) {
+        Ok((_, value)) => Ok(value),
+        Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(diagnostics(input, e)),
+        // the parsers are all complete, so they never ask for more input
+        Err(Err::Incomplete(_)) => unreachable!(),
+    }
 }
 
 fn main() {
-    let data = "{\"name\":[\"iamazy\",1,false,{\"age\":1}]}";
+    let data = "{\"name\":[\"iamazy\",1,false,{\"age\":1}],\"bio\":\"hello \\\"world\\\" \\ud83d\\ude00\",\"tags\":null}";
 
     println!(
         "will try to parse valid JSON data: \n\n*************\n{}\n*************\n",
@@ -134,4 +293,104 @@ fn main() {
         "parsing a valid file:\n{:#?}\n",
         root::<(&str, ErrorKind)>(data)
     );
+
+    let data = "{\n  \"name\": [\"iamazy\", 1, false,\n    {\"age\": 01}]\n}";
+
+    println!(
+        "will try to parse invalid JSON data: \n\n*************\n{}\n*************\n",
+        data
+    );
+
+    if let Err(diagnostics) = parse_json(data) {
+        println!("parsing an invalid file:");
+        for diagnostic in diagnostics {
+            println!("{}\n", diagnostic);
+        }
+    }
+}
+
+#[test]
+fn parse_values() {
+    assert_eq!(parse_json(" null "), Ok(JsonValue::Null));
+    assert_eq!(parse_json("-12.5e-1"), Ok(JsonValue::Num(-1.25)));
+    assert_eq!(parse_json("true"), Ok(JsonValue::Boolean(true)));
+    assert_eq!(parse_json("[ ]\n"), Ok(JsonValue::Array(vec![])));
+    assert_eq!(
+        parse_json("{\"a\": [null, {}], \"a\": 2}"),
+        Ok(JsonValue::Object(
+            vec![("a".to_string(), JsonValue::Num(2.0))]
+                .into_iter()
+                .collect()
+        ))
+    );
+    assert!(parse_json("TRUE").is_err());
+    assert!(parse_json("01").is_err());
+    assert!(parse_json("1.").is_err());
+    assert!(parse_json("[1,]").is_err());
+}
+
+#[test]
+fn parse_strings() {
+    assert_eq!(
+        parse_json(r#""hello world""#),
+        Ok(JsonValue::Str("hello world".to_string()))
+    );
+    assert_eq!(
+        parse_json(r#""\"\\\/\b\f\n\r\t""#),
+        Ok(JsonValue::Str("\"\\/\u{08}\u{0C}\n\r\t".to_string()))
+    );
+    assert_eq!(
+        parse_json(r#""é😀""#),
+        Ok(JsonValue::Str("é😀".to_string()))
+    );
+    assert!(parse_json(r#""\uD83D""#).is_err());
+    assert!(parse_json(r#""\uDE00""#).is_err());
+    assert!(parse_json(r#""\x""#).is_err());
+    assert!(parse_json("\"tab\there\"").is_err());
+}
+
+#[test]
+fn report_line_and_column() {
+    let diagnostics = parse_json("{\n  \"a\": [1, 2\n}").unwrap_err();
+    assert_eq!(
+        diagnostics[0],
+        Diagnostic {
+            line: 3,
+            column: 1,
+            message: "expected ']', found '}'".to_string(),
+            source_line: "}".to_string(),
+        }
+    );
+    assert_eq!(diagnostics[1].message, "in array");
+    assert_eq!((diagnostics[1].line, diagnostics[1].column), (2, 8));
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "3:1: expected ']', found '}'\n}\n^"
+    );
+
+    let diagnostics = parse_json("[1] 2").unwrap_err();
+    assert_eq!(diagnostics[0].message, "expected end of input, found '2'");
+    assert_eq!(diagnostics[0].column, 5);
+}
+
+/// Every `y_` file in the corpus must parse and every `n_` file must not
+#[test]
+fn conformance_corpus() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/corpus");
+    let mut checked = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        let input = std::fs::read(&path).unwrap();
+        let result = str::from_utf8(&input).map(parse_json);
+        if name.starts_with("y_") {
+            assert!(matches!(result, Ok(Ok(_))), "{} should parse", name);
+        } else if name.starts_with("n_") {
+            assert!(!matches!(result, Ok(Ok(_))), "{} should not parse", name);
+        } else {
+            continue;
+        }
+        checked += 1;
+    }
+    assert!(checked > 0);
 }
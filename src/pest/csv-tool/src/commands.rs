@@ -0,0 +1,239 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use crate::csv::write_record;
+use crate::dialect::Dialect;
+use crate::filter::Filter;
+use crate::table::{ColumnType, Table, Value};
+
+/// Prints the type of each column, how many cells are filled, and their range
+pub fn stats<W: Write>(table: &Table, out: &mut W) -> io::Result<()> {
+    let mut lines = vec![[
+        "column", "type", "count", "nulls", "distinct", "min", "max", "mean",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect::<Vec<_>>()];
+    for column in 0..table.width() {
+        let values: Vec<Value<'_>> = (0..table.rows.len())
+            .map(|row| table.value(row, column))
+            .filter(|value| *value != Value::Null)
+            .collect();
+        let distinct: HashSet<&str> = (0..table.rows.len())
+            .map(|row| table.cell(row, column))
+            .filter(|cell| !cell.is_empty())
+            .collect();
+        let extreme = |wanted: Ordering| {
+            values
+                .iter()
+                .copied()
+                .fold(None, |best: Option<Value<'_>>, value| match best {
+                    Some(best) if best.compare(&value) != Some(wanted) => Some(value),
+                    None => Some(value),
+                    best => best,
+                })
+                .map_or_else(|| "-".to_string(), display)
+        };
+        let numeric = matches!(table.types[column], ColumnType::Integer | ColumnType::Float);
+        let mean = if numeric && !values.is_empty() {
+            let sum: f64 = values.iter().filter_map(Value::as_f64).sum();
+            format!("{}", sum / values.len() as f64)
+        } else {
+            "-".to_string()
+        };
+        lines.push(vec![
+            table.headers[column].clone(),
+            table.types[column].name().to_string(),
+            values.len().to_string(),
+            (table.rows.len() - values.len()).to_string(),
+            distinct.len().to_string(),
+            extreme(Ordering::Less),
+            extreme(Ordering::Greater),
+            mean,
+        ]);
+    }
+    print_aligned(&lines, out)
+}
+
+/// Writes the given columns of every row, and of the header if there is one, in the dialect
+/// of the input
+pub fn select<W: Write>(
+    table: &Table,
+    dialect: &Dialect,
+    columns: &[usize],
+    out: &mut W,
+) -> io::Result<()> {
+    if table.has_header {
+        write_record(
+            out,
+            dialect,
+            columns.iter().map(|c| table.headers[*c].as_str()),
+        )?;
+    }
+    for row in 0..table.rows.len() {
+        write_record(out, dialect, columns.iter().map(|c| table.cell(row, *c)))?;
+    }
+    Ok(())
+}
+
+/// Writes the rows matching a filter, after the header if there is one
+pub fn filter<W: Write>(
+    table: &Table,
+    dialect: &Dialect,
+    filter: &Filter,
+    out: &mut W,
+) -> io::Result<()> {
+    if table.has_header {
+        write_record(out, dialect, table.headers.iter().map(String::as_str))?;
+    }
+    for row in (0..table.rows.len()).filter(|row| filter.matches(table, *row)) {
+        write_record(out, dialect, (0..table.width()).map(|c| table.cell(row, c)))?;
+    }
+    Ok(())
+}
+
+/// Writes a JSON array with a line per row, holding an object keyed by header if there is one
+/// and an array otherwise. Cells are typed by their column, and empty cells are `null`.
+pub fn json<W: Write>(table: &Table, out: &mut W) -> io::Result<()> {
+    let keys = json_keys(&table.headers);
+    out.write_all(b"[")?;
+    for row in 0..table.rows.len() {
+        out.write_all(if row == 0 { b"\n  " } else { b",\n  " })?;
+        out.write_all(if table.has_header { b"{" } else { b"[" })?;
+        for (column, key) in keys.iter().enumerate() {
+            if column > 0 {
+                out.write_all(b", ")?;
+            }
+            if table.has_header {
+                write_json_string(out, key)?;
+                out.write_all(b": ")?;
+            }
+            match table.value(row, column) {
+                Value::Text(text) => write_json_string(out, text)?,
+                Value::Float(n) if !n.is_finite() => out.write_all(b"null")?,
+                value => out.write_all(display(value).as_bytes())?,
+            }
+        }
+        out.write_all(if table.has_header { b"}" } else { b"]" })?;
+    }
+    out.write_all(if table.rows.is_empty() {
+        b"]\n"
+    } else {
+        b"\n]\n"
+    })
+}
+
+/// The headers as object keys, with a repeated header numbered from its second use, e.g. `id`,
+/// `id_2`, skipping numbers another header already takes
+fn json_keys(headers: &[String]) -> Vec<String> {
+    let mut taken: HashSet<String> = headers.iter().cloned().collect();
+    let mut seen = HashSet::new();
+    let mut keys = Vec::with_capacity(headers.len());
+    for header in headers {
+        if seen.insert(header) {
+            keys.push(header.clone());
+            continue;
+        }
+        let key = (2..)
+            .map(|n| format!("{}_{}", header, n))
+            .find(|key| !taken.contains(key))
+            .unwrap();
+        taken.insert(key.clone());
+        keys.push(key);
+    }
+    keys
+}
+
+fn display(value: Value<'_>) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Float(n) => n.to_string(),
+        Value::Text(text) => text.to_string(),
+    }
+}
+
+fn write_json_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if c < '\u{20}' => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Prints lines of cells in columns, left aligned
+fn print_aligned<W: Write>(lines: &[Vec<String>], out: &mut W) -> io::Result<()> {
+    let mut widths = vec![0; lines.first().map_or(0, Vec::len)];
+    for line in lines {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for line in lines {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+#[test]
+fn convert_to_json() {
+    let records = vec![
+        vec!["name", "age", "note"],
+        vec!["ann \"a\"", "31", ""],
+        vec!["bob", "17.5", "x"],
+    ];
+    let table = Table::new(
+        records
+            .into_iter()
+            .map(|row| row.into_iter().map(String::from).collect())
+            .collect(),
+        None,
+    );
+    let mut out = Vec::new();
+    json(&table, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "[\n  {\"name\": \"ann \\\"a\\\"\", \"age\": 31, \"note\": null},\n  {\"name\": \"bob\", \"age\": 17.5, \"note\": \"x\"}\n]\n"
+    );
+}
+
+#[test]
+fn json_keys_are_distinct() {
+    let headers: Vec<String> = ["id", "id", "id_2", "id", "name"]
+        .iter()
+        .map(|header| header.to_string())
+        .collect();
+    assert_eq!(
+        json_keys(&headers),
+        vec!["id", "id_3", "id_2", "id_4", "name"]
+    );
+
+    let table = Table::new(
+        vec![
+            vec!["id".to_string(), "id".to_string()],
+            vec!["1".to_string(), "2".to_string()],
+        ],
+        None,
+    );
+    let mut out = Vec::new();
+    json(&table, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "[\n  {\"id\": 1, \"id_2\": 2}\n]\n"
+    );
+}
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use pest::error::{ErrorVariant, InputLocation};
+use pest::Parser;
+
+use crate::dialect::Dialect;
+use crate::error::Error;
+
+#[derive(Parser)]
+#[grammar = "csv.pest"]
+pub struct CSVParser;
+
+/// Where and why the input does not parse as CSV in the given dialect
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+    /// 1-based, not counting blank lines and comments
+    pub record: usize,
+    /// 1-based
+    pub field: usize,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, counted in characters
+    pub column: usize,
+    /// The input line containing the error, without its line break
+    pub source_line: String,
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let number = self.line.to_string();
+        writeln!(
+            f,
+            "record {}, field {} (line {}, column {}): {}",
+            self.record, self.field, self.line, self.column, self.message
+        )?;
+        writeln!(f, "{} | {}", number, self.source_line)?;
+        write!(
+            f,
+            "{:width$} | {:>column$}",
+            "",
+            '^',
+            width = number.len(),
+            column = self.column
+        )
+    }
+}
+
+/// Parses CSV text into records, unquoting fields. Blank lines and comments are skipped.
+pub fn parse(text: &str, dialect: &Dialect) -> Result<Vec<Vec<String>>, Error> {
+    let header = dialect.header();
+    let input = header.clone() + text;
+    let rule = match dialect.comment {
+        Some(_) => Rule::commented_file,
+        None => Rule::file,
+    };
+    let file = CSVParser::parse(rule, &input)
+        .map_err(|e| Error::Csv(Box::new(syntax_error(text, header.len(), dialect, e))))?
+        .next()
+        .unwrap();
+    let records = file
+        .into_inner()
+        .filter(|record| record.as_rule() == Rule::record && !record.as_str().is_empty())
+        .map(|record| {
+            record
+                .into_inner()
+                .map(|field| match field.as_rule() {
+                    Rule::quoted_field => {
+                        unescape(field.into_inner().next().unwrap().as_str(), dialect)
+                    }
+                    _ if dialect.trim => field.as_str().trim_matches(&[' ', '\t'][..]).to_string(),
+                    _ => field.as_str().to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    Ok(records)
+}
+
+/// Replaces escaped quotes and escape characters inside a quoted field
+fn unescape(text: &str, dialect: &Dialect) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(&next)
+                if c == dialect.escape && (next == dialect.quote || next == dialect.escape) =>
+            {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Locates a pest error in the text without the dialect header, and describes what was
+/// expected in terms of the dialect
+fn syntax_error(
+    text: &str,
+    header_len: usize,
+    dialect: &Dialect,
+    error: pest::error::Error<Rule>,
+) -> SyntaxError {
+    let position = match error.location {
+        InputLocation::Pos(position) => position,
+        InputLocation::Span((start, _)) => start,
+    };
+    let offset = position.saturating_sub(header_len).min(text.len());
+    let (record, field) = locate(&text[..offset], dialect);
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+
+    let found = match text[offset..].chars().next() {
+        Some('\n') | Some('\r') => "the end of the line".to_string(),
+        Some(c) => format!("`{}`", c),
+        None => "the end of the input".to_string(),
+    };
+    // pest fails at a quote that is never closed, as no field can start with it
+    let before = text[line_start..offset].trim_end_matches(&[' ', '\t'][..]);
+    let opens_field = before.is_empty() || before.ends_with(dialect.delimiter);
+    let message = match error.variant {
+        ErrorVariant::CustomError { message } => message,
+        _ if text[offset..].starts_with(dialect.quote) && opens_field => {
+            "the quoted field starting here is not closed".to_string()
+        }
+        _ if text[offset..].starts_with(dialect.quote) => format!(
+            "unexpected `{}` in an unquoted field, which must be quoted as a whole",
+            dialect.quote
+        ),
+        _ => format!(
+            "expected `{}` or the end of the line, found {}",
+            dialect.delimiter.escape_default(),
+            found
+        ),
+    };
+
+    SyntaxError {
+        record,
+        field,
+        line: text[..offset].matches('\n').count() + 1,
+        column: text[line_start..offset].chars().count() + 1,
+        source_line: text[line_start..line_end]
+            .trim_end_matches('\r')
+            .to_string(),
+        message,
+    }
+}
+
+/// Returns the record and field number at the end of `text`, which may end inside a record
+fn locate(text: &str, dialect: &Dialect) -> (usize, usize) {
+    let (mut record, mut field) = (1, 1);
+    let mut quoted = false;
+    // whether the current line is empty so far, or a comment
+    let (mut blank, mut comment) = (true, false);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            let next = chars.peek().copied();
+            if c == dialect.escape && (next == Some(dialect.quote) || next == Some(dialect.escape))
+            {
+                chars.next();
+            } else if c == dialect.quote {
+                quoted = false;
+            }
+            continue;
+        }
+        match c {
+            '\n' => {
+                if !blank && !comment {
+                    record += 1;
+                }
+                field = 1;
+                blank = true;
+                comment = false;
+            }
+            '\r' => {}
+            _ if comment => {}
+            c if blank && Some(c) == dialect.comment => comment = true,
+            c => {
+                blank = false;
+                if c == dialect.delimiter {
+                    field += 1;
+                } else if c == dialect.quote {
+                    quoted = true;
+                }
+            }
+        }
+    }
+    (record, field)
+}
+
+/// Writes one record in the given dialect, quoting the fields that need it
+pub fn write_record<'a, W, I>(out: &mut W, dialect: &Dialect, fields: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a str>,
+{
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            write!(out, "{}", dialect.delimiter)?;
+        }
+        let needs_quotes = field.starts_with(|c| Some(c) == dialect.comment)
+            || field.contains(|c| {
+                c == dialect.delimiter || c == dialect.quote || c == '\r' || c == '\n'
+            });
+        if needs_quotes {
+            let mut escaped = String::from(dialect.escape);
+            escaped.push(dialect.quote);
+            let field = if dialect.escape == dialect.quote {
+                field.replace(dialect.quote, &escaped)
+            } else {
+                let mut escaped_escape = String::from(dialect.escape);
+                escaped_escape.push(dialect.escape);
+                field
+                    .replace(dialect.escape, &escaped_escape)
+                    .replace(dialect.quote, &escaped)
+            };
+            write!(out, "{}{}{}", dialect.quote, field, dialect.quote)?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+    }
+    out.write_all(b"\n")
+}
+
+#[test]
+fn parse_rfc4180() {
+    let dialect = Dialect::default();
+    let records = parse(
+        "a,\"b,1\",\"say \"\"hi\"\"\"\r\n,\"two\nlines\",\n\n3,4,5\n",
+        &dialect,
+    )
+    .unwrap();
+    assert_eq!(
+        records,
+        vec![
+            vec!["a", "b,1", "say \"hi\""],
+            vec!["", "two\nlines", ""],
+            vec!["3", "4", "5"],
+        ]
+    );
+    assert!(parse("a,\"b\"c\n", &dialect).is_err());
+    assert!(parse("a,\"b\n", &dialect).is_err());
+}
+
+#[test]
+fn parse_dialects() {
+    let dialect = Dialect {
+        delimiter: ';',
+        quote: '\'',
+        escape: '\\',
+        comment: Some('#'),
+        trim: true,
+    };
+    let records = parse(
+        "# exported\nname ; note\n Lee ; 'it\\'s; \\\\ok' \n#done\n",
+        &dialect,
+    )
+    .unwrap();
+    assert_eq!(
+        records,
+        vec![vec!["name", "note"], vec!["Lee", "it's; \\ok"]]
+    );
+}
+
+#[test]
+fn report_syntax_errors() {
+    let error = |text: &str| match parse(text, &Dialect::default()) {
+        Err(Error::Csv(e)) => *e,
+        other => panic!("unexpected result {:?}", other),
+    };
+    let e = error("a,b\n\"c\nd\",\"e\"f\n");
+    assert_eq!((e.record, e.field, e.line, e.column), (2, 2, 3, 7));
+    assert_eq!(e.message, "expected `,` or the end of the line, found `f`");
+    assert_eq!(
+        e.to_string(),
+        "record 2, field 2 (line 3, column 7): expected `,` or the end of the line, found `f`\n\
+         3 | d\",\"e\"f\n  |       ^"
+    );
+
+    let e = error("a,b\nc, \"d\n");
+    assert_eq!((e.record, e.field, e.line, e.column), (2, 2, 2, 4));
+    assert_eq!(e.message, "the quoted field starting here is not closed");
+}
+
+#[test]
+fn quote_on_write() {
+    let mut out = Vec::new();
+    write_record(&mut out, &Dialect::default(), vec!["a", "b,c", "d\"e"]).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "a,\"b,c\",\"d\"\"e\"\n");
+
+    let mut out = Vec::new();
+    let dialect = Dialect {
+        delimiter: '\t',
+        escape: '\\',
+        ..Dialect::default()
+    };
+    write_record(&mut out, &dialect, vec!["a b", "c\td\"\\"]).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "a b\t\"c\td\\\"\\\\\"\n");
+}
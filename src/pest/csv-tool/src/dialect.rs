@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+/// The delimiters tried when sniffing, in order of preference
+const DELIMITERS: [char; 4] = [',', ';', '\t', '|'];
+/// How many lines sniffing looks at
+const SAMPLE_LINES: usize = 10;
+
+/// The characters a CSV file is written with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dialect {
+    pub delimiter: char,
+    pub quote: char,
+    /// Escapes a quote or itself inside a quoted field. When it is the quote itself, quotes are
+    /// doubled as in RFC 4180.
+    pub escape: char,
+    /// Starts a line to skip
+    pub comment: Option<char>,
+    /// Whether to strip spaces and tabs around unquoted fields
+    pub trim: bool,
+}
+
+impl Default for Dialect {
+    /// The dialect of RFC 4180
+    fn default() -> Self {
+        Dialect {
+            delimiter: ',',
+            quote: '"',
+            escape: '"',
+            comment: None,
+            trim: false,
+        }
+    }
+}
+
+impl Dialect {
+    /// Guesses the dialect from the first lines of a file. The delimiter is the candidate that
+    /// occurs equally often outside quotes on the most lines, such that a malformed line or a
+    /// stray delimiter in a field does not throw it off.
+    pub fn sniff(text: &str) -> Dialect {
+        let comment = text
+            .lines()
+            .take(SAMPLE_LINES)
+            .find(|line| line.starts_with('#'))
+            .map(|_| '#');
+        let lines: Vec<&str> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .take(SAMPLE_LINES)
+            .collect();
+
+        let quote = if !lines.iter().any(|line| line.contains('"'))
+            && lines.iter().any(|line| starts_quoted_field(line, '\''))
+        {
+            '\''
+        } else {
+            '"'
+        };
+        let mut escaped_quote = String::from('\\');
+        escaped_quote.push(quote);
+        let escape = if lines.iter().any(|line| line.contains(&escaped_quote)) {
+            '\\'
+        } else {
+            quote
+        };
+
+        let mut delimiter = Dialect::default().delimiter;
+        let mut best = (0, 0);
+        for candidate in DELIMITERS.iter().copied() {
+            let mut frequencies = HashMap::new();
+            for line in &lines {
+                match count_unquoted(line, candidate, quote) {
+                    0 => {}
+                    count => *frequencies.entry(count).or_insert(0) += 1,
+                }
+            }
+            // how many lines agree on the most common count, and that count
+            let score = frequencies
+                .into_iter()
+                .map(|(count, lines)| (lines, count))
+                .max()
+                .unwrap_or((0, 0));
+            if score > best {
+                best = score;
+                delimiter = candidate;
+            }
+        }
+
+        Dialect {
+            delimiter,
+            quote,
+            escape,
+            comment,
+            trim: false,
+        }
+    }
+
+    /// Checks that the characters can tell fields, quotes and comments apart
+    pub fn validate(&self) -> Result<(), String> {
+        let mut chars = vec![("delimiter", self.delimiter), ("quote", self.quote)];
+        if self.escape != self.quote {
+            chars.push(("escape", self.escape));
+        }
+        if let Some(comment) = self.comment {
+            chars.push(("comment", comment));
+        }
+        for (i, (name, c)) in chars.iter().enumerate() {
+            if *c == '\n' || *c == '\r' {
+                return Err(format!("the {} character can't be a line break", name));
+            }
+            if let Some((other, _)) = chars[..i].iter().find(|(_, other)| other == c) {
+                return Err(format!(
+                    "the {} and {} characters are both `{}`",
+                    other, name, c
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the line declaring the dialect to the grammar, which precedes the input
+    pub(crate) fn header(&self) -> String {
+        let mut header: String = [self.delimiter, self.quote, self.escape].iter().collect();
+        header.extend(self.comment);
+        header.push('\n');
+        header
+    }
+}
+
+/// Counts a character in a line, outside quoted fields
+fn count_unquoted(line: &str, c: char, quote: char) -> usize {
+    let mut quoted = false;
+    let mut count = 0;
+    for ch in line.chars() {
+        if ch == quote {
+            quoted = !quoted;
+        } else if ch == c && !quoted {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Whether a quote character starts the line or follows one of the candidate delimiters
+fn starts_quoted_field(line: &str, quote: char) -> bool {
+    line.trim_start().starts_with(quote)
+        || DELIMITERS.iter().any(|delimiter| {
+            line.match_indices(*delimiter).any(|(i, _)| {
+                line[i + delimiter.len_utf8()..]
+                    .trim_start()
+                    .starts_with(quote)
+            })
+        })
+}
+
+#[test]
+fn sniff_dialects() {
+    assert_eq!(Dialect::sniff("a,b,c\n1,2,3\n"), Dialect::default());
+
+    let dialect = Dialect::sniff("# exported\nname;city\n\"Dupont; J.\";Paris\nLee;Oslo\n");
+    assert_eq!(dialect.delimiter, ';');
+    assert_eq!(dialect.comment, Some('#'));
+
+    let dialect = Dialect::sniff("a;b;c\n1;2,5;3\n4;5;6\n7;8\n");
+    assert_eq!(dialect.delimiter, ';');
+
+    let dialect = Dialect::sniff("id\tnote\n1\t'it\\'s'\n2\t'ok, fine'\n");
+    assert_eq!(dialect.delimiter, '\t');
+    assert_eq!(dialect.quote, '\'');
+    assert_eq!(dialect.escape, '\\');
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+use std::io;
+
+use crate::{csv, filter};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Csv(Box<csv::SyntaxError>),
+    Filter(Box<pest::error::Error<filter::Rule>>),
+    /// A column that is neither a header nor a 1-based index
+    UnknownColumn(String),
+    Usage(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Csv(e) => write!(f, "invalid CSV at {}", e),
+            Error::Filter(e) => write!(f, "invalid filter\n{}", e),
+            Error::UnknownColumn(column) => write!(f, "unknown column `{}`", column),
+            Error::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
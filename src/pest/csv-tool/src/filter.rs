@@ -0,0 +1,209 @@
+use std::cmp::Ordering;
+
+use pest::iterators::{Pair, Pairs};
+use pest::prec_climber::{Assoc, Operator, PrecClimber};
+use pest::Parser;
+
+use crate::error::Error;
+use crate::table::{Table, Value};
+
+#[derive(Parser)]
+#[grammar = "filter.pest"]
+pub struct FilterParser;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// The left side's text contains the right side's
+    Contains,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Operand {
+    Null,
+    Bool(bool),
+    /// The number with its text as written
+    Number(f64, String),
+    Text(String),
+    Column(usize),
+}
+
+/// A parsed filter expression, with columns resolved against a table
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Compare(Operand, CompareOp, Operand),
+}
+
+impl Filter {
+    pub fn parse(expr: &str, table: &Table) -> Result<Filter, Error> {
+        let filter = FilterParser::parse(Rule::filter, expr)
+            .map_err(|e| Error::Filter(Box::new(e)))?
+            .next()
+            .unwrap();
+        // `&&` binds tighter than `||`
+        let climber = PrecClimber::new(vec![
+            Operator::new(Rule::or, Assoc::Left),
+            Operator::new(Rule::and, Assoc::Left),
+        ]);
+        build_expr(
+            filter.into_inner().next().unwrap().into_inner(),
+            &climber,
+            table,
+        )
+    }
+
+    pub fn matches(&self, table: &Table, row: usize) -> bool {
+        match self {
+            Filter::And(a, b) => a.matches(table, row) && b.matches(table, row),
+            Filter::Or(a, b) => a.matches(table, row) || b.matches(table, row),
+            Filter::Not(a) => !a.matches(table, row),
+            Filter::Compare(left, op, right) => {
+                compare(left.resolve(table, row), *op, right.resolve(table, row))
+            }
+        }
+    }
+}
+
+impl Operand {
+    /// Returns the operand's value in a row, along with its text
+    fn resolve<'a>(&'a self, table: &'a Table, row: usize) -> (Value<'a>, &'a str) {
+        match self {
+            Operand::Null => (Value::Null, ""),
+            Operand::Bool(true) => (Value::Bool(true), "true"),
+            Operand::Bool(false) => (Value::Bool(false), "false"),
+            Operand::Number(n, text) => (Value::Float(*n), text),
+            Operand::Text(text) => (Value::Text(text), text),
+            Operand::Column(column) => (table.value(row, *column), table.cell(row, *column)),
+        }
+    }
+}
+
+/// Compares values of the same kind by value. Otherwise, null only equals null, and any other
+/// values are compared by text, such that `age == "31"` matches a numeric column.
+fn compare(left: (Value<'_>, &str), op: CompareOp, right: (Value<'_>, &str)) -> bool {
+    if op == CompareOp::Contains {
+        return left.1.contains(right.1);
+    }
+    let ordering = match (left.0, right.0) {
+        (Value::Null, _) | (_, Value::Null) => left.0.compare(&right.0),
+        (a, b) => a.compare(&b).or_else(|| Some(left.1.cmp(right.1))),
+    };
+    match op {
+        CompareOp::Eq => ordering == Some(Ordering::Equal),
+        CompareOp::Ne => ordering != Some(Ordering::Equal),
+        CompareOp::Lt => ordering == Some(Ordering::Less),
+        CompareOp::Le => matches!(ordering, Some(Ordering::Less) | Some(Ordering::Equal)),
+        CompareOp::Gt => ordering == Some(Ordering::Greater),
+        CompareOp::Ge => matches!(ordering, Some(Ordering::Greater) | Some(Ordering::Equal)),
+        CompareOp::Contains => unreachable!(),
+    }
+}
+
+fn build_expr(
+    pairs: Pairs<'_, Rule>,
+    climber: &PrecClimber<Rule>,
+    table: &Table,
+) -> Result<Filter, Error> {
+    climber.climb(
+        pairs,
+        |pair| build_term(pair, climber, table),
+        |left, op, right| {
+            let (left, right) = (Box::new(left?), Box::new(right?));
+            Ok(match op.as_rule() {
+                Rule::and => Filter::And(left, right),
+                _ => Filter::Or(left, right),
+            })
+        },
+    )
+}
+
+fn build_term(
+    pair: Pair<'_, Rule>,
+    climber: &PrecClimber<Rule>,
+    table: &Table,
+) -> Result<Filter, Error> {
+    match pair.as_rule() {
+        Rule::expr => build_expr(pair.into_inner(), climber, table),
+        Rule::not => {
+            let term = pair.into_inner().next().unwrap();
+            Ok(Filter::Not(Box::new(build_term(term, climber, table)?)))
+        }
+        _ => {
+            let mut inner = pair.into_inner();
+            let left = build_operand(inner.next().unwrap(), table)?;
+            let op = match inner.next().unwrap().as_str() {
+                "==" => CompareOp::Eq,
+                "!=" => CompareOp::Ne,
+                "<" => CompareOp::Lt,
+                "<=" => CompareOp::Le,
+                ">" => CompareOp::Gt,
+                ">=" => CompareOp::Ge,
+                _ => CompareOp::Contains,
+            };
+            let right = build_operand(inner.next().unwrap(), table)?;
+            Ok(Filter::Compare(left, op, right))
+        }
+    }
+}
+
+fn build_operand(pair: Pair<'_, Rule>, table: &Table) -> Result<Operand, Error> {
+    Ok(match pair.as_rule() {
+        Rule::null => Operand::Null,
+        Rule::boolean => Operand::Bool(pair.as_str() == "true"),
+        // the grammar only admits valid numbers
+        Rule::number => Operand::Number(pair.as_str().parse().unwrap(), pair.as_str().to_string()),
+        Rule::string => Operand::Text(pair.into_inner().next().unwrap().as_str().to_string()),
+        _ => {
+            let name = pair.into_inner().next().unwrap();
+            match name.as_rule() {
+                Rule::index => Operand::Column(table.column(name.as_str())?),
+                _ => Operand::Column(
+                    table
+                        .headers
+                        .iter()
+                        .position(|header| header == name.as_str())
+                        .ok_or_else(|| Error::UnknownColumn(name.as_str().to_string()))?,
+                ),
+            }
+        }
+    })
+}
+
+#[test]
+fn filter_rows() {
+    let records = vec![
+        vec!["name", "age", "zip"],
+        vec!["ann", "31", "02134"],
+        vec!["bob", "17", ""],
+        vec!["test user", "45", "10001"],
+    ];
+    let table = Table::new(
+        records
+            .into_iter()
+            .map(|row| row.into_iter().map(String::from).collect())
+            .collect(),
+        None,
+    );
+    let matching = |expr: &str| -> Vec<usize> {
+        let filter = Filter::parse(expr, &table).unwrap();
+        (0..table.rows.len())
+            .filter(|row| filter.matches(&table, *row))
+            .collect()
+    };
+    assert_eq!(matching("age >= 18"), vec![0, 2]);
+    assert_eq!(matching("age >= 18 && !(name ~ 'test')"), vec![0]);
+    assert_eq!(matching("age < 20 || $1 == \"test user\""), vec![1, 2]);
+    assert_eq!(matching("zip == \"02134\""), vec![0]);
+    assert_eq!(matching("age == \"31\""), vec![0]);
+    assert_eq!(matching("zip == null"), vec![1]);
+    assert!(Filter::parse("height > 2", &table).is_err());
+    assert!(Filter::parse("age >", &table).is_err());
+}
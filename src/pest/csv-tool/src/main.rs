@@ -2,17 +2,201 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
+mod commands;
+mod csv;
+mod dialect;
+mod error;
+mod filter;
+mod table;
 
-use pest::Parser;
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::process;
 
-#[derive(Parser)]
-#[grammar = "csv.pest"]
-pub struct CSVParser;
+use crate::dialect::Dialect;
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::table::Table;
 
-fn main() {
-    let successful_parse = CSVParser::parse(Rule::field, "-273.15");
-    println!("{:?}", successful_parse);
+const USAGE: &str = "usage: csv-tool [options] <command> [file]
+
+Reads CSV from the file, or from standard input if it is missing or `-`. The dialect is sniffed
+from the first lines, except for the characters given as options.
+
+commands:
+    stats                 print the type and range of each column
+    select <columns>      keep columns, by name or 1-based index, separated by commas
+    filter <expression>   keep rows matching an expression, e.g. `age >= 18 && city == \"Paris\"`
+    json                  convert to a JSON array, of objects if there is a header
+
+options:
+    --header              treat the first record as a header
+    --no-header           treat the first record as data, instead of detecting a header
+    --delimiter <char>    separate fields with the character, `tab` for a tab
+    --quote <char>        quote fields with the character
+    --escape <char>       escape quotes in quoted fields with the character instead of doubling them
+    --comment <char>      skip lines starting with the character
+    --trim                strip spaces and tabs around unquoted fields";
+
+enum Command {
+    Stats,
+    Select(String),
+    Filter(String),
+    Json,
+}
+
+/// The dialect characters given as options, overriding the sniffed ones
+#[derive(Default)]
+struct DialectOptions {
+    delimiter: Option<char>,
+    quote: Option<char>,
+    escape: Option<char>,
+    comment: Option<char>,
+    trim: bool,
+}
+
+impl DialectOptions {
+    fn apply(&self, sniffed: Dialect) -> Dialect {
+        let quote = self.quote.unwrap_or(sniffed.quote);
+        // quotes stay doubled, unless the sniffed dialect escapes them
+        let escape = match self.escape {
+            Some(escape) => escape,
+            None if sniffed.escape == sniffed.quote => quote,
+            None => sniffed.escape,
+        };
+        Dialect {
+            delimiter: self.delimiter.unwrap_or(sniffed.delimiter),
+            quote,
+            escape,
+            comment: self.comment.or(sniffed.comment),
+            trim: self.trim,
+        }
+    }
+}
+
+struct Args {
+    command: Command,
+    header: Option<bool>,
+    dialect: DialectOptions,
+    file: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, Error> {
+    let mut header = None;
+    let mut dialect = DialectOptions::default();
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--header" => header = Some(true),
+            "--no-header" => header = Some(false),
+            "--delimiter" => dialect.delimiter = Some(char_option(&arg, args.next())?),
+            "--quote" => dialect.quote = Some(char_option(&arg, args.next())?),
+            "--escape" => dialect.escape = Some(char_option(&arg, args.next())?),
+            "--comment" => dialect.comment = Some(char_option(&arg, args.next())?),
+            "--trim" => dialect.trim = true,
+            "-h" | "--help" => return Err(Error::Usage(USAGE.to_string())),
+            _ if arg.starts_with("--") => {
+                return Err(Error::Usage(format!(
+                    "unknown option `{}`\n\n{}",
+                    arg, USAGE
+                )))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    let command = match positional.next().as_deref() {
+        Some("stats") => Command::Stats,
+        Some("json") => Command::Json,
+        Some("select") => Command::Select(positional.next().ok_or_else(|| missing("columns"))?),
+        Some("filter") => Command::Filter(positional.next().ok_or_else(|| missing("expression"))?),
+        Some(other) => {
+            return Err(Error::Usage(format!(
+                "unknown command `{}`\n\n{}",
+                other, USAGE
+            )))
+        }
+        None => return Err(Error::Usage(USAGE.to_string())),
+    };
+    let file = positional.next().filter(|file| file != "-");
+    if let Some(extra) = positional.next() {
+        return Err(Error::Usage(format!(
+            "unexpected argument `{}`\n\n{}",
+            extra, USAGE
+        )));
+    }
+    Ok(Args {
+        command,
+        header,
+        dialect,
+        file,
+    })
+}
 
-    let unsuccessful_parse = CSVParser::parse(Rule::field, "this is not a number");
-    println!("{:?}", unsuccessful_parse);
+/// Reads the value of an option taking a single character
+fn char_option(option: &str, value: Option<String>) -> Result<char, Error> {
+    let value = value.ok_or_else(|| missing(&format!("value for `{}`", option)))?;
+    let mut chars = value.chars();
+    match (value.as_str(), chars.next(), chars.next()) {
+        ("tab", _, _) | ("\\t", _, _) => Ok('\t'),
+        (_, Some(c), None) => Ok(c),
+        _ => Err(Error::Usage(format!(
+            "`{}` takes a single character, not `{}`\n\n{}",
+            option, value, USAGE
+        ))),
+    }
+}
+
+fn missing(what: &str) -> Error {
+    Error::Usage(format!("missing {}\n\n{}", what, USAGE))
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    let text = match &args.file {
+        Some(file) => fs::read_to_string(file)?,
+        None => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    };
+    let dialect = args.dialect.apply(Dialect::sniff(&text));
+    dialect.validate().map_err(Error::Usage)?;
+    let table = Table::new(csv::parse(&text, &dialect)?, args.header);
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match &args.command {
+        Command::Stats => commands::stats(&table, &mut out)?,
+        Command::Json => commands::json(&table, &mut out)?,
+        Command::Select(columns) => {
+            let columns = columns
+                .split(',')
+                .map(|column| table.column(column.trim()))
+                .collect::<Result<Vec<_>, _>>()?;
+            commands::select(&table, &dialect, &columns, &mut out)?
+        }
+        Command::Filter(expr) => {
+            let filter = Filter::parse(expr, &table)?;
+            commands::filter(&table, &dialect, &filter, &mut out)?
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(run);
+    match result {
+        Ok(()) => {}
+        Err(Error::Usage(message)) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+        Err(e) => {
+            eprintln!("csv-tool: {}", e);
+            process::exit(1);
+        }
+    }
 }
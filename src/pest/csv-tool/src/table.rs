@@ -0,0 +1,273 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::error::Error;
+
+/// A cell read as the type of its column
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(&'a str),
+}
+
+impl<'a> Value<'a> {
+    /// Reads a cell as the narrowest type it fits
+    pub fn infer(cell: &'a str) -> Value<'a> {
+        if cell.is_empty() {
+            Value::Null
+        } else if cell.eq_ignore_ascii_case("true") {
+            Value::Bool(true)
+        } else if cell.eq_ignore_ascii_case("false") {
+            Value::Bool(false)
+        } else if !is_number(cell) {
+            Value::Text(cell)
+        } else if let Ok(n) = cell.parse() {
+            Value::Int(n)
+        } else {
+            cell.parse().map_or(Value::Text(cell), Value::Float)
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Int(n) => Some(n as f64),
+            Value::Float(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Orders values of the same kind, treating integers and floats alike
+    pub fn compare(&self, other: &Value<'_>) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (a, b) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        }
+    }
+}
+
+/// Whether a cell is written as a decimal number, unlike `inf` or `NaN` which `f64` also
+/// accepts. Cells with a leading `+`, or a leading zero before other digits such as zip codes
+/// and IDs, are kept as text so that they are written back as they were.
+fn is_number(cell: &str) -> bool {
+    let digits = cell.strip_prefix('-').unwrap_or(cell).as_bytes();
+    let leading_zero = digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit();
+    !cell.starts_with('+')
+        && !leading_zero
+        && cell.bytes().any(|b| b.is_ascii_digit())
+        && cell
+            .bytes()
+            .all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    /// No values at all
+    Empty,
+    Boolean,
+    Integer,
+    Float,
+    Text,
+}
+
+impl ColumnType {
+    fn of(value: &Value<'_>) -> ColumnType {
+        match value {
+            Value::Null => ColumnType::Empty,
+            Value::Bool(_) => ColumnType::Boolean,
+            Value::Int(_) => ColumnType::Integer,
+            Value::Float(_) => ColumnType::Float,
+            Value::Text(_) => ColumnType::Text,
+        }
+    }
+
+    /// Returns the narrowest type that fits the values of both types
+    fn merge(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (ColumnType::Empty, t) | (t, ColumnType::Empty) => t,
+            (a, b) if a == b => a,
+            (ColumnType::Integer, ColumnType::Float) | (ColumnType::Float, ColumnType::Integer) => {
+                ColumnType::Float
+            }
+            _ => ColumnType::Text,
+        }
+    }
+
+    /// Reads a cell of a column of this type
+    pub fn read<'a>(self, cell: &'a str) -> Value<'a> {
+        match (self, Value::infer(cell)) {
+            (ColumnType::Text, _) if !cell.is_empty() => Value::Text(cell),
+            (ColumnType::Float, Value::Int(n)) => Value::Float(n as f64),
+            (_, value) => value,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColumnType::Empty => "empty",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Text => "text",
+        }
+    }
+}
+
+/// Records split into an optional header and rows, with a type inferred for each column
+#[derive(Debug)]
+pub struct Table {
+    pub has_header: bool,
+    /// Column names, numbered `column1` and up if the input has no header
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    pub types: Vec<ColumnType>,
+}
+
+impl Table {
+    /// Builds a table, detecting whether the first record is a header unless `header` says so
+    pub fn new(mut records: Vec<Vec<String>>, header: Option<bool>) -> Table {
+        let has_header = header.unwrap_or_else(|| detect_header(&records));
+        let width = records.iter().map(Vec::len).max().unwrap_or(0);
+        let headers = if has_header && !records.is_empty() {
+            let mut headers = records.remove(0);
+            for i in headers.len()..width {
+                headers.push(format!("column{}", i + 1));
+            }
+            headers
+        } else {
+            (1..=width).map(|i| format!("column{}", i)).collect()
+        };
+        let types = infer_types(&records, width);
+        Table {
+            has_header,
+            headers,
+            rows: records,
+            types,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// Finds a column by header, or else by 1-based index
+    pub fn column(&self, name: &str) -> Result<usize, Error> {
+        if let Some(i) = self.headers.iter().position(|header| header == name) {
+            return Ok(i);
+        }
+        match name.parse::<usize>() {
+            Ok(i) if i >= 1 && i <= self.width() => Ok(i - 1),
+            _ => Err(Error::UnknownColumn(name.to_string())),
+        }
+    }
+
+    /// Returns a cell's text, which is empty for cells missing from short rows
+    pub fn cell(&self, row: usize, column: usize) -> &str {
+        self.rows[row].get(column).map_or("", String::as_str)
+    }
+
+    pub fn value(&self, row: usize, column: usize) -> Value<'_> {
+        self.types[column].read(self.cell(row, column))
+    }
+}
+
+fn infer_types(rows: &[Vec<String>], width: usize) -> Vec<ColumnType> {
+    let mut types = vec![ColumnType::Empty; width];
+    for row in rows {
+        for (t, cell) in types.iter_mut().zip(row) {
+            *t = t.merge(ColumnType::of(&Value::infer(cell)));
+        }
+    }
+    types
+}
+
+/// Guesses whether the first record names the columns: each typed column votes for a header if
+/// the first record's cell does not fit the column's type. If all columns are text, the first
+/// record is a header if its cells are present and distinct.
+fn detect_header(records: &[Vec<String>]) -> bool {
+    let (first, rest) = match records.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+    let types = infer_types(rest, first.len());
+    let mut votes = 0;
+    for (cell, t) in first.iter().zip(types) {
+        if t == ColumnType::Empty || t == ColumnType::Text {
+            continue;
+        }
+        if t.merge(ColumnType::of(&Value::infer(cell))) == t {
+            votes -= 1;
+        } else {
+            votes += 1;
+        }
+    }
+    if votes != 0 {
+        return votes > 0;
+    }
+    let mut seen = HashSet::new();
+    first
+        .iter()
+        .all(|cell| matches!(Value::infer(cell), Value::Text(_)) && seen.insert(cell))
+}
+
+#[cfg(test)]
+fn records(rows: &[&[&str]]) -> Vec<Vec<String>> {
+    rows.iter()
+        .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+        .collect()
+}
+
+#[test]
+fn infer_column_types() {
+    let table = Table::new(
+        records(&[
+            &["id", "price", "name", "active", "note"],
+            &["1", "9.5", "pen", "true", ""],
+            &["2", "10", "ink", "FALSE", ""],
+        ]),
+        None,
+    );
+    assert!(table.has_header);
+    assert_eq!(
+        table.types,
+        vec![
+            ColumnType::Integer,
+            ColumnType::Float,
+            ColumnType::Text,
+            ColumnType::Boolean,
+            ColumnType::Empty
+        ]
+    );
+    assert_eq!(table.value(1, 1), Value::Float(10.0));
+    assert_eq!(table.column("name").unwrap(), 2);
+    assert_eq!(table.column("4").unwrap(), 3);
+    assert!(table.column("6").is_err());
+}
+
+#[test]
+fn infer_values() {
+    assert_eq!(Value::infer("0"), Value::Int(0));
+    assert_eq!(Value::infer("-12"), Value::Int(-12));
+    assert_eq!(Value::infer("0.5"), Value::Float(0.5));
+    assert_eq!(Value::infer("-0.5e+1"), Value::Float(-5.0));
+    assert_eq!(Value::infer("02134"), Value::Text("02134"));
+    assert_eq!(Value::infer("-01"), Value::Text("-01"));
+    assert_eq!(Value::infer("00.5"), Value::Text("00.5"));
+    assert_eq!(Value::infer("+1"), Value::Text("+1"));
+    assert_eq!(Value::infer("NaN"), Value::Text("NaN"));
+}
+
+#[test]
+fn detect_missing_header() {
+    let table = Table::new(records(&[&["1", "a"], &["2", "b"]]), None);
+    assert!(!table.has_header);
+    assert_eq!(table.headers, vec!["column1", "column2"]);
+    assert_eq!(table.rows.len(), 2);
+
+    let table = Table::new(records(&[&["city", "country"], &["Paris", "France"]]), None);
+    assert!(table.has_header);
+}
@@ -8,7 +8,10 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     Internal(String),
 
-    Abort
+    Abort,
+
+    /// An invalid value, e.g. a malformed client command
+    Value(String),
 }
 
 impl std::error::Error for Error {}
@@ -20,6 +23,7 @@ impl fmt::Display for Error {
                 write!(f, "{}", s)
             }
             Error::Abort => write!(f, "Operation aborted"),
+            Error::Value(s) => write!(f, "{}", s),
         }
     }
 }
@@ -30,6 +34,12 @@ impl From<Box<bincode::ErrorKind>> for Error {
     }
 }
 
+impl From<tokio_rustls::rustls::TLSError> for Error {
+    fn from(err: tokio_rustls::rustls::TLSError) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
 impl From<std::array::TryFromSliceError> for Error {
     fn from(err: std::array::TryFromSliceError) -> Self {
         Error::Internal(err.to_string())
@@ -0,0 +1,67 @@
+use crate::error::Result;
+use crate::kv::{deserialize, serialize, Mutation, Query};
+use crate::raft;
+use std::ops::{Bound, RangeBounds};
+
+/// A typed client for the replicated key/value state machine
+#[derive(Clone)]
+pub struct Client {
+    raft: raft::Client,
+}
+
+impl Client {
+    /// Creates a new key/value client on top of a Raft client
+    pub fn new(raft: raft::Client) -> Self {
+        Self { raft }
+    }
+
+    async fn mutate<V: serde::de::DeserializeOwned>(&self, mutation: Mutation) -> Result<V> {
+        deserialize(&self.raft.mutate(serialize(&mutation)?).await?)
+    }
+
+    async fn query<V: serde::de::DeserializeOwned>(&self, query: Query) -> Result<V> {
+        deserialize(&self.raft.query(serialize(&query)?).await?)
+    }
+
+    /// Sets a key to a value
+    pub async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.mutate(Mutation::Set { key: key.to_vec(), value }).await
+    }
+
+    /// Deletes a key, returning true if it existed
+    pub async fn delete(&self, key: &[u8]) -> Result<bool> {
+        self.mutate(Mutation::Delete { key: key.to_vec() }).await
+    }
+
+    /// Sets a key to a value, or deletes it if None, if its current value is the expected one
+    /// (None if missing). Returns true if the value was swapped
+    pub async fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expect: Option<Vec<u8>>,
+        value: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        self.mutate(Mutation::CompareAndSwap { key: key.to_vec(), expect, value }).await
+    }
+
+    /// Gets the value of a key, if it exists
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.query(Query::Get { key: key.to_vec() }).await
+    }
+
+    /// Scans an ordered range of keys
+    pub async fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let bound = |bound: Bound<&Vec<u8>>| match bound {
+            Bound::Included(key) => Bound::Included(key.clone()),
+            Bound::Excluded(key) => Bound::Excluded(key.clone()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let (start, end) = (bound(range.start_bound()), bound(range.end_bound()));
+        self.query(Query::Scan { start, end }).await
+    }
+
+    /// Scans all keys starting with a prefix, in order
+    pub async fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.query(Query::ScanPrefix { prefix: prefix.to_vec() }).await
+    }
+}
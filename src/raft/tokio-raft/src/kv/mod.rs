@@ -0,0 +1,43 @@
+mod client;
+mod state;
+
+pub use client::Client;
+pub use state::State;
+
+use crate::error::{Error, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::ops::Bound;
+
+/// A key/value state machine mutation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Mutation {
+    /// Sets a key to a value, returning nothing
+    Set { key: Vec<u8>, value: Vec<u8> },
+    /// Deletes a key, returning true if it existed
+    Delete { key: Vec<u8> },
+    /// Sets a key to a value, or deletes it if None, if its current value is the expected one
+    /// (None if missing). Returns true if the value was swapped
+    CompareAndSwap { key: Vec<u8>, expect: Option<Vec<u8>>, value: Option<Vec<u8>> },
+}
+
+/// A key/value state machine query
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Query {
+    /// Gets the value of a key, returning None if it does not exist
+    Get { key: Vec<u8> },
+    /// Scans an ordered range of keys, returning the key/value pairs
+    Scan { start: Bound<Vec<u8>>, end: Bound<Vec<u8>> },
+    /// Scans all keys starting with a prefix, in order, returning the key/value pairs
+    ScanPrefix { prefix: Vec<u8> },
+}
+
+/// Encodes a command or result for the Raft log and client responses
+fn serialize<V: serde::Serialize>(value: &V) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(value)?)
+}
+
+/// Decodes a command or result. Commands come from clients, so a malformed one is a value
+/// error rather than an internal one, which would halt the state machine
+fn deserialize<'a, V: serde::Deserialize<'a>>(bytes: &'a [u8]) -> Result<V> {
+    bincode::deserialize(bytes).map_err(|err| Error::Value(format!("Invalid key/value command: {}", err)))
+}
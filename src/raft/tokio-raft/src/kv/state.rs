@@ -0,0 +1,198 @@
+use crate::error::{Error, Result};
+use crate::kv::{deserialize, serialize, Mutation, Query};
+use crate::raft::{Session, SessionResults};
+use crate::storage::kv::{Range, Store};
+use ::log::debug;
+use std::ops::Bound;
+
+/// A key in the underlying store. Data keys are kept in a separate keyspace from the state
+/// machine metadata
+#[derive(Clone, Debug, PartialEq)]
+enum Key<'a> {
+    /// The last applied Raft index
+    AppliedIndex,
+    /// Mutation results for a client session
+    Session(&'a [u8]),
+    /// A user key
+    Data(&'a [u8]),
+}
+
+impl<'a> Key<'a> {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::AppliedIndex => vec![0x00],
+            Self::Session(id) => [&[0x01], *id].concat(),
+            Self::Data(key) => [&[0x02], *key].concat(),
+        }
+    }
+
+    /// Encodes a range of user keys as a range of data keys. Unbounded ranges are limited to the
+    /// data keyspace. Empty or inverted ranges are rejected, since the store can't scan them.
+    fn data_range(start: Bound<Vec<u8>>, end: Bound<Vec<u8>>) -> Result<Range> {
+        match (&start, &end) {
+            (Bound::Included(s), Bound::Included(e)) if s > e => {
+                return Err(Error::Value(format!("Invalid scan range {:?} to {:?}", start, end)))
+            }
+            (Bound::Included(s), Bound::Excluded(e))
+            | (Bound::Excluded(s), Bound::Included(e))
+            | (Bound::Excluded(s), Bound::Excluded(e))
+                if s >= e =>
+            {
+                return Err(Error::Value(format!("Invalid scan range {:?} to {:?}", start, end)))
+            }
+            _ => {}
+        }
+        let start = match start {
+            Bound::Included(key) => Bound::Included(Key::Data(&key).encode()),
+            Bound::Excluded(key) => Bound::Excluded(Key::Data(&key).encode()),
+            Bound::Unbounded => Bound::Included(vec![0x02]),
+        };
+        let end = match end {
+            Bound::Included(key) => Bound::Included(Key::Data(&key).encode()),
+            Bound::Excluded(key) => Bound::Excluded(Key::Data(&key).encode()),
+            Bound::Unbounded => Bound::Excluded(vec![0x03]),
+        };
+        Ok(Range::from((start, end)))
+    }
+
+    /// Decodes a data key, stripping the keyspace prefix
+    fn decode_data(key: &[u8]) -> Result<Vec<u8>> {
+        match key.split_first() {
+            Some((0x02, key)) => Ok(key.to_vec()),
+            _ => Err(Error::Internal(format!("Invalid data key {:?}", key))),
+        }
+    }
+}
+
+/// A replicated, ordered key/value state machine. The applied index and client session results
+/// are stored along with the data, and flushed with each mutation, so the state machine resumes
+/// from the store's contents on restart. With an in-memory store, nothing outlives the process.
+pub struct State {
+    store: Box<dyn Store>,
+    applied_index: u64,
+}
+
+impl State {
+    /// Creates a new key/value state machine, loading the applied index from the store
+    pub fn new(store: Box<dyn Store>) -> Result<Self> {
+        let applied_index = store
+            .get(&Key::AppliedIndex.encode())?
+            .map(|v| bincode::deserialize(&v))
+            .transpose()?
+            .unwrap_or(0);
+        Ok(Self { store, applied_index })
+    }
+
+    /// Applies a mutation, returning the encoded result
+    fn apply(&mut self, mutation: Mutation) -> Result<Vec<u8>> {
+        match mutation {
+            Mutation::Set { key, value } => {
+                self.store.set(&Key::Data(&key).encode(), value)?;
+                serialize(&())
+            }
+            Mutation::Delete { key } => {
+                let key = Key::Data(&key).encode();
+                let existed = self.store.get(&key)?.is_some();
+                self.store.delete(&key)?;
+                serialize(&existed)
+            }
+            Mutation::CompareAndSwap { key, expect, value } => {
+                let key = Key::Data(&key).encode();
+                if self.store.get(&key)? != expect {
+                    return serialize(&false);
+                }
+                match value {
+                    Some(value) => self.store.set(&key, value)?,
+                    None => self.store.delete(&key)?,
+                }
+                serialize(&true)
+            }
+        }
+    }
+
+    /// Scans a range of data keys, returning the key/value pairs
+    fn scan(&self, range: Range) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.store
+            .scan(range)
+            .map(|r| r.and_then(|(k, v)| Ok((Key::decode_data(&k)?, v))))
+            .collect()
+    }
+}
+
+impl crate::raft::State for State {
+    fn applied_index(&self) -> u64 {
+        self.applied_index
+    }
+
+    fn mutate(
+        &mut self,
+        index: u64,
+        command: Vec<u8>,
+        session: Option<(&Session, SessionResults)>,
+    ) -> Result<Vec<u8>> {
+        let result = deserialize(&command).and_then(|mutation: Mutation| {
+            debug!("Applying key/value mutation {:?}", mutation);
+            self.apply(mutation)
+        });
+        if let Err(error @ Error::Internal(_)) = result {
+            return Err(error);
+        }
+        if let Some((session, mut results)) = session {
+            results.results.insert(session.seq, result.clone());
+            self.store.set(&Key::Session(&session.id).encode(), bincode::serialize(&results)?)?;
+        }
+        // The entry is applied even if the mutation failed, so record the index either way. The
+        // session results are flushed along with it
+        self.store.set(&Key::AppliedIndex.encode(), bincode::serialize(&index)?)?;
+        self.store.flush()?;
+        self.applied_index = index;
+        result
+    }
+
+    fn query(&self, command: Vec<u8>) -> Result<Vec<u8>> {
+        match deserialize(&command)? {
+            Query::Get { key } => serialize(&self.store.get(&Key::Data(&key).encode())?),
+            Query::Scan { start, end } => serialize(&self.scan(Key::data_range(start, end)?)?),
+            Query::ScanPrefix { prefix } => serialize(&self.scan(Range::prefix(&Key::Data(&prefix).encode()))?),
+        }
+    }
+
+    fn load_session(&self, id: &[u8]) -> Result<Option<SessionResults>> {
+        self.store.get(&Key::Session(id).encode())?.map(|v| Ok(bincode::deserialize(&v)?)).transpose()
+    }
+}
+
+#[test]
+fn scan_ranges() {
+    use crate::raft::State as _;
+    let mut state = State::new(Box::new(crate::storage::kv::Memory::new())).unwrap();
+    for (index, key) in [b"a", b"b", b"c"].iter().enumerate() {
+        let set = serialize(&Mutation::Set { key: key.to_vec(), value: key.to_vec() }).unwrap();
+        state.mutate(index as u64 + 1, set, None).unwrap();
+    }
+    let scan = |start: Bound<&[u8]>, end: Bound<&[u8]>| -> Result<Vec<Vec<u8>>> {
+        let start = start.map(|k| k.to_vec());
+        let end = end.map(|k| k.to_vec());
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> =
+            deserialize(&state.query(serialize(&Query::Scan { start, end })?)?)?;
+        Ok(pairs.into_iter().map(|(k, _)| k).collect())
+    };
+    let keys = |keys: &[&[u8]]| Ok(keys.iter().map(|k| k.to_vec()).collect());
+
+    assert_eq!(scan(Bound::Unbounded, Bound::Unbounded), keys(&[b"a", b"b", b"c"]));
+    assert_eq!(scan(Bound::Excluded(b"a"), Bound::Included(b"c")), keys(&[b"b", b"c"]));
+    assert_eq!(scan(Bound::Included(b"a"), Bound::Excluded(b"c")), keys(&[b"a", b"b"]));
+    assert_eq!(scan(Bound::Included(b"b"), Bound::Included(b"b")), keys(&[b"b"]));
+    assert_eq!(scan(Bound::Unbounded, Bound::Excluded(b"b")), keys(&[b"a"]));
+    assert_eq!(scan(Bound::Excluded(b"b"), Bound::Unbounded), keys(&[b"c"]));
+
+    for (start, end) in [
+        (Bound::Included(&b"c"[..]), Bound::Included(&b"a"[..])),
+        (Bound::Excluded(&b"b"[..]), Bound::Excluded(&b"b"[..])),
+        (Bound::Included(&b"b"[..]), Bound::Excluded(&b"b"[..])),
+    ]
+    .iter()
+    {
+        assert!(matches!(scan(*start, *end), Err(Error::Value(_))));
+    }
+}
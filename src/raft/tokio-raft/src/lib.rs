@@ -1,3 +1,4 @@
-mod storage;
-mod error;
-mod raft;
\ No newline at end of file
+pub mod storage;
+pub mod error;
+pub mod raft;
+pub mod kv;
@@ -1,12 +1,59 @@
 use crate::error::{Error, Result};
 use tokio::sync::{mpsc, oneshot};
-use crate::raft::message::{Response, Request};
+use crate::raft::message::{Response, Request, Session};
 use crate::raft::node::Status;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+use ::log::debug;
+
+/// The time to wait for a mutation to be applied before retrying it
+const MUTATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of times a mutation is retried after a timeout or leader change
+const MUTATE_RETRIES: u32 = 3;
+
+/// The delay before retrying a mutation
+const MUTATE_RETRY_DELAY: Duration = Duration::from_millis(200);
 
 /// A client for a local Raft server
 #[derive(Clone)]
 pub struct Client {
-    request_tx: mpsc::UnboundedSender<(Request, oneshot::Sender<Result<Response>>)>
+    request_tx: mpsc::UnboundedSender<(Request, oneshot::Sender<Result<Response>>)>,
+    /// The client session id, used to apply retried mutations exactly once
+    session: Vec<u8>,
+    /// Session sequence numbers, shared between clones of the client
+    sequence: Arc<Mutex<Sequence>>,
+}
+
+/// Tracks the sequence numbers of a client session
+#[derive(Default)]
+struct Sequence {
+    /// The next sequence number to use
+    next: u64,
+    /// Sequence numbers of mutations that have not yet returned a result
+    pending: BTreeSet<u64>,
+}
+
+impl Sequence {
+    /// Starts a new mutation, returning its sequence number
+    fn start(&mut self) -> u64 {
+        let seq = self.next;
+        self.next += 1;
+        self.pending.insert(seq);
+        seq
+    }
+
+    /// Finishes a mutation once its result has been received
+    fn finish(&mut self, seq: u64) {
+        self.pending.remove(&seq);
+    }
+
+    /// Returns the sequence number below which all results have been received
+    fn ack(&self) -> u64 {
+        self.pending.iter().next().cloned().unwrap_or(self.next)
+    }
 }
 
 impl Client {
@@ -15,7 +62,9 @@ impl Client {
         request_tx: mpsc::UnboundedSender<(Request, oneshot::Sender<Result<Response>>)>,
     ) -> Self {
         Self {
-            request_tx
+            request_tx,
+            session: Uuid::new_v4().as_bytes().to_vec(),
+            sequence: Arc::new(Mutex::new(Sequence::default())),
         }
     }
 
@@ -26,11 +75,34 @@ impl Client {
         response_rx.await?
     }
 
-    /// Mutates the Raft state machine
+    /// Mutates the Raft state machine. The mutation is retried on timeouts and leader changes,
+    /// and applied exactly once
     pub async fn mutate(&self, command: Vec<u8>) -> Result<Vec<u8>> {
-        match self.request(Request::Mutate(command)).await? {
-            Response::State(response) => Ok(response),
-            resp => Err(Error::Internal(format!("Unexpected Raft mutate response {:?}", resp)))
+        let seq = self.sequence.lock()?.start();
+        let result = self.mutate_session(seq, command).await;
+        self.sequence.lock()?.finish(seq);
+        result
+    }
+
+    async fn mutate_session(&self, seq: u64, command: Vec<u8>) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let session = Session { id: self.session.clone(), seq, ack: self.sequence.lock()?.ack() };
+            let request = self.request(Request::Mutate { command: command.clone(), session });
+            let result = match tokio::time::timeout(MUTATE_TIMEOUT, request).await {
+                Ok(result) => result,
+                Err(_) => Err(Error::Abort),
+            };
+            match result {
+                Err(Error::Abort) if attempt < MUTATE_RETRIES => {
+                    attempt += 1;
+                    debug!("Retrying Raft mutation {} (attempt {})", seq, attempt);
+                    tokio::time::delay_for(MUTATE_RETRY_DELAY).await;
+                }
+                Ok(Response::State(response)) => return Ok(response),
+                Ok(resp) => return Err(Error::Internal(format!("Unexpected Raft mutate response {:?}", resp))),
+                Err(error) => return Err(error),
+            }
         }
     }
 
@@ -48,4 +120,4 @@ impl Client {
             resp => Err(Error::Internal(format!("Unexpected Raft status response {:?}", resp)))
         }
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,5 @@
 use crate::error::{Result, Error};
+use crate::raft::message::Session;
 use crate::storage::log;
 use serde_derive::{Deserialize, Serialize};
 use ::log::debug;
@@ -13,7 +14,9 @@ pub struct Entry {
     /// The term in which the entry was added
     pub term: u64,
     /// The state machine command. None is used to commit noops during leader election
-    pub command: Option<Vec<u8>>
+    pub command: Option<Vec<u8>>,
+    /// The client session which submitted the command, if any
+    pub session: Option<Session>,
 }
 
 /// A metadata key
@@ -79,11 +82,17 @@ impl Log {
     }
 
     /// Append a command to the log, returning the entry
-    pub fn append(&mut self, term: u64, command: Option<Vec<u8>>) -> Result<Entry> {
+    pub fn append(
+        &mut self,
+        term: u64,
+        command: Option<Vec<u8>>,
+        session: Option<Session>,
+    ) -> Result<Entry> {
         let entry = Entry {
             index: self.last_index + 1,
             term,
-            command
+            command,
+            session,
         };
         debug!("Appending log entry {}: {:?}", entry.index, entry);
         self.store.append(Self::serialize(&entry)?)?;
@@ -139,7 +148,7 @@ impl Log {
                 }
                 self.truncate(entry.index - 1)?;
             }
-            self.append(entry.term, entry.command)?;
+            self.append(entry.term, entry.command, entry.session)?;
         }
         Ok(self.last_index)
     }
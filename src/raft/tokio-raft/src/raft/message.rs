@@ -36,7 +36,9 @@ pub enum Event {
         /// The index of the leader's last committed log entry
         commit_index: u64,
         /// The term of the leader's last committed log entry
-        commit_term: u64
+        commit_term: u64,
+        /// The heartbeat sequence number, echoed back by followers to confirm leadership
+        seq: u64,
     },
     /// Followers confirm loyalty to leader after heartbeats
     ConfirmLeader {
@@ -46,6 +48,10 @@ pub enum Event {
         /// If false, the follower does not have the entry at commit_index
         /// and would like the leader to replicate it
         has_committed: bool,
+        /// The sequence number of the confirmed heartbeat
+        seq: u64,
+        /// The last index applied by the follower's state machine, for apply lag metrics
+        applied_index: u64,
     },
     /// Candidates solicit votes from all peers
     SolicitVote {
@@ -67,11 +73,26 @@ pub enum Event {
     },
     /// Followers may accept a set of log entries from a leader
     AcceptEntries {
-        /// The index of the last log entry
+        /// The index of the last replicated log entry
         last_index: u64
     },
     /// Followers may also reject a set of log entries from a leader
-    RejectEntries,
+    RejectEntries {
+        /// The base index of the rejected entries
+        base_index: u64
+    },
+    /// Followers serving reads locally ask the leader for a read index
+    SolicitReadIndex {
+        /// The id of the client query
+        id: Vec<u8>,
+    },
+    /// Leaders grant a read index once their leadership has been confirmed
+    GrantReadIndex {
+        /// The id of the client query
+        id: Vec<u8>,
+        /// The index which must be applied before the query can be executed
+        index: u64,
+    },
     /// A client request
     ClientRequest {
         /// The request Id
@@ -89,10 +110,24 @@ pub enum Event {
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Request {
     Query(Vec<u8>),
-    Mutate(Vec<u8>),
+    Mutate {
+        command: Vec<u8>,
+        session: Session,
+    },
     Status
 }
 
+/// A client session sequence number, used to apply retried mutations exactly once
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// The session id
+    pub id: Vec<u8>,
+    /// The sequence number of the mutation within the session
+    pub seq: u64,
+    /// The client has received results for all sequence numbers below this
+    pub ack: u64,
+}
+
 /// A client response
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Response {
@@ -0,0 +1,296 @@
+use crate::error::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::stream::StreamExt;
+use ::log::{debug, error};
+
+/// Replication latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// The maximum size of a status HTTP request head, in bytes
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// A Raft node role, as exported in metrics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Follower = 0,
+    Candidate = 1,
+    Leader = 2,
+}
+
+impl Role {
+    fn from_u64(role: u64) -> Self {
+        match role {
+            1 => Role::Candidate,
+            2 => Role::Leader,
+            _ => Role::Follower,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Role::Follower => "follower",
+            Role::Candidate => "candidate",
+            Role::Leader => "leader",
+        }
+    }
+}
+
+/// A histogram of durations with fixed buckets
+struct Histogram {
+    /// Observations per bucket, not cumulative. The last one counts observations above all buckets
+    buckets: Vec<AtomicU64>,
+    /// The sum of all observations, in microseconds
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: (0..=LATENCY_BUCKETS.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS.iter().position(|le| seconds <= *le).unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn export(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut count = 0;
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(self.buckets.iter()) {
+            count += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, count);
+        }
+        count += self.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// Raft node metrics, updated by the node and state machine driver and exported in the
+/// Prometheus text format
+pub struct Metrics {
+    node: String,
+    role: AtomicU64,
+    term: AtomicU64,
+    role_changes: AtomicU64,
+    elections: AtomicU64,
+    last_index: AtomicU64,
+    commit_index: AtomicU64,
+    applied_index: AtomicU64,
+    log_size: AtomicU64,
+    driver_queries: AtomicU64,
+    /// The number of committed entries missing from each peer's log, when leader
+    peer_commit_lag: Mutex<BTreeMap<String, u64>>,
+    /// The number of committed entries not yet applied by each peer, when leader
+    peer_apply_lag: Mutex<BTreeMap<String, u64>>,
+    replication_latency: Histogram,
+}
+
+impl Metrics {
+    /// Creates a new set of metrics for a node
+    pub fn new(node: &str) -> Self {
+        Self {
+            node: node.to_string(),
+            role: AtomicU64::new(Role::Follower as u64),
+            term: AtomicU64::new(0),
+            role_changes: AtomicU64::new(0),
+            elections: AtomicU64::new(0),
+            last_index: AtomicU64::new(0),
+            commit_index: AtomicU64::new(0),
+            applied_index: AtomicU64::new(0),
+            log_size: AtomicU64::new(0),
+            driver_queries: AtomicU64::new(0),
+            peer_commit_lag: Mutex::new(BTreeMap::new()),
+            peer_apply_lag: Mutex::new(BTreeMap::new()),
+            replication_latency: Histogram::new(),
+        }
+    }
+
+    /// Records the current node state, counting role changes and elections started since the
+    /// last observation
+    pub fn observe_node(&self, role: Role, term: u64, last_index: u64, commit_index: u64) {
+        let old_role = Role::from_u64(self.role.swap(role as u64, Ordering::Relaxed));
+        let old_term = self.term.swap(term, Ordering::Relaxed);
+        if role != old_role {
+            self.role_changes.fetch_add(1, Ordering::Relaxed);
+        }
+        if role == Role::Candidate && (role != old_role || term != old_term) {
+            self.elections.fetch_add(1, Ordering::Relaxed);
+        }
+        self.last_index.store(last_index, Ordering::Relaxed);
+        self.commit_index.store(commit_index, Ordering::Relaxed);
+        if role != Role::Leader && old_role == Role::Leader {
+            if let Ok(mut lag) = self.peer_commit_lag.lock() {
+                lag.clear();
+            }
+            if let Ok(mut lag) = self.peer_apply_lag.lock() {
+                lag.clear();
+            }
+        }
+    }
+
+    /// Records the size of the log store, in bytes
+    pub fn observe_log_size(&self, size: u64) {
+        self.log_size.store(size, Ordering::Relaxed);
+    }
+
+    /// Records the last index replicated to and applied by a peer, when leader
+    pub fn observe_peer(
+        &self,
+        peer: &str,
+        commit_index: u64,
+        peer_last_index: u64,
+        peer_applied_index: u64,
+    ) {
+        if let Ok(mut lag) = self.peer_commit_lag.lock() {
+            lag.insert(peer.to_string(), commit_index.saturating_sub(peer_last_index));
+        }
+        if let Ok(mut lag) = self.peer_apply_lag.lock() {
+            lag.insert(peer.to_string(), commit_index.saturating_sub(peer_applied_index));
+        }
+    }
+
+    /// Records the latency of a replication round trip to a peer
+    pub fn observe_replication(&self, latency: Duration) {
+        self.replication_latency.observe(latency);
+    }
+
+    /// Records the state machine driver's applied index and number of queued queries
+    pub fn observe_driver(&self, applied_index: u64, queries: u64) {
+        self.applied_index.store(applied_index, Ordering::Relaxed);
+        self.driver_queries.store(queries, Ordering::Relaxed);
+    }
+
+    /// Returns the state machine driver's applied index, as last observed
+    pub fn applied_index(&self) -> u64 {
+        self.applied_index.load(Ordering::Relaxed)
+    }
+
+    /// Exports the metrics in the Prometheus text format
+    pub fn export(&self) -> String {
+        let mut out = String::new();
+        let role = Role::from_u64(self.role.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP raft_role The current role of the node");
+        let _ = writeln!(out, "# TYPE raft_role gauge");
+        for r in [Role::Follower, Role::Candidate, Role::Leader].iter() {
+            let _ = writeln!(out, "raft_role{{role=\"{}\"}} {}", r.name(), (*r == role) as u64);
+        }
+        let scalar = |out: &mut String, name: &str, kind: &str, help: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        };
+        scalar(&mut out, "raft_term", "gauge", "The current term", &self.term);
+        scalar(&mut out, "raft_role_changes_total", "counter", "The number of role changes", &self.role_changes);
+        scalar(&mut out, "raft_elections_total", "counter", "The number of elections started", &self.elections);
+        scalar(&mut out, "raft_last_index", "gauge", "The index of the last log entry", &self.last_index);
+        scalar(&mut out, "raft_commit_index", "gauge", "The index of the last committed entry", &self.commit_index);
+        scalar(&mut out, "raft_applied_index", "gauge", "The index of the last applied entry", &self.applied_index);
+        scalar(&mut out, "raft_log_size_bytes", "gauge", "The size of the log store", &self.log_size);
+        scalar(&mut out, "raft_driver_queries", "gauge", "The number of queued queries", &self.driver_queries);
+
+        let commit_index = self.commit_index.load(Ordering::Relaxed);
+        let applied_index = self.applied_index.load(Ordering::Relaxed);
+        let _ = writeln!(out, "# HELP raft_apply_lag Committed entries not yet applied to the state machine");
+        let _ = writeln!(out, "# TYPE raft_apply_lag gauge");
+        let _ = writeln!(out, "raft_apply_lag {}", commit_index.saturating_sub(applied_index));
+        let _ = writeln!(out, "# HELP raft_peer_commit_lag Committed entries not yet replicated to a peer");
+        let _ = writeln!(out, "# TYPE raft_peer_commit_lag gauge");
+        if let Ok(lag) = self.peer_commit_lag.lock() {
+            for (peer, lag) in lag.iter() {
+                let _ = writeln!(out, "raft_peer_commit_lag{{peer=\"{}\"}} {}", peer, lag);
+            }
+        }
+        let _ = writeln!(out, "# HELP raft_peer_apply_lag Committed entries not yet applied by a peer");
+        let _ = writeln!(out, "# TYPE raft_peer_apply_lag gauge");
+        if let Ok(lag) = self.peer_apply_lag.lock() {
+            for (peer, lag) in lag.iter() {
+                let _ = writeln!(out, "raft_peer_apply_lag{{peer=\"{}\"}} {}", peer, lag);
+            }
+        }
+        self.replication_latency.export(
+            &mut out,
+            "raft_replication_latency_seconds",
+            "Latency from replicating entries to a peer until it accepts them",
+        );
+        out
+    }
+
+    /// Returns a brief human-readable node status
+    pub fn status(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "node: {}", self.node);
+        let _ = writeln!(out, "role: {}", Role::from_u64(self.role.load(Ordering::Relaxed)).name());
+        let _ = writeln!(out, "term: {}", self.term.load(Ordering::Relaxed));
+        let _ = writeln!(out, "last_index: {}", self.last_index.load(Ordering::Relaxed));
+        let _ = writeln!(out, "commit_index: {}", self.commit_index.load(Ordering::Relaxed));
+        let _ = writeln!(out, "applied_index: {}", self.applied_index.load(Ordering::Relaxed));
+        out
+    }
+}
+
+/// Serves metrics at /metrics and the node status at /status over HTTP, such that monitoring can
+/// scrape a node without joining the cluster
+pub async fn serve(mut listener: TcpListener, metrics: Arc<Metrics>) -> Result<()> {
+    while let Some(socket) = listener.try_next().await? {
+        let peer = socket.peer_addr()?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_request(socket, metrics).await {
+                error!("Status request from {} failed: {}", peer, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Serves a single HTTP request, closing the connection afterwards
+async fn serve_request(mut socket: TcpStream, metrics: Arc<Metrics>) -> Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_SIZE {
+            return Err(Error::Internal("Status request too large".into()));
+        }
+        match socket.read(&mut buf).await? {
+            0 => return Ok(()),
+            n => head.extend_from_slice(&buf[..n]),
+        }
+    }
+    let head = String::from_utf8(head)?;
+    let mut request_line = head.lines().next().unwrap_or("").split(' ');
+    let (method, path) = (request_line.next().unwrap_or(""), request_line.next().unwrap_or(""));
+    debug!("Status request {} {}", method, path);
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", metrics.export()),
+        ("GET", "/status") => ("200 OK", "text/plain", metrics.status()),
+        ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    Ok(())
+}
@@ -3,4 +3,14 @@ mod log;
 mod message;
 mod client;
 mod state;
-mod server;
\ No newline at end of file
+mod server;
+mod metrics;
+mod transport;
+
+pub use client::Client;
+pub use server::Server;
+pub use log::Log;
+pub use message::{Request, Response, Session};
+pub use node::{ReadOptions, Status};
+pub use state::{State, SessionResults};
+pub use transport::Transport;
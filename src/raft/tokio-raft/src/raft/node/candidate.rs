@@ -45,10 +45,7 @@ impl RoleNode<Candidate> {
         let peers = self.peers.clone();
         let last_index = self.log.last_index;
         let mut node = self.become_role(Leader::new(peers, last_index))?;
-        node.send(Address::Peers,Event::Heartbeat {
-            commit_index: node.log.commit_index,
-            commit_term: node.log.commit_term
-        })?;
+        node.heartbeat()?;
         node.append(None)?;
         node.abort_proxied()?;
         Ok(node)
@@ -97,7 +94,9 @@ impl RoleNode<Candidate> {
             Event::ConfirmLeader {..}
             | Event::ReplicateEntries { .. }
             | Event::AcceptEntries { .. }
-            | Event::RejectEntries { .. } => {
+            | Event::RejectEntries { .. }
+            | Event::SolicitReadIndex { .. }
+            | Event::GrantReadIndex { .. } => {
                 warn!("Received unexpected message {:?}", msg);
             }
         }
@@ -1,8 +1,9 @@
 use rand::Rng;
+use std::collections::HashMap;
 use crate::error::Result;
 use crate::raft::node::{ELECTION_TIMEOUT_MIN, ELECTION_TIMEOUT_MAX, RoleNode, Node};
 use crate::raft::node::candidate::Candidate;
-use crate::raft::message::{Address, Event, Message, Response};
+use crate::raft::message::{Address, Event, Message, Request, Response};
 use crate::raft::state::Instruction;
 use ::log::{debug, info, warn};
 
@@ -17,6 +18,11 @@ pub struct Follower {
     leader_seen_timeout: u64,
     /// The node we voted for in the current term, if any
     voted_for: Option<String>,
+    /// Query commands waiting for a read index from the leader. <id, command>
+    read_reqs: HashMap<Vec<u8>, Vec<u8>>,
+    /// The number of ticks since we last confirmed a leader heartbeat, granting it a lease. This
+    /// is kept across leader changes, since the lease outlives our knowledge of the leader
+    lease_ticks: u64,
 }
 
 
@@ -25,11 +31,20 @@ impl Follower {
         Self {
             leader: leader.map(String::from),
             voted_for: voted_for.map(String::from),
+            read_reqs: HashMap::new(),
+            lease_ticks: ELECTION_TIMEOUT_MIN,
             leader_seen_ticks: 0,
             leader_seen_timeout: rand::thread_rng()
                 .gen_range(ELECTION_TIMEOUT_MIN, ELECTION_TIMEOUT_MAX),
         }
     }
+
+    /// Assumes a lease was granted just now, e.g. before a restart, since a lease granted by a
+    /// previous incarnation of the node is not persisted
+    pub fn with_lease_granted(mut self) -> Self {
+        self.lease_ticks = 0;
+        self
+    }
 }
 
 impl RoleNode<Follower> {
@@ -55,7 +70,9 @@ impl RoleNode<Follower> {
             info!("Discovered leader {}, following", leader);
             voted_for = self.role.voted_for;
         };
+        let lease_ticks = self.role.lease_ticks;
         self.role = Follower::new(Some(leader), voted_for.as_deref());
+        self.role.lease_ticks = lease_ticks;
         self.abort_proxied()?;
         self.forward_queued(Address::Peer(leader.to_string()))?;
         Ok(self.into())
@@ -73,6 +90,16 @@ impl RoleNode<Follower> {
             warn!("Ignoring invalid message: {}", err);
             return Ok(self.into());
         }
+        // A leader may be serving reads from a lease we granted it, so we must not help elect
+        // anyone else until it would have expired. This holds whether or not we use leases
+        // ourselves, such that nodes need not agree on ReadOptions::lease.
+        if !self.is_leader(&msg.from)
+            && self.role.lease_ticks < ELECTION_TIMEOUT_MIN
+            && matches!(msg.event, Event::SolicitVote { .. })
+        {
+            debug!("Ignoring vote solicitation from {:?} during leader lease", msg.from);
+            return Ok(self.into());
+        }
         if let Address::Peer(from) = &msg.from {
             if msg.term > self.term || self.role.leader.is_none() {
                 return self.become_follower(from, msg.term)?.step(msg);
@@ -83,7 +110,7 @@ impl RoleNode<Follower> {
         }
 
         match msg.event {
-            Event::Heartbeat { commit_index, commit_term } => {
+            Event::Heartbeat { commit_index, commit_term, seq } => {
                 if self.is_leader(&msg.from) {
                     let has_committed = self.log.has(commit_index, commit_term)?;
                     if has_committed && commit_index > self.log.commit_index {
@@ -94,7 +121,12 @@ impl RoleNode<Follower> {
                             self.state_tx.send(Instruction::Apply { entry})?;
                         }
                     }
-                    self.send(msg.from, Event::ConfirmLeader { commit_index, has_committed})?;
+                    self.role.lease_ticks = 0;
+                    let applied_index = self.metrics.applied_index();
+                    self.send(
+                        msg.from,
+                        Event::ConfirmLeader { commit_index, has_committed, seq, applied_index },
+                    )?;
                 }
             }
             Event::SolicitVote { last_index, last_term} => {
@@ -120,13 +152,43 @@ impl RoleNode<Follower> {
                 if self.is_leader(&msg.from) {
                     if base_index > 0 && !self.log.has(base_index, base_term)? {
                         debug!("Rejecting log entries at base {}", base_index);
-                        self.send(msg.from, Event::RejectEntries)?;
+                        self.send(msg.from, Event::RejectEntries { base_index })?;
                     } else {
-                        let last_index = self.log.splice(entries)?;
+                        // Our log may extend past the replicated entries, but only these are
+                        // known to match the leader's log
+                        let last_index = base_index + entries.len() as u64;
+                        self.log.splice(entries)?;
                         self.send(msg.from, Event::AcceptEntries { last_index})?;
                     }
                 }
             }
+            Event::ClientRequest { id, request: Request::Query(command) }
+                if self.reads.follower_reads && self.role.leader.is_some() =>
+            {
+                let leader = self.role.leader.clone().unwrap();
+                self.proxied_reqs.insert(id.clone(), msg.from);
+                self.role.read_reqs.insert(id.clone(), command);
+                self.send(Address::Peer(leader), Event::SolicitReadIndex { id })?;
+            }
+
+            Event::GrantReadIndex { id, index } => {
+                if self.is_leader(&msg.from) {
+                    if let (Some(command), Some(address)) =
+                        (self.role.read_reqs.remove(&id), self.proxied_reqs.remove(&id))
+                    {
+                        self.state_tx.send(Instruction::Query {
+                            id,
+                            address,
+                            command,
+                            term: 0,
+                            index,
+                            quorum: 1,
+                        })?;
+                        self.state_tx.send(Instruction::Vote { term: 0, index, address: Address::Local })?;
+                    }
+                }
+            }
+
             Event::ClientRequest { ref id, .. } => {
                 if let Some(leader) = self.role.leader.as_deref() {
                     self.proxied_reqs.insert(id.clone(), msg.from);
@@ -141,11 +203,15 @@ impl RoleNode<Follower> {
                     status.server = self.id.clone();
                 }
                 self.proxied_reqs.remove(&id);
+                self.role.read_reqs.remove(&id);
                 self.send(Address::Client, Event::ClientResponse { id, response})?;
             }
 
             Event::GrantVote => {},
-            Event::ConfirmLeader { ..} | Event::AcceptEntries { ..} | Event::RejectEntries {..} => {
+            Event::ConfirmLeader { ..}
+            | Event::AcceptEntries { ..}
+            | Event::RejectEntries {..}
+            | Event::SolicitReadIndex { .. } => {
                 warn!("Received unexpected message {:?}", msg);
             }
         };
@@ -154,10 +220,54 @@ impl RoleNode<Follower> {
 
     pub fn tick(mut self) -> Result<Node> {
         self.role.leader_seen_ticks += 1;
+        self.role.lease_ticks += 1;
         if self.role.leader_seen_ticks >= self.role.leader_seen_timeout {
             Ok(self.become_candidate()?.into())
         } else {
             Ok(self.into())
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn lease_expires_before_voting() {
+    use crate::raft::node::{setup, sent, ReadOptions};
+    let (mut follower, mut node_rx, _state_rx) =
+        setup("a", &["b", "c"], ReadOptions::default(), Follower::new(Some("b"), None));
+    follower.role.leader_seen_timeout = u64::MAX;
+    let mut node: Node = follower.into();
+    let heartbeat = Message {
+        term: 1,
+        from: Address::Peer("b".into()),
+        to: Address::Peer("a".into()),
+        event: Event::Heartbeat { commit_index: 0, commit_term: 0, seq: 1 },
+    };
+    let solicit = Message {
+        term: 2,
+        from: Address::Peer("c".into()),
+        to: Address::Peer("a".into()),
+        event: Event::SolicitVote { last_index: 0, last_term: 0 },
+    };
+
+    node = node.step(heartbeat).unwrap();
+    assert_eq!(
+        sent(&mut node_rx).pop().map(|m| m.event),
+        Some(Event::ConfirmLeader { commit_index: 0, has_committed: true, seq: 1, applied_index: 0 })
+    );
+    for _ in 1..ELECTION_TIMEOUT_MIN {
+        node = node.tick().unwrap();
+        node = node.step(solicit.clone()).unwrap();
+        assert_eq!(sent(&mut node_rx), vec![]);
+    }
+
+    node = node.tick().unwrap();
+    node = node.step(solicit).unwrap();
+    let msgs = sent(&mut node_rx);
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].to, Address::Peer("c".into()));
+    assert_eq!(msgs[0].event, Event::GrantVote);
+    match node {
+        Node::Follower(n) => assert_eq!(n.term, 2),
+        _ => panic!("expected follower"),
+    }
+}
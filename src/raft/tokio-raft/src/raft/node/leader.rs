@@ -1,20 +1,50 @@
-use std::collections::HashMap;
-use crate::raft::node::{RoleNode, Node, Status, HEARTBEAT_INTERVAL};
+use std::collections::{BTreeMap, HashMap};
+use crate::raft::node::{
+    RoleNode, Node, Status, ELECTION_TIMEOUT_MIN, HEARTBEAT_INTERVAL, LEASE_DURATION, REPLICATE_TIMEOUT,
+};
 use crate::raft::node::follower::Follower;
 use crate::error::{Result, Error};
 use crate::raft::state::Instruction;
 use crate::raft::message::{Address, Event, Message, Request, Response};
+use std::cmp::max;
+use std::time::Instant;
 use ::log::{debug, info, warn};
 
+/// The number of ticks after which a heartbeat not confirmed by a quorum is expired. It could
+/// no longer extend the lease, and the leader has likely been replaced by then.
+const HEARTBEAT_EXPIRY: u64 = ELECTION_TIMEOUT_MIN;
+
 /// A leader serves requests and replicates the log to followers
 #[derive(Debug)]
 pub struct Leader {
     /// Number of ticks since last heartbeat
     heartbeat_ticks: u64,
-    /// The next index to replicate to peer
+    /// The next index to replicate to peer. Entries are pipelined, so this is usually ahead of
+    /// the entries accepted by the peer
     peer_next_index: HashMap<String, u64>,
     /// The last index known to be replicated on a peer
-    peer_last_index: HashMap<String ,u64>
+    peer_last_index: HashMap<String ,u64>,
+    /// Number of ticks since a peer with unreplicated entries last responded to them
+    peer_stalled_ticks: HashMap<String, u64>,
+    /// When entries were first replicated to a peer, for latency metrics. Dropped when the peer
+    /// stalls, such that a dead peer does not accumulate them. <peer, <last_index, sent>>
+    peer_replicated_at: HashMap<String, BTreeMap<u64, Instant>>,
+    /// The last index applied by a peer's state machine, as of its last heartbeat confirmation
+    peer_applied_index: HashMap<String, u64>,
+    /// Number of ticks since becoming leader, used as the lease clock
+    ticks: u64,
+    /// The sequence number of the last heartbeat sent
+    heartbeat_seq: u64,
+    /// The tick at which each heartbeat not yet confirmed by a quorum was sent. Heartbeats are
+    /// expired after HEARTBEAT_EXPIRY ticks. <seq, tick>
+    heartbeat_sent: BTreeMap<u64, u64>,
+    /// The last heartbeat sequence number confirmed by a peer
+    peer_heartbeat_seq: HashMap<String, u64>,
+    /// The tick at which the leader lease expires
+    lease_expiry: u64,
+    /// Read index requests waiting for a quorum to confirm a heartbeat, aborted if it expires.
+    /// <seq, [(from, id, index)]>
+    read_index_reqs: BTreeMap<u64, Vec<(Address, Vec<u8>, u64)>>,
 }
 
 impl Leader {
@@ -23,11 +53,23 @@ impl Leader {
         let mut leader = Self {
             heartbeat_ticks: 0,
             peer_next_index: HashMap::new(),
-            peer_last_index: HashMap::new()
+            peer_last_index: HashMap::new(),
+            peer_stalled_ticks: HashMap::new(),
+            peer_replicated_at: HashMap::new(),
+            peer_applied_index: HashMap::new(),
+            ticks: 0,
+            heartbeat_seq: 0,
+            heartbeat_sent: BTreeMap::new(),
+            peer_heartbeat_seq: HashMap::new(),
+            lease_expiry: 0,
+            read_index_reqs: BTreeMap::new(),
         };
         for peer in peers {
             leader.peer_next_index.insert(peer.clone(), last_index + 1);
             leader.peer_last_index.insert(peer.clone(), 0);
+            leader.peer_stalled_ticks.insert(peer.clone(), 0);
+            leader.peer_heartbeat_seq.insert(peer.clone(), 0);
+            leader.peer_applied_index.insert(peer.clone(), 0);
         }
         leader
     }
@@ -44,14 +86,24 @@ impl RoleNode<Leader> {
         self.become_role(Follower::new(Some(leader), None))
     }
 
+    /// Appends a command to the log and replicates it to peers
     pub fn append(&mut self, command: Option<Vec<u8>>) -> Result<u64> {
-        let entry = self.log.append(self.term, command)?;
-        for peer in self.peers.iter() {
-            self.replicate(peer)?;
-        }
+        let entry = self.log.append(self.term, command, None)?;
+        self.flush()?;
         Ok(entry.index)
     }
 
+    /// Replicates any entries that have not yet been sent to peers, such that entries appended
+    /// since the last flush are sent as a single batch
+    pub fn flush(&mut self) -> Result<()> {
+        for peer in self.peers.clone() {
+            if self.role.peer_next_index.get(&peer).cloned().unwrap_or(0) <= self.log.last_index {
+                self.replicate(&peer)?;
+            }
+        }
+        Ok(())
+    }
+
     fn commit(&mut self) -> Result<u64> {
         let mut last_indexes = vec![self.log.last_index];
         last_indexes.extend(self.role.peer_last_index.values());
@@ -74,8 +126,100 @@ impl RoleNode<Leader> {
         Ok(self.log.commit_index)
     }
 
-    /// Replicates the log to a peer
-    fn replicate(&self, peer: &str) -> Result<()> {
+    /// Records replication progress of peers in the node metrics
+    pub fn observe_peers(&self) {
+        for (peer, last_index) in self.role.peer_last_index.iter() {
+            let applied_index = self.role.peer_applied_index.get(peer).cloned().unwrap_or(0);
+            self.metrics.observe_peer(peer, self.log.commit_index, *last_index, applied_index);
+        }
+    }
+
+    /// Broadcasts a heartbeat to all peers, returning its sequence number
+    pub fn heartbeat(&mut self) -> Result<u64> {
+        self.role.heartbeat_seq += 1;
+        let seq = self.role.heartbeat_seq;
+        self.role.heartbeat_sent.insert(seq, self.role.ticks);
+        self.send(
+            Address::Peers,
+            Event::Heartbeat {
+                commit_index: self.log.commit_index,
+                commit_term: self.log.commit_term,
+                seq,
+            },
+        )?;
+        Ok(seq)
+    }
+
+    /// Records a heartbeat confirmation from a peer. Once a quorum has confirmed a heartbeat, the
+    /// lease is extended from the time it was sent, and read indexes waiting for it are granted.
+    fn confirm_heartbeat(&mut self, peer: &str, seq: u64) -> Result<()> {
+        match self.role.peer_heartbeat_seq.get_mut(peer) {
+            Some(confirmed) if seq > *confirmed => *confirmed = seq,
+            _ => return Ok(()),
+        }
+        let mut seqs: Vec<u64> = self.role.peer_heartbeat_seq.values().cloned().collect();
+        seqs.sort();
+        seqs.reverse();
+        // The leader implicitly confirms its own heartbeats, so we need one peer less than quorum
+        let quorum_seq = seqs[self.quorum() as usize - 2];
+
+        if self.reads.lease {
+            if let Some(sent) = self.role.heartbeat_sent.get(&quorum_seq) {
+                self.role.lease_expiry = self.role.lease_expiry.max(sent + LEASE_DURATION);
+            }
+        }
+        self.role.heartbeat_sent = self.role.heartbeat_sent.split_off(&quorum_seq);
+
+        let pending = self.role.read_index_reqs.split_off(&(quorum_seq + 1));
+        for (_, reqs) in std::mem::replace(&mut self.role.read_index_reqs, pending) {
+            for (to, id, index) in reqs {
+                self.send(to, Event::GrantReadIndex { id, index })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Expires heartbeats which a quorum has not confirmed within HEARTBEAT_EXPIRY ticks, aborting
+    /// read index requests waiting for them. This bounds the heartbeats and requests kept while
+    /// the leader is cut off from a quorum.
+    fn expire_heartbeats(&mut self) -> Result<()> {
+        let expired = match self.role.heartbeat_sent.iter().take_while(|(_, sent)| {
+            **sent + HEARTBEAT_EXPIRY <= self.role.ticks
+        }).last() {
+            Some((seq, _)) => *seq,
+            None => return Ok(()),
+        };
+        debug!("Heartbeats up to {} were not confirmed by a quorum, expiring", expired);
+        self.role.heartbeat_sent = self.role.heartbeat_sent.split_off(&(expired + 1));
+        let pending = self.role.read_index_reqs.split_off(&(expired + 1));
+        for (_, reqs) in std::mem::replace(&mut self.role.read_index_reqs, pending) {
+            for (to, id, _) in reqs {
+                self.send(to, Event::ClientResponse { id, response: Err(Error::Abort) })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the leader holds a valid lease
+    fn has_lease(&self) -> bool {
+        self.reads.lease && self.role.ticks < self.role.lease_expiry
+    }
+
+    /// Returns the index which must be applied before serving a read. Until an entry from the
+    /// current term has been committed, entries committed by previous leaders may be above our
+    /// commit index, but they are always in our log.
+    fn read_index(&self) -> u64 {
+        if self.log.commit_term == self.term {
+            self.log.commit_index
+        } else {
+            self.log.last_index
+        }
+    }
+
+    /// Replicates the log to a peer, from the next index to the end of the log. The next index
+    /// is advanced past the sent entries, such that further entries can be pipelined without
+    /// waiting for the peer to accept these.
+    fn replicate(&mut self, peer: &str) -> Result<()> {
         let peer_next = self.role.peer_next_index
             .get(peer)
             .cloned()
@@ -92,6 +236,14 @@ impl RoleNode<Leader> {
         };
         let entries = self.log.scan(peer_next..).collect::<Result<Vec<_>>>()?;
         debug!("Replicating {} entries at base {} to {}", entries.len(), base_index, peer);
+        self.role.peer_next_index.insert(peer.to_string(), self.log.last_index + 1);
+        if !entries.is_empty() {
+            self.role.peer_replicated_at
+                .entry(peer.to_string())
+                .or_default()
+                .entry(self.log.last_index)
+                .or_insert_with(Instant::now);
+        }
         self.send(Address::Peer(peer.to_string()), Event::ReplicateEntries {base_index, base_term, entries})?;
         Ok(())
     }
@@ -107,14 +259,23 @@ impl RoleNode<Leader> {
             }
         }
         match msg.event {
-            Event::ConfirmLeader { commit_index, has_committed} => {
+            Event::ConfirmLeader { commit_index, has_committed, seq, applied_index } => {
                 if let Address::Peer(from) = msg.from.clone() {
+                    if let Some(applied) = self.role.peer_applied_index.get_mut(&from) {
+                        *applied = max(*applied, applied_index);
+                    }
                     self.state_tx.send(Instruction::Vote {
                         term: msg.term,
                         index: commit_index,
                         address: msg.from
                     })?;
+                    self.confirm_heartbeat(&from, seq)?;
+                    // The peer is missing committed entries, so entries in flight to it may have
+                    // been lost. Resend everything it has not accepted, rather than only entries
+                    // past the pipelined next index, which may be none at all.
                     if !has_committed {
+                        let peer_last = self.role.peer_last_index.get(&from).cloned().unwrap_or(0);
+                        self.role.peer_next_index.insert(from.clone(), peer_last + 1);
                         self.replicate(&from)?;
                     }
                 }
@@ -122,23 +283,59 @@ impl RoleNode<Leader> {
 
             Event::AcceptEntries { last_index} => {
                 if let Address::Peer(from) = msg.from {
-                    self.role.peer_last_index.insert(from.clone(), last_index);
-                    self.role.peer_next_index.insert(from, last_index + 1);
+                    // Accepts of pipelined entries may arrive after later ones, so only move forward
+                    let peer_last = self.role.peer_last_index.entry(from.clone()).or_insert(0);
+                    *peer_last = max(*peer_last, last_index);
+                    let peer_next = self.role.peer_next_index.entry(from.clone()).or_insert(0);
+                    *peer_next = max(*peer_next, last_index + 1);
+                    self.role.peer_stalled_ticks.insert(from.clone(), 0);
+                    if let Some(sent) = self.role.peer_replicated_at.get_mut(&from) {
+                        let pending = sent.split_off(&(last_index + 1));
+                        for (_, at) in std::mem::replace(sent, pending) {
+                            self.metrics.observe_replication(at.elapsed());
+                        }
+                    }
                 }
                 self.commit()?;
             }
 
-            Event::RejectEntries => {
+            Event::RejectEntries { base_index } => {
                 if let Address::Peer(from) = msg.from {
-                    self.role.peer_next_index.entry(from.clone()).and_modify(|i| {
-                        if *i > 1{
-                            *i -= 1
-                        }
-                    });
-                    self.replicate(&from);
+                    self.role.peer_stalled_ticks.insert(from.clone(), 0);
+                    // Entries pipelined after the rejected ones will also be rejected, so we only
+                    // back off once, to the rejected base entry
+                    let peer_next = self.role.peer_next_index.get(&from).cloned().unwrap_or(0);
+                    if base_index < peer_next {
+                        let peer_last = self.role.peer_last_index.get(&from).cloned().unwrap_or(0);
+                        self.role.peer_next_index.insert(from.clone(), max(base_index, peer_last + 1));
+                        self.replicate(&from)?;
+                    }
+                }
+            }
+
+            Event::SolicitReadIndex { id } => {
+                let index = self.read_index();
+                if self.has_lease() {
+                    self.send(msg.from, Event::GrantReadIndex { id, index })?;
+                } else {
+                    let seq = self.heartbeat()?;
+                    self.role.read_index_reqs.entry(seq).or_default().push((msg.from, id, index));
                 }
             }
 
+            Event::ClientRequest { id, request: Request::Query(command) } if self.has_lease() => {
+                let index = self.read_index();
+                self.state_tx.send(Instruction::Query {
+                    id,
+                    address: msg.from,
+                    command,
+                    term: self.term,
+                    index,
+                    quorum: 1,
+                })?;
+                self.state_tx.send(Instruction::Vote { term: self.term, index, address: Address::Local })?;
+            }
+
             Event::ClientRequest { id, request: Request::Query(command) } => {
                 self.state_tx.send(Instruction::Query {
                     id,
@@ -154,18 +351,13 @@ impl RoleNode<Leader> {
                     address: Address::Local,
                 })?;
                 if !self.peers.is_empty() {
-                    self.send(
-                        Address::Peers,
-                        Event::Heartbeat {
-                            commit_index: self.log.commit_index,
-                            commit_term: self.log.commit_term,
-                        },
-                    )?;
+                    self.heartbeat()?;
                 }
             }
 
-            Event::ClientRequest { id, request: Request::Mutate(command) } => {
-                let index = self.append(Some(command))?;
+            Event::ClientRequest { id, request: Request::Mutate { command, session } } => {
+                // The entry is replicated along with any other pending entries when flushed
+                let index = self.log.append(self.term, Some(command), Some(session))?.index;
                 self.state_tx.send(Instruction::Notify { id, address: msg.from, index })?;
                 if self.peers.is_empty() {
                     self.commit()?;
@@ -198,7 +390,7 @@ impl RoleNode<Leader> {
             // election that we won after a quorum.
             Event::SolicitVote { .. } | Event::GrantVote => {}
 
-            Event::Heartbeat { .. } | Event::ReplicateEntries { .. } => {
+            Event::Heartbeat { .. } | Event::ReplicateEntries { .. } | Event::GrantReadIndex { .. } => {
                 warn!("Received unexpected message {:?}", msg)
             }
         }
@@ -207,19 +399,129 @@ impl RoleNode<Leader> {
 
     /// Processes a logical clock tick.
     pub fn tick(mut self) -> Result<Node> {
+        self.role.ticks += 1;
+        self.expire_heartbeats()?;
+        // Resend unaccepted entries to peers that have stopped responding to them, in case they
+        // were lost, e.g. due to a full send buffer or a reconnect
+        for peer in self.peers.clone() {
+            let peer_last = self.role.peer_last_index.get(&peer).cloned().unwrap_or(0);
+            let stalled = self.role.peer_stalled_ticks.entry(peer.clone()).or_insert(0);
+            if peer_last >= self.log.last_index {
+                *stalled = 0;
+                continue;
+            }
+            *stalled += 1;
+            if *stalled >= REPLICATE_TIMEOUT {
+                *stalled = 0;
+                debug!("Replication to {} stalled, resending entries from {}", peer, peer_last + 1);
+                // The peer may be dead, so only time the resent entries
+                self.role.peer_replicated_at.remove(&peer);
+                self.role.peer_next_index.insert(peer.clone(), peer_last + 1);
+                self.replicate(&peer)?;
+            }
+        }
         if !self.peers.is_empty() {
             self.role.heartbeat_ticks += 1;
             if self.role.heartbeat_ticks >= HEARTBEAT_INTERVAL {
                 self.role.heartbeat_ticks = 0;
-                self.send(
-                    Address::Peers,
-                    Event::Heartbeat {
-                        commit_index: self.log.commit_index,
-                        commit_term: self.log.commit_term,
-                    },
-                )?;
+                self.heartbeat()?;
             }
         }
         Ok(self.into())
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn read_index_after_quorum() {
+    use crate::raft::node::{setup, sent, ReadOptions};
+    let peers = ["b", "c", "d", "e"];
+    let leader = Leader::new(peers.iter().map(|p| p.to_string()).collect(), 0);
+    let (leader, mut node_rx, _state_rx) = setup("a", &peers, ReadOptions::default(), leader);
+    let mut node: Node = leader.into();
+    let message = |from: &str, event| Message {
+        term: 1,
+        from: Address::Peer(from.into()),
+        to: Address::Peer("a".into()),
+        event,
+    };
+    let confirm = |seq| Event::ConfirmLeader { commit_index: 0, has_committed: true, seq, applied_index: 0 };
+    let grants = |msgs: Vec<Message>| -> Vec<(Address, Event)> {
+        msgs.into_iter()
+            .filter(|m| matches!(m.event, Event::GrantReadIndex { .. } | Event::ClientResponse { .. }))
+            .map(|m| (m.to, m.event))
+            .collect()
+    };
+
+    node = node.step(message("b", Event::SolicitReadIndex { id: vec![1] })).unwrap();
+    let msgs = sent(&mut node_rx);
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].to, Address::Peers);
+    assert!(matches!(msgs[0].event, Event::Heartbeat { seq: 1, .. }));
+
+    // The leader and one peer are not a quorum of five, nor is a repeated confirmation
+    node = node.step(message("c", confirm(1))).unwrap();
+    node = node.step(message("c", confirm(1))).unwrap();
+    assert_eq!(grants(sent(&mut node_rx)), vec![]);
+
+    node = node.step(message("d", confirm(1))).unwrap();
+    assert_eq!(
+        grants(sent(&mut node_rx)),
+        vec![(Address::Peer("b".into()), Event::GrantReadIndex { id: vec![1], index: 0 })]
+    );
+
+    // Without confirmations, the request is aborted once its heartbeat expires
+    node = node.step(message("b", Event::SolicitReadIndex { id: vec![2] })).unwrap();
+    for _ in 1..HEARTBEAT_EXPIRY {
+        node = node.tick().unwrap();
+    }
+    assert_eq!(grants(sent(&mut node_rx)), vec![]);
+    node.tick().unwrap();
+    assert_eq!(
+        grants(sent(&mut node_rx)),
+        vec![(Address::Peer("b".into()), Event::ClientResponse { id: vec![2], response: Err(Error::Abort) })]
+    );
+}
+
+#[test]
+fn replicate_backoff() {
+    use crate::raft::node::{setup, sent, ReadOptions};
+    let peers = ["b", "c"];
+    let (mut leader, mut node_rx, _state_rx) =
+        setup("a", &peers, ReadOptions::default(), Leader::new(vec!["b".into(), "c".into()], 3));
+    for _ in 0..3 {
+        leader.log.append(1, Some(vec![]), None).unwrap();
+    }
+    let mut node: Node = leader.into();
+    let message = |event| Message {
+        term: 1,
+        from: Address::Peer("b".into()),
+        to: Address::Peer("a".into()),
+        event,
+    };
+    let replicated = |msgs: Vec<Message>| -> Vec<(u64, Vec<u64>)> {
+        msgs.into_iter()
+            .filter_map(|m| match m.event {
+                Event::ReplicateEntries { base_index, entries, .. } => {
+                    Some((base_index, entries.iter().map(|e| e.index).collect()))
+                }
+                _ => None,
+            })
+            .collect()
+    };
+
+    // The peer rejects the base of the pipelined entries, so we back off to it
+    node = node.step(message(Event::RejectEntries { base_index: 3 })).unwrap();
+    assert_eq!(replicated(sent(&mut node_rx)), vec![(2, vec![3])]);
+    // But never below entries the peer has accepted
+    node = node.step(message(Event::AcceptEntries { last_index: 1 })).unwrap();
+    node = node.step(message(Event::RejectEntries { base_index: 0 })).unwrap();
+    assert_eq!(replicated(sent(&mut node_rx)), vec![(1, vec![2, 3])]);
+    // Rejections of entries that are no longer in flight are ignored
+    node = node.step(message(Event::RejectEntries { base_index: 4 })).unwrap();
+    assert_eq!(replicated(sent(&mut node_rx)), vec![]);
+
+    // A heartbeat confirmation without the committed entries resends all unaccepted entries
+    let confirm = Event::ConfirmLeader { commit_index: 1, has_committed: false, seq: 1, applied_index: 0 };
+    node.step(message(confirm)).unwrap();
+    assert_eq!(replicated(sent(&mut node_rx)), vec![(1, vec![2, 3])]);
+}
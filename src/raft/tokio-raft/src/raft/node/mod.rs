@@ -3,6 +3,7 @@ mod follower;
 mod candidate;
 
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use crate::raft::log::Log;
 use crate::raft::node::candidate::Candidate;
@@ -11,6 +12,7 @@ use crate::raft::node::leader::Leader;
 use crate::raft::message::{Message, Address, Event};
 use crate::error::{Result, Error};
 use crate::raft::state::{State, Driver, Instruction};
+use crate::raft::metrics::{Metrics, Role};
 use ::log::{debug, info};
 use serde_derive::{Deserialize, Serialize};
 
@@ -24,6 +26,30 @@ const ELECTION_TIMEOUT_MIN: u64 = 8 * HEARTBEAT_INTERVAL;
 /// The maximum election timeout, in ticks
 const ELECTION_TIMEOUT_MAX: u64 = 15 * HEARTBEAT_INTERVAL;
 
+/// The number of ticks without a response from a peer before unaccepted entries are resent
+const REPLICATE_TIMEOUT: u64 = 5 * HEARTBEAT_INTERVAL;
+
+/// The maximum clock drift between nodes tolerated by leader leases, in percent
+const LEASE_CLOCK_DRIFT: u64 = 10;
+
+/// The duration of a leader lease, in ticks, counted from when the confirming heartbeat was sent.
+/// Followers withhold votes for ELECTION_TIMEOUT_MIN ticks after confirming a heartbeat, which may
+/// be one tick short in real time, so the lease is shorter than that by the clock drift bound.
+const LEASE_DURATION: u64 = (ELECTION_TIMEOUT_MIN - 1) * (100 - LEASE_CLOCK_DRIFT) / 100;
+
+/// Options for serving read-only client queries
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReadOptions {
+    /// Serve leader queries locally while holding a lease from a quorum of heartbeat
+    /// confirmations, instead of a heartbeat round trip per query. Followers always withhold
+    /// votes while a lease they granted may be held, so this only needs setting on the nodes
+    /// which should serve reads from leases, but all nodes must use the same tick duration.
+    pub lease: bool,
+    /// Execute queries submitted to followers locally, once the read index granted by the
+    /// leader has been applied
+    pub follower_reads: bool,
+}
+
 /// Node status
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
@@ -51,7 +77,9 @@ impl Node {
         peers: Vec<String>,
         log: Log,
         mut state: Box<dyn State>,
-        node_tx: mpsc::UnboundedSender<Message>
+        node_tx: mpsc::UnboundedSender<Message>,
+        reads: ReadOptions,
+        metrics: Arc<Metrics>,
     ) -> Result<Self> {
         let applied_index = state.applied_index();
         if applied_index > log.commit_index {
@@ -61,7 +89,7 @@ impl Node {
             )));
         }
         let (state_tx, state_rx) = mpsc::unbounded_channel();
-        let mut driver = Driver::new(state_rx, node_tx.clone());
+        let mut driver = Driver::new(state_rx, node_tx.clone(), metrics.clone());
         if log.commit_index > applied_index {
             info!("Replaying log entries {} to {}", applied_index + 1, log.commit_index);
             driver.replay(&mut *state, log.scan((applied_index + 1)..=log.commit_index))?;
@@ -78,15 +106,19 @@ impl Node {
             state_tx,
             queued_reqs: Vec::new(),
             proxied_reqs: HashMap::new(),
-            role: Follower::new(None, voted_for.as_deref())
+            reads,
+            metrics,
+            role: Follower::new(None, voted_for.as_deref()).with_lease_granted(),
         };
-        if node.peers.is_empty() {
+        let node: Node = if node.peers.is_empty() {
             info!("No peers specified, starting as leader");
             let last_index = node.log.last_index;
-            Ok(node.become_role(Leader::new(vec![], last_index))?.into())
+            node.become_role(Leader::new(vec![], last_index))?.into()
         } else {
-            Ok(node.into())
-        }
+            node.into()
+        };
+        node.observe();
+        Ok(node)
     }
 
     /// Returns the node id
@@ -100,19 +132,53 @@ impl Node {
 
     pub fn step(self, msg: Message) -> Result<Self> {
         debug!("Steppinng {:?}", msg);
-        match self {
+        let node = match self {
             Node::Candidate(n) => n.step(msg),
             Node::Follower(n) => n.step(msg),
             Node::Leader(n) => n.step(msg)
+        }?;
+        node.observe();
+        Ok(node)
+    }
+
+    /// Replicates entries appended since the last flush. Callers should step all pending
+    /// messages before flushing, such that client mutations are replicated as a single batch
+    pub fn flush(self) -> Result<Self> {
+        match self {
+            Node::Leader(mut n) => {
+                n.flush()?;
+                Ok(n.into())
+            }
+            node => Ok(node),
         }
     }
 
     /// Moves time forward by a tick
     pub fn tick(self) -> Result<Self>{
-        match self {
+        let node = match self {
             Node::Candidate(n) => n.tick(),
             Node::Follower(n) => n.tick(),
             Node::Leader(n) => n.tick()
+        }?;
+        node.observe();
+        // The store size may be expensive to compute, so we only sample it once per tick
+        match &node {
+            Node::Candidate(n) => n.metrics.observe_log_size(n.log.store.size()),
+            Node::Follower(n) => n.metrics.observe_log_size(n.log.store.size()),
+            Node::Leader(n) => n.metrics.observe_log_size(n.log.store.size()),
+        }
+        Ok(node)
+    }
+
+    /// Records the node state in its metrics
+    fn observe(&self) {
+        match self {
+            Node::Candidate(n) => n.observe(Role::Candidate),
+            Node::Follower(n) => n.observe(Role::Follower),
+            Node::Leader(n) => {
+                n.observe(Role::Leader);
+                n.observe_peers();
+            }
         }
     }
 }
@@ -147,6 +213,9 @@ pub struct RoleNode<R> {
     queued_reqs: Vec<(Address, Event)>,
     /// Keeps track of proxied client request, to abort on new leader election
     proxied_reqs: HashMap<Vec<u8>, Address>,
+    /// How read-only client queries are served
+    reads: ReadOptions,
+    metrics: Arc<Metrics>,
     role: R
 }
 
@@ -162,10 +231,22 @@ impl<R> RoleNode<R> {
             state_tx: self.state_tx,
             queued_reqs: self.queued_reqs,
             proxied_reqs: self.proxied_reqs,
+            reads: self.reads,
+            metrics: self.metrics,
             role
         })
     }
 
+    /// Records the node state in its metrics
+    fn observe(&self, role: Role) {
+        self.metrics.observe_node(
+            role,
+            self.term,
+            self.log.last_index,
+            self.log.commit_index,
+        );
+    }
+
     /// Aborts any proxied requests
     fn abort_proxied(&mut self) -> Result<()> {
         for (id, address) in std::mem::replace(&mut self.proxied_reqs, HashMap::new()) {
@@ -237,4 +318,40 @@ impl<R> RoleNode<R> {
             Address::Client => Err(Error::Internal("Received message for client".into()))
         }
     }
-}
\ No newline at end of file
+}
+/// Sets up a node with the given role for tests, at term 1 with an empty in-memory log,
+/// returning it along with receivers for its outbound messages and state machine instructions
+#[cfg(test)]
+fn setup<R>(
+    id: &str,
+    peers: &[&str],
+    reads: ReadOptions,
+    role: R,
+) -> (RoleNode<R>, mpsc::UnboundedReceiver<Message>, mpsc::UnboundedReceiver<Instruction>) {
+    let (node_tx, node_rx) = mpsc::unbounded_channel();
+    let (state_tx, state_rx) = mpsc::unbounded_channel();
+    let node = RoleNode {
+        id: id.to_string(),
+        peers: peers.iter().map(|p| p.to_string()).collect(),
+        term: 1,
+        log: Log::new(Box::new(crate::storage::log::Memory::new())).unwrap(),
+        node_tx,
+        state_tx,
+        queued_reqs: Vec::new(),
+        proxied_reqs: HashMap::new(),
+        reads,
+        metrics: Arc::new(Metrics::new(id)),
+        role,
+    };
+    (node, node_rx, state_rx)
+}
+
+/// Returns the messages sent by a test node since the last call
+#[cfg(test)]
+fn sent(node_rx: &mut mpsc::UnboundedReceiver<Message>) -> Vec<Message> {
+    let mut msgs = Vec::new();
+    while let Ok(msg) = node_rx.try_recv() {
+        msgs.push(msg);
+    }
+    msgs
+}
@@ -1,14 +1,16 @@
 use std::time::Duration;
-use crate::raft::node::Node;
-use std::collections::HashMap;
+use crate::raft::node::{Node, ReadOptions};
+use crate::raft::metrics::{self, Metrics};
+use crate::raft::transport::{Frame, FramedStream, PeerStream, Transport};
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
 use crate::raft::message::{Message, Request, Event, Response, Address};
 use crate::raft::log::Log;
 use crate::error::{Result, Error};
 use crate::raft::state::State;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::stream::StreamExt;
 use tokio::sync::{mpsc, oneshot};
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use uuid::Uuid;
 use futures::{sink::SinkExt, FutureExt};
 use ::log::{debug, error};
@@ -17,11 +19,17 @@ use ::log::{debug, error};
 /// The duration of a Raft tick, the unit of time for e.g. heartbeats and elections
 const TICK: Duration = Duration::from_millis(100);
 
+/// The maximum number of pending messages stepped into the node before flushing, i.e. the
+/// maximum number of client mutations replicated as a single batch
+const MAX_BATCH: usize = 1000;
+
 /// A Raft server
 pub struct Server {
     node: Node,
     peers: HashMap<String, String>,
     node_rx: mpsc::UnboundedReceiver<Message>,
+    metrics: Arc<Metrics>,
+    transport: Transport,
 }
 
 impl Server {
@@ -30,32 +38,59 @@ impl Server {
         id: &str,
         peers: HashMap<String, String>,
         log: Log,
-        state: Box<dyn State>
+        state: Box<dyn State>,
+        reads: ReadOptions,
+        transport: Transport,
     ) -> Result<Self> {
         let (node_tx, node_rx) = mpsc::unbounded_channel();
+        let metrics = Arc::new(Metrics::new(id));
         Ok(Self {
             node: Node::new(
                 id,
                 peers.iter().map(|(k, _)|k.to_string()).collect(),
                 log,
                 state,
-                node_tx
+                node_tx,
+                reads,
+                metrics.clone(),
             ).await?,
             peers,
-            node_rx
+            node_rx,
+            metrics,
+            transport,
         })
     }
 
+    /// Returns the node metrics
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Serves Raft peers on the listener, and metrics and status over HTTP on the status listener
+    /// if given
     pub async fn serve(
         self,
         listener: TcpListener,
+        status_listener: Option<TcpListener>,
         client_rx: mpsc::UnboundedReceiver<(Request, oneshot::Sender<Result<Response>>)>
     ) -> Result<()> {
+        if let Some(status_listener) = status_listener {
+            let metrics = self.metrics.clone();
+            tokio::spawn(async move {
+                if let Err(err) = metrics::serve(status_listener, metrics).await {
+                    error!("Raft status server failed: {}", err);
+                }
+            });
+        }
         let (tcp_in_tx, tcp_in_rx) = mpsc::unbounded_channel::<Message>();
         let (tcp_out_tx, tcp_out_rx) = mpsc::unbounded_channel::<Message>();
-        let (task, tcp_receiver) = Self::tcp_receive(listener, tcp_in_tx).remote_handle();
+        let peer_ids = self.peers.keys().cloned().collect();
+        let (task, tcp_receiver) =
+            Self::tcp_receive(listener, self.transport.clone(), self.node.id(), peer_ids, tcp_in_tx)
+                .remote_handle();
         tokio::spawn(task);
-        let (task, tcp_sender) = Self::tcp_send(self.node.id(), self.peers, tcp_out_rx).remote_handle();
+        let (task, tcp_sender) =
+            Self::tcp_send(self.node.id(), self.peers, self.transport, tcp_out_rx).remote_handle();
         tokio::spawn(task);
         let (task, eventloop) = Self::eventloop(self.node, self.node_rx, client_rx, tcp_in_rx, tcp_out_tx)
             .remote_handle();
@@ -76,7 +111,16 @@ impl Server {
         loop {
             tokio::select! {
                 _ = ticker.tick() => node = node.tick()?,
-                Some(msg) = tcp_rx.next() => node = node.step(msg)?,
+                Some(msg) = tcp_rx.next() => {
+                    node = node.step(msg)?;
+                    for _ in 1..MAX_BATCH {
+                        match tcp_rx.try_recv() {
+                            Ok(msg) => node = node.step(msg)?,
+                            Err(_) => break,
+                        }
+                    }
+                    node = node.flush()?;
+                }
                 Some(msg) = node_rx.next() => {
                     match msg {
                         Message { to: Address::Peer(_), ..} => tcp_tx.send(msg)?,
@@ -91,30 +135,60 @@ impl Server {
                     }
                 }
                 Some((request, response_tx)) = client_rx.next() => {
-                    let id = Uuid::new_v4().as_bytes().to_vec();
-                    requests.insert(id.clone(), response_tx);
-                    node = node.step(Message {
-                        from: Address::Client,
-                        to: Address::Local,
-                        term: 0,
-                        event: Event::ClientRequest{ id, request},
-                    })?;
+                    node = Self::step_request(node, &mut requests, request, response_tx)?;
+                    for _ in 1..MAX_BATCH {
+                        match client_rx.try_recv() {
+                            Ok((request, response_tx)) => {
+                                node = Self::step_request(node, &mut requests, request, response_tx)?
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    node = node.flush()?;
                 }
             }
         }
     }
 
+    /// Steps a client request into the node, tracking it for the response
+    fn step_request(
+        node: Node,
+        requests: &mut HashMap<Vec<u8>, oneshot::Sender<Result<Response>>>,
+        request: Request,
+        response_tx: oneshot::Sender<Result<Response>>,
+    ) -> Result<Node> {
+        let id = Uuid::new_v4().as_bytes().to_vec();
+        requests.insert(id.clone(), response_tx);
+        node.step(Message {
+            from: Address::Client,
+            to: Address::Local,
+            term: 0,
+            event: Event::ClientRequest{ id, request},
+        })
+    }
+
     /// Receives inbound message from peers via Tcp
     async fn tcp_receive(
         mut listener: TcpListener,
+        transport: Transport,
+        node_id: String,
+        peer_ids: HashSet<String>,
         in_tx: mpsc::UnboundedSender<Message>
     ) -> Result<()> {
+        let peer_ids = Arc::new(peer_ids);
         while let Some(socket) = listener.try_next().await? {
             let peer = socket.peer_addr()?;
             let peer_in_tx = in_tx.clone();
+            let transport = transport.clone();
+            let peer_ids = peer_ids.clone();
+            let node_id = node_id.clone();
             tokio::spawn(async move {
                 debug!("Raft peer {} connected", peer);
-                match Self::tcp_receive_peer(socket, peer_in_tx).await {
+                let result = match transport.accept(socket, &peer_ids, &node_id).await {
+                    Ok((id, stream)) => Self::tcp_receive_peer(id, stream, peer_in_tx).await,
+                    Err(err) => Err(err),
+                };
+                match result {
                     Ok(()) => debug!("Raft peer {} disconnected", peer),
                     Err(err) => error!("Raft peer {} error: {}", peer, err.to_string())
                 }
@@ -123,16 +197,21 @@ impl Server {
         Ok(())
     }
 
+    /// Receives messages from an authenticated peer. Peers may only send messages on their own
+    /// behalf, so other senders are rejected
     async fn tcp_receive_peer(
-        socket: TcpStream,
+        id: String,
+        mut stream: FramedStream<Box<dyn PeerStream>>,
         in_tx: mpsc::UnboundedSender<Message>
     ) -> Result<()> {
-        let mut stream = tokio_serde::SymmetricallyFramed::<_, Message, _>::new(
-            Framed::new(socket, LengthDelimitedCodec::new()),
-            tokio_serde::formats::SymmetricalBincode::<Message>::default(),
-        );
-        while let Some(message) = stream.try_next().await? {
-            in_tx.send(message)?;
+        while let Some(frame) = stream.try_next().await? {
+            match frame {
+                Frame::Message(message) if message.from == Address::Peer(id.clone()) => in_tx.send(message)?,
+                Frame::Message(message) => {
+                    return Err(Error::Internal(format!("Peer {} sent message from {:?}", id, message.from)))
+                }
+                Frame::Hello { .. } => return Err(Error::Internal(format!("Peer {} sent repeated hello", id))),
+            }
         }
         Ok(())
     }
@@ -141,13 +220,14 @@ impl Server {
     async fn tcp_send(
         node_id: String,
         peers: HashMap<String, String>,
+        transport: Transport,
         mut out_rx: mpsc::UnboundedReceiver<Message>
     ) -> Result<()> {
         let mut peer_txs : HashMap<String, mpsc::Sender<Message>> = HashMap::new();
         for (id, addr) in peers.into_iter() {
             let (tx, rx) = mpsc::channel::<Message>(1000);
-            peer_txs.insert(id, tx);
-            tokio::spawn(Self::tcp_send_peer(addr, rx));
+            peer_txs.insert(id.clone(), tx);
+            tokio::spawn(Self::tcp_send_peer(node_id.clone(), id, addr, transport.clone(), rx));
         }
 
         while let Some(mut message) = out_rx.next().await {
@@ -178,12 +258,18 @@ impl Server {
         Ok(())
     }
 
-    async fn tcp_send_peer(addr: String, mut out_rx: mpsc::Receiver<Message>) {
+    async fn tcp_send_peer(
+        node_id: String,
+        id: String,
+        addr: String,
+        transport: Transport,
+        mut out_rx: mpsc::Receiver<Message>
+    ) {
         loop {
-            match TcpStream::connect(&addr).await {
-                Ok(socket) => {
+            match transport.connect(&addr, &id, &node_id).await {
+                Ok(stream) => {
                     debug!("Connected to Raft peer {}", addr);
-                    match Self::tcp_send_peer_session(socket, &mut out_rx).await {
+                    match Self::tcp_send_peer_session(stream, &mut out_rx).await {
                         Ok(()) => break,
                         Err(err) => error!("Failed to sending to Raft peer {}: {}", addr, err)
                     }
@@ -196,15 +282,11 @@ impl Server {
     }
 
     async fn tcp_send_peer_session(
-        socket: TcpStream,
+        mut stream: FramedStream<Box<dyn PeerStream>>,
         out_rx: &mut mpsc::Receiver<Message>
     ) -> Result<()> {
-        let mut stream = tokio_serde::SymmetricallyFramed::<_, Message, _>::new(
-            Framed::new(socket, LengthDelimitedCodec::new()),
-            tokio_serde::formats::SymmetricalBincode::<Message>::default()
-        );
         while let Some(message) = out_rx.next().await {
-            stream.send(message).await?;
+            stream.send(Frame::Message(message)).await?;
         }
         Ok(())
     }
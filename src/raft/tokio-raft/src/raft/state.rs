@@ -1,9 +1,12 @@
 use crate::error::{Result, Error};
 use crate::raft::log::{Entry, Scan};
-use crate::raft::message::{Address, Message, Event, Response};
+use crate::raft::message::{Address, Message, Event, Response, Session};
 use crate::raft::node::Status;
+use crate::raft::metrics::Metrics;
 use std::collections::{HashSet, HashMap, BTreeMap};
+use std::sync::Arc;
 use ::log::{debug, error};
+use serde_derive::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tokio::stream::StreamExt as _;
 
@@ -13,11 +16,32 @@ pub trait State: Send {
     fn applied_index(&self) -> u64;
 
     /// Mutates the state machine. If the state machine returns Error::Internal, The Raft node halts.
-    /// For any other error, the state is applied and the error propagated to the caller
-    fn mutate(&mut self, index: u64, command: Vec<u8>) -> Result<Vec<u8>>;
+    /// For any other error, the state is applied and the error propagated to the caller.
+    ///
+    /// If the command was submitted in a client session, the result must be recorded in the given
+    /// session results under the session sequence number, and saved in the same store write as
+    /// the applied index, otherwise retried mutations may be applied twice after a restart
+    fn mutate(
+        &mut self,
+        index: u64,
+        command: Vec<u8>,
+        session: Option<(&Session, SessionResults)>,
+    ) -> Result<Vec<u8>>;
 
     /// Queries the state machine, All errors are propagated to the caller
     fn query(&self, command: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Loads the mutation results of a client session, if any
+    fn load_session(&self, id: &[u8]) -> Result<Option<SessionResults>>;
+}
+
+/// The results of mutations applied for a client session, which the client may still retry
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionResults {
+    /// The client has received results for all sequence numbers below this
+    pub ack: u64,
+    /// The results of applied mutations, by sequence number
+    pub results: BTreeMap<u64, Result<Vec<u8>>>,
 }
 
 
@@ -56,12 +80,14 @@ pub struct Driver {
     notify: HashMap<u64, (Address, Vec<u8>)>,
     /// Execute client queries when they receive a quorum. <index, <id, query>>
     queries: BTreeMap<u64, BTreeMap<Vec<u8>, Query>>,
+    metrics: Arc<Metrics>,
 }
 
 impl Driver {
     pub fn new(
         state_rx: mpsc::UnboundedReceiver<Instruction>,
         node_tx: mpsc::UnboundedSender<Message>,
+        metrics: Arc<Metrics>,
     ) -> Self {
         Self {
             state_rx,
@@ -69,6 +95,7 @@ impl Driver {
             applied_index: 0,
             notify: HashMap::new(),
             queries: BTreeMap::new(),
+            metrics,
         }
     }
 
@@ -90,15 +117,50 @@ impl Driver {
         while let Some(entry) = scan.next().transpose()? {
             debug!("Replaying {:?}", entry);
             if let Some(command) = entry.command {
-                match state.mutate(entry.index, command) {
-                    Err(error @ Error::Internal(_)) => return Err(error),
-                    _ => self.applied_index = entry.index
-                }
+                // There are no clients waiting for results during replay
+                let _ = Self::mutate(state, entry.index, command, entry.session)?;
+                self.applied_index = entry.index;
             }
         }
         Ok(())
     }
 
+    /// Applies a command to the state machine, returning the result for the client. Mutations
+    /// retried within a client session are only applied once, returning the original result.
+    /// Errors from the outer result halt the state machine.
+    fn mutate(
+        state: &mut dyn State,
+        index: u64,
+        command: Vec<u8>,
+        session: Option<Session>,
+    ) -> Result<Result<Vec<u8>>> {
+        let session = match session {
+            Some(session) => session,
+            None => {
+                return match state.mutate(index, command, None) {
+                    Err(error @ Error::Internal(_)) => Err(error),
+                    result => Ok(result),
+                }
+            }
+        };
+        let mut results = state.load_session(&session.id)?.unwrap_or_default();
+        if let Some(result) = results.results.get(&session.seq) {
+            debug!("Skipping duplicate mutation {} for session {:?}", session.seq, session.id);
+            return Ok(result.clone());
+        }
+        if session.seq < results.ack {
+            // The client has already received the result of the original mutation
+            debug!("Skipping stale mutation {} for session {:?}", session.seq, session.id);
+            return Ok(Err(Error::Abort));
+        }
+        results.ack = results.ack.max(session.ack);
+        results.results = results.results.split_off(&results.ack);
+        match state.mutate(index, command, Some((&session, results))) {
+            Err(error @ Error::Internal(_)) => Err(error),
+            result => Ok(result),
+        }
+    }
+
     pub async fn execute(&mut self, i: Instruction, state: &mut dyn State) -> Result<()> {
         debug!("Executing {:?}", i);
         match i {
@@ -106,13 +168,13 @@ impl Driver {
                 self.notify_abort()?;
                 self.query_abort()?;
             }
-            Instruction::Apply { entry: Entry { index, command, .. } } => {
+            Instruction::Apply { entry: Entry { index, command, session, .. } } => {
                 if let Some(command) = command {
                     debug!("Applying state machine command {}: {:?}", index, command);
-                    match tokio::task::block_in_place(|| state.mutate(index, command)) {
-                        Err(error @ Error::Internal(_)) => return Err(error),
-                        result => self.notify_applied(index, result)?
-                    };
+                    let result = tokio::task::block_in_place(|| {
+                        Self::mutate(state, index, command, session)
+                    })?;
+                    self.notify_applied(index, result)?;
                 }
                 // We have to track applied_index here, separately from the state machine, because
                 // no-op log entries are significant for whether a query should be executed
@@ -146,6 +208,8 @@ impl Driver {
                 self.query_execute(state)?;
             }
         }
+        let queries = self.queries.values().map(|q| q.len() as u64).sum();
+        self.metrics.observe_driver(self.applied_index, queries);
         Ok(())
     }
 
@@ -246,4 +310,26 @@ impl Driver {
         debug!("Sending {:?}", msg);
         Ok(self.node_tx.send(msg)?)
     }
-}
\ No newline at end of file
+}
+#[test]
+fn mutate_sessions_once() {
+    use crate::kv::{self, Mutation};
+    let mut state = kv::State::new(Box::new(crate::storage::kv::Memory::new())).unwrap();
+    let command = |mutation| bincode::serialize(&mutation).unwrap();
+    let delete = || command(Mutation::Delete { key: b"a".to_vec() });
+    let session = |seq, ack| Some(Session { id: b"s".to_vec(), seq, ack });
+    let existed = |existed: bool| Ok(bincode::serialize(&existed).unwrap());
+
+    let set = command(Mutation::Set { key: b"a".to_vec(), value: vec![1] });
+    Driver::mutate(&mut state, 1, set, None).unwrap().unwrap();
+    assert_eq!(Driver::mutate(&mut state, 2, delete(), session(0, 0)).unwrap(), existed(true));
+    // A duplicate returns the original result, without deleting again
+    assert_eq!(Driver::mutate(&mut state, 3, delete(), session(0, 0)).unwrap(), existed(true));
+    assert_eq!(Driver::mutate(&mut state, 4, delete(), session(1, 1)).unwrap(), existed(false));
+    // The client has acknowledged the result of 0, so it is no longer kept
+    assert_eq!(Driver::mutate(&mut state, 5, delete(), session(0, 1)).unwrap(), Err(Error::Abort));
+
+    let results = state.load_session(b"s").unwrap().unwrap();
+    assert_eq!(results.ack, 1);
+    assert_eq!(results.results.keys().collect::<Vec<_>>(), vec![&1]);
+}
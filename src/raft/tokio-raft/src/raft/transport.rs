@@ -0,0 +1,239 @@
+use crate::error::{Error, Result};
+use crate::raft::message::Message;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig,
+    Session,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use ::log::warn;
+
+/// The peer protocol version. Peers speaking a different version are rejected
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// A frame exchanged between Raft peers
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Frame {
+    /// The first frame sent by each side of a peer session, identifying the sender
+    Hello {
+        /// The cluster the sender belongs to
+        cluster_id: String,
+        /// The sender's protocol version
+        version: u32,
+        /// The sender's node id
+        node_id: String,
+    },
+    /// A Raft message
+    Message(Message),
+}
+
+/// A framed peer connection
+pub type FramedStream<S> = tokio_serde::SymmetricallyFramed<
+    Framed<S, LengthDelimitedCodec>,
+    Frame,
+    tokio_serde::formats::SymmetricalBincode<Frame>,
+>;
+
+/// Frames a peer connection
+pub fn framed<S: AsyncRead + AsyncWrite + Unpin>(stream: S) -> FramedStream<S> {
+    tokio_serde::SymmetricallyFramed::new(
+        Framed::new(stream, LengthDelimitedCodec::new()),
+        tokio_serde::formats::SymmetricalBincode::<Frame>::default(),
+    )
+}
+
+/// Mutual TLS configuration for peer connections
+#[derive(Clone)]
+struct Tls {
+    acceptor: TlsAcceptor,
+    connector: TlsConnector,
+}
+
+/// The transport used between Raft peers. Both sides of a connection identify themselves with a
+/// hello frame, which must match our cluster id and protocol version, and the node id expected
+/// of the peer. With TLS, peers must present a certificate signed by the cluster CA and valid for
+/// their node id as a DNS name.
+#[derive(Clone)]
+pub struct Transport {
+    cluster_id: String,
+    tls: Option<Tls>,
+}
+
+impl Transport {
+    /// Creates a plaintext transport. Peer identities are not authenticated, so any host which
+    /// can reach the node may claim to be a peer: only use it for tests or trusted networks
+    pub fn insecure(cluster_id: &str) -> Self {
+        warn!("Using plaintext Raft transport, peer identities are not authenticated");
+        Self { cluster_id: cluster_id.to_string(), tls: None }
+    }
+
+    /// Creates a mutual TLS transport, from PEM-encoded CA certificates, and the node's
+    /// certificate chain and private key
+    pub fn tls(cluster_id: &str, ca: &[u8], cert: &[u8], key: &[u8]) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        match roots.add_pem_file(&mut BufReader::new(ca)) {
+            Ok((valid, _)) if valid > 0 => {}
+            _ => return Err(Error::Internal("No valid CA certificates found".into())),
+        }
+        let certs = Self::load_certs(cert)?;
+        let key = Self::load_key(key)?;
+
+        let mut server = ServerConfig::new(AllowAnyAuthenticatedClient::new(roots.clone()));
+        server.set_single_cert(certs.clone(), key.clone())?;
+        let mut client = ClientConfig::new();
+        client.root_store = roots;
+        client.set_single_client_cert(certs, key)?;
+
+        Ok(Self {
+            cluster_id: cluster_id.to_string(),
+            tls: Some(Tls {
+                acceptor: TlsAcceptor::from(Arc::new(server)),
+                connector: TlsConnector::from(Arc::new(client)),
+            }),
+        })
+    }
+
+    fn load_certs(pem: &[u8]) -> Result<Vec<Certificate>> {
+        match pemfile::certs(&mut BufReader::new(pem)) {
+            Ok(certs) if !certs.is_empty() => Ok(certs),
+            _ => Err(Error::Internal("No valid certificates found".into())),
+        }
+    }
+
+    fn load_key(pem: &[u8]) -> Result<PrivateKey> {
+        let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(pem)).unwrap_or_default();
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut BufReader::new(pem)).unwrap_or_default();
+        }
+        keys.into_iter().next().ok_or_else(|| Error::Internal("No valid private key found".into()))
+    }
+
+    /// Returns the hello frame for the given local node
+    pub fn hello(&self, node_id: &str) -> Frame {
+        Frame::Hello {
+            cluster_id: self.cluster_id.clone(),
+            version: PROTOCOL_VERSION,
+            node_id: node_id.to_string(),
+        }
+    }
+
+    /// Checks the hello frame received from a peer, returning the node id it claims
+    async fn receive_hello(&self, stream: &mut FramedStream<Box<dyn PeerStream>>) -> Result<String> {
+        use tokio::stream::StreamExt;
+        match stream.try_next().await? {
+            Some(Frame::Hello { cluster_id, version, node_id }) => {
+                if cluster_id != self.cluster_id {
+                    return Err(Error::Internal(format!("Peer is from other cluster {}", cluster_id)));
+                }
+                if version != PROTOCOL_VERSION {
+                    return Err(Error::Internal(format!("Peer has incompatible protocol version {}", version)));
+                }
+                Ok(node_id)
+            }
+            Some(frame) => Err(Error::Internal(format!("Expected hello, got {:?}", frame))),
+            None => Err(Error::Internal("Peer disconnected before hello".into())),
+        }
+    }
+
+    /// Accepts an inbound peer connection, and replies to its hello frame with the one for the
+    /// local node. Returns the authenticated peer id and the framed connection, positioned after
+    /// the hello frame
+    pub async fn accept(
+        &self,
+        socket: TcpStream,
+        peers: &HashSet<String>,
+        local_id: &str,
+    ) -> Result<(String, FramedStream<Box<dyn PeerStream>>)> {
+        use futures::sink::SinkExt;
+        let (stream, cert): (Box<dyn PeerStream>, _) = match &self.tls {
+            Some(tls) => {
+                let stream = tls.acceptor.accept(socket).await?;
+                let cert = stream.get_ref().1.get_peer_certificates().and_then(|c| c.into_iter().next());
+                (Box::new(stream), cert)
+            }
+            None => (Box::new(socket), None),
+        };
+        let mut stream = framed(stream);
+        let node_id = self.receive_hello(&mut stream).await?;
+        if !peers.contains(&node_id) {
+            return Err(Error::Internal(format!("Unknown peer {}", node_id)));
+        }
+        if self.tls.is_some() {
+            let cert = cert.ok_or_else(|| Error::Internal("Peer presented no certificate".into()))?;
+            Self::verify_identity(&cert, &node_id)?;
+        }
+        stream.send(self.hello(local_id)).await?;
+        Ok((node_id, stream))
+    }
+
+    /// Connects to a peer, verifying its identity with TLS, and exchanges hello frames with it,
+    /// which must identify it as the given peer
+    pub async fn connect(
+        &self,
+        addr: &str,
+        peer_id: &str,
+        node_id: &str,
+    ) -> Result<FramedStream<Box<dyn PeerStream>>> {
+        use futures::sink::SinkExt;
+        let socket = TcpStream::connect(addr).await?;
+        let stream: Box<dyn PeerStream> = match &self.tls {
+            Some(tls) => {
+                let name = webpki::DNSNameRef::try_from_ascii_str(peer_id)
+                    .map_err(|_| Error::Internal(format!("Peer id {} is not a valid DNS name", peer_id)))?;
+                Box::new(tls.connector.connect(name, socket).await?)
+            }
+            None => Box::new(socket),
+        };
+        let mut stream = framed(stream);
+        stream.send(self.hello(node_id)).await?;
+        let hello_id = self.receive_hello(&mut stream).await?;
+        if hello_id != peer_id {
+            return Err(Error::Internal(format!("Expected peer {}, but {} answered", peer_id, hello_id)));
+        }
+        Ok(stream)
+    }
+
+    /// Verifies that a peer certificate is valid for the given node id, as a DNS name. The
+    /// certificate chain itself has already been verified during the TLS handshake.
+    fn verify_identity(cert: &Certificate, node_id: &str) -> Result<()> {
+        let name = webpki::DNSNameRef::try_from_ascii_str(node_id)
+            .map_err(|_| Error::Internal(format!("Peer id {} is not a valid DNS name", node_id)))?;
+        webpki::EndEntityCert::from(&cert.0)
+            .and_then(|cert| cert.verify_is_valid_for_dns_name(name))
+            .map_err(|err| Error::Internal(format!("Peer certificate is not valid for {}: {:?}", node_id, err)))
+    }
+}
+
+/// A peer connection stream, either plaintext or TLS
+pub trait PeerStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> PeerStream for S {}
+
+#[tokio::test]
+async fn exchange_hellos() {
+    let transport = Transport::insecure("cluster");
+    let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let peers: HashSet<String> = vec!["a".to_string()].into_iter().collect();
+    let server = transport.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = server.accept(socket, &peers, "b").await;
+        }
+    });
+
+    assert!(transport.connect(&addr, "b", "a").await.is_ok());
+    // The node answering must be the one we meant to connect to
+    assert!(transport.connect(&addr, "c", "a").await.is_err());
+    // The acceptor rejects unknown peers and other clusters without answering
+    assert!(transport.connect(&addr, "b", "c").await.is_err());
+    assert!(Transport::insecure("other").connect(&addr, "b", "a").await.is_err());
+}
@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::error::Result;
+use crate::storage::kv::{Store, Range, Scan};
+
+/// An in-memory key/value store
+pub struct Memory {
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            data: BTreeMap::new()
+        }
+    }
+}
+
+impl fmt::Display for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "memory")
+    }
+}
+
+impl Store for Memory {
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.data.remove(key);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    fn scan(&self, range: Range) -> Scan {
+        Box::new(self.data.range(range).map(|(k, v)| Ok((k.clone(), v.clone()))))
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.data.insert(key.to_vec(), value);
+        Ok(())
+    }
+}
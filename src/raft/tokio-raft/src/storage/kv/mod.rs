@@ -0,0 +1,109 @@
+pub(crate) mod memory;
+use std::fmt::Display;
+use crate::error::Result;
+use std::ops::{Bound, RangeBounds};
+
+pub use memory::Memory;
+
+/// An ordered key/value store
+pub trait Store: Display + Send + Sync {
+    /// Deletes a key, or does nothing if it does not exist
+    fn delete(&mut self, key: &[u8]) -> Result<()>;
+
+    /// Flushes any buffered data to the underlying storage medium
+    fn flush(&mut self) -> Result<()>;
+
+    /// Gets a value for a key, if it exists
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Iterates over an ordered range of key/value pairs
+    fn scan(&self, range: Range) -> Scan;
+
+    /// Sets a value for a key, replacing the existing value if any
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()>;
+}
+
+/// A scan range
+pub struct Range {
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+}
+
+impl Range {
+    /// Creates a new range from the given Rust range, We can't use the
+    /// RangeBound directly in scan() since that prevents us from Store into
+    /// a trait object
+    pub fn from<R: RangeBounds<Vec<u8>>>(range: R) -> Self {
+        Self {
+            start: match range.start_bound() {
+                Bound::Included(v) => Bound::Included(v.to_vec()),
+                Bound::Excluded(v) => Bound::Excluded(v.to_vec()),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+            end: match range.end_bound() {
+                Bound::Included(v) => Bound::Included(v.to_vec()),
+                Bound::Excluded(v) => Bound::Excluded(v.to_vec()),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+        }
+    }
+
+    /// Creates a range covering all keys starting with the given prefix
+    pub fn prefix(prefix: &[u8]) -> Self {
+        let mut end = prefix.to_vec();
+        // The end is the prefix with its last byte incremented, ignoring trailing 0xff bytes
+        while let Some(0xff) = end.last() {
+            end.pop();
+        }
+        match end.last_mut() {
+            Some(last) => {
+                *last += 1;
+                Self { start: Bound::Included(prefix.to_vec()), end: Bound::Excluded(end) }
+            }
+            None => Self { start: Bound::Included(prefix.to_vec()), end: Bound::Unbounded },
+        }
+    }
+}
+
+impl RangeBounds<Vec<u8>> for Range {
+    fn start_bound(&self) -> Bound<&Vec<u8>> {
+        match &self.start {
+            Bound::Included(v) => Bound::Included(v),
+            Bound::Excluded(v) => Bound::Excluded(v),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+
+    fn end_bound(&self) -> Bound<&Vec<u8>> {
+        match &self.end {
+            Bound::Included(v) => Bound::Included(v),
+            Bound::Excluded(v) => Bound::Excluded(v),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+}
+
+/// Iterator over a key/value range
+pub type Scan<'a> = Box<dyn DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+#[test]
+fn prefix_ranges() {
+    let bounds = |range: Range| (range.start, range.end);
+    assert_eq!(
+        bounds(Range::prefix(b"ab")),
+        (Bound::Included(b"ab".to_vec()), Bound::Excluded(b"ac".to_vec()))
+    );
+    assert_eq!(
+        bounds(Range::prefix(&[0x01, 0xff, 0xff])),
+        (Bound::Included(vec![0x01, 0xff, 0xff]), Bound::Excluded(vec![0x02]))
+    );
+    assert_eq!(bounds(Range::prefix(&[0xff])), (Bound::Included(vec![0xff]), Bound::Unbounded));
+    assert_eq!(bounds(Range::prefix(b"")), (Bound::Included(vec![]), Bound::Unbounded));
+
+    let mut store = Memory::new();
+    for key in [&b"a"[..], b"ab", b"ab\xff", b"abc", b"ac", b"b"].iter() {
+        store.set(key, vec![]).unwrap();
+    }
+    let keys: Vec<Vec<u8>> = store.scan(Range::prefix(b"ab")).map(|r| r.unwrap().0).collect();
+    assert_eq!(keys, vec![b"ab".to_vec(), b"abc".to_vec(), b"ab\xff".to_vec()]);
+}
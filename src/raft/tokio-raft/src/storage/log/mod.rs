@@ -3,6 +3,8 @@ use std::fmt::Display;
 use crate::error::Result;
 use std::ops::{Bound, RangeBounds};
 
+pub use memory::Memory;
+
 
 /// A log store. Entry indexes are 1-based, to match Raft semantics
 pub trait Store: Display + Sync + Send {
@@ -1 +1,2 @@
-pub(crate) mod log;
\ No newline at end of file
+pub mod log;
+pub mod kv;
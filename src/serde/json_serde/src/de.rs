@@ -1,36 +1,195 @@
-use std::ops::{AddAssign, MulAssign, Neg};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io;
+use std::str;
+use std::marker::PhantomData;
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::forward_to_deserialize_any;
 use serde::de::{
     self, DeserializeSeed, EnumAccess, IntoDeserializer,
     MapAccess, SeqAccess, VariantAccess, Visitor
 };
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorCode, Position, Result};
+use crate::number::{self, RAW_NUMBER_TOKEN};
 
 pub struct Deserializer<'de> {
+    /// The whole input, for locating errors
+    source: &'de str,
+    /// The remaining input
     input: &'de str,
+    /// The path to the value being decoded
+    path: Vec<Segment<'de>>,
+    /// Whether numbers are kept as their raw text for `Number` and `Value`, see
+    /// `with_arbitrary_precision`
+    arbitrary_precision: bool,
 }
 
 impl<'de> Deserializer<'de> {
+    // Named after serde_json's constructor. It borrows the input, so it can't be `FromStr`
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(input: &'de str) -> Self {
         Deserializer {
-            input
+            source: input,
+            input,
+            path: Vec::new(),
+            arbitrary_precision: false,
         }
     }
+
+    /// Keeps numbers as their raw text when deserializing a `Number` or `Value`, rather than
+    /// converting them to `u64`, `i64` or `f64`, such that no precision is lost. Other types
+    /// see numbers as usual.
+    pub fn with_arbitrary_precision(mut self) -> Self {
+        self.arbitrary_precision = true;
+        self
+    }
+
+    /// Checks that the whole input has been consumed
+    pub fn end(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        if self.input.is_empty() {
+            Ok(())
+        } else {
+            Err(self.locate(ErrorCode::TrailingCharacters.into()))
+        }
+    }
+
+    /// Sets the error's position to the current one, and its path to that of the value being
+    /// decoded, unless already set
+    pub fn locate(&self, err: Error) -> Error {
+        err.locate(
+            || Position::new(self.source, self.source.len() - self.input.len()),
+            || self.path(),
+        )
+    }
+
+    /// Decodes a scalar, locating any error not located already at the scalar's start, rather
+    /// than where decoding stopped
+    fn scalar<T>(&mut self, decode: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.skip_whitespace();
+        let start = self.source.len() - self.input.len();
+        decode(self).map_err(|err| err.locate(|| Position::new(self.source, start), || self.path()))
+    }
+
+    /// Formats the path to the value being decoded, e.g. `servers[3].port`
+    fn path(&self) -> String {
+        let mut path = String::new();
+        for segment in &self.path {
+            match segment {
+                Segment::Key(key) if path.is_empty() => path += key,
+                Segment::Key(key) => {
+                    path += ".";
+                    path += key;
+                }
+                Segment::Index(index) => path += &format!("[{}]", index),
+            }
+        }
+        path
+    }
+}
+
+/// A step in the path to the value being decoded
+enum Segment<'de> {
+    Key(Cow<'de, str>),
+    Index(usize),
 }
 
 pub fn from_str<'a, T: Deserialize<'a>>(s: &'a str) -> Result<T> {
     let mut deserializer = Deserializer::from_str(s);
-    let t = T::deserialize(&mut deserializer)?;
-    if deserializer.input.is_empty() {
-        Ok(t)
-    } else {
-        Err(Error::TrailingCharacters)
+    let t = T::deserialize(&mut deserializer).map_err(|err| deserializer.locate(err))?;
+    deserializer.end()?;
+    Ok(t)
+}
+
+/// Deserializes a value from UTF-8 bytes, rejecting invalid UTF-8
+pub fn from_slice<'a, T: Deserialize<'a>>(v: &'a [u8]) -> Result<T> {
+    match str::from_utf8(v) {
+        Ok(s) => from_str(s),
+        Err(err) => {
+            let valid = str::from_utf8(&v[..err.valid_up_to()]).unwrap();
+            let err = Error::from(ErrorCode::InvalidUtf8);
+            Err(err.locate(|| Position::new(valid, valid.len()), String::new))
+        }
+    }
+}
+
+/// Deserializes a value from a reader. The whole document is read into an internal buffer
+/// first; use a `StreamDeserializer` to process a sequence of values in constant memory.
+pub fn from_reader<R: io::Read, T: DeserializeOwned>(mut reader: R) -> Result<T> {
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+    from_slice(&buffer)
+}
+
+/// Deserializes consecutive values from newline-delimited JSON, one line at a time, such that
+/// memory use is bounded by the longest line. Blank lines are skipped. Error positions are
+/// relative to the whole stream.
+pub struct StreamDeserializer<R, T> {
+    reader: R,
+    /// The current line, reused between values
+    line: Vec<u8>,
+    /// The number of lines read so far
+    line_number: usize,
+    /// The byte offset of the current line in the stream
+    offset: usize,
+    /// Set after a read error, which ends the stream
+    failed: bool,
+    marker: PhantomData<T>,
+}
+
+impl<R: io::BufRead, T: DeserializeOwned> StreamDeserializer<R, T> {
+    pub fn new(reader: R) -> Self {
+        StreamDeserializer {
+            reader,
+            line: Vec::new(),
+            line_number: 0,
+            offset: 0,
+            failed: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// The number of lines read so far
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+}
+
+impl<R: io::BufRead, T: DeserializeOwned> Iterator for StreamDeserializer<R, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        while !self.failed {
+            self.offset += self.line.len();
+            self.line.clear();
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => self.line_number += 1,
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err.into()));
+                }
+            }
+            if self.line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let (line_number, offset) = (self.line_number, self.offset);
+            return Some(from_slice(&self.line).map_err(|err| err.shift(line_number, offset)));
+        }
+        None
     }
 }
 
 impl<'de> Deserializer<'de> {
+    fn skip_whitespace(&mut self) {
+        self.input = self.input.trim_start_matches(&[' ', '\t', '\n', '\r'][..]);
+    }
+
+    /// Peeks at the next character, after any whitespace
     fn peek_char(&mut self) -> Result<char> {
-        self.input.chars().next().ok_or(Error::Eof)
+        self.skip_whitespace();
+        Ok(self.input.chars().next().ok_or(ErrorCode::Eof)?)
     }
 
     fn next_char(&mut self) -> Result<char> {
@@ -40,6 +199,7 @@ impl<'de> Deserializer<'de> {
     }
 
     fn parse_bool(&mut self) -> Result<bool> {
+        self.skip_whitespace();
         if self.input.starts_with("true") {
             self.input = &self.input["true".len()..];
             Ok(true)
@@ -47,57 +207,197 @@ impl<'de> Deserializer<'de> {
             self.input = &self.input["false".len()..];
             Ok(false)
         } else {
-            Err(Error::ExpectedBoolean)
+            Err(ErrorCode::ExpectedBoolean.into())
         }
     }
 
-    fn parse_unsigned<T>(&mut self) -> Result<T>
-        where
-            T: AddAssign<T> + MulAssign<T> + From<u8>,
-    {
-        let mut int = match self.next_char()? {
-            ch @ '0'..='9' => T::from(ch as u8 - b'0'),
-            _ => {
-                return Err(Error::ExpectedInteger);
+    /// Consumes a number following the RFC 8259 grammar, returning its text and whether it is
+    /// an integer, i.e. has neither a fraction nor an exponent
+    fn parse_number(&mut self) -> Result<(&'de str, bool)> {
+        let bytes = self.input.as_bytes();
+        let digits = |mut i: usize| {
+            while let Some(b'0'..=b'9') = bytes.get(i) {
+                i += 1;
             }
+            i
         };
-        loop {
-            match self.input.chars().next() {
-                Some(ch @ '0'..='9') => {
-                    self.input = &self.input[1..];
-                    int *= T::from(10);
-                    int += T::from(ch as u8 - b'0');
-                }
-                _ => {
-                    return Ok(int);
+        let mut i = 0;
+        if bytes.get(i) == Some(&b'-') {
+            i += 1;
+        }
+        match bytes.get(i) {
+            Some(b'0') => {
+                i += 1;
+                if let Some(b'0'..=b'9') = bytes.get(i) {
+                    return Err(ErrorCode::InvalidNumber.into());
                 }
             }
+            Some(b'1'..=b'9') => i = digits(i),
+            Some(_) => return Err(ErrorCode::InvalidNumber.into()),
+            None => return Err(ErrorCode::Eof.into()),
+        }
+        let mut integer = true;
+        if bytes.get(i) == Some(&b'.') {
+            integer = false;
+            match bytes.get(i + 1) {
+                Some(b'0'..=b'9') => i = digits(i + 1),
+                _ => return Err(ErrorCode::InvalidNumber.into()),
+            }
+        }
+        if let Some(b'e') | Some(b'E') = bytes.get(i) {
+            integer = false;
+            i += 1;
+            if let Some(b'+') | Some(b'-') = bytes.get(i) {
+                i += 1;
+            }
+            match bytes.get(i) {
+                Some(b'0'..=b'9') => i = digits(i),
+                _ => return Err(ErrorCode::InvalidNumber.into()),
+            }
+        }
+        let (number, rest) = self.input.split_at(i);
+        self.input = rest;
+        Ok((number, integer))
+    }
+
+    /// Consumes an integer, returning whether it is negative and its magnitude
+    fn parse_integer(&mut self) -> Result<(bool, u64)> {
+        match self.peek_char()? {
+            '-' | '0'..='9' => {}
+            _ => return Err(ErrorCode::ExpectedInteger.into()),
+        }
+        match self.parse_number()? {
+            (number, true) => integer_magnitude(number),
+            (_, false) => Err(ErrorCode::ExpectedInteger.into()),
+        }
+    }
+
+    fn parse_unsigned<T>(&mut self) -> Result<T>
+        where
+            T: TryFrom<u64>,
+    {
+        match self.parse_integer()? {
+            (false, magnitude) | (true, magnitude @ 0) => {
+                T::try_from(magnitude).map_err(|_| ErrorCode::NumberOutOfRange.into())
+            }
+            (true, _) => Err(ErrorCode::NumberOutOfRange.into()),
         }
     }
 
     fn parse_signed<T>(&mut self) -> Result<T>
         where
-            T: Neg<Output = T> + AddAssign<T> + MulAssign<T> + From<i8>,
+            T: TryFrom<i64>,
     {
-        unimplemented!()
+        let (negative, magnitude) = self.parse_integer()?;
+        let int = signed(negative, magnitude).ok_or(ErrorCode::NumberOutOfRange)?;
+        T::try_from(int).map_err(|_| ErrorCode::NumberOutOfRange.into())
+    }
+
+    /// Consumes a number of any form, returning its text for conversion to a float
+    fn parse_float(&mut self) -> Result<&'de str> {
+        match self.peek_char()? {
+            '-' | '0'..='9' => Ok(self.parse_number()?.0),
+            _ => Err(ErrorCode::ExpectedNumber.into()),
+        }
     }
 
-    fn parse_string(&mut self) -> Result<&'de str> {
-        if self.next_char()? != '"' {
-            return Err(Error::ExpectedString);
+    /// Consumes a string, borrowing it from the input unless it contains escapes
+    fn parse_string(&mut self) -> Result<Cow<'de, str>> {
+        if self.peek_char()? != '"' {
+            return Err(ErrorCode::ExpectedString.into());
         }
-        match self.input.find('"') {
-            Some(len) => {
-                let s = &self.input[..len];
-                self.input = &self.input[len + 1..];
-                Ok(s)
+        let input = &self.input[1..];
+        let bytes = input.as_bytes();
+        // The unescaped string so far, once an escape has been seen
+        let mut unescaped: Option<String> = None;
+        // The start of the text not yet copied into unescaped
+        let mut start = 0;
+        let mut i = 0;
+        loop {
+            match bytes.get(i) {
+                Some(b'"') => {
+                    self.input = &input[i + 1..];
+                    return Ok(match unescaped {
+                        None => Cow::Borrowed(&input[..i]),
+                        Some(mut s) => {
+                            s.push_str(&input[start..i]);
+                            Cow::Owned(s)
+                        }
+                    });
+                }
+                Some(b'\\') => {
+                    let s = unescaped.get_or_insert_with(String::new);
+                    s.push_str(&input[start..i]);
+                    self.input = &input[i..];
+                    let (ch, len) = parse_escape(&input[i..]).map_err(|err| self.locate(err))?;
+                    s.push(ch);
+                    i += len;
+                    start = i;
+                }
+                Some(b) if *b < 0x20 => {
+                    self.input = &input[i..];
+                    return Err(self.locate(ErrorCode::ControlCharacterInString.into()));
+                }
+                Some(_) => i += 1,
+                None => {
+                    self.input = &input[i..];
+                    return Err(self.locate(ErrorCode::Eof.into()));
+                }
             }
-            None => Err(Error::Eof),
         }
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+/// Decodes the escape sequence at the start of the input, returning the character and the
+/// length of the sequence. A UTF-16 surrogate pair spans two `\\u` escapes.
+fn parse_escape(input: &str) -> Result<(char, usize)> {
+    let ch = match input.as_bytes().get(1) {
+        Some(b'"') => '"',
+        Some(b'\\') => '\\',
+        Some(b'/') => '/',
+        Some(b'b') => '\u{8}',
+        Some(b'f') => '\u{c}',
+        Some(b'n') => '\n',
+        Some(b'r') => '\r',
+        Some(b't') => '\t',
+        Some(b'u') => {
+            let high = parse_hex4(&input[2..])?;
+            let code_point = match high {
+                0xD800..=0xDBFF => {
+                    if !input[6..].starts_with("\\u") {
+                        return Err(ErrorCode::InvalidUnicodeCodePoint.into());
+                    }
+                    let low = parse_hex4(&input[8..])?;
+                    if !(0xDC00..=0xDFFF).contains(&low) {
+                        return Err(ErrorCode::InvalidUnicodeCodePoint.into());
+                    }
+                    let code_point = 0x10000 + ((u32::from(high) - 0xD800) << 10) + (u32::from(low) - 0xDC00);
+                    return Ok((std::char::from_u32(code_point).unwrap(), 12));
+                }
+                0xDC00..=0xDFFF => return Err(ErrorCode::InvalidUnicodeCodePoint.into()),
+                code_point => u32::from(code_point),
+            };
+            return Ok((std::char::from_u32(code_point).unwrap(), 6));
+        }
+        Some(_) => return Err(ErrorCode::InvalidEscape.into()),
+        None => return Err(ErrorCode::Eof.into()),
+    };
+    Ok((ch, 2))
+}
+
+/// Parses the four hex digits of a `\\u` escape
+fn parse_hex4(input: &str) -> Result<u16> {
+    let hex = &input.as_bytes()[..input.len().min(4)];
+    if !hex.iter().all(u8::is_ascii_hexdigit) {
+        Err(ErrorCode::InvalidEscape.into())
+    } else if hex.len() < 4 {
+        Err(ErrorCode::Eof.into())
+    } else {
+        Ok(u16::from_str_radix(&input[..4], 16).unwrap())
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
@@ -108,11 +408,25 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             'n' => self.deserialize_unit(visitor),
             't' | 'f' => self.deserialize_bool(visitor),
             '"' => self.deserialize_str(visitor),
-            '0'..='9' => self.deserialize_u64(visitor),
-            '-' => self.deserialize_i64(visitor),
+            '-' | '0'..='9' => self.scalar(|de| {
+                let (number, integer) = de.parse_number()?;
+                // Integers that don't fit in 64 bits lose precision rather than failing
+                if integer {
+                    match integer_magnitude(number) {
+                        Ok((false, magnitude)) => return visitor.visit_u64(magnitude),
+                        Ok((true, magnitude)) => {
+                            if let Some(int) = signed(true, magnitude) {
+                                return visitor.visit_i64(int);
+                            }
+                        }
+                        Err(_) => {}
+                    }
+                }
+                visitor.visit_f64(finite(number.parse::<f64>())?)
+            }),
             '[' => self.deserialize_seq(visitor),
             '{' => self.deserialize_map(visitor),
-            _ => Err(Error::Syntax),
+            _ => Err(ErrorCode::Syntax.into()),
         }
     }
 
@@ -120,91 +434,104 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
-        visitor.visit_bool(self.parse_bool()?)
+        self.scalar(|de| visitor.visit_bool(de.parse_bool()?))
     }
 
     fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_i8(self.parse_signed()?)
+        self.scalar(|de| visitor.visit_i8(de.parse_signed()?))
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_i16(self.parse_signed()?)
+        self.scalar(|de| visitor.visit_i16(de.parse_signed()?))
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_i32(self.parse_signed()?)
+        self.scalar(|de| visitor.visit_i32(de.parse_signed()?))
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_i64(self.parse_signed()?)
+        self.scalar(|de| visitor.visit_i64(de.parse_signed()?))
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_u8(self.parse_unsigned()?)
+        self.scalar(|de| visitor.visit_u8(de.parse_unsigned()?))
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_u16(self.parse_unsigned()?)
+        self.scalar(|de| visitor.visit_u16(de.parse_unsigned()?))
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_u32(self.parse_unsigned()?)
+        self.scalar(|de| visitor.visit_u32(de.parse_unsigned()?))
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_u64(self.parse_unsigned()?)
+        self.scalar(|de| visitor.visit_u64(de.parse_unsigned()?))
     }
 
-    fn deserialize_f32<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        unimplemented!()
+        self.scalar(|de| match de.parse_float()?.parse::<f32>() {
+            Ok(v) if v.is_finite() => visitor.visit_f32(v),
+            _ => Err(ErrorCode::NumberOutOfRange.into()),
+        })
     }
 
-    fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        unimplemented!()
+        self.scalar(|de| visitor.visit_f64(finite(de.parse_float()?.parse::<f64>())?))
     }
 
-    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        unimplemented!()
+        self.scalar(|de| {
+            let s = de.parse_string()?;
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => visitor.visit_char(ch),
+                _ => Err(ErrorCode::ExpectedChar.into()),
+            }
+        })
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_borrowed_str(self.parse_string()?)
+        self.scalar(|de| match de.parse_string()? {
+            Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+            Cow::Owned(s) => visitor.visit_string(s),
+        })
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
@@ -214,24 +541,33 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         self.deserialize_str(visitor)
     }
 
-    fn deserialize_bytes<V>(self, _visitor: V) -> Result<V::Value>
+    // Bytes are serialized as an array of numbers, but may also be given as a string
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        unimplemented!()
+        if self.peek_char()? == '"' {
+            self.scalar(|de| match de.parse_string()? {
+                Cow::Borrowed(s) => visitor.visit_borrowed_bytes(s.as_bytes()),
+                Cow::Owned(s) => visitor.visit_byte_buf(s.into_bytes()),
+            })
+        } else {
+            self.deserialize_seq(visitor)
+        }
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_bytes(visitor)
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
+        self.skip_whitespace();
         if self.input.starts_with("null") {
             self.input = &self.input["null".len()..];
             visitor.visit_none()
@@ -244,11 +580,12 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         where
             V: Visitor<'de>,
     {
+        self.skip_whitespace();
         if self.input.starts_with("null") {
             self.input = &self.input["null".len()..];
             visitor.visit_unit()
         } else {
-            Err(Error::ExpectedNull)
+            Err(ErrorCode::ExpectedNull.into())
         }
     }
 
@@ -263,30 +600,39 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         self.deserialize_unit(visitor)
     }
 
+    // `Number` and `Value` ask for raw numbers through the token
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
-        visitor.visit_newtype_struct(self)
+        if name != RAW_NUMBER_TOKEN {
+            return visitor.visit_newtype_struct(self);
+        }
+        match self.peek_char()? {
+            '-' | '0'..='9' if self.arbitrary_precision => {
+                number::visit_raw(self.parse_number()?.0, visitor)
+            }
+            _ => self.deserialize_any(visitor),
+        }
     }
 
-    fn deserialize_seq<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
         if self.next_char()? == '[' {
-            let value = visitor.visit_seq(CommaSeparated::new(&mut self))?;
+            let value = visitor.visit_seq(CommaSeparated::new(self))?;
             if self.next_char()? == ']' {
                 Ok(value)
             } else {
-                Err(Error::ExpectedArrayEnd)
+                Err(ErrorCode::ExpectedArrayEnd.into())
             }
         } else {
-            Err(Error::ExpectedArray)
+            Err(ErrorCode::ExpectedArray.into())
         }
     }
 
@@ -309,22 +655,22 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         self.deserialize_seq(visitor)
     }
 
-    fn deserialize_map<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
     {
         // Parse the opening brace of the map.
         if self.next_char()? == '{' {
             // Give the visitor access to each entry of the map.
-            let value = visitor.visit_map(CommaSeparated::new(&mut self))?;
+            let value = visitor.visit_map(CommaSeparated::new(self))?;
             // Parse the closing brace of the map.
             if self.next_char()? == '}' {
                 Ok(value)
             } else {
-                Err(Error::ExpectedMapEnd)
+                Err(ErrorCode::ExpectedMapEnd.into())
             }
         } else {
-            Err(Error::ExpectedMap)
+            Err(ErrorCode::ExpectedMap.into())
         }
     }
 
@@ -350,16 +696,19 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             V: Visitor<'de>,
     {
         if self.peek_char()? == '"' {
-            visitor.visit_enum(self.parse_string()?.into_deserializer())
+            self.scalar(|de| match de.parse_string()? {
+                Cow::Borrowed(s) => visitor.visit_enum(s.into_deserializer()),
+                Cow::Owned(s) => visitor.visit_enum(s.into_deserializer()),
+            })
         } else if self.next_char()? == '{' {
             let value = visitor.visit_enum(Enum::new(self))?;
             if self.next_char()? == '}' {
                 Ok(value)
             } else {
-                Err(Error::ExpectedMapEnd)
+                Err(ErrorCode::ExpectedMapEnd.into())
             }
         } else {
-            Err(Error::ExpectedEnum)
+            Err(ErrorCode::ExpectedEnum.into())
         }
     }
 
@@ -378,9 +727,46 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     }
 }
 
+/// Returns the sign and magnitude of an integer's text, as returned by `parse_number`
+fn integer_magnitude(number: &str) -> Result<(bool, u64)> {
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, number),
+    };
+    let mut magnitude: u64 = 0;
+    for digit in digits.bytes() {
+        magnitude = magnitude
+            .checked_mul(10)
+            .and_then(|m| m.checked_add(u64::from(digit - b'0')))
+            .ok_or(ErrorCode::NumberOutOfRange)?;
+    }
+    Ok((negative, magnitude))
+}
+
+/// Applies a sign to a magnitude, if the result fits in an `i64`
+fn signed(negative: bool, magnitude: u64) -> Option<i64> {
+    if !negative {
+        i64::try_from(magnitude).ok()
+    } else if magnitude == i64::MIN as u64 {
+        Some(i64::MIN)
+    } else {
+        i64::try_from(magnitude).ok().map(|int| -int)
+    }
+}
+
+/// Rejects floats that overflowed to infinity
+fn finite(v: std::result::Result<f64, std::num::ParseFloatError>) -> Result<f64> {
+    match v {
+        Ok(v) if v.is_finite() => Ok(v),
+        _ => Err(ErrorCode::NumberOutOfRange.into()),
+    }
+}
+
 struct CommaSeparated<'a, 'de: 'a> {
     de: &'a mut Deserializer<'de>,
     first: bool,
+    /// The number of elements decoded so far
+    index: usize,
 }
 
 impl<'a, 'de> CommaSeparated<'a, 'de> {
@@ -388,8 +774,21 @@ impl<'a, 'de> CommaSeparated<'a, 'de> {
         CommaSeparated {
             de,
             first: true,
+            index: 0,
         }
     }
+
+    /// Decodes a value at the given path segment. The segment stays on the path when decoding
+    /// fails, such that the error is located at the value
+    fn decode<T>(&mut self, segment: Segment<'de>, seed: T) -> Result<T::Value>
+        where
+            T: DeserializeSeed<'de>,
+    {
+        self.de.path.push(segment);
+        let value = seed.deserialize(&mut *self.de).map_err(|err| self.de.locate(err))?;
+        self.de.path.pop();
+        Ok(value)
+    }
 }
 
 impl<'de, 'a> SeqAccess<'de> for CommaSeparated<'a, 'de> {
@@ -403,10 +802,12 @@ impl<'de, 'a> SeqAccess<'de> for CommaSeparated<'a, 'de> {
             return Ok(None);
         }
         if !self.first && self.de.next_char()? != ',' {
-            return Err(Error::ExpectedArrayComma);
+            return Err(ErrorCode::ExpectedArrayComma.into());
         }
         self.first = false;
-        seed.deserialize(&mut *self.de).map(Some)
+        let value = self.decode(Segment::Index(self.index), seed)?;
+        self.index += 1;
+        Ok(Some(value))
     }
 }
 
@@ -421,10 +822,17 @@ impl<'de, 'a> MapAccess<'de> for CommaSeparated<'a, 'de> {
             return Ok(None);
         }
         if !self.first && self.de.next_char()? != ',' {
-            return Err(Error::ExpectedMapComma);
+            return Err(ErrorCode::ExpectedMapComma.into());
         }
         self.first = false;
-        seed.deserialize(&mut *self.de).map(Some)
+        let key = self.de.input;
+        let value = seed.deserialize(MapKey { de: &mut *self.de }).map_err(|err| self.de.locate(err))?;
+        // Keep the key for the value's path, decoded again from its text as the seed may not
+        // have kept it
+        let key = key[..key.len() - self.de.input.len()].trim_start();
+        let key = Deserializer::from_str(key).parse_string().unwrap_or(Cow::Borrowed(key));
+        self.de.path.push(Segment::Key(key));
+        Ok(Some(value))
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
@@ -432,9 +840,126 @@ impl<'de, 'a> MapAccess<'de> for CommaSeparated<'a, 'de> {
             V: DeserializeSeed<'de>,
     {
         if self.de.next_char()? != ':' {
-            return Err(Error::ExpectedMapColon);
+            return Err(ErrorCode::ExpectedMapColon.into());
         }
-        seed.deserialize(&mut *self.de)
+        // The key's segment was pushed by next_key_seed
+        let segment = self.de.path.pop().unwrap_or(Segment::Index(self.index));
+        self.decode(segment, seed)
+    }
+}
+
+/// Decodes a map key, which is always a string. Integer and bool keys are parsed from its
+/// text, as the serializer writes them
+struct MapKey<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+}
+
+/// Parses the whole text of a key as a scalar of another type
+fn parse_key<T>(
+    key: &str,
+    expected: ErrorCode,
+    parse: impl FnOnce(&mut Deserializer<'_>) -> Result<T>,
+) -> Result<T> {
+    let mut de = Deserializer::from_str(key);
+    match parse(&mut de) {
+        Ok(v) if de.input.is_empty() && !key.starts_with(char::is_whitespace) => Ok(v),
+        Err(err) if err.code() == &ErrorCode::NumberOutOfRange => Err(err),
+        _ => Err(expected.into()),
+    }
+}
+
+macro_rules! deserialize_quoted {
+    ($($method:ident => $visit:ident($parse:ident, $expected:ident))*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+                where
+                    V: Visitor<'de>,
+            {
+                self.de.scalar(|de| {
+                    let key = de.parse_string()?;
+                    visitor.$visit(parse_key(&key, ErrorCode::$expected, |de| de.$parse())?)
+                })
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for MapKey<'a, 'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        self.de.deserialize_str(visitor)
+    }
+
+    deserialize_quoted!(
+        deserialize_bool => visit_bool(parse_bool, ExpectedBoolean)
+        deserialize_i8 => visit_i8(parse_signed, ExpectedInteger)
+        deserialize_i16 => visit_i16(parse_signed, ExpectedInteger)
+        deserialize_i32 => visit_i32(parse_signed, ExpectedInteger)
+        deserialize_i64 => visit_i64(parse_signed, ExpectedInteger)
+        deserialize_u8 => visit_u8(parse_unsigned, ExpectedInteger)
+        deserialize_u16 => visit_u16(parse_unsigned, ExpectedInteger)
+        deserialize_u32 => visit_u32(parse_unsigned, ExpectedInteger)
+        deserialize_u64 => visit_u64(parse_unsigned, ExpectedInteger)
+    );
+
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        self.de.deserialize_char(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        self.de.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        self.de.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+    {
+        self.de.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        f32 f64 str string unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
     }
 }
 
@@ -460,7 +985,7 @@ impl<'de, 'a> EnumAccess<'de> for Enum<'a, 'de> {
         if self.de.next_char()? == ':' {
             Ok((val, self))
         } else {
-            Err(Error::ExpectedMapColon)
+            Err(ErrorCode::ExpectedMapColon.into())
         }
     }
 }
@@ -469,7 +994,7 @@ impl<'de, 'a> VariantAccess<'de> for Enum<'a, 'de> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
-        Err(Error::ExpectedString)
+        Err(ErrorCode::ExpectedString.into())
     }
 
     fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
@@ -512,4 +1037,184 @@ fn test_struct() {
         seq: vec!["a".to_owned(), "b".to_owned()],
     };
     assert_eq!(expected, from_str(j).unwrap());
+}
+
+#[test]
+fn test_numbers() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Test {
+        small: i8,
+        int: i32,
+        min: i64,
+        max: u64,
+        float: f64,
+        exp: f64,
+        single: f32,
+    }
+
+    let j = r#"{"small":-128,"int":-0,"min":-9223372036854775808,"max":18446744073709551615,"float":-12.5,"exp":1.5E-3,"single":0.1}"#;
+    let expected = Test {
+        small: -128,
+        int: 0,
+        min: i64::MIN,
+        max: u64::MAX,
+        float: -12.5,
+        exp: 0.0015,
+        single: 0.1,
+    };
+    assert_eq!(expected, from_str(j).unwrap());
+    assert_eq!(Ok(2e10), from_str::<f64>("2e+10"));
+    assert_eq!(Ok(3.0), from_str::<f64>("3"));
+    assert_eq!(Ok('x'), from_str::<char>(r#""x""#));
+}
+
+#[test]
+fn test_number_errors() {
+    assert_eq!(&ErrorCode::NumberOutOfRange, from_str::<u8>("256").unwrap_err().code());
+    assert_eq!(&ErrorCode::NumberOutOfRange, from_str::<u32>("-1").unwrap_err().code());
+    assert_eq!(&ErrorCode::NumberOutOfRange, from_str::<i64>("9223372036854775808").unwrap_err().code());
+    assert_eq!(&ErrorCode::NumberOutOfRange, from_str::<u64>("18446744073709551616").unwrap_err().code());
+    assert_eq!(&ErrorCode::NumberOutOfRange, from_str::<f64>("1e400").unwrap_err().code());
+    assert_eq!(&ErrorCode::NumberOutOfRange, from_str::<f32>("1e39").unwrap_err().code());
+    assert_eq!(&ErrorCode::ExpectedInteger, from_str::<i32>("1.5").unwrap_err().code());
+    assert_eq!(&ErrorCode::InvalidNumber, from_str::<u32>("01").unwrap_err().code());
+    assert_eq!(&ErrorCode::InvalidNumber, from_str::<f64>("1.").unwrap_err().code());
+    assert_eq!(&ErrorCode::InvalidNumber, from_str::<f64>("-.5").unwrap_err().code());
+    assert_eq!(&ErrorCode::InvalidNumber, from_str::<f64>("1e").unwrap_err().code());
+    assert_eq!(&ErrorCode::ExpectedNumber, from_str::<f64>("true").unwrap_err().code());
+}
+
+#[test]
+fn test_arbitrary_precision() {
+    use crate::Number;
+
+    let j = "[123456789012345678901234567890,-1.000000000000000000001e-400,7]";
+    let mut deserializer = Deserializer::from_str(j).with_arbitrary_precision();
+    let numbers = Vec::<Number>::deserialize(&mut deserializer).unwrap();
+    deserializer.end().unwrap();
+    assert_eq!(Some("123456789012345678901234567890"), numbers[0].as_raw());
+    assert_eq!(Some("-1.000000000000000000001e-400"), numbers[1].as_raw());
+    assert_eq!(Some(7), numbers[2].as_u64());
+    assert_eq!(j, crate::to_string(&numbers).unwrap());
+
+    // Only `Number` and `Value` see raw numbers
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(untagged)]
+    enum Port {
+        Number(u16),
+        Name(String),
+    }
+    let j = r#"[[8080,"http"],{"big":1e400,"port":443}]"#;
+    let mut deserializer = Deserializer::from_str(j).with_arbitrary_precision();
+    let (ports, value) = <(Vec<Port>, crate::Value)>::deserialize(&mut deserializer).unwrap();
+    assert_eq!(vec![Port::Number(8080), Port::Name("http".to_string())], ports);
+    assert_eq!(Some("1e400"), value["big"].as_number().and_then(crate::Number::as_raw));
+    assert_eq!(Some(443), value["port"].as_u64());
+    assert_eq!(Some(443), crate::from_value::<u16>(value["port"].clone()).ok());
+    assert_eq!(value, crate::from_value::<crate::Value>(value.clone()).unwrap());
+
+    let numbers: Vec<Number> = from_str("[18446744073709551616,-5,0.5]").unwrap();
+    assert_eq!(None, numbers[0].as_raw());
+    assert_eq!(Some(18446744073709551616.0), numbers[0].as_f64());
+    assert_eq!(Some(-5), numbers[1].as_i64());
+    assert_eq!(Some(0.5), numbers[2].as_f64());
+}
+
+#[test]
+fn test_error_position() {
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        name: String,
+        servers: Vec<Server>,
+    }
+
+    let j = "{\"name\":\"db\",\n\"servers\":[{\"host\":\"a\",\"port\":1},\n{\"host\":\"b\",\"port\":70000}]}";
+    let err = from_str::<Config>(j).unwrap_err();
+    assert_eq!(&ErrorCode::NumberOutOfRange, err.code());
+    assert_eq!("servers[1].port", err.path());
+    let position = err.position().unwrap();
+    assert_eq!((3, 20, 67), (position.line, position.column, position.offset));
+    assert_eq!("{\"host\":\"b\",\"port\":70000}]}", position.snippet);
+
+    // Type and range errors are located at the start of the scalar, syntax errors where found
+    let column = |err: Error| err.position().unwrap().column;
+    assert_eq!(3, column(from_str::<u8>("  300").unwrap_err()));
+    assert_eq!(3, column(from_str::<u8>("  1.5").unwrap_err()));
+    assert_eq!(2, column(from_str::<f32>(" 1e39").unwrap_err()));
+    assert_eq!(2, column(from_str::<char>(" \"ab\"").unwrap_err()));
+    assert_eq!(7, column(from_str::<Vec<String>>("[\"a\", 5]").unwrap_err()));
+    assert_eq!(7, column(from_str::<Vec<bool>>("[true,nul]").unwrap_err()));
+    assert_eq!(4, column(from_str::<String>("\"ab\\x\"").unwrap_err()));
+
+    let err = from_str::<Config>("{\"name\":\"db\",\"servers\":[{\"host\":\"a\"}]}").unwrap_err();
+    assert_eq!(&ErrorCode::Message("missing field `port`".to_string()), err.code());
+    assert_eq!("servers[0]", err.path());
+    assert_eq!(
+        "servers[0]: missing field `port` at line 1 column 36: e\":\"db\",\"servers\":[{\"host\":\"a\"}]}",
+        err.to_string()
+    );
+
+    let err = from_str::<std::collections::HashMap<String, u8>>(r#"{"k\u0041":300}"#).unwrap_err();
+    assert_eq!("kA", err.path());
+    let err = from_str::<std::collections::HashMap<String, u8>>(r#"{"a\"b":300}"#).unwrap_err();
+    assert_eq!("a\"b", err.path());
+
+    let err = from_str::<Vec<u8>>("[1,2]x").unwrap_err();
+    assert_eq!(&ErrorCode::TrailingCharacters, err.code());
+    assert_eq!((1, 6), (err.position().unwrap().line, err.position().unwrap().column));
+}
+
+#[test]
+fn test_reader() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Event {
+        id: u64,
+        kind: String,
+    }
+
+    let j = "{\"id\": 1, \"kind\": \"start\"}";
+    assert_eq!(Event { id: 1, kind: "start".to_string() }, from_reader(j.as_bytes()).unwrap());
+
+    let log = "{\"id\":1,\"kind\":\"start\"}\n\n{\"id\":2,\"kind\":\"stop\"}\r\n{\"id\":x}\n{\"id\":4,\"kind\":\"start\"}";
+    let mut events = StreamDeserializer::<_, Event>::new(log.as_bytes());
+    assert_eq!(Event { id: 1, kind: "start".to_string() }, events.next().unwrap().unwrap());
+    assert_eq!(Event { id: 2, kind: "stop".to_string() }, events.next().unwrap().unwrap());
+    let err = events.next().unwrap().unwrap_err();
+    assert_eq!(&ErrorCode::ExpectedInteger, err.code());
+    let position = err.position().unwrap();
+    assert_eq!((4, 7, 55), (position.line, position.column, position.offset));
+    assert_eq!(Event { id: 4, kind: "start".to_string() }, events.next().unwrap().unwrap());
+    assert!(events.next().is_none());
+    assert_eq!(5, events.line_number());
+}
+
+#[test]
+fn test_strings() {
+    assert_eq!(Ok("plain"), from_str::<&str>(r#""plain""#));
+    assert!(from_str::<&str>(r#""a\nb""#).is_err());
+    assert_eq!(
+        "quote \" backslash \\ slash / \u{8}\u{c}\n\r\t é \u{1F600}",
+        from_str::<String>(r#""quote \" backslash \\ slash \/ \b\f\n\r\t \u00e9 \ud83d\ude00""#).unwrap()
+    );
+    assert_eq!(vec!["a\"b".to_string(), "".to_string()], from_str::<Vec<String>>(r#"["a\"b", ""]"#).unwrap());
+
+    let code = |j: &str| from_str::<String>(j).unwrap_err().code().clone();
+    assert_eq!(ErrorCode::InvalidUnicodeCodePoint, code(r#""\ud83d""#));
+    assert_eq!(ErrorCode::InvalidUnicodeCodePoint, code(r#""\ud83d\u0041""#));
+    assert_eq!(ErrorCode::InvalidUnicodeCodePoint, code(r#""\ude00""#));
+    assert_eq!(ErrorCode::InvalidEscape, code(r#""\x41""#));
+    assert_eq!(ErrorCode::InvalidEscape, code(r#""\u12g4""#));
+    assert_eq!(ErrorCode::Eof, code(r#""abc"#));
+    assert_eq!(ErrorCode::ControlCharacterInString, code("\"a\nb\""));
+
+    let err = from_slice::<String>(b"\"ab\xff\"").unwrap_err();
+    assert_eq!(&ErrorCode::InvalidUtf8, err.code());
+    assert_eq!(4, err.position().unwrap().column);
 }
\ No newline at end of file
@@ -1,17 +1,113 @@
-use std;
 use std::fmt::{self, Display};
 use serde::{de, ser};
 use serde::export::Formatter;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The number of characters of context kept on each side of an error position
+const SNIPPET_CONTEXT: usize = 30;
+
+/// An error, with the position in the input and the path of the value being decoded when it
+/// came from the `Deserializer`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    code: ErrorCode,
+    position: Option<Position>,
+    /// The path to the value being decoded, e.g. `servers[3].port`, or empty for the root
+    path: String,
+}
+
+/// A position in the deserializer input
+#[derive(Clone, Debug, PartialEq)]
+pub struct Position {
+    /// The byte offset from the start of the input
+    pub offset: usize,
+    /// The line number, starting at 1
+    pub line: usize,
+    /// The column number in characters, starting at 1
+    pub column: usize,
+    /// The text of the line around the position
+    pub snippet: String,
+}
+
+impl Position {
+    /// Locates a byte offset in the input
+    pub(crate) fn new(input: &str, offset: usize) -> Self {
+        let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[offset..].find('\n').map_or(input.len(), |i| offset + i);
+        let before: Vec<char> = input[line_start..offset].chars().collect();
+        let snippet_start = before.len().saturating_sub(SNIPPET_CONTEXT);
+        let snippet = before[snippet_start..]
+            .iter()
+            .chain(input[offset..line_end].chars().take(SNIPPET_CONTEXT).collect::<Vec<_>>().iter())
+            .collect();
+        Position {
+            offset,
+            line: input[..line_start].matches('\n').count() + 1,
+            column: before.len() + 1,
+            snippet,
+        }
+    }
+}
+
+impl Error {
+    /// The kind of error
+    pub fn code(&self) -> &ErrorCode {
+        &self.code
+    }
+
+    /// The position in the input where the error occurred, if deserializing
+    pub fn position(&self) -> Option<&Position> {
+        self.position.as_ref()
+    }
+
+    /// The path to the value being decoded when the error occurred, e.g. `servers[3].port`
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Sets the position and path of the error, unless already set by a nested value
+    pub(crate) fn locate(mut self, position: impl FnOnce() -> Position, path: impl FnOnce() -> String) -> Self {
+        if self.position.is_none() {
+            self.position = Some(position());
+            self.path = path();
+        }
+        self
+    }
+
+    /// Moves the error's position, for an input that started at the given line and byte offset
+    /// of a larger one
+    pub(crate) fn shift(mut self, line: usize, offset: usize) -> Self {
+        if let Some(position) = &mut self.position {
+            position.line += line - 1;
+            position.offset += offset;
+        }
+        self
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        ErrorCode::Io(err.to_string()).into()
+    }
+}
+
+impl From<ErrorCode> for Error {
+    fn from(code: ErrorCode) -> Self {
+        Error { code, position: None, path: String::new() }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
-pub enum Error {
+pub enum ErrorCode {
     Message(String),
+    Io(String),
     Eof,
     Syntax,
     ExpectedBoolean,
     ExpectedInteger,
+    ExpectedNumber,
+    ExpectedChar,
     ExpectedString,
     ExpectedNull,
     ExpectedArray,
@@ -22,13 +118,21 @@ pub enum Error {
     ExpectedMapComma,
     ExpectedMapEnd,
     ExpectedEnum,
+    InvalidNumber,
+    InvalidEscape,
+    InvalidUnicodeCodePoint,
+    InvalidUtf8,
+    ControlCharacterInString,
+    NumberOutOfRange,
+    NonFiniteFloat,
+    KeyMustBeAString,
     TrailingCharacters
 }
 
 impl ser::Error for Error {
     fn custom<T>(msg: T) -> Self where
         T: Display {
-        Error::Message(msg.to_string())
+        ErrorCode::Message(msg.to_string()).into()
     }
 }
 
@@ -36,29 +140,53 @@ impl de::Error for Error {
 
     fn custom<T>(msg: T) -> Self where
         T: Display {
-        Error::Message(msg.to_string())
+        ErrorCode::Message(msg.to_string()).into()
     }
 }
 
 impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        Display::fmt(&self.code, f)?;
+        if let Some(position) = &self.position {
+            write!(f, " at line {} column {}: {}", position.line, position.column, position.snippet)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ErrorCode {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Error::Message(msg) => f.write_str(msg),
-            Error::Eof => f.write_str("unexpected end of input"),
-            Error::Syntax => f.write_str("syntax error"),
-            Error::ExpectedBoolean => f.write_str("expected boolean error"),
-            Error::ExpectedInteger => f.write_str("expected integer error"),
-            Error::ExpectedString => f.write_str("expected string error"),
-            Error::ExpectedNull => f.write_str("expected null error"),
-            Error::ExpectedArray => f.write_str("expected array error"),
-            Error::ExpectedArrayComma =>f.write_str("expected array comma error"),
-            Error::ExpectedArrayEnd => f.write_str("expected array end error"),
-            Error::ExpectedMap => f.write_str("expected map error"),
-            Error::ExpectedMapColon => f.write_str("expected map colon error"),
-            Error::ExpectedMapComma => f.write_str("expected map comma error"),
-            Error::ExpectedMapEnd => f.write_str("expected map end error"),
-            Error::ExpectedEnum => f.write_str("expected enum error"),
-            Error::TrailingCharacters => f.write_str("expected trailing characters error"),
+            ErrorCode::Message(msg) => f.write_str(msg),
+            ErrorCode::Io(msg) => write!(f, "io error: {}", msg),
+            ErrorCode::Eof => f.write_str("unexpected end of input"),
+            ErrorCode::Syntax => f.write_str("syntax error"),
+            ErrorCode::ExpectedBoolean => f.write_str("expected boolean error"),
+            ErrorCode::ExpectedInteger => f.write_str("expected integer error"),
+            ErrorCode::ExpectedNumber => f.write_str("expected number error"),
+            ErrorCode::ExpectedChar => f.write_str("expected char error"),
+            ErrorCode::ExpectedString => f.write_str("expected string error"),
+            ErrorCode::ExpectedNull => f.write_str("expected null error"),
+            ErrorCode::ExpectedArray => f.write_str("expected array error"),
+            ErrorCode::ExpectedArrayComma =>f.write_str("expected array comma error"),
+            ErrorCode::ExpectedArrayEnd => f.write_str("expected array end error"),
+            ErrorCode::ExpectedMap => f.write_str("expected map error"),
+            ErrorCode::ExpectedMapColon => f.write_str("expected map colon error"),
+            ErrorCode::ExpectedMapComma => f.write_str("expected map comma error"),
+            ErrorCode::ExpectedMapEnd => f.write_str("expected map end error"),
+            ErrorCode::ExpectedEnum => f.write_str("expected enum error"),
+            ErrorCode::InvalidNumber => f.write_str("invalid number error"),
+            ErrorCode::InvalidEscape => f.write_str("invalid escape error"),
+            ErrorCode::InvalidUnicodeCodePoint => f.write_str("invalid unicode code point error"),
+            ErrorCode::InvalidUtf8 => f.write_str("invalid utf-8 error"),
+            ErrorCode::ControlCharacterInString => f.write_str("control character in string error"),
+            ErrorCode::NumberOutOfRange => f.write_str("number out of range error"),
+            ErrorCode::NonFiniteFloat => f.write_str("non-finite float error"),
+            ErrorCode::KeyMustBeAString => f.write_str("key must be a string error"),
+            ErrorCode::TrailingCharacters => f.write_str("expected trailing characters error"),
         }
     }
 }
@@ -0,0 +1,282 @@
+use std::fmt::{Display, LowerExp};
+use std::io::{self, Write};
+
+/// Controls how the `Serializer` writes JSON tokens, i.e. separators, indentation and scalar
+/// values. Every method has a default writing compact JSON.
+pub trait Formatter {
+    fn write_null<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"null")
+    }
+
+    fn write_bool<W: ?Sized + Write>(&mut self, writer: &mut W, value: bool) -> io::Result<()> {
+        writer.write_all(if value { b"true" } else { b"false" })
+    }
+
+    fn write_i64<W: ?Sized + Write>(&mut self, writer: &mut W, value: i64) -> io::Result<()> {
+        write!(writer, "{}", value)
+    }
+
+    fn write_u64<W: ?Sized + Write>(&mut self, writer: &mut W, value: u64) -> io::Result<()> {
+        write!(writer, "{}", value)
+    }
+
+    /// Writes a finite f32, in the shortest form that reads back as the same f32
+    fn write_f32<W: ?Sized + Write>(&mut self, writer: &mut W, value: f32) -> io::Result<()> {
+        writer.write_all(format_float(value).as_bytes())
+    }
+
+    /// Writes a finite f64, in the shortest form that reads back as the same f64
+    fn write_f64<W: ?Sized + Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        writer.write_all(format_float(value).as_bytes())
+    }
+
+    /// Writes the text of a number deserialized with arbitrary precision
+    fn write_raw_number<W: ?Sized + Write>(&mut self, writer: &mut W, value: &str) -> io::Result<()> {
+        writer.write_all(value.as_bytes())
+    }
+
+    /// Writes a string, with quotes and escapes
+    fn write_str<W: ?Sized + Write>(&mut self, writer: &mut W, value: &str) -> io::Result<()> {
+        write_escaped(writer, value)
+    }
+
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"[")
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"]")
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b",")
+        }
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"{")
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"}")
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b",")
+        }
+    }
+
+    fn end_object_key<W: ?Sized + Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b":")
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes JSON without any whitespace
+#[derive(Clone, Debug, Default)]
+pub struct CompactFormatter;
+
+impl Formatter for CompactFormatter {}
+
+/// Writes JSON with each array element and object entry on its own line, indented by depth
+#[derive(Clone, Debug)]
+pub struct PrettyFormatter {
+    indent: String,
+    depth: usize,
+    /// Whether the current array or object has any elements
+    has_value: bool,
+}
+
+impl PrettyFormatter {
+    /// Creates a formatter indenting by two spaces
+    pub fn new() -> Self {
+        PrettyFormatter::with_indent("  ")
+    }
+
+    pub fn with_indent(indent: &str) -> Self {
+        PrettyFormatter {
+            indent: indent.to_string(),
+            depth: 0,
+            has_value: false,
+        }
+    }
+
+    fn write_indent<W: ?Sized + Write>(&self, writer: &mut W) -> io::Result<()> {
+        for _ in 0..self.depth {
+            writer.write_all(self.indent.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn begin<W: ?Sized + Write>(&mut self, writer: &mut W, open: &[u8]) -> io::Result<()> {
+        self.depth += 1;
+        self.has_value = false;
+        writer.write_all(open)
+    }
+
+    fn end<W: ?Sized + Write>(&mut self, writer: &mut W, close: &[u8]) -> io::Result<()> {
+        self.depth -= 1;
+        if self.has_value {
+            writer.write_all(b"\n")?;
+            self.write_indent(writer)?;
+        }
+        writer.write_all(close)
+    }
+
+    fn begin_value<W: ?Sized + Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        writer.write_all(if first { b"\n" } else { b",\n" })?;
+        self.write_indent(writer)
+    }
+}
+
+impl Default for PrettyFormatter {
+    fn default() -> Self {
+        PrettyFormatter::new()
+    }
+}
+
+impl Formatter for PrettyFormatter {
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.begin(writer, b"[")
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.end(writer, b"]")
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        self.begin_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.begin(writer, b"{")
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.end(writer, b"}")
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(&mut self, writer: &mut W, first: bool) -> io::Result<()> {
+        self.begin_value(writer, first)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b": ")
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        self.has_value = true;
+        Ok(())
+    }
+}
+
+/// Writes compact JSON with numbers in a single normal form, such that equal values always
+/// produce the same bytes when combined with sorted keys: integral floats below 1e21 are written
+/// as integers, and negative zero as `0`
+#[derive(Clone, Debug, Default)]
+pub struct CanonicalFormatter;
+
+impl Formatter for CanonicalFormatter {
+    fn write_f32<W: ?Sized + Write>(&mut self, writer: &mut W, value: f32) -> io::Result<()> {
+        write_canonical_float(writer, value)
+    }
+
+    fn write_f64<W: ?Sized + Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        write_canonical_float(writer, value)
+    }
+
+    fn write_raw_number<W: ?Sized + Write>(&mut self, writer: &mut W, value: &str) -> io::Result<()> {
+        if value.contains(&['.', 'e', 'E'][..]) {
+            match value.parse::<f64>() {
+                Ok(float) if float.is_finite() => self.write_f64(writer, float),
+                _ => writer.write_all(value.as_bytes()),
+            }
+        } else if value == "-0" {
+            writer.write_all(b"0")
+        } else {
+            writer.write_all(value.as_bytes())
+        }
+    }
+}
+
+fn write_canonical_float<W, F>(writer: &mut W, value: F) -> io::Result<()>
+    where W: ?Sized + Write, F: Copy + Display + LowerExp + Into<f64> {
+    let float: f64 = value.into();
+    if float == 0.0 {
+        writer.write_all(b"0")
+    } else if float.fract() == 0.0 && float.abs() < 1e21 {
+        write!(writer, "{}", value)
+    } else {
+        writer.write_all(format_float(value).as_bytes())
+    }
+}
+
+/// Formats a finite float in the shortest form that parses back to the same value. Very large
+/// and very small magnitudes use exponent notation, and integral values keep a `.0` suffix such
+/// that they read back as floats.
+pub(crate) fn format_float<F: Display + LowerExp>(v: F) -> String {
+    let exponential = format!("{:e}", v);
+    let exponent: i32 = exponential
+        .rsplit('e')
+        .next()
+        .and_then(|e| e.parse().ok())
+        .unwrap_or(0);
+    if !(-6..21).contains(&exponent) {
+        return exponential;
+    }
+    let mut plain = v.to_string();
+    if !plain.contains('.') {
+        plain += ".0";
+    }
+    plain
+}
+
+/// Writes a string with quotes, escaping quotes, backslashes and control characters
+fn write_escaped<W: ?Sized + Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, b) in value.bytes().enumerate() {
+        let escape: &[u8] = match b {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            0x08 => b"\\b",
+            0x0c => b"\\f",
+            0x00..=0x1f => b"",
+            _ => continue,
+        };
+        writer.write_all(&value.as_bytes()[start..i])?;
+        if escape.is_empty() {
+            write!(writer, "\\u{:04x}", b)?;
+        } else {
+            writer.write_all(escape)?;
+        }
+        start = i + 1;
+    }
+    writer.write_all(&value.as_bytes()[start..])?;
+    writer.write_all(b"\"")
+}
@@ -1,5 +1,20 @@
+#[macro_use]
+mod macros;
 mod error;
 mod ser;
+mod format;
 mod de;
+mod number;
+mod map;
+mod value;
 
-pub use error::{Error, Result};
\ No newline at end of file
+pub use error::{Error, ErrorCode, Position, Result};
+pub use ser::{
+    to_string, to_string_canonical, to_string_pretty, to_vec, to_vec_canonical, to_writer, to_writer_canonical,
+    to_writer_pretty, Serializer,
+};
+pub use format::{CanonicalFormatter, CompactFormatter, Formatter, PrettyFormatter};
+pub use de::{from_reader, from_slice, from_str, Deserializer, StreamDeserializer};
+pub use number::Number;
+pub use map::Map;
+pub use value::{from_value, to_value, Value};
\ No newline at end of file
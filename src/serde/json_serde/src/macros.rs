@@ -0,0 +1,113 @@
+/// Constructs a `Value` from JSON-like syntax. Object keys are string literals or parenthesized
+/// expressions, and any other value is an expression converted with `to_value`, or `null` when
+/// it has no JSON form, such as a map with non-string keys.
+///
+/// ```
+/// let port = 8080;
+/// let config = json_serde::json!({
+///     "hosts": ["a", "b"],
+///     "port": port,
+///     "tls": null,
+/// });
+/// assert_eq!(config["port"].as_u64(), Some(8080));
+/// ```
+#[macro_export]
+macro_rules! json {
+    ($($json:tt)+) => {
+        $crate::json_internal!($($json)+)
+    };
+}
+
+// Munches tokens one element or entry at a time, since values like `-1` or `a + b` span several
+// token trees and nested arrays and objects must be matched before falling back to expressions.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! json_internal {
+    // Done with an array, with or without a trailing comma
+    (@array [$($elems:expr,)*]) => {
+        vec![$($elems,)*]
+    };
+    (@array [$($elems:expr),*]) => {
+        vec![$($elems),*]
+    };
+
+    // The next element is null, an array or an object
+    (@array [$($elems:expr,)*] null $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!(null)] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] [$($array:tt)*] $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!([$($array)*])] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] {$($map:tt)*} $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!({$($map)*})] $($rest)*)
+    };
+
+    // The next element is an expression, with or without a following comma
+    (@array [$($elems:expr,)*] $next:expr, $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!($next),] $($rest)*)
+    };
+    (@array [$($elems:expr,)*] $last:expr) => {
+        $crate::json_internal!(@array [$($elems,)* $crate::json_internal!($last)])
+    };
+
+    // The comma after an element
+    (@array [$($elems:expr),*] , $($rest:tt)*) => {
+        $crate::json_internal!(@array [$($elems,)*] $($rest)*)
+    };
+
+    // Done with an object, or the comma after an entry
+    (@object $object:ident) => {};
+    (@object $object:ident , $($rest:tt)*) => {
+        $crate::json_internal!(@object $object $($rest)*);
+    };
+
+    // The next value is null, an array or an object
+    (@object $object:ident $key:tt : null $($rest:tt)*) => {
+        let _ = $object.insert($crate::json_internal!(@key $key), $crate::json_internal!(null));
+        $crate::json_internal!(@object $object $($rest)*);
+    };
+    (@object $object:ident $key:tt : [$($array:tt)*] $($rest:tt)*) => {
+        let _ = $object.insert($crate::json_internal!(@key $key), $crate::json_internal!([$($array)*]));
+        $crate::json_internal!(@object $object $($rest)*);
+    };
+    (@object $object:ident $key:tt : {$($map:tt)*} $($rest:tt)*) => {
+        let _ = $object.insert($crate::json_internal!(@key $key), $crate::json_internal!({$($map)*}));
+        $crate::json_internal!(@object $object $($rest)*);
+    };
+
+    // The next value is an expression, with or without a following comma
+    (@object $object:ident $key:tt : $value:expr , $($rest:tt)*) => {
+        let _ = $object.insert($crate::json_internal!(@key $key), $crate::json_internal!($value));
+        $crate::json_internal!(@object $object $($rest)*);
+    };
+    (@object $object:ident $key:tt : $value:expr) => {
+        let _ = $object.insert($crate::json_internal!(@key $key), $crate::json_internal!($value));
+    };
+
+    (@key $key:expr) => {
+        ::std::string::ToString::to_string(&$key)
+    };
+
+    (null) => {
+        $crate::Value::Null
+    };
+    ([]) => {
+        $crate::Value::Array(vec![])
+    };
+    ([ $($tt:tt)+ ]) => {
+        $crate::Value::Array($crate::json_internal!(@array [] $($tt)+))
+    };
+    ({}) => {
+        $crate::Value::Object($crate::Map::new())
+    };
+    ({ $($tt:tt)+ }) => {
+        $crate::Value::Object({
+            let mut object = $crate::Map::new();
+            $crate::json_internal!(@object object $($tt)+);
+            object
+        })
+    };
+    ($other:expr) => {
+        $crate::to_value(&$other).unwrap_or($crate::Value::Null)
+    };
+}
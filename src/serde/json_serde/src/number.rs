@@ -0,0 +1,225 @@
+use std::fmt::{self, Display};
+use std::iter;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{IntoDeserializer, MapAccess, Unexpected};
+use serde::de::value::MapDeserializer;
+use crate::error::ErrorCode;
+use crate::format::format_float;
+
+/// The newtype struct name under which a raw number is passed between `Number` and the
+/// serializer, such that its text is written as-is rather than as a string. `Number` and `Value`
+/// also deserialize a newtype struct of this name, to ask for raw numbers in arbitrary precision
+/// mode; the deserializer answers with an object whose one key is this name.
+pub(crate) const RAW_NUMBER_TOKEN: &str = "$json_serde::private::RawNumber";
+
+/// Passes a number's text to a visitor that asked for it through `RAW_NUMBER_TOKEN`
+pub(crate) fn visit_raw<'de, V, E, T>(raw: T, visitor: V) -> Result<V::Value, E>
+    where V: de::Visitor<'de>, E: de::Error, T: IntoDeserializer<'de, E> {
+    visitor.visit_map(MapDeserializer::new(iter::once((RAW_NUMBER_TOKEN, raw))))
+}
+
+/// A JSON number
+#[derive(Clone, Debug, PartialEq)]
+pub struct Number {
+    n: N,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum N {
+    PosInt(u64),
+    /// Always less than zero
+    NegInt(i64),
+    /// Always finite
+    Float(f64),
+    /// The number's text, when deserialized with arbitrary precision
+    Raw(String),
+}
+
+impl Number {
+    /// Creates a number from its text, as passed by the deserializer in arbitrary precision mode
+    pub(crate) fn from_raw(raw: String) -> Number {
+        Number { n: N::Raw(raw) }
+    }
+
+    /// Creates a number from a float, unless it is NaN or infinite
+    pub fn from_f64(f: f64) -> Option<Number> {
+        if f.is_finite() {
+            Some(Number { n: N::Float(f) })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_u64(&self) -> bool {
+        self.as_u64().is_some()
+    }
+
+    pub fn is_i64(&self) -> bool {
+        self.as_i64().is_some()
+    }
+
+    pub fn is_f64(&self) -> bool {
+        match &self.n {
+            N::Float(_) => true,
+            N::Raw(raw) => raw.contains(&['.', 'e', 'E'][..]),
+            _ => false,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match &self.n {
+            N::PosInt(n) => Some(*n),
+            N::Raw(raw) => raw.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match &self.n {
+            N::PosInt(n) if *n <= i64::MAX as u64 => Some(*n as i64),
+            N::NegInt(n) => Some(*n),
+            N::Raw(raw) => raw.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the number as a float, which may lose precision
+    pub fn as_f64(&self) -> Option<f64> {
+        match &self.n {
+            N::PosInt(n) => Some(*n as f64),
+            N::NegInt(n) => Some(*n as f64),
+            N::Float(f) => Some(*f),
+            N::Raw(raw) => raw.parse().ok().filter(|f: &f64| f.is_finite()),
+        }
+    }
+
+    /// Returns the number's text, if it was deserialized with arbitrary precision
+    pub fn as_raw(&self) -> Option<&str> {
+        match &self.n {
+            N::Raw(raw) => Some(raw),
+            _ => None,
+        }
+    }
+
+    /// Passes the number to a visitor that expects a primitive. A raw number is passed as the
+    /// first of `u64`, `i64` and `f64` that holds it, and a float may lose precision.
+    pub(crate) fn visit<'de, V, E>(&self, visitor: V) -> Result<V::Value, E>
+        where V: de::Visitor<'de>, E: de::Error {
+        match &self.n {
+            N::PosInt(n) => visitor.visit_u64(*n),
+            N::NegInt(n) => visitor.visit_i64(*n),
+            N::Float(f) => visitor.visit_f64(*f),
+            N::Raw(raw) => {
+                if let Ok(n) = raw.parse() {
+                    visitor.visit_u64(n)
+                } else if let Ok(n) = raw.parse() {
+                    visitor.visit_i64(n)
+                } else {
+                    match self.as_f64() {
+                        Some(f) => visitor.visit_f64(f),
+                        None => Err(de::Error::custom(ErrorCode::NumberOutOfRange)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.n {
+            N::PosInt(n) => Display::fmt(n, f),
+            N::NegInt(n) => Display::fmt(n, f),
+            N::Float(n) => f.write_str(&format_float(*n)),
+            N::Raw(raw) => f.write_str(raw),
+        }
+    }
+}
+
+macro_rules! from_unsigned {
+    ($($ty:ty)*) => {
+        $(
+            impl From<$ty> for Number {
+                fn from(n: $ty) -> Self {
+                    Number { n: N::PosInt(n as u64) }
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! from_signed {
+    ($($ty:ty)*) => {
+        $(
+            impl From<$ty> for Number {
+                fn from(n: $ty) -> Self {
+                    if n < 0 {
+                        Number { n: N::NegInt(n as i64) }
+                    } else {
+                        Number { n: N::PosInt(n as u64) }
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_unsigned!(u8 u16 u32 u64 usize);
+from_signed!(i8 i16 i32 i64 isize);
+
+impl Serialize for Number {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer {
+        match &self.n {
+            N::PosInt(n) => serializer.serialize_u64(*n),
+            N::NegInt(n) => serializer.serialize_i64(*n),
+            N::Float(f) => serializer.serialize_f64(*f),
+            N::Raw(raw) => serializer.serialize_newtype_struct(RAW_NUMBER_TOKEN, raw),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Number {
+    fn deserialize<D>(deserializer: D) -> Result<Number, D::Error>
+        where D: Deserializer<'de> {
+        deserializer.deserialize_newtype_struct(RAW_NUMBER_TOKEN, NumberVisitor)
+    }
+}
+
+struct NumberVisitor;
+
+impl<'de> de::Visitor<'de> for NumberVisitor {
+    type Value = Number;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a JSON number")
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Number, E> {
+        Ok(v.into())
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Number, E> {
+        Ok(v.into())
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Number, E>
+        where E: de::Error {
+        Number::from_f64(v).ok_or_else(|| de::Error::custom("non-finite float"))
+    }
+
+    // Reached through deserializers that don't know the token
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Number, D::Error>
+        where D: Deserializer<'de> {
+        deserializer.deserialize_any(self)
+    }
+
+    // Only produced for the token in arbitrary precision mode, see `visit_raw`
+    fn visit_map<A>(self, mut access: A) -> Result<Number, A::Error>
+        where A: MapAccess<'de> {
+        match access.next_key::<String>()? {
+            Some(key) if key == RAW_NUMBER_TOKEN => Ok(Number::from_raw(access.next_value()?)),
+            _ => Err(de::Error::invalid_type(Unexpected::Map, &self)),
+        }
+    }
+}
@@ -1,20 +1,248 @@
+use std::io::{self, Write};
 use serde::{ser, Serialize};
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorCode, Result};
+use crate::format::{CanonicalFormatter, CompactFormatter, Formatter, PrettyFormatter};
+use crate::number::RAW_NUMBER_TOKEN;
+use crate::value::MapKeySerializer;
+
+pub struct Serializer<W, F = CompactFormatter> {
+    writer: W,
+    formatter: F,
+    /// Whether the next element is the first in its array or object, i.e. takes no comma
+    first: bool,
+    /// Set while serializing the text of a raw number, which is written without quotes
+    raw_number: bool,
+    /// Whether object entries are written in key order
+    sort_keys: bool,
+    /// The objects being buffered to sort their entries, innermost last
+    sorting: Vec<SortedObject>,
+}
+
+/// An object whose entries are buffered until its end, to be written in key order
+#[derive(Default)]
+struct SortedObject {
+    /// The key, the written key and the rest of each completed entry. Entries are sorted on the
+    /// key itself, as the written one is quoted and escaped
+    entries: Vec<(String, Vec<u8>, Vec<u8>)>,
+    /// The key and the written key of the current entry
+    key: Option<(String, Vec<u8>)>,
+    /// The output of the current entry
+    buffer: Vec<u8>,
+}
+
+impl SortedObject {
+    fn finish_entry(&mut self) {
+        if let Some((key, written)) = self.key.take() {
+            self.entries.push((key, written, std::mem::take(&mut self.buffer)));
+        }
+    }
+}
+
+impl<W: Write> Serializer<W> {
+    pub fn new(writer: W) -> Self {
+        Serializer::with_formatter(writer, CompactFormatter)
+    }
+}
+
+impl<W: Write> Serializer<W, PrettyFormatter> {
+    pub fn pretty(writer: W) -> Self {
+        Serializer::with_formatter(writer, PrettyFormatter::new())
+    }
+}
+
+impl<W: Write, F: Formatter> Serializer<W, F> {
+    pub fn with_formatter(writer: W, formatter: F) -> Self {
+        Serializer {
+            writer,
+            formatter,
+            first: true,
+            raw_number: false,
+            sort_keys: false,
+            sorting: Vec::new(),
+        }
+    }
+
+    /// Writes the entries of maps and structs in key order, rather than the order given. Each
+    /// object is buffered in memory until its end.
+    pub fn with_sorted_keys(mut self) -> Self {
+        self.sort_keys = true;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes with the formatter, into the innermost object being sorted if any
+    fn emit<G>(&mut self, write: G) -> Result<()>
+        where G: FnOnce(&mut F, &mut dyn Write) -> io::Result<()> {
+        let writer: &mut dyn Write = match self.sorting.last_mut() {
+            Some(object) => &mut object.buffer,
+            None => &mut self.writer,
+        };
+        Ok(write(&mut self.formatter, writer)?)
+    }
+
+    fn begin_array(&mut self) -> Result<()> {
+        self.first = true;
+        self.emit(|f, w| f.begin_array(w))
+    }
+
+    fn array_value<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize {
+        let first = std::mem::replace(&mut self.first, false);
+        self.emit(|f, w| f.begin_array_value(w, first))?;
+        value.serialize(&mut *self)?;
+        self.emit(|f, w| f.end_array_value(w))
+    }
+
+    /// Ends an array. Its parent, if any, now has at least one element
+    fn end_array(&mut self) -> Result<()> {
+        self.first = false;
+        self.emit(|f, w| f.end_array(w))
+    }
+
+    fn begin_object(&mut self) -> Result<()> {
+        self.first = true;
+        self.emit(|f, w| f.begin_object(w))?;
+        if self.sort_keys {
+            self.sorting.push(SortedObject::default());
+        }
+        Ok(())
+    }
 
-pub struct Serializer {
-    output: String
+    fn object_key<T>(&mut self, key: &T) -> Result<()>
+        where T: ?Sized + Serialize {
+        if self.sort_keys {
+            // The key's separator is written once the entry's position is known
+            if let Some(object) = self.sorting.last_mut() {
+                object.finish_entry();
+            }
+            let key = self.serialize_key(key)?;
+            if let Some(object) = self.sorting.last_mut() {
+                object.key = Some((key, std::mem::take(&mut object.buffer)));
+            }
+        } else {
+            let first = std::mem::replace(&mut self.first, false);
+            self.emit(|f, w| f.begin_object_key(w, first))?;
+            self.serialize_key(key)?;
+        }
+        self.emit(|f, w| f.end_object_key(w))
+    }
+
+    /// Writes a key as a string, as JSON requires, from its text if it is an integer, bool or
+    /// char. Returns the key's text
+    fn serialize_key<T>(&mut self, key: &T) -> Result<String>
+        where T: ?Sized + Serialize {
+        let key = key.serialize(MapKeySerializer)?;
+        self.emit(|f, w| f.write_str(w, &key))?;
+        Ok(key)
+    }
+
+    fn object_value<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize {
+        self.emit(|f, w| f.begin_object_value(w))?;
+        value.serialize(&mut *self)?;
+        self.emit(|f, w| f.end_object_value(w))
+    }
+
+    /// Ends an object, writing its entries in key order if sorting. Its parent, if any, now
+    /// has at least one element
+    fn end_object(&mut self) -> Result<()> {
+        self.first = false;
+        if self.sort_keys {
+            if let Some(mut object) = self.sorting.pop() {
+                object.finish_entry();
+                object.entries.sort_by(|a, b| a.0.cmp(&b.0));
+                for (i, (_, key, rest)) in object.entries.iter().enumerate() {
+                    self.emit(|f, w| {
+                        f.begin_object_key(w, i == 0)?;
+                        w.write_all(key)?;
+                        w.write_all(rest)
+                    })?;
+                }
+            }
+        }
+        self.emit(|f, w| f.end_object(w))
+    }
+
+    /// Starts the single-entry object wrapping an enum variant's value, which is never sorted
+    fn begin_variant(&mut self, variant: &str) -> Result<()> {
+        self.emit(|f, w| {
+            f.begin_object(w)?;
+            f.begin_object_key(w, true)?;
+            f.write_str(w, variant)?;
+            f.end_object_key(w)?;
+            f.begin_object_value(w)
+        })
+    }
+
+    fn end_variant(&mut self) -> Result<()> {
+        self.first = false;
+        self.emit(|f, w| {
+            f.end_object_value(w)?;
+            f.end_object(w)
+        })
+    }
 }
 
 pub fn to_string<T>(value: &T) -> Result<String>
     where T: Serialize {
-    let mut serializer = Serializer {
-        output: String::new()
-    };
-    value.serialize(&mut serializer)?;
-    Ok(serializer.output)
+    into_string(to_vec(value)?)
+}
+
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize {
+    let mut output = Vec::with_capacity(128);
+    to_writer(&mut output, value)?;
+    Ok(output)
+}
+
+/// Serializes a value as JSON into a writer, as it goes. The writer receives many small writes,
+/// so should be buffered, e.g. with `io::BufWriter`.
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
+    where W: Write, T: Serialize {
+    value.serialize(&mut Serializer::new(writer))
+}
+
+/// Serializes a value as JSON indented by two spaces. Use `Serializer::with_formatter` and
+/// `PrettyFormatter::with_indent` for other indents.
+pub fn to_string_pretty<T>(value: &T) -> Result<String>
+    where T: Serialize {
+    let mut output = Vec::with_capacity(128);
+    to_writer_pretty(&mut output, value)?;
+    into_string(output)
+}
+
+pub fn to_writer_pretty<W, T>(writer: W, value: &T) -> Result<()>
+    where W: Write, T: Serialize {
+    value.serialize(&mut Serializer::pretty(writer))
+}
+
+/// Serializes a value as canonical JSON, with sorted keys, no whitespace and normalized
+/// numbers, such that equal values produce the same bytes for hashing and signing
+pub fn to_vec_canonical<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize {
+    let mut output = Vec::with_capacity(128);
+    to_writer_canonical(&mut output, value)?;
+    Ok(output)
+}
+
+pub fn to_string_canonical<T>(value: &T) -> Result<String>
+    where T: Serialize {
+    into_string(to_vec_canonical(value)?)
+}
+
+pub fn to_writer_canonical<W, T>(writer: W, value: &T) -> Result<()>
+    where W: Write, T: Serialize {
+    value.serialize(&mut Serializer::with_formatter(writer, CanonicalFormatter).with_sorted_keys())
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+fn into_string(output: Vec<u8>) -> Result<String> {
+    String::from_utf8(output).map_err(|err| ErrorCode::Message(err.to_string()).into())
+}
+
+impl<W: Write, F: Formatter> ser::Serializer for &mut Serializer<W, F> {
     type Ok = ();
 
     type Error = Error;
@@ -28,8 +256,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
 
 
     fn serialize_bool(self, v: bool) -> Result<()> {
-        self.output += if v { "true" } else { "false" };
-        Ok(())
+        self.emit(|f, w| f.write_bool(w, v))
     }
 
     fn serialize_i8(self, v: i8) -> Result<()> {
@@ -45,8 +272,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.output += &v.to_string();
-        Ok(())
+        self.emit(|f, w| f.write_i64(w, v))
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
@@ -62,17 +288,22 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.output += &v.to_string();
-        Ok(())
+        self.emit(|f, w| f.write_u64(w, v))
     }
 
+    // Formatted as an f32, since widening it would print digits beyond its precision
     fn serialize_f32(self, v: f32) -> Result<()> {
-        self.serialize_f64(f64::from(v))
+        if !v.is_finite() {
+            return Err(ErrorCode::NonFiniteFloat.into());
+        }
+        self.emit(|f, w| f.write_f32(w, v))
     }
 
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.output += &v.to_string();
-        Ok(())
+        if !v.is_finite() {
+            return Err(ErrorCode::NonFiniteFloat.into());
+        }
+        self.emit(|f, w| f.write_f64(w, v))
     }
 
     fn serialize_char(self, v: char) -> Result<()> {
@@ -80,10 +311,11 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
-        self.output += "\"";
-        self.output += v;
-        self.output += "\"";
-        Ok(())
+        if self.raw_number {
+            self.raw_number = false;
+            return self.emit(|f, w| f.write_raw_number(w, v));
+        }
+        self.emit(|f, w| f.write_str(w, v))
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
@@ -105,8 +337,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_unit(self) -> Result<()> {
-        self.output += "null";
-        Ok(())
+        self.emit(|f, w| f.write_null(w))
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
@@ -124,10 +355,13 @@ impl<'a> ser::Serializer for &'a mut Serializer {
 
     fn serialize_newtype_struct<T>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T
     ) -> Result<()> where T: ?Sized + Serialize{
-        value.serialize(self)
+        self.raw_number = name == RAW_NUMBER_TOKEN;
+        value.serialize(&mut *self)?;
+        self.raw_number = false;
+        Ok(())
     }
 
     fn serialize_newtype_variant<T>(
@@ -137,16 +371,13 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         value: &T
     ) -> Result<()> where T: ?Sized + Serialize {
-        self.output += "{";
-        variant.serialize(&mut *self)?;
-        self.output +=":";
+        self.begin_variant(variant)?;
         value.serialize(&mut *self)?;
-        self.output += "}";
-        Ok(())
+        self.end_variant()
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        self.output += "[";
+        self.begin_array()?;
         Ok(self)
     }
 
@@ -169,14 +400,13 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         _len: usize
     ) -> Result<Self::SerializeTupleVariant> {
-        self.output += "{";
-        variant.serialize(&mut *self)?;
-        self.output += ":[";
+        self.begin_variant(variant)?;
+        self.begin_array()?;
         Ok(self)
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        self.output += "{";
+        self.begin_object()?;
         Ok(self)
     }
 
@@ -195,38 +425,33 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        self.output += "{";
-        variant.serialize(&mut *self)?;
-        self.output += ":{";
+        self.begin_variant(variant)?;
+        self.begin_object()?;
         Ok(self)
     }
 
-    fn collect_str<T: ?Sized>(self, value: &T) -> Result<()> where
-        T: std::fmt::Display {
+    fn collect_str<T>(self, value: &T) -> Result<()> where
+        T: ?Sized + std::fmt::Display {
         self.serialize_str(&value.to_string())
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl<W: Write, F: Formatter> ser::SerializeSeq for &mut Serializer<W, F> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_element<T>(&mut self, value: &T) -> Result<()>
         where T: ?Sized + Serialize {
-        if !self.output.ends_with('[') {
-            self.output += ",";
-        }
-        value.serialize(&mut **self)
+        self.array_value(value)
     }
 
     fn end(self) -> Result<()> {
-        self.output += "]";
-        Ok(())
+        self.end_array()
     }
 
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl<W: Write, F: Formatter> ser::SerializeTuple for &mut Serializer<W, F> {
     type Ok = ();
     type Error = Error;
 
@@ -234,19 +459,15 @@ impl<'a> ser::SerializeTuple for &'a mut Serializer {
         where
             T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('[') {
-            self.output += ",";
-        }
-        value.serialize(&mut **self)
+        self.array_value(value)
     }
 
     fn end(self) -> Result<()> {
-        self.output += "]";
-        Ok(())
+        self.end_array()
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl<W: Write, F: Formatter> ser::SerializeTupleStruct for &mut Serializer<W, F> {
     type Ok = ();
     type Error = Error;
 
@@ -254,19 +475,15 @@ impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
         where
             T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('[') {
-            self.output += ",";
-        }
-        value.serialize(&mut **self)
+        self.array_value(value)
     }
 
     fn end(self) -> Result<()> {
-        self.output += "]";
-        Ok(())
+        self.end_array()
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl<W: Write, F: Formatter> ser::SerializeTupleVariant for &mut Serializer<W, F> {
     type Ok = ();
     type Error = Error;
 
@@ -274,19 +491,16 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
         where
             T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('[') {
-            self.output += ",";
-        }
-        value.serialize(&mut **self)
+        self.array_value(value)
     }
 
     fn end(self) -> Result<()> {
-        self.output += "]}";
-        Ok(())
+        self.end_array()?;
+        self.end_variant()
     }
 }
 
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+impl<W: Write, F: Formatter> ser::SerializeMap for &mut Serializer<W, F> {
     type Ok = ();
     type Error = Error;
 
@@ -294,27 +508,22 @@ impl<'a> ser::SerializeMap for &'a mut Serializer {
         where
             T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('{') {
-            self.output += ",";
-        }
-        key.serialize(&mut **self)
+        self.object_key(key)
     }
 
     fn serialize_value<T>(&mut self, value: &T) -> Result<()>
         where
             T: ?Sized + Serialize,
     {
-        self.output += ":";
-        value.serialize(&mut **self)
+        self.object_value(value)
     }
 
     fn end(self) -> Result<()> {
-        self.output += "}";
-        Ok(())
+        self.end_object()
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl<W: Write, F: Formatter> ser::SerializeStruct for &mut Serializer<W, F> {
     type Ok = ();
     type Error = Error;
 
@@ -322,21 +531,16 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
         where
             T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('{') {
-            self.output += ",";
-        }
-        key.serialize(&mut **self)?;
-        self.output += ":";
-        value.serialize(&mut **self)
+        self.object_key(key)?;
+        self.object_value(value)
     }
 
     fn end(self) -> Result<()> {
-        self.output += "}";
-        Ok(())
+        self.end_object()
     }
 }
 
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl<W: Write, F: Formatter> ser::SerializeStructVariant for &mut Serializer<W, F> {
     type Ok = ();
     type Error = Error;
 
@@ -344,17 +548,13 @@ impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
         where
             T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('{') {
-            self.output += ",";
-        }
-        key.serialize(&mut **self)?;
-        self.output += ":";
-        value.serialize(&mut **self)
+        self.object_key(key)?;
+        self.object_value(value)
     }
 
     fn end(self) -> Result<()> {
-        self.output += "}}";
-        Ok(())
+        self.end_object()?;
+        self.end_variant()
     }
 }
 
@@ -401,4 +601,149 @@ fn test_enum() {
     let s = E::Struct { a: 1 };
     let expected = r#"{"Struct":{"a":1}}"#;
     assert_eq!(to_string(&s).unwrap(), expected);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_floats() {
+    assert_eq!(to_string(&1.0f64).unwrap(), "1.0");
+    assert_eq!(to_string(&-0.0f64).unwrap(), "-0.0");
+    assert_eq!(to_string(&0.1f64).unwrap(), "0.1");
+    assert_eq!(to_string(&0.1f32).unwrap(), "0.1");
+    assert_eq!(to_string(&1e300f64).unwrap(), "1e300");
+    assert_eq!(to_string(&1.5e-10f64).unwrap(), "1.5e-10");
+    assert_eq!(to_string(&123456789.125f64).unwrap(), "123456789.125");
+    assert_eq!(to_string(&f64::NAN).unwrap_err().code(), &ErrorCode::NonFiniteFloat);
+    assert_eq!(to_string(&f32::INFINITY).unwrap_err().code(), &ErrorCode::NonFiniteFloat);
+
+    for v in &[std::f64::consts::PI, 5e-324, f64::MAX, -2.5e-8, 1e21, 1e20] {
+        assert_eq!(crate::de::from_str::<f64>(&to_string(v).unwrap()), Ok(*v));
+    }
+}
+
+#[test]
+fn test_writer() {
+    #[derive(Serialize)]
+    enum E {
+        Tuple(Vec<u32>, ()),
+        Struct { a: Vec<Vec<u32>>, b: bool },
+    }
+
+    let mut output = Vec::new();
+    to_writer(&mut output, &vec![E::Tuple(vec![], ()), E::Struct { a: vec![vec![], vec![1, 2]], b: true }]).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        r#"[{"Tuple":[[],null]},{"Struct":{"a":[[],[1,2]],"b":true}}]"#
+    );
+}
+
+#[test]
+fn test_escape() {
+    let s = "quote \" backslash \\ \u{8}\u{c}\n\r\t \u{1} é \u{1F600} /";
+    let expected = "\"quote \\\" backslash \\\\ \\b\\f\\n\\r\\t \\u0001 é \u{1F600} /\"";
+    assert_eq!(to_string(&s).unwrap(), expected);
+    assert_eq!(crate::de::from_str::<String>(expected).unwrap(), s);
+}
+
+#[test]
+fn test_pretty() {
+    #[derive(Serialize)]
+    enum E {
+        Tuple(u32, u32),
+        Struct { a: Vec<u32>, b: () },
+    }
+
+    let v = crate::json!({"name": "db", "empty": [], "nested": {}, "list": [1, {"x": true}]});
+    let expected = r#"{
+  "name": "db",
+  "empty": [],
+  "nested": {},
+  "list": [
+    1,
+    {
+      "x": true
+    }
+  ]
+}"#;
+    assert_eq!(to_string_pretty(&v).unwrap(), expected);
+    assert_eq!(format!("{:#}", v), expected);
+
+    let mut serializer = Serializer::with_formatter(Vec::new(), PrettyFormatter::with_indent("\t"));
+    vec![E::Tuple(1, 2), E::Struct { a: vec![3], b: () }].serialize(&mut serializer).unwrap();
+    let expected = "[\n\t{\n\t\t\"Tuple\": [\n\t\t\t1,\n\t\t\t2\n\t\t]\n\t},\n\t{\n\t\t\"Struct\": {\n\t\t\t\"a\": [\n\t\t\t\t3\n\t\t\t],\n\t\t\t\"b\": null\n\t\t}\n\t}\n]";
+    assert_eq!(String::from_utf8(serializer.into_inner()).unwrap(), expected);
+}
+
+#[test]
+fn test_sorted_keys() {
+    let v = crate::json!({"b": {"z": 1, "y": [{"d": 0, "c": 0}]}, "a": null, "c": []});
+    let mut serializer = Serializer::pretty(Vec::new()).with_sorted_keys();
+    v.serialize(&mut serializer).unwrap();
+    let expected = r#"{
+  "a": null,
+  "b": {
+    "y": [
+      {
+        "c": 0,
+        "d": 0
+      }
+    ],
+    "z": 1
+  },
+  "c": []
+}"#;
+    assert_eq!(String::from_utf8(serializer.into_inner()).unwrap(), expected);
+
+    // Keys are ordered as written before quoting and escaping
+    let v = crate::json!({"ab": 5, "a#": 7, "a\"": 6, "a\n": 4, "a b": 3, "a!": 2, "a": 1});
+    let expected = r#"{"a":1,"a\n":4,"a b":3,"a!":2,"a\"":6,"a#":7,"ab":5}"#;
+    assert_eq!(to_string_canonical(&v).unwrap(), expected);
+    let mut serializer = Serializer::new(Vec::new()).with_sorted_keys();
+    v.serialize(&mut serializer).unwrap();
+    assert_eq!(String::from_utf8(serializer.into_inner()).unwrap(), expected);
+}
+
+#[test]
+fn test_canonical() {
+    let a = crate::json!({"b": [1.0, -0.0, 2.5, 1e21, 1e-7], "a": {"y": 1, "x": 2}});
+    assert_eq!(to_string_canonical(&(0.1f32, 3.0f32)).unwrap(), "[0.1,3]");
+    let b = crate::json!({"a": {"x": 2, "y": 1}, "b": [1, 0, 2.5, 1e21, 1e-7]});
+    let expected = r#"{"a":{"x":2,"y":1},"b":[1,0,2.5,1e21,1e-7]}"#;
+    assert_eq!(to_string_canonical(&a).unwrap(), expected);
+    assert_eq!(to_vec_canonical(&a).unwrap(), to_vec_canonical(&b).unwrap());
+
+    let mut deserializer = crate::Deserializer::from_str("[1.50e2, -0, 123456789012345678901234567890]").with_arbitrary_precision();
+    let raw = <Vec<crate::Number> as serde::Deserialize>::deserialize(&mut deserializer).unwrap();
+    assert_eq!(to_string_canonical(&raw).unwrap(), "[150,0,123456789012345678901234567890]");
+}
+
+#[test]
+fn test_map_keys() {
+    use std::collections::BTreeMap;
+
+    let ints: BTreeMap<i32, i32> = vec![(-1, 2), (3, 4)].into_iter().collect();
+    assert_eq!(to_string(&ints).unwrap(), r#"{"-1":2,"3":4}"#);
+    let bools: BTreeMap<bool, &str> = vec![(true, "y")].into_iter().collect();
+    assert_eq!(to_string_pretty(&bools).unwrap(), "{\n  \"true\": \"y\"\n}");
+    let chars: BTreeMap<char, u8> = vec![('"', 1)].into_iter().collect();
+    assert_eq!(to_string_canonical(&chars).unwrap(), r#"{"\"":1}"#);
+
+    let tuples: BTreeMap<(u8, u8), u8> = vec![((1, 2), 3)].into_iter().collect();
+    assert_eq!(to_string(&tuples).unwrap_err().code(), &ErrorCode::KeyMustBeAString);
+
+    // Keys are parsed back from their text
+    assert_eq!(crate::from_str::<BTreeMap<i32, i32>>(&to_string(&ints).unwrap()).unwrap(), ints);
+    assert_eq!(crate::from_str::<BTreeMap<bool, &str>>(&to_string(&bools).unwrap()).unwrap(), bools);
+    assert_eq!(crate::from_str::<BTreeMap<char, u8>>(&to_string(&chars).unwrap()).unwrap(), chars);
+    let ports: BTreeMap<u16, BTreeMap<bool, u8>> = crate::from_str(r#"{"443":{"false":0},"80":{}}"#).unwrap();
+    assert_eq!(to_string(&ports).unwrap(), r#"{"80":{},"443":{"false":0}}"#);
+    for (j, code) in [
+        (r#"{"01":1}"#, ErrorCode::ExpectedInteger),
+        (r#"{" 1":1}"#, ErrorCode::ExpectedInteger),
+        (r#"{"1x":1}"#, ErrorCode::ExpectedInteger),
+        (r#"{1:1}"#, ErrorCode::ExpectedString),
+        (r#"{"256":1}"#, ErrorCode::NumberOutOfRange),
+    ] {
+        let err = crate::from_str::<BTreeMap<u8, u8>>(j).unwrap_err();
+        assert_eq!((err.code(), err.position().unwrap().column), (&code, 2));
+    }
+}
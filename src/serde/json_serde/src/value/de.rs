@@ -0,0 +1,311 @@
+use std::vec;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
+use serde::de::value::StringDeserializer;
+use serde::forward_to_deserialize_any;
+use crate::error::{Error, ErrorCode, Result};
+use crate::number::{self, RAW_NUMBER_TOKEN};
+use crate::map::Map;
+use crate::value::Value;
+
+impl Value {
+    fn unexpected(&self) -> Unexpected<'_> {
+        match self {
+            Value::Null => Unexpected::Unit,
+            Value::Bool(b) => Unexpected::Bool(*b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+                (Some(n), _, _) => Unexpected::Unsigned(n),
+                (_, Some(n), _) => Unexpected::Signed(n),
+                (_, _, Some(f)) => Unexpected::Float(f),
+                _ => Unexpected::Other("number"),
+            },
+            Value::String(s) => Unexpected::Str(s),
+            Value::Array(_) => Unexpected::Seq,
+            Value::Object(_) => Unexpected::Map,
+        }
+    }
+
+    fn deserialize_number<'de, V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        match self {
+            Value::Number(n) => n.visit(visitor),
+            other => de::Deserializer::deserialize_any(other, visitor),
+        }
+    }
+}
+
+macro_rules! deserialize_number {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+                where V: Visitor<'de> {
+                self.deserialize_number(visitor)
+            }
+        )*
+    };
+}
+
+/// Reads a typed value out of a `Value`, as `from_value` does
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Number(n) => n.visit(visitor),
+            Value::String(s) => visitor.visit_string(s),
+            Value::Array(array) => visit_array(array, visitor),
+            Value::Object(map) => visit_object(map, visitor),
+        }
+    }
+
+    deserialize_number!(
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_f32 deserialize_f64
+    );
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    /// Passes a raw number as such to `Number` and `Value`, which ask for it through the token
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        if name != RAW_NUMBER_TOKEN {
+            return visitor.visit_newtype_struct(self);
+        }
+        match self {
+            Value::Number(n) => match n.as_raw() {
+                Some(raw) => number::visit_raw(raw, visitor),
+                None => n.visit(visitor),
+            },
+            other => de::Deserializer::deserialize_any(other, visitor),
+        }
+    }
+
+    /// Reads a unit variant from its name, or any other from an object with the variant as its
+    /// one key
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> where V: Visitor<'de> {
+        let (variant, value) = match self {
+            Value::String(variant) => (variant, None),
+            Value::Object(map) => {
+                let mut entries = map.into_iter();
+                match (entries.next(), entries.next()) {
+                    (Some((variant, value)), None) => (variant, Some(value)),
+                    _ => return Err(ErrorCode::ExpectedEnum.into()),
+                }
+            }
+            other => return Err(de::Error::invalid_type(other.unexpected(), &"string or map")),
+        };
+        visitor.visit_enum(EnumDeserializer { variant, value })
+    }
+
+    forward_to_deserialize_any! {
+        bool char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+fn visit_array<'de, V>(array: Vec<Value>, visitor: V) -> Result<V::Value>
+    where V: Visitor<'de> {
+    let len = array.len();
+    let mut seq = SeqDeserializer { iter: array.into_iter() };
+    let value = visitor.visit_seq(&mut seq)?;
+    if seq.iter.len() == 0 {
+        Ok(value)
+    } else {
+        Err(de::Error::invalid_length(len, &"fewer elements in array"))
+    }
+}
+
+fn visit_object<'de, V>(map: Map, visitor: V) -> Result<V::Value>
+    where V: Visitor<'de> {
+    let len = map.len();
+    let mut access = MapDeserializer { iter: map.into_iter(), value: None };
+    let value = visitor.visit_map(&mut access)?;
+    if access.iter.len() == 0 {
+        Ok(value)
+    } else {
+        Err(de::Error::invalid_length(len, &"fewer elements in map"))
+    }
+}
+
+struct SeqDeserializer {
+    iter: vec::IntoIter<Value>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+        where T: DeserializeSeed<'de> {
+        self.iter.next().map(|value| seed.deserialize(value)).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapDeserializer {
+    iter: vec::IntoIter<(String, Value)>,
+    /// The value of the entry whose key was last read
+    value: Option<Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapDeserializer {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+        where K: DeserializeSeed<'de> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(MapKeyDeserializer { key }).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where V: DeserializeSeed<'de> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("next_value_seed called before next_key_seed")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Reads an object key, which is parsed when an integer or bool is expected, as the serializer
+/// writes those keys as their text
+struct MapKeyDeserializer {
+    key: String,
+}
+
+macro_rules! deserialize_key {
+    ($($method:ident => $visit:ident)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+                where V: Visitor<'de> {
+                match self.key.parse() {
+                    Ok(v) => visitor.$visit(v),
+                    Err(_) => Err(de::Error::invalid_value(Unexpected::Str(&self.key), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for MapKeyDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        visitor.visit_string(self.key)
+    }
+
+    deserialize_key!(
+        deserialize_bool => visit_bool
+        deserialize_i8 => visit_i8 deserialize_i16 => visit_i16
+        deserialize_i32 => visit_i32 deserialize_i64 => visit_i64
+        deserialize_u8 => visit_u8 deserialize_u16 => visit_u16
+        deserialize_u32 => visit_u32 deserialize_u64 => visit_u64
+    );
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> where V: Visitor<'de> {
+        visitor.visit_enum(self.key.into_deserializer() as StringDeserializer<Error>)
+    }
+
+    forward_to_deserialize_any! {
+        f32 f64 char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    /// The variant's value, which is missing for a unit variant
+    value: Option<Value>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, VariantDeserializer)>
+        where V: DeserializeSeed<'de> {
+        let variant = self.variant.into_deserializer() as StringDeserializer<Error>;
+        Ok((seed.deserialize(variant)?, VariantDeserializer { value: self.value }))
+    }
+}
+
+struct VariantDeserializer {
+    value: Option<Value>,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            None | Some(Value::Null) => Ok(()),
+            Some(other) => Err(de::Error::invalid_type(other.unexpected(), &"unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+        where T: DeserializeSeed<'de> {
+        match self.value {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, &"newtype variant")),
+        }
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        match self.value {
+            Some(Value::Array(array)) => visit_array(array, visitor),
+            Some(other) => Err(de::Error::invalid_type(other.unexpected(), &"tuple variant")),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, &"tuple variant")),
+        }
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+        where V: Visitor<'de> {
+        match self.value {
+            Some(Value::Object(map)) => visit_object(map, visitor),
+            Some(other) => Err(de::Error::invalid_type(other.unexpected(), &"struct variant")),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, &"struct variant")),
+        }
+    }
+}
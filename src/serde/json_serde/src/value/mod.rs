@@ -0,0 +1,472 @@
+mod ser;
+mod de;
+
+pub(crate) use self::ser::MapKeySerializer;
+
+use std::fmt;
+use std::ops;
+use serde::de::{Deserialize, DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+use crate::error::Result;
+use crate::map::Map;
+use crate::number::{Number, RAW_NUMBER_TOKEN};
+use crate::ser::{to_string, to_string_pretty};
+
+/// Any JSON value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Value>),
+    Object(Map),
+}
+
+/// Returned when indexing a missing element
+static NULL: Value = Value::Null;
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<&Number> {
+        match self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_number().and_then(Number::as_u64)
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_number().and_then(Number::as_i64)
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.as_number().and_then(Number::as_f64)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_array_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&Map> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn as_object_mut(&mut self) -> Option<&mut Map> {
+        match self {
+            Value::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Looks up a value by an RFC 6901 JSON Pointer, e.g. `/servers/3/port`. The empty pointer
+    /// refers to the whole value.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        if !pointer.starts_with('/') {
+            return None;
+        }
+        let mut target = self;
+        for token in pointer.split('/').skip(1) {
+            let token = unescape_token(token);
+            target = match target {
+                Value::Object(map) => map.get(&token)?,
+                Value::Array(array) => array.get(array_index(&token)?)?,
+                _ => return None,
+            };
+        }
+        Some(target)
+    }
+
+    /// Looks up a value by an RFC 6901 JSON Pointer, like `pointer`, for modification
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        if !pointer.starts_with('/') {
+            return None;
+        }
+        let mut target = self;
+        for token in pointer.split('/').skip(1) {
+            let token = unescape_token(token);
+            target = match target {
+                Value::Object(map) => map.get_mut(&token)?,
+                Value::Array(array) => array.get_mut(array_index(&token)?)?,
+                _ => return None,
+            };
+        }
+        Some(target)
+    }
+}
+
+/// Unescapes a JSON Pointer reference token
+fn unescape_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// Parses a JSON Pointer array index, which has no sign or leading zeros
+fn array_index(token: &str) -> Option<usize> {
+    if token.is_empty() || !token.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if token.len() > 1 && token.starts_with('0') {
+        return None;
+    }
+    token.parse().ok()
+}
+
+/// Converts a value into a `Value`. Floats that are NaN or infinite become `Null`, and map keys
+/// must be strings, integers, bools or chars.
+pub fn to_value<T>(value: &T) -> Result<Value>
+    where T: Serialize {
+    value.serialize(self::ser::Serializer)
+}
+
+/// Converts a `Value` into a typed value
+pub fn from_value<T>(value: Value) -> Result<T>
+    where T: DeserializeOwned {
+    T::deserialize(value)
+}
+
+/// Indexes into an array, returning `Null` when out of bounds or not an array
+impl ops::Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        match self {
+            Value::Array(array) => array.get(index).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+/// Indexes into an object, returning `Null` when the key is missing or not an object
+impl ops::Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(map) => map.get(key).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+/// Indexes into an array for modification. Panics when out of bounds or not an array
+impl ops::IndexMut<usize> for Value {
+    fn index_mut(&mut self, index: usize) -> &mut Value {
+        match self {
+            Value::Array(array) => {
+                let len = array.len();
+                array.get_mut(index).unwrap_or_else(|| {
+                    panic!("cannot access index {} of JSON array of length {}", index, len)
+                })
+            }
+            _ => panic!("cannot access index {} of non-array JSON value", index),
+        }
+    }
+}
+
+/// Indexes into an object for modification, inserting `Null` when the key is missing. `Null`
+/// is treated as an empty object. Panics when not an object
+impl ops::IndexMut<&str> for Value {
+    fn index_mut(&mut self, key: &str) -> &mut Value {
+        if let Value::Null = self {
+            *self = Value::Object(Map::new());
+        }
+        match self {
+            Value::Object(map) => {
+                if !map.contains_key(key) {
+                    map.insert(key.to_string(), Value::Null);
+                }
+                map.get_mut(key).unwrap()
+            }
+            _ => panic!("cannot access key {:?} of non-object JSON value", key),
+        }
+    }
+}
+
+/// Formats the value as compact JSON, or pretty-printed JSON with the `{:#}` flag
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = if f.alternate() { to_string_pretty(self) } else { to_string(self) };
+        f.write_str(&s.map_err(|_| fmt::Error)?)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(array: Vec<Value>) -> Self {
+        Value::Array(array)
+    }
+}
+
+impl From<Map> for Value {
+    fn from(map: Map) -> Self {
+        Value::Object(map)
+    }
+}
+
+macro_rules! from_integer {
+    ($($ty:ty)*) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(n: $ty) -> Self {
+                    Value::Number(n.into())
+                }
+            }
+        )*
+    };
+}
+
+from_integer!(u8 u16 u32 u64 usize i8 i16 i32 i64 isize);
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: Serializer {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Number(n) => n.serialize(serializer),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Array(array) => array.serialize(serializer),
+            Value::Object(map) => map.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Value, D::Error>
+        where D: Deserializer<'de> {
+        deserializer.deserialize_newtype_struct(RAW_NUMBER_TOKEN, ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E>
+        where E: serde::de::Error {
+        Number::from_f64(v).map(Value::Number).ok_or_else(|| E::custom("non-finite float"))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> std::result::Result<Value, D::Error>
+        where D: Deserializer<'de> {
+        Value::deserialize(deserializer)
+    }
+
+    // Reached through deserializers that don't know the token
+    fn visit_newtype_struct<D>(self, deserializer: D) -> std::result::Result<Value, D::Error>
+        where D: Deserializer<'de> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A>(self, mut access: A) -> std::result::Result<Value, A::Error>
+        where A: SeqAccess<'de> {
+        let mut array = Vec::new();
+        while let Some(value) = access.next_element()? {
+            array.push(value);
+        }
+        Ok(Value::Array(array))
+    }
+
+    fn visit_map<A>(self, mut access: A) -> std::result::Result<Value, A::Error>
+        where A: MapAccess<'de> {
+        let mut map = Map::new();
+        match access.next_key::<String>()? {
+            // A raw number in arbitrary precision mode, see `number::visit_raw`
+            Some(key) if key == RAW_NUMBER_TOKEN => {
+                return Ok(Value::Number(Number::from_raw(access.next_value()?)));
+            }
+            Some(key) => {
+                map.insert(key, access.next_value()?);
+            }
+            None => {}
+        }
+        while let Some((key, value)) = access.next_entry()? {
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+}
+
+#[test]
+fn test_value() {
+    let j = r#"{"name":"db","servers":[{"host":"a","port":5432},{"host":"b/c","port":-1}],"ratio":0.5,"tls":null}"#;
+    let mut v: Value = crate::from_str(j).unwrap();
+    assert_eq!(v["name"], Value::String("db".to_string()));
+    assert_eq!(v["servers"][1]["port"].as_i64(), Some(-1));
+    assert_eq!(v["ratio"].as_f64(), Some(0.5));
+    assert!(v["tls"].is_null());
+    assert!(v["missing"][7].is_null());
+    assert_eq!(to_string(&v).unwrap(), j);
+
+    v["servers"][0]["port"] = 6543.into();
+    v["tls"]["enabled"] = true.into();
+    assert_eq!(v.pointer("/servers/0/port").and_then(Value::as_u64), Some(6543));
+    assert_eq!(v.pointer("/tls/enabled"), Some(&Value::Bool(true)));
+    assert_eq!(v.as_object().unwrap().keys().collect::<Vec<_>>(), vec!["name", "servers", "ratio", "tls"]);
+}
+
+#[test]
+fn test_pointer() {
+    let v: Value = crate::from_str(r#"{"a/b":{"m~n":[10,20]},"":1}"#).unwrap();
+    assert_eq!(v.pointer(""), Some(&v));
+    assert_eq!(v.pointer("/"), Some(&Value::from(1)));
+    assert_eq!(v.pointer("/a~1b/m~0n/1"), Some(&Value::from(20)));
+    assert_eq!(v.pointer("/a~1b/m~0n/01"), None);
+    assert_eq!(v.pointer("/a~1b/m~0n/2"), None);
+    assert_eq!(v.pointer("a~1b"), None);
+}
+
+#[test]
+fn test_convert() {
+    #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+    struct Server {
+        host: String,
+        port: u16,
+        weights: Vec<f64>,
+    }
+
+    let server = Server { host: "a".to_string(), port: 80, weights: vec![1.5, -2.0] };
+    let v = to_value(&server).unwrap();
+    assert_eq!(v["weights"][1].as_f64(), Some(-2.0));
+    assert_eq!(from_value::<Server>(v).unwrap(), server);
+    assert!(from_value::<Server>(Value::from(1)).is_err());
+
+    #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+    }
+
+    let shapes = vec![Shape::Empty, Shape::Circle(0.5), Shape::Rect { w: 2, h: 3 }];
+    let v = to_value(&shapes).unwrap();
+    assert_eq!(v.to_string(), r#"["Empty",{"Circle":0.5},{"Rect":{"w":2,"h":3}}]"#);
+    assert_eq!(from_value::<Vec<Shape>>(v).unwrap(), shapes);
+
+    let mut ports = std::collections::BTreeMap::new();
+    ports.insert(80u16, Some("http"));
+    ports.insert(443, None);
+    let v = to_value(&ports).unwrap();
+    assert_eq!(v.to_string(), r#"{"80":"http","443":null}"#);
+    assert_eq!(from_value::<std::collections::BTreeMap<u16, Option<String>>>(v).unwrap().len(), 2);
+
+    assert_eq!(to_value(&[f64::NAN, f64::INFINITY, 1.0]).unwrap().to_string(), "[null,null,1.0]");
+    assert!(from_value::<(u8, u8)>(crate::json!([1, 2, 3])).is_err());
+}
+
+#[test]
+fn test_json_macro() {
+    let port = 8080;
+    let hosts = vec!["a", "b"];
+    let key = "dynamic";
+    let v = crate::json!({
+        "null": null,
+        "port": port,
+        "offset": -1,
+        "hosts": hosts,
+        "nested": [true, { "x": [1, 2.5, "s"] }, []],
+        (key): {},
+    });
+    assert_eq!(
+        v.to_string(),
+        r#"{"null":null,"port":8080,"offset":-1,"hosts":["a","b"],"nested":[true,{"x":[1,2.5,"s"]},[]],"dynamic":{}}"#
+    );
+    assert_eq!(crate::json!(null), Value::Null);
+    assert_eq!(crate::json!([port + 1, null]), Value::Array(vec![8081.into(), Value::Null]));
+    assert_eq!(crate::json!({ "ratio": f64::NAN }).to_string(), r#"{"ratio":null}"#);
+}